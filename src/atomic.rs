@@ -0,0 +1,334 @@
+//! Crash-safe index writes and the build lock
+//!
+//! Every index file is first written to a temporary sibling named
+//! `<file>.tmp-<pid>-<n>` and fsynced. Only once all files of an index are
+//! staged are they renamed over the old ones, the paths file last, so an
+//! interrupted build leaves the previous index intact plus some temporary
+//! files. Readers only ever open the final names and so never see them.
+//!
+//! Builders serialize on an advisory lock held on `<base>.lock`. A temporary
+//! file found while nobody holds the lock was left by an interrupted build.
+
+use crate::bundle::Section;
+use crate::error::{Result, TokenizerError};
+use crate::persistence::lock_file;
+use std::ffi::OsString;
+use std::fs::{File, TryLockError};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Marker between a file name and its temporary suffix
+const TEMP_MARKER: &str = ".tmp-";
+
+/// Distinguishes temporary files staged by one process
+static NEXT_TEMP: AtomicU64 = AtomicU64::new(0);
+
+/// Files written to temporary siblings, waiting to be renamed into place
+///
+/// Temporary files not committed are removed on drop.
+#[derive(Debug, Default)]
+pub(crate) struct StagedWrites {
+    /// (temporary file, final path) in commit order
+    staged: Vec<(PathBuf, PathBuf)>,
+    /// Files removed on commit
+    removals: Vec<PathBuf>,
+}
+
+impl StagedWrites {
+    /// Write the new contents of `path` to a temporary sibling and fsync it
+    pub(crate) fn stage(
+        &mut self,
+        path: &Path,
+        write: impl FnOnce(&mut BufWriter<File>) -> Result<()>,
+    ) -> Result<()> {
+        let temp = temp_path(path);
+        let file = File::create(&temp).map_err(|e| TokenizerError::Io(e.to_string()))?;
+        self.staged.push((temp, path.to_path_buf()));
+
+        let mut writer = BufWriter::new(file);
+        write(&mut writer)?;
+        writer
+            .flush()
+            .map_err(|e| TokenizerError::Io(e.to_string()))?;
+        let file = writer
+            .into_inner()
+            .map_err(|e| TokenizerError::Io(e.to_string()))?;
+        file.sync_all()
+            .map_err(|e| TokenizerError::Io(e.to_string()))
+    }
+
+    /// Remove `path` on commit, if it exists
+    pub(crate) fn remove(&mut self, path: &Path) {
+        self.removals.push(path.to_path_buf());
+    }
+
+    /// Rename every staged file into place, in staging order, then apply
+    /// the removals
+    pub(crate) fn commit(mut self) -> Result<()> {
+        let mut directories: Vec<PathBuf> = Vec::new();
+        for (temp, path) in std::mem::take(&mut self.staged) {
+            if let Err(e) = std::fs::rename(&temp, &path) {
+                let _ = std::fs::remove_file(&temp);
+                return Err(TokenizerError::Io(e.to_string()));
+            }
+            let directory = parent_dir(&path).to_path_buf();
+            if !directories.contains(&directory) {
+                directories.push(directory);
+            }
+        }
+
+        for path in std::mem::take(&mut self.removals) {
+            match std::fs::remove_file(&path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(TokenizerError::Io(e.to_string()));
+                }
+                _ => {}
+            }
+        }
+
+        // Make the renames themselves durable
+        #[cfg(unix)]
+        for directory in directories {
+            File::open(&directory)
+                .and_then(|dir| dir.sync_all())
+                .map_err(|e| TokenizerError::Io(e.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for StagedWrites {
+    fn drop(&mut self) {
+        for (temp, _) in &self.staged {
+            let _ = std::fs::remove_file(temp);
+        }
+    }
+}
+
+/// Write a single file crash-safely
+pub(crate) fn write_atomic(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<()>,
+) -> Result<()> {
+    let mut staged = StagedWrites::default();
+    staged.stage(path, write)?;
+    staged.commit()
+}
+
+/// Advisory lock serializing the builds of one index
+///
+/// Held until dropped. The lock file itself is left in place.
+#[derive(Debug)]
+pub struct BuildLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl BuildLock {
+    /// Take the build lock of the index at `base`, waiting for other builds
+    ///
+    /// Temporary files left by interrupted builds are removed once the lock
+    /// is held.
+    pub fn acquire(base: &Path) -> Result<Self> {
+        let (file, path) = open_lock_file(base)?;
+        file.lock().map_err(|e| TokenizerError::Io(e.to_string()))?;
+        Self::locked(file, path, base)
+    }
+
+    /// Take the build lock of the index at `base`, failing with
+    /// `TokenizerError::IndexLocked` if another build holds it
+    pub fn try_acquire(base: &Path) -> Result<Self> {
+        let (file, path) = open_lock_file(base)?;
+        match file.try_lock() {
+            Ok(()) => Self::locked(file, path, base),
+            Err(TryLockError::WouldBlock) => {
+                Err(TokenizerError::IndexLocked(path.display().to_string()))
+            }
+            Err(TryLockError::Error(e)) => Err(TokenizerError::Io(e.to_string())),
+        }
+    }
+
+    /// Path of the lock file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn locked(file: File, path: PathBuf, base: &Path) -> Result<Self> {
+        for temp in temp_files(base)? {
+            std::fs::remove_file(&temp).map_err(|e| TokenizerError::Io(e.to_string()))?;
+        }
+        Ok(Self { file, path })
+    }
+}
+
+impl Drop for BuildLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+fn open_lock_file(base: &Path) -> Result<(File, PathBuf)> {
+    let path = lock_file(base);
+    let file = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .map_err(|e| TokenizerError::Io(e.to_string()))?;
+    Ok((file, path))
+}
+
+/// List the temporary files of the index at `base` left by interrupted builds
+///
+/// Returns nothing while a build holds the lock, since its temporary files
+/// are still being written. Readers can ignore or delete what is returned.
+pub fn orphaned_temp_files(base: &Path) -> Result<Vec<PathBuf>> {
+    if !lock_file(base).exists() {
+        return temp_files(base);
+    }
+    let (file, _) = open_lock_file(base)?;
+    match file.try_lock() {
+        Ok(()) => temp_files(base),
+        Err(TryLockError::WouldBlock) => Ok(Vec::new()),
+        Err(TryLockError::Error(e)) => Err(TokenizerError::Io(e.to_string())),
+    }
+}
+
+/// List every temporary file staged for the index at `base`
+///
+/// Only temporaries of the index's own files count: `base` itself (a legacy
+/// index or a bundle) and its split files. Those of a sibling index such as
+/// `index.foo.tkix` next to `index.tkix` belong to another lock.
+fn temp_files(base: &Path) -> Result<Vec<PathBuf>> {
+    let own_names: Vec<String> = std::iter::once(base.to_path_buf())
+        .chain(Section::ALL.iter().map(|section| section.file(base)))
+        .filter_map(|path| Some(path.file_name()?.to_string_lossy().into_owned()))
+        .collect();
+
+    let mut found = Vec::new();
+    let entries = match std::fs::read_dir(parent_dir(base)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(found),
+        Err(e) => return Err(TokenizerError::Io(e.to_string())),
+    };
+    for entry in entries {
+        let entry = entry.map_err(|e| TokenizerError::Io(e.to_string()))?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        let own = name
+            .rsplit_once(TEMP_MARKER)
+            .is_some_and(|(file, _)| own_names.iter().any(|own| own == file));
+        if own && is_temp_name(&name) {
+            found.push(entry.path());
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+/// Check whether a file name has the temporary suffix `.tmp-<pid>-<n>`
+fn is_temp_name(name: &str) -> bool {
+    name.rsplit_once(TEMP_MARKER).is_some_and(|(_, suffix)| {
+        let mut parts = suffix.split('-');
+        let numeric = |part: Option<&str>| {
+            part.is_some_and(|p| !p.is_empty() && p.bytes().all(|b| b.is_ascii_digit()))
+        };
+        numeric(parts.next()) && numeric(parts.next()) && parts.next().is_none()
+    })
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.file_name().unwrap_or_default());
+    name.push(format!(
+        "{}{}-{}",
+        TEMP_MARKER,
+        std::process::id(),
+        NEXT_TEMP.fetch_add(1, Ordering::Relaxed)
+    ));
+    path.with_file_name(name)
+}
+
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_staged_writes_commit_and_abort() {
+        let dir = tempdir().unwrap();
+        let base = dir.path().join("index.tkix");
+        let a = dir.path().join("index.exact");
+        let b = dir.path().join("index.tri");
+        std::fs::write(&a, "old").unwrap();
+
+        // A failure while staging leaves the old file and no temp files
+        let mut staged = StagedWrites::default();
+        staged.stage(&a, |w| Ok(w.write_all(b"new")?)).unwrap();
+        let failed = staged.stage(&b, |_| Err(TokenizerError::Io("disk full".to_string())));
+        assert!(failed.is_err());
+        drop(staged);
+        assert_eq!(std::fs::read_to_string(&a).unwrap(), "old");
+        assert!(temp_files(&base).unwrap().is_empty());
+
+        let mut staged = StagedWrites::default();
+        staged.stage(&b, |w| Ok(w.write_all(b"b")?)).unwrap();
+        staged.remove(&a);
+        assert_eq!(temp_files(&base).unwrap().len(), 1);
+        staged.commit().unwrap();
+        assert_eq!(std::fs::read_to_string(&b).unwrap(), "b");
+        assert!(!a.exists());
+        assert!(temp_files(&base).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_build_lock_and_orphans() {
+        let dir = tempdir().unwrap();
+        let base = dir.path().join("index.tkix");
+        let orphan = dir.path().join("index.exact.tmp-123-0");
+        std::fs::write(&orphan, "partial").unwrap();
+        std::fs::write(dir.path().join("index.exact"), "kept").unwrap();
+        std::fs::write(dir.path().join("other.exact.tmp-123-0"), "kept").unwrap();
+        // A sibling index whose name starts with this one's
+        let sibling = dir.path().join("index.foo.exact.tmp-123-0");
+        std::fs::write(&sibling, "kept").unwrap();
+        assert_eq!(
+            orphaned_temp_files(&base).unwrap(),
+            std::slice::from_ref(&orphan)
+        );
+
+        let lock = BuildLock::try_acquire(&base).unwrap();
+        assert_eq!(lock.path(), lock_file(&base));
+        assert!(!orphan.exists());
+        assert!(sibling.exists());
+        assert!(matches!(
+            BuildLock::try_acquire(&base),
+            Err(TokenizerError::IndexLocked(_))
+        ));
+
+        // Temp files of a running build are not orphans
+        std::fs::write(&orphan, "partial").unwrap();
+        assert!(orphaned_temp_files(&base).unwrap().is_empty());
+        drop(lock);
+        assert_eq!(orphaned_temp_files(&base).unwrap(), [orphan]);
+        assert!(BuildLock::try_acquire(&base).is_ok());
+    }
+
+    #[test]
+    fn test_temp_names() {
+        assert!(is_temp_name("index.paths.tmp-42-7"));
+        assert!(!is_temp_name("index.paths"));
+        assert!(!is_temp_name("index.tmp-data.exact"));
+        assert!(!is_temp_name("index.paths.tmp-42"));
+        let temp = temp_path(Path::new("/x/index.tri"));
+        assert!(is_temp_name(&temp.file_name().unwrap().to_string_lossy()));
+        assert_eq!(temp.parent(), Some(Path::new("/x")));
+    }
+}
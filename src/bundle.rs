@@ -0,0 +1,673 @@
+//! Single-file bundle holding every file of a split index
+//!
+//! ```text
+//! magic          [u8; 4]  "TKBN"
+//! version        u16
+//! reserved       u16
+//! section_count  u64
+//! sections       section_count x { kind: u32, reserved: u32, offset: u64, len: u64,
+//!                                  checksum: u64 }
+//! data           the sections, located by offset/len
+//! trailer        { checksum: u64, magic: [u8; 4] "TKCK" }
+//! ```
+//!
+//! Each section holds the bytes of one split file unchanged, so it keeps its
+//! own header and checksums. The section checksum covers all of its bytes and
+//! the trailer checksum covers the header and table of contents.
+//!
+//! Opening a bundle reads only the header and table of contents; sections are
+//! sliced out of the mapping when loaded. Since sections check themselves,
+//! their checksums in the table of contents are only checked when unpacking.
+
+use crate::atomic::{write_atomic, StagedWrites};
+use crate::checksum::{checksum, corrupted, split_trailer, write_trailer, ChecksumWriter};
+use crate::dictionary::TokenDictionary;
+use crate::error::{Result, TokenizerError};
+use crate::index::{
+    ExactTokenIndex, IndexHeader, IndexSet, PathIndex, TrigramIndex, FORMAT_VERSION,
+};
+use crate::persistence::{
+    dictionary_from, exact_from, exact_view_from, frequencies_from, in_file, load_all_from,
+    load_dictionary, load_exact, load_exact_view, load_frequencies, load_paths, load_positions,
+    load_subwords, load_trigram, load_trigram_view, map_file, paths_from, positions_from,
+    subwords_from, trigram_from, trigram_view_from, version_mismatch, MappedBytes, EXT_DICT,
+    EXT_EXACT, EXT_EXACT_LOWER, EXT_EXACT_NORMALIZED, EXT_PATHS, EXT_POS, EXT_SUB, EXT_TF,
+    EXT_TF_LOWER, EXT_TRIGRAM,
+};
+use crate::positions::PositionIndex;
+use crate::ranking::TermFrequencies;
+use crate::subword::SubwordIndex;
+use crate::tokenizer::Tokenizer;
+use crate::verify::verify_index;
+use crate::view::{ExactTokenView, TrigramView};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Magic bytes of a bundle
+pub const MAGIC_BUNDLE: &[u8; 4] = b"TKBN";
+
+/// Bytes before the table of contents
+const HEADER_LEN: usize = 16;
+
+/// Bytes per section in the table of contents
+const SECTION_LEN: usize = 32;
+
+/// A file of a split index, stored as one section of a bundle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    Paths,
+    Exact,
+    ExactLower,
+    ExactNormalized,
+    Trigram,
+    Dictionary,
+    Positions,
+    Frequencies,
+    FrequenciesLower,
+    Subwords,
+}
+
+impl Section {
+    /// Every section, in the order they are packed
+    pub const ALL: [Section; 10] = [
+        Section::Paths,
+        Section::Exact,
+        Section::ExactLower,
+        Section::ExactNormalized,
+        Section::Trigram,
+        Section::Dictionary,
+        Section::Positions,
+        Section::Frequencies,
+        Section::FrequenciesLower,
+        Section::Subwords,
+    ];
+
+    /// Sections every index has
+    pub const REQUIRED: [Section; 4] = [
+        Section::Paths,
+        Section::Exact,
+        Section::ExactLower,
+        Section::Trigram,
+    ];
+
+    /// Extension of the split file holding the section
+    pub fn extension(self) -> &'static str {
+        match self {
+            Section::Paths => EXT_PATHS,
+            Section::Exact => EXT_EXACT,
+            Section::ExactLower => EXT_EXACT_LOWER,
+            Section::ExactNormalized => EXT_EXACT_NORMALIZED,
+            Section::Trigram => EXT_TRIGRAM,
+            Section::Dictionary => EXT_DICT,
+            Section::Positions => EXT_POS,
+            Section::Frequencies => EXT_TF,
+            Section::FrequenciesLower => EXT_TF_LOWER,
+            Section::Subwords => EXT_SUB,
+        }
+    }
+
+    /// Path of the split file holding the section for the index at `base`
+    pub fn file(self, base: &Path) -> PathBuf {
+        base.with_extension(self.extension())
+    }
+
+    /// Section kind stored in the table of contents
+    fn kind(self) -> u32 {
+        match self {
+            Section::Paths => 1,
+            Section::Exact => 2,
+            Section::ExactLower => 3,
+            Section::ExactNormalized => 4,
+            Section::Trigram => 5,
+            Section::Dictionary => 6,
+            Section::Positions => 7,
+            Section::Frequencies => 8,
+            Section::FrequenciesLower => 9,
+            Section::Subwords => 10,
+        }
+    }
+
+    fn from_kind(kind: u32) -> Option<Self> {
+        Section::ALL
+            .into_iter()
+            .find(|section| section.kind() == kind)
+    }
+}
+
+/// Location of a section in a bundle
+#[derive(Debug, Clone, Copy)]
+struct SectionEntry {
+    section: Section,
+    offset: usize,
+    len: usize,
+    checksum: u64,
+}
+
+/// An opened bundle; sections are read from the mapping on demand
+#[derive(Debug, Clone)]
+pub struct Bundle {
+    path: PathBuf,
+    data: MappedBytes,
+    sections: Vec<SectionEntry>,
+}
+
+impl Bundle {
+    /// Open a bundle, checking its header and table of contents
+    pub fn open(path: &Path) -> Result<Self> {
+        let data = map_file(path)?;
+        let sections = parse_contents(data.as_ref()).map_err(in_file(path))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            data,
+            sections,
+        })
+    }
+
+    /// Path of the bundle
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Check if the bundle has a section
+    pub fn has(&self, section: Section) -> bool {
+        self.entry(section).is_some()
+    }
+
+    /// Iterate over the sections of the bundle in storage order
+    pub fn sections(&self) -> impl Iterator<Item = Section> + '_ {
+        self.sections.iter().map(|entry| entry.section)
+    }
+
+    /// Name a section in errors, as `<bundle>:<extension>`
+    pub fn section_name(&self, section: Section) -> PathBuf {
+        PathBuf::from(format!("{}:{}", self.path.display(), section.extension()))
+    }
+
+    /// Check a section against the checksum in the table of contents
+    pub fn verify_section(&self, section: Section) -> Result<()> {
+        let entry = self.require(section)?;
+        let bytes = &self.data.as_ref()[entry.offset..entry.offset + entry.len];
+        if checksum(bytes) != entry.checksum {
+            return Err(TokenizerError::Corrupted(format!(
+                "{}: section checksum mismatch",
+                self.section_name(section).display()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Bytes of a section, sharing the bundle's mapping
+    pub(crate) fn section(&self, section: Section) -> Result<MappedBytes> {
+        let entry = self.require(section)?;
+        Ok(self.data.slice(entry.offset..entry.offset + entry.len))
+    }
+
+    fn entry(&self, section: Section) -> Option<&SectionEntry> {
+        self.sections.iter().find(|entry| entry.section == section)
+    }
+
+    fn require(&self, section: Section) -> Result<&SectionEntry> {
+        self.entry(section).ok_or_else(|| {
+            TokenizerError::InvalidIndexFormat(format!(
+                "Bundle {} has no {} section",
+                self.path.display(),
+                section.extension()
+            ))
+        })
+    }
+}
+
+/// Parse and check the header and table of contents of a bundle
+fn parse_contents(data: &[u8]) -> Result<Vec<SectionEntry>> {
+    if data.get(..4) != Some(&MAGIC_BUNDLE[..]) {
+        return Err(TokenizerError::InvalidIndexFormat(
+            "Invalid magic bytes for bundle".to_string(),
+        ));
+    }
+    if data.len() < HEADER_LEN {
+        return Err(corrupted("file is truncated"));
+    }
+    let version = u16::from_le_bytes([data[4], data[5]]);
+    if version != FORMAT_VERSION {
+        return Err(version_mismatch(version));
+    }
+
+    let (payload, expected) = split_trailer(data)?;
+    let count = read_u64(data, 8) as usize;
+    let contents_end = count
+        .checked_mul(SECTION_LEN)
+        .and_then(|len| len.checked_add(HEADER_LEN))
+        .filter(|end| *end <= payload.len())
+        .ok_or_else(|| corrupted("table of contents extends past the end of the file"))?;
+    if checksum(&payload[..contents_end]) != expected {
+        return Err(corrupted("table of contents checksum mismatch"));
+    }
+
+    let mut sections: Vec<SectionEntry> = Vec::with_capacity(count);
+    for i in 0..count {
+        let entry = HEADER_LEN + i * SECTION_LEN;
+        let kind = u32::from_le_bytes([
+            data[entry],
+            data[entry + 1],
+            data[entry + 2],
+            data[entry + 3],
+        ]);
+        let section = Section::from_kind(kind)
+            .ok_or_else(|| corrupted(&format!("unknown section kind {}", kind)))?;
+        let offset = read_u64(data, entry + 8) as usize;
+        let len = read_u64(data, entry + 16) as usize;
+        if offset
+            .checked_add(len)
+            .is_none_or(|end| end > payload.len())
+        {
+            return Err(corrupted(&format!(
+                "{} section extends past the end of the file",
+                section.extension()
+            )));
+        }
+        if sections.iter().any(|other| other.section == section) {
+            return Err(corrupted(&format!(
+                "duplicate {} section",
+                section.extension()
+            )));
+        }
+        sections.push(SectionEntry {
+            section,
+            offset,
+            len,
+            checksum: read_u64(data, entry + 24),
+        });
+    }
+    Ok(sections)
+}
+
+/// Write a bundle of `sections`
+fn write_bundle<W: Write>(writer: &mut W, sections: &[(Section, MappedBytes)]) -> Result<()> {
+    let io = |e: std::io::Error| TokenizerError::Io(e.to_string());
+
+    let mut contents = ChecksumWriter::new(&mut *writer);
+    contents.write_all(MAGIC_BUNDLE).map_err(io)?;
+    contents
+        .write_all(&FORMAT_VERSION.to_le_bytes())
+        .map_err(io)?;
+    contents.write_all(&[0u8; 2]).map_err(io)?;
+    contents
+        .write_all(&(sections.len() as u64).to_le_bytes())
+        .map_err(io)?;
+
+    let mut offset = (HEADER_LEN + sections.len() * SECTION_LEN) as u64;
+    for (section, data) in sections {
+        let data = data.as_ref();
+        contents
+            .write_all(&section.kind().to_le_bytes())
+            .map_err(io)?;
+        contents.write_all(&[0u8; 4]).map_err(io)?;
+        contents.write_all(&offset.to_le_bytes()).map_err(io)?;
+        contents
+            .write_all(&(data.len() as u64).to_le_bytes())
+            .map_err(io)?;
+        contents
+            .write_all(&checksum(data).to_le_bytes())
+            .map_err(io)?;
+        offset += data.len() as u64;
+    }
+    let (_, contents_checksum) = contents.into_parts();
+
+    for (_, data) in sections {
+        writer.write_all(data.as_ref()).map_err(io)?;
+    }
+    write_trailer(writer, contents_checksum).map_err(io)
+}
+
+/// Check if `path` is a bundle
+pub fn is_bundle(path: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok_and(|_| &magic == MAGIC_BUNDLE)
+}
+
+/// Pack the split index at `base` into a bundle at `bundle_path`
+///
+/// The index is verified first; a damaged one is not packed. Callers that
+/// race with builds of the index should hold its `BuildLock`.
+pub fn pack_index(base: &Path, bundle_path: &Path) -> Result<()> {
+    let report = verify_index(base)?;
+    if !report.is_ok() {
+        return Err(TokenizerError::Corrupted(format!(
+            "{} has {} problems; see `tokenizer verify`",
+            base.display(),
+            report.problem_count()
+        )));
+    }
+
+    let mut sections = Vec::new();
+    for section in Section::ALL {
+        let path = section.file(base);
+        if path.exists() {
+            sections.push((section, map_file(&path)?));
+        }
+    }
+    write_atomic(bundle_path, |writer| write_bundle(writer, &sections))
+}
+
+/// Unpack a bundle into a split index at `base`
+///
+/// Every section is checked first. Optional files the bundle lacks are
+/// removed, and the files are replaced together like `save_all` does.
+pub fn unpack_index(bundle_path: &Path, base: &Path) -> Result<()> {
+    let bundle = Bundle::open(bundle_path)?;
+    for section in Section::REQUIRED {
+        bundle.section(section)?;
+    }
+    for section in bundle.sections() {
+        bundle.verify_section(section)?;
+    }
+
+    let mut staged = StagedWrites::default();
+    for section in Section::ALL {
+        if section == Section::Paths {
+            continue;
+        }
+        if bundle.has(section) {
+            let data = bundle.section(section)?;
+            staged.stage(&section.file(base), |w| {
+                w.write_all(data.as_ref())
+                    .map_err(|e| TokenizerError::Io(e.to_string()))
+            })?;
+        } else {
+            staged.remove(&section.file(base));
+        }
+    }
+    // Renamed last: a new paths file means every other file is in place
+    let paths = bundle.section(Section::Paths)?;
+    staged.stage(&Section::Paths.file(base), |w| {
+        w.write_all(paths.as_ref())
+            .map_err(|e| TokenizerError::Io(e.to_string()))
+    })?;
+    staged.commit()
+}
+
+/// The files of an index in either layout: split files next to a base path,
+/// or sections of a bundle
+#[derive(Debug, Clone)]
+pub enum IndexFiles {
+    Split(PathBuf),
+    Bundle(Bundle),
+}
+
+impl IndexFiles {
+    /// Open the index at `path`: a bundle, or the base path of split files
+    pub fn open(path: &Path) -> Result<Self> {
+        if is_bundle(path) {
+            return Bundle::open(path).map(IndexFiles::Bundle);
+        }
+        if Section::Paths.file(path).exists() {
+            return Ok(IndexFiles::Split(path.to_path_buf()));
+        }
+        Err(TokenizerError::IndexNotFound(path.display().to_string()))
+    }
+
+    /// Check if the index has a file or section
+    pub fn has(&self, section: Section) -> bool {
+        match self {
+            IndexFiles::Split(base) => section.file(base).exists(),
+            IndexFiles::Bundle(bundle) => bundle.has(section),
+        }
+    }
+
+    /// Name of a file or section, used in errors
+    pub fn name(&self, section: Section) -> PathBuf {
+        match self {
+            IndexFiles::Split(base) => section.file(base),
+            IndexFiles::Bundle(bundle) => bundle.section_name(section),
+        }
+    }
+
+    /// Size in bytes of a file or section, or 0 if the index has none
+    pub fn size(&self, section: Section) -> u64 {
+        match self {
+            IndexFiles::Split(base) => std::fs::metadata(section.file(base))
+                .map(|m| m.len())
+                .unwrap_or(0),
+            IndexFiles::Bundle(bundle) => bundle.entry(section).map_or(0, |entry| entry.len as u64),
+        }
+    }
+
+    /// Load the path index
+    pub fn load_paths(&self) -> Result<PathIndex> {
+        match self {
+            IndexFiles::Split(base) => load_paths(&Section::Paths.file(base)),
+            IndexFiles::Bundle(bundle) => paths_from(
+                bundle.section(Section::Paths)?.as_ref(),
+                &bundle.section_name(Section::Paths),
+            ),
+        }
+    }
+
+    /// Load an exact index (`Exact`, `ExactLower` or `ExactNormalized`)
+    pub fn load_exact(&self, section: Section) -> Result<ExactTokenIndex> {
+        self.load_exact_tokenized(section, None)
+    }
+
+    /// Load an exact index, checking `tokenizer` against its header if given
+    pub(crate) fn load_exact_tokenized(
+        &self,
+        section: Section,
+        tokenizer: Option<&Arc<dyn Tokenizer>>,
+    ) -> Result<ExactTokenIndex> {
+        match (self, tokenizer) {
+            (IndexFiles::Split(base), None) => load_exact(&section.file(base)),
+            (IndexFiles::Split(base), Some(tokenizer)) => {
+                let path = section.file(base);
+                let data = std::fs::read(&path).map_err(|e| TokenizerError::Io(e.to_string()))?;
+                exact_from(&data[..], &path, Some(tokenizer))
+            }
+            (IndexFiles::Bundle(bundle), tokenizer) => exact_from(
+                bundle.section(section)?,
+                &bundle.section_name(section),
+                tokenizer,
+            ),
+        }
+    }
+
+    /// Open an exact index as a view (`Exact`, `ExactLower` or
+    /// `ExactNormalized`)
+    pub fn load_exact_view(&self, section: Section) -> Result<ExactTokenView> {
+        match self {
+            IndexFiles::Split(base) => load_exact_view(&section.file(base)),
+            IndexFiles::Bundle(bundle) => exact_view_from(
+                bundle.section(section)?,
+                &bundle.section_name(section),
+                None,
+            ),
+        }
+    }
+
+    /// Load the trigram index
+    pub fn load_trigram(&self) -> Result<TrigramIndex> {
+        match self {
+            IndexFiles::Split(base) => load_trigram(&Section::Trigram.file(base)),
+            IndexFiles::Bundle(bundle) => trigram_from(
+                bundle.section(Section::Trigram)?,
+                &bundle.section_name(Section::Trigram),
+            ),
+        }
+    }
+
+    /// Open the trigram index as a view
+    pub fn load_trigram_view(&self) -> Result<TrigramView> {
+        match self {
+            IndexFiles::Split(base) => load_trigram_view(&Section::Trigram.file(base)),
+            IndexFiles::Bundle(bundle) => trigram_view_from(
+                bundle.section(Section::Trigram)?,
+                &bundle.section_name(Section::Trigram),
+            ),
+        }
+    }
+
+    /// Load the token dictionary
+    pub fn load_dictionary(&self) -> Result<(IndexHeader, TokenDictionary)> {
+        self.load_with(Section::Dictionary, load_dictionary, dictionary_from)
+    }
+
+    /// Load the token positions
+    pub fn load_positions(&self) -> Result<(IndexHeader, PositionIndex)> {
+        self.load_with(Section::Positions, load_positions, positions_from)
+    }
+
+    /// Load term frequencies (`Frequencies` or `FrequenciesLower`)
+    pub fn load_frequencies(&self, section: Section) -> Result<(IndexHeader, TermFrequencies)> {
+        self.load_with(section, load_frequencies, frequencies_from)
+    }
+
+    /// Load the subword index
+    pub fn load_subwords(&self) -> Result<(IndexHeader, SubwordIndex)> {
+        self.load_with(Section::Subwords, load_subwords, subwords_from)
+    }
+
+    /// Load every file and check they belong together, like `load_all`
+    pub fn load_all(&self) -> Result<IndexSet> {
+        load_all_from(self, None)
+    }
+
+    /// Load every file of an index built with `tokenizer`, like
+    /// `load_all_with_tokenizer`
+    pub fn load_all_with_tokenizer(&self, tokenizer: Arc<dyn Tokenizer>) -> Result<IndexSet> {
+        load_all_from(self, Some(&tokenizer))
+    }
+
+    fn load_with<T>(
+        &self,
+        section: Section,
+        from_file: fn(&Path) -> Result<T>,
+        from_bytes: fn(&[u8], &Path) -> Result<T>,
+    ) -> Result<T> {
+        match self {
+            IndexFiles::Split(base) => from_file(&section.file(base)),
+            IndexFiles::Bundle(bundle) => from_bytes(
+                bundle.section(section)?.as_ref(),
+                &bundle.section_name(section),
+            ),
+        }
+    }
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::{load_all, save_sample_index};
+    use crate::query::{query_exact, QueryOptions};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_pack_open_and_unpack() {
+        let src = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        let base = out.path().join("index.tkix");
+        let bundle_path = out.path().join("index.tkb");
+        save_sample_index(src.path(), &base);
+
+        pack_index(&base, &bundle_path).unwrap();
+        assert!(is_bundle(&bundle_path));
+        assert!(!is_bundle(&Section::Paths.file(&base)));
+
+        let files = IndexFiles::open(&bundle_path).unwrap();
+        let IndexFiles::Bundle(bundle) = &files else {
+            panic!("not opened as a bundle");
+        };
+        assert!(bundle.has(Section::Positions));
+        for section in bundle.sections() {
+            bundle.verify_section(section).unwrap();
+        }
+
+        // Views and full loads from the bundle match the split files
+        let paths = files.load_paths().unwrap();
+        let view = files.load_exact_view(Section::Exact).unwrap();
+        let result = query_exact(&paths, &view, "parse_header", &QueryOptions::default()).unwrap();
+        assert_eq!(result.files, [src.path().join("a.rs")]);
+        let from_bundle = files.load_all().unwrap();
+        let from_split = load_all(&base).unwrap();
+        assert_eq!(from_bundle.paths.header, from_split.paths.header);
+        assert_eq!(
+            from_bundle.exact.token_count(),
+            from_split.exact.token_count()
+        );
+        assert!(from_bundle.exact.positions().is_some());
+
+        // Sections are the split files as they are, and report their size
+        let split = IndexFiles::open(&base).unwrap();
+        for section in Section::ALL {
+            assert_eq!(files.size(section), split.size(section), "{:?}", section);
+        }
+        assert!(files.size(Section::Trigram) > 0);
+
+        // Unpacking recreates the same files and drops stale optional ones
+        let unpacked = out.path().join("copy.tkix");
+        std::fs::write(Section::Subwords.file(&unpacked), "stale").unwrap();
+        unpack_index(&bundle_path, &unpacked).unwrap();
+        for section in Section::ALL {
+            let original = std::fs::read(section.file(&base)).ok();
+            assert_eq!(
+                std::fs::read(section.file(&unpacked)).ok(),
+                original,
+                "{:?}",
+                section
+            );
+        }
+    }
+
+    #[test]
+    fn test_corrupted_bundle() {
+        let src = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        let base = out.path().join("index.tkix");
+        let bundle_path = out.path().join("index.tkb");
+        save_sample_index(src.path(), &base);
+        pack_index(&base, &bundle_path).unwrap();
+        let data = std::fs::read(&bundle_path).unwrap();
+
+        // A flip in the table of contents fails the open
+        let mut flipped = data.clone();
+        flipped[HEADER_LEN + 9] ^= 0x01;
+        std::fs::write(&bundle_path, &flipped).unwrap();
+        assert!(matches!(
+            Bundle::open(&bundle_path),
+            Err(TokenizerError::Corrupted(_))
+        ));
+
+        // A flip in the paths section fails that section only
+        let bundle = {
+            std::fs::write(&bundle_path, &data).unwrap();
+            Bundle::open(&bundle_path).unwrap()
+        };
+        let paths = bundle.entry(Section::Paths).unwrap().offset;
+        let mut flipped = data.clone();
+        flipped[paths + 20] ^= 0x01;
+        std::fs::write(&bundle_path, &flipped).unwrap();
+        let files = IndexFiles::open(&bundle_path).unwrap();
+        assert!(matches!(
+            files.load_paths(),
+            Err(TokenizerError::Corrupted(_))
+        ));
+        assert!(files.load_trigram().is_ok());
+        assert!(matches!(
+            unpack_index(&bundle_path, &out.path().join("copy.tkix")),
+            Err(TokenizerError::Corrupted(_))
+        ));
+
+        std::fs::write(&bundle_path, &data[..data.len() - 1]).unwrap();
+        assert!(matches!(
+            Bundle::open(&bundle_path),
+            Err(TokenizerError::Corrupted(_))
+        ));
+    }
+}
@@ -0,0 +1,232 @@
+//! Checksums that detect truncated and bit-flipped index files
+//!
+//! Every split index file ends with a fixed trailer:
+//!
+//! ```text
+//! checksum     u64
+//! magic        [u8; 4]  "TKCK"
+//! ```
+//!
+//! In table files the checksum covers the header and entry table, and each
+//! bitmap carries its own checksum so that views can check just the bitmaps
+//! they read. In all other files it covers every byte before the trailer.
+//!
+//! The checksum is a 64-bit hash over little-endian words. Each step is a
+//! bijection of the state, so any change confined to one word is always
+//! detected, and the length is mixed in last so truncation is too. It is not
+//! meant to resist deliberate tampering.
+
+use crate::error::{Result, TokenizerError};
+use std::io::Write;
+
+/// Bytes of the trailer
+pub(crate) const TRAILER_LEN: usize = 12;
+
+/// Magic bytes ending every checksummed file
+const MAGIC_TRAILER: &[u8; 4] = b"TKCK";
+
+const SEED: u64 = 0x243F_6A88_85A3_08D3;
+const MULTIPLIER: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// Streaming checksum; the result does not depend on how input is split
+#[derive(Debug, Clone)]
+pub(crate) struct Checksum {
+    state: u64,
+    pending: [u8; 8],
+    pending_len: usize,
+    len: u64,
+}
+
+impl Checksum {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: SEED,
+            pending: [0; 8],
+            pending_len: 0,
+            len: 0,
+        }
+    }
+
+    /// Add bytes to the checksum
+    pub(crate) fn update(&mut self, mut bytes: &[u8]) {
+        self.len += bytes.len() as u64;
+
+        if self.pending_len > 0 {
+            let take = bytes.len().min(8 - self.pending_len);
+            self.pending[self.pending_len..self.pending_len + take].copy_from_slice(&bytes[..take]);
+            self.pending_len += take;
+            bytes = &bytes[take..];
+            if self.pending_len < 8 {
+                return;
+            }
+            self.mix(u64::from_le_bytes(self.pending));
+            self.pending_len = 0;
+        }
+
+        let mut words = bytes.chunks_exact(8);
+        for word in &mut words {
+            let mut buf = [0u8; 8];
+            buf.copy_from_slice(word);
+            self.mix(u64::from_le_bytes(buf));
+        }
+        let rest = words.remainder();
+        self.pending[..rest.len()].copy_from_slice(rest);
+        self.pending_len = rest.len();
+    }
+
+    /// Get the checksum of all bytes added
+    pub(crate) fn finish(mut self) -> u64 {
+        if self.pending_len > 0 {
+            self.pending[self.pending_len..].fill(0);
+            self.mix(u64::from_le_bytes(self.pending));
+        }
+        self.mix(self.len);
+
+        let mut hash = self.state;
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(0xFF51_AFD7_ED55_8CCD);
+        hash ^= hash >> 29;
+        hash
+    }
+
+    #[inline]
+    fn mix(&mut self, word: u64) {
+        self.state = (self.state ^ word).wrapping_mul(MULTIPLIER).rotate_left(29);
+    }
+}
+
+/// Checksum of a byte slice
+pub(crate) fn checksum(bytes: &[u8]) -> u64 {
+    let mut checksum = Checksum::new();
+    checksum.update(bytes);
+    checksum.finish()
+}
+
+/// Writer that checksums everything written through it
+pub(crate) struct ChecksumWriter<W> {
+    inner: W,
+    checksum: Checksum,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        Self {
+            inner,
+            checksum: Checksum::new(),
+        }
+    }
+
+    /// Get the writer back without writing a trailer
+    pub(crate) fn into_parts(self) -> (W, u64) {
+        (self.inner, self.checksum.finish())
+    }
+
+    /// Write the trailer for everything written so far
+    pub(crate) fn finish(self) -> std::io::Result<W> {
+        let (mut inner, checksum) = self.into_parts();
+        write_trailer(&mut inner, checksum)?;
+        Ok(inner)
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.checksum.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Write a trailer holding `checksum`
+pub(crate) fn write_trailer<W: Write>(writer: &mut W, checksum: u64) -> std::io::Result<()> {
+    writer.write_all(&checksum.to_le_bytes())?;
+    writer.write_all(MAGIC_TRAILER)
+}
+
+/// Split a file into the bytes before its trailer and the stored checksum
+pub(crate) fn split_trailer(data: &[u8]) -> Result<(&[u8], u64)> {
+    let Some(payload_len) = data.len().checked_sub(TRAILER_LEN) else {
+        return Err(corrupted("file is truncated"));
+    };
+    let (payload, trailer) = data.split_at(payload_len);
+    if &trailer[8..] != MAGIC_TRAILER {
+        return Err(corrupted(
+            "checksum trailer is missing; the file is truncated",
+        ));
+    }
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&trailer[..8]);
+    Ok((payload, u64::from_le_bytes(buf)))
+}
+
+/// Check the trailer of a file whose checksum covers everything before it
+///
+/// Returns the bytes before the trailer.
+pub(crate) fn verify_trailer(data: &[u8]) -> Result<&[u8]> {
+    let (payload, expected) = split_trailer(data)?;
+    if checksum(payload) != expected {
+        return Err(corrupted("checksum mismatch"));
+    }
+    Ok(payload)
+}
+
+/// Build a `TokenizerError::Corrupted`; the loaders add the file name
+pub(crate) fn corrupted(problem: &str) -> TokenizerError {
+    TokenizerError::Corrupted(problem.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksum_streaming_and_sensitivity() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let whole = checksum(&data);
+
+        for split in [0, 1, 7, 8, 9, 500, 999, 1000] {
+            let mut streamed = Checksum::new();
+            streamed.update(&data[..split]);
+            streamed.update(&data[split..]);
+            assert_eq!(streamed.finish(), whole, "split at {}", split);
+        }
+
+        // Every single bit flip, truncation and appended zeros are detected
+        for bit in 0..data.len() * 8 {
+            let mut flipped = data.clone();
+            flipped[bit / 8] ^= 1 << (bit % 8);
+            assert_ne!(checksum(&flipped), whole);
+        }
+        assert_ne!(checksum(&data[..999]), whole);
+        assert_ne!(checksum(&[&data[..], &[0]].concat()), whole);
+        assert_ne!(checksum(b""), checksum(&[0]));
+    }
+
+    #[test]
+    fn test_trailer() {
+        let mut writer = ChecksumWriter::new(Vec::new());
+        writer.write_all(b"payload").unwrap();
+        let data = writer.finish().unwrap();
+        assert_eq!(data.len(), 7 + TRAILER_LEN);
+        assert_eq!(verify_trailer(&data).unwrap(), b"payload");
+
+        let mut flipped = data.clone();
+        flipped[2] ^= 0x10;
+        assert!(matches!(
+            verify_trailer(&flipped),
+            Err(TokenizerError::Corrupted(_))
+        ));
+        assert!(matches!(
+            verify_trailer(&data[..data.len() - 1]),
+            Err(TokenizerError::Corrupted(_))
+        ));
+        assert!(matches!(
+            verify_trailer(b"TKCK"),
+            Err(TokenizerError::Corrupted(_))
+        ));
+    }
+}
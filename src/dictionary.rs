@@ -0,0 +1,395 @@
+//! Token dictionary: the token strings behind exact token hashes
+//!
+//! Stored next to the exact index as a `.dict` file:
+//!
+//! ```text
+//! magic        [u8; 4]  "TKID"
+//! version      u16
+//! reserved     u16
+//! index_id     [u8; 16]
+//! created_at   u64
+//! tokenizer    u64
+//! token_count  u64
+//! tokens       token_count x { shared: varint, suffix_len: varint, suffix: [u8] }
+//! ```
+//!
+//! Tokens are sorted bytewise and front-coded: each entry stores how many
+//! leading bytes it shares with the previous token plus the remaining
+//! suffix. Varints are unsigned LEB128. Hashes are not stored; they are
+//! recomputed on load, which also surfaces hash collisions.
+
+use crate::error::{Result, TokenizerError};
+use crate::index::{IndexHeader, FORMAT_VERSION};
+use crate::table::{parse_header, write_header, HEADER_LEN};
+use crate::tokenizer::{hash_token, hash_token_lower, hash_token_normalized, normalize_token};
+use crate::unicode::fold_token;
+use rustc_hash::FxHashMap;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::ops::Bound;
+use std::sync::Arc;
+
+/// Magic bytes of the dictionary file
+pub(crate) const MAGIC_DICT: &[u8; 4] = b"TKID";
+
+/// Sorted set of exact-mode token strings, addressable by token hash
+#[derive(Debug, Clone, Default)]
+pub struct TokenDictionary {
+    /// Token bytes in sorted order, with their hashes
+    by_token: BTreeMap<Arc<[u8]>, u64>,
+
+    /// First token seen for each hash
+    by_hash: FxHashMap<u64, Arc<[u8]>>,
+}
+
+impl TokenDictionary {
+    /// Create an empty dictionary
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a token; returns its hash
+    pub fn insert(&mut self, token: &[u8]) -> u64 {
+        let hash = hash_token(token);
+        self.insert_hashed(hash, token);
+        hash
+    }
+
+    /// Add a token whose hash is already known
+    pub(crate) fn insert_hashed(&mut self, hash: u64, token: &[u8]) {
+        match self.by_hash.get(&hash) {
+            Some(existing) if **existing == *token => {}
+            // A different token with the same hash: keep both strings, but
+            // the hash keeps resolving to the first one
+            Some(_) => {
+                self.by_token.entry(Arc::from(token)).or_insert(hash);
+            }
+            None => {
+                let token: Arc<[u8]> = Arc::from(token);
+                self.by_token.insert(token.clone(), hash);
+                self.by_hash.insert(hash, token);
+            }
+        }
+    }
+
+    /// Add every token of another dictionary
+    pub(crate) fn extend(&mut self, other: &TokenDictionary) {
+        // The strings hashes resolve to go first, so they keep resolving
+        // to them unless this dictionary already has another
+        for (hash, token) in &other.by_hash {
+            self.insert_hashed(*hash, token);
+        }
+        for (token, hash) in &other.by_token {
+            self.insert_hashed(*hash, token);
+        }
+    }
+
+    /// Get the token string for a hash
+    pub fn get(&self, hash: u64) -> Option<&[u8]> {
+        self.by_hash.get(&hash).map(|token| &**token)
+    }
+
+    /// Get the number of distinct token strings
+    pub fn len(&self) -> usize {
+        self.by_token.len()
+    }
+
+    /// Check whether the dictionary has no tokens
+    pub fn is_empty(&self) -> bool {
+        self.by_token.is_empty()
+    }
+
+    /// Get the number of tokens whose hash is shared with another token
+    pub fn collision_count(&self) -> usize {
+        self.by_token.len() - self.by_hash.len()
+    }
+
+    /// Iterate over `(token, hash)` pairs in bytewise token order
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], u64)> + '_ {
+        self.by_token.iter().map(|(token, hash)| (&**token, *hash))
+    }
+
+    /// Iterate over the `(token, hash)` pairs of tokens starting with `prefix`
+    pub fn iter_prefix<'a>(&'a self, prefix: &'a [u8]) -> impl Iterator<Item = (&'a [u8], u64)> {
+        self.by_token
+            .range::<[u8], _>((Bound::Included(prefix), Bound::Unbounded))
+            .map(|(token, hash)| (&**token, *hash))
+            .take_while(move |(token, _)| token.starts_with(prefix))
+    }
+
+    /// Build the vocabulary of the case-insensitive exact index
+    ///
+    /// Tokens are case-folded and keyed by `hash_token_lower`, matching
+    /// the hashes stored in the `.exacti` file.
+    pub fn lowercased(&self) -> TokenDictionary {
+        let mut lower = TokenDictionary::new();
+        for (token, _) in self.iter() {
+            lower.insert_lower(token);
+        }
+        lower
+    }
+
+    /// Add the case-folded form of a token under its case-insensitive hash
+    pub(crate) fn insert_lower(&mut self, token: &[u8]) {
+        self.insert_hashed(hash_token_lower(token), &fold_token(token));
+    }
+
+    /// Build the vocabulary of the normalized exact index
+    ///
+    /// Tokens are stripped of `_` and `-`, case-folded and keyed by
+    /// `hash_token_normalized`, matching the hashes stored in the `.exactn`
+    /// file.
+    pub fn normalized(&self) -> TokenDictionary {
+        let mut normalized = TokenDictionary::new();
+        for (token, _) in self.iter() {
+            normalized.insert_normalized(token);
+        }
+        normalized
+    }
+
+    /// Add the normalized form of a token under its normalized hash
+    pub(crate) fn insert_normalized(&mut self, token: &[u8]) {
+        let form = normalize_token(token);
+        if !form.is_empty() {
+            self.insert_hashed(hash_token_normalized(token), &form);
+        }
+    }
+
+    /// Group the tokens by their normalized form
+    ///
+    /// Returns each normalized form with the spellings that collapse into
+    /// it, both in bytewise order.
+    pub fn spellings(&self) -> Vec<(Vec<u8>, Vec<&[u8]>)> {
+        let mut forms: BTreeMap<Vec<u8>, Vec<&[u8]>> = BTreeMap::new();
+        for (token, _) in self.iter() {
+            let form = normalize_token(token);
+            if !form.is_empty() {
+                forms.entry(form).or_default().push(token);
+            }
+        }
+        forms.into_iter().collect()
+    }
+}
+
+/// Write a dictionary file containing the tokens that pass `keep`
+pub(crate) fn write_dictionary<W: Write>(
+    writer: &mut W,
+    header: &IndexHeader,
+    dictionary: &TokenDictionary,
+    keep: impl Fn(u64) -> bool,
+) -> std::io::Result<()> {
+    let tokens: Vec<&[u8]> = dictionary
+        .iter()
+        .filter(|(_, hash)| keep(*hash))
+        .map(|(token, _)| token)
+        .collect();
+
+    write_header(writer, MAGIC_DICT, header, tokens.len())?;
+
+    let mut previous: &[u8] = &[];
+    for token in tokens {
+        let shared = previous
+            .iter()
+            .zip(token)
+            .take_while(|(a, b)| a == b)
+            .count();
+        write_varint(writer, shared as u64)?;
+        write_varint(writer, (token.len() - shared) as u64)?;
+        writer.write_all(&token[shared..])?;
+        previous = token;
+    }
+
+    Ok(())
+}
+
+/// Decode a dictionary file
+pub(crate) fn decode_dictionary(data: &[u8]) -> Result<(IndexHeader, TokenDictionary)> {
+    let (header, count) = parse_header(data, MAGIC_DICT)?;
+    if header.version != FORMAT_VERSION {
+        return Err(TokenizerError::InvalidIndexFormat(format!(
+            "Version mismatch: expected {}, got {}",
+            FORMAT_VERSION, header.version
+        )));
+    }
+
+    let corrupt = |what: &str| TokenizerError::InvalidIndexFormat(format!("Dictionary {}", what));
+
+    let mut dictionary = TokenDictionary::new();
+    let mut position = HEADER_LEN;
+    let mut previous: Vec<u8> = Vec::new();
+    for i in 0..count {
+        let shared = read_varint(data, &mut position).ok_or_else(|| corrupt("is truncated"))?;
+        let suffix_len = read_varint(data, &mut position).ok_or_else(|| corrupt("is truncated"))?;
+        let shared = usize::try_from(shared).map_err(|_| corrupt("entry is invalid"))?;
+        let suffix_len = usize::try_from(suffix_len).map_err(|_| corrupt("entry is invalid"))?;
+
+        let suffix = position
+            .checked_add(suffix_len)
+            .filter(|end| *end <= data.len())
+            .map(|end| &data[position..end])
+            .ok_or_else(|| corrupt("is truncated"))?;
+        position += suffix_len;
+
+        if shared > previous.len() {
+            return Err(corrupt("entry shares more bytes than the previous token"));
+        }
+        let mut token = previous[..shared].to_vec();
+        token.extend_from_slice(suffix);
+        if i > 0 && token <= previous {
+            return Err(corrupt("is not sorted"));
+        }
+
+        dictionary.insert(&token);
+        previous = token;
+    }
+
+    if position != data.len() {
+        return Err(corrupt("has trailing bytes"));
+    }
+
+    Ok((header, dictionary))
+}
+
+pub(crate) fn write_varint<W: Write>(writer: &mut W, mut value: u64) -> std::io::Result<()> {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            return writer.write_all(&[byte]);
+        }
+        writer.write_all(&[byte | 0x80])?;
+    }
+}
+
+pub(crate) fn read_varint(data: &[u8], position: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*position)?;
+        *position += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(dictionary: &TokenDictionary) -> TokenDictionary {
+        let header = IndexHeader::new();
+        let mut data = Vec::new();
+        write_dictionary(&mut data, &header, dictionary, |_| true).unwrap();
+        let (decoded_header, decoded) = decode_dictionary(&data).unwrap();
+        assert_eq!(decoded_header, header);
+        decoded
+    }
+
+    #[test]
+    fn test_front_coded_roundtrip() {
+        let mut dictionary = TokenDictionary::new();
+        for token in [
+            "parse_header",
+            "parse",
+            "parse_body",
+            "zebra",
+            "Parse",
+            "a-b",
+        ] {
+            dictionary.insert(token.as_bytes());
+        }
+
+        let decoded = roundtrip(&dictionary);
+        let tokens: Vec<&[u8]> = decoded.iter().map(|(token, _)| token).collect();
+        assert_eq!(
+            tokens,
+            vec![
+                &b"Parse"[..],
+                b"a-b",
+                b"parse",
+                b"parse_body",
+                b"parse_header",
+                b"zebra"
+            ]
+        );
+        let hash = hash_token(b"parse_body");
+        assert_eq!(decoded.get(hash), Some(&b"parse_body"[..]));
+    }
+
+    #[test]
+    fn test_write_filters_tokens() {
+        let mut dictionary = TokenDictionary::new();
+        let keep = dictionary.insert(b"keep");
+        dictionary.insert(b"drop");
+
+        let mut data = Vec::new();
+        write_dictionary(&mut data, &IndexHeader::new(), &dictionary, |hash| {
+            hash == keep
+        })
+        .unwrap();
+        let (_, decoded) = decode_dictionary(&data).unwrap();
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(decoded.get(keep), Some(&b"keep"[..]));
+    }
+
+    #[test]
+    fn test_collisions_counted() {
+        let mut dictionary = TokenDictionary::new();
+        dictionary.insert_hashed(7, b"first");
+        dictionary.insert_hashed(7, b"second");
+        dictionary.insert_hashed(7, b"first");
+
+        assert_eq!(dictionary.len(), 2);
+        assert_eq!(dictionary.collision_count(), 1);
+        assert_eq!(dictionary.get(7), Some(&b"first"[..]));
+    }
+
+    #[test]
+    fn test_normalized_spellings() {
+        let mut dictionary = TokenDictionary::new();
+        for token in ["userId", "user_id", "USER_ID", "userName", "__"] {
+            dictionary.insert(token.as_bytes());
+        }
+
+        let spellings = dictionary.spellings();
+        assert_eq!(spellings.len(), 2);
+        assert_eq!(spellings[0].0, b"userid");
+        assert_eq!(
+            spellings[0].1,
+            [&b"USER_ID"[..], &b"userId"[..], &b"user_id"[..]]
+        );
+        assert_eq!(spellings[1].1, [&b"userName"[..]]);
+
+        let normalized = dictionary.normalized();
+        assert_eq!(normalized.len(), 2);
+        assert_eq!(
+            normalized.get(hash_token_normalized(b"User-Id")),
+            Some(&b"userid"[..])
+        );
+    }
+
+    #[test]
+    fn test_corrupt_files_rejected() {
+        let mut dictionary = TokenDictionary::new();
+        dictionary.insert(b"alpha");
+        dictionary.insert(b"beta");
+        let mut data = Vec::new();
+        write_dictionary(&mut data, &IndexHeader::new(), &dictionary, |_| true).unwrap();
+
+        assert!(decode_dictionary(&data[..data.len() - 1]).is_err());
+
+        let mut trailing = data.clone();
+        trailing.push(0);
+        assert!(decode_dictionary(&trailing).is_err());
+
+        // Swap the suffix of "beta" for one that sorts before "alpha"
+        let mut unsorted = data.clone();
+        let last = unsorted.len() - 4;
+        unsorted[last..].copy_from_slice(b"aaaa");
+        assert!(decode_dictionary(&unsorted).is_err());
+
+        assert!(decode_dictionary(b"TKIE").is_err());
+    }
+}
@@ -20,9 +20,24 @@ pub enum TokenizerError {
     #[error("Invalid glob pattern: {0}")]
     InvalidPattern(String),
 
+    #[error("Corrupted index file: {0}")]
+    Corrupted(String),
+
     #[error("Index files mismatch: {0}")]
     IndexMismatch(String),
 
+    #[error("Tokenizer mismatch: {0}")]
+    TokenizerMismatch(String),
+
+    #[error("Index is locked by another build: {0}")]
+    IndexLocked(String),
+
+    #[error("Query syntax error at position {position}: {message}")]
+    QuerySyntax { position: usize, message: String },
+
+    #[error("Server error: {0}")]
+    Server(String),
+
     #[error("Missing query mode: must specify --exact or --fuzzy")]
     MissingQueryMode,
 }
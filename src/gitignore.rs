@@ -0,0 +1,263 @@
+//! Gitignore-style filtering for directory walks
+//!
+//! Tracks the `.gitignore` / `.ignore` files that apply to each directory as
+//! the walk descends, plus `.git/info/exclude` and the global git excludes
+//! file for directories inside a git repository.
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Per-directory ignore files, lowest precedence first
+const IGNORE_FILES: &[&str] = &[".gitignore", ".ignore"];
+
+/// Where a layer's patterns are anchored relative to the walk root
+#[derive(Debug, Clone)]
+enum Anchor {
+    /// Directory at or below the walk root (root-relative path)
+    Below(PathBuf),
+    /// Directory above the walk root; holds the walk root relative to it
+    Above(PathBuf),
+}
+
+/// One set of ignore rules anchored at a directory
+#[derive(Debug)]
+struct Layer {
+    matcher: Gitignore,
+    anchor: Anchor,
+}
+
+impl Layer {
+    /// Match a root-relative path against this layer's patterns
+    fn matched(&self, rel_path: &Path, is_dir: bool) -> Match<()> {
+        let result = match &self.anchor {
+            Anchor::Below(dir) => match rel_path.strip_prefix(dir) {
+                Ok(path) => self.matcher.matched(path, is_dir),
+                Err(_) => return Match::None,
+            },
+            Anchor::Above(prefix) => self.matcher.matched(prefix.join(rel_path), is_dir),
+        };
+        match result {
+            Match::None => Match::None,
+            Match::Ignore(_) => Match::Ignore(()),
+            Match::Whitelist(_) => Match::Whitelist(()),
+        }
+    }
+}
+
+/// Stack of ignore rules from the repository root down to the current directory
+///
+/// Cloning is cheap: layers are shared, so each directory only pays for the
+/// ignore files it contains itself.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct IgnoreStack {
+    /// Canonical walk root, used to build matchers with absolute roots
+    root: PathBuf,
+    /// Layers ordered from lowest to highest precedence
+    layers: Arc<Vec<Arc<Layer>>>,
+    /// Whether git-specific excludes have been loaded for the enclosing repository
+    in_repo: bool,
+}
+
+impl IgnoreStack {
+    /// Create the stack for a walk root, including ignore files from parent
+    /// directories when the root is inside a git repository
+    pub(crate) fn for_root(root: &Path) -> Self {
+        let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+        let mut stack = Self {
+            root: root.clone(),
+            layers: Arc::new(Vec::new()),
+            in_repo: false,
+        };
+
+        // Find the enclosing repository, if the root is below its top level
+        let ancestors: Vec<&Path> = root.ancestors().skip(1).collect();
+        let Some(repo_idx) = ancestors.iter().position(|dir| dir.join(".git").exists()) else {
+            return stack;
+        };
+
+        let mut layers = Vec::new();
+        let repo_root = ancestors[repo_idx];
+        let prefix = root
+            .strip_prefix(repo_root)
+            .unwrap_or(Path::new(""))
+            .to_path_buf();
+        layers.extend(git_layers(repo_root, Anchor::Above(prefix)));
+
+        // Ignore files from the repository root down to the walk root's parent
+        for dir in ancestors[..=repo_idx].iter().rev() {
+            let prefix = root
+                .strip_prefix(dir)
+                .unwrap_or(Path::new(""))
+                .to_path_buf();
+            if let Some(layer) = dir_layer(dir, Anchor::Above(prefix)) {
+                layers.push(Arc::new(layer));
+            }
+        }
+
+        stack.layers = Arc::new(layers);
+        stack.in_repo = true;
+        stack
+    }
+
+    /// Return the stack for a child directory (given relative to the walk root)
+    pub(crate) fn enter_dir(&self, rel_dir: &Path) -> Self {
+        let abs_dir = self.root.join(rel_dir);
+        let mut added: Vec<Arc<Layer>> = Vec::new();
+        let mut in_repo = self.in_repo;
+
+        if !in_repo && abs_dir.join(".git").exists() {
+            added.extend(git_layers(&abs_dir, Anchor::Below(rel_dir.to_path_buf())));
+            in_repo = true;
+        }
+
+        if let Some(layer) = dir_layer(&abs_dir, Anchor::Below(rel_dir.to_path_buf())) {
+            added.push(Arc::new(layer));
+        }
+
+        if added.is_empty() {
+            return Self {
+                in_repo,
+                ..self.clone()
+            };
+        }
+
+        let mut layers = Vec::with_capacity(self.layers.len() + added.len());
+        layers.extend(self.layers.iter().cloned());
+        layers.extend(added);
+
+        Self {
+            root: self.root.clone(),
+            layers: Arc::new(layers),
+            in_repo,
+        }
+    }
+
+    /// Check whether a root-relative path is ignored
+    ///
+    /// The most specific layer with a matching rule decides, so negated
+    /// patterns in nested ignore files can re-include paths.
+    pub(crate) fn is_ignored(&self, rel_path: &Path, is_dir: bool) -> bool {
+        for layer in self.layers.iter().rev() {
+            match layer.matched(rel_path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+        false
+    }
+}
+
+/// Build the layer for the ignore files directly inside `dir`
+///
+/// Both files are combined into one matcher; `.ignore` is added last so its
+/// rules take precedence over `.gitignore`.
+fn dir_layer(dir: &Path, anchor: Anchor) -> Option<Layer> {
+    let mut builder = GitignoreBuilder::new(dir);
+    let mut found = false;
+
+    for name in IGNORE_FILES {
+        let path = dir.join(name);
+        if path.is_file() {
+            // Partial errors still leave the valid globs in the builder
+            let _ = builder.add(path);
+            found = true;
+        }
+    }
+
+    if !found {
+        return None;
+    }
+
+    let matcher = builder.build().ok()?;
+    Some(Layer { matcher, anchor })
+}
+
+/// Build the git-specific layers for a repository root: global excludes and
+/// `.git/info/exclude` (lowest precedence first)
+fn git_layers(repo_root: &Path, anchor: Anchor) -> Vec<Arc<Layer>> {
+    let mut layers = Vec::new();
+
+    let (global, _) = Gitignore::global();
+    if !global.is_empty() {
+        layers.push(Arc::new(Layer {
+            matcher: global,
+            anchor: anchor.clone(),
+        }));
+    }
+
+    let exclude = repo_root.join(".git").join("info").join("exclude");
+    if exclude.is_file() {
+        let mut builder = GitignoreBuilder::new(repo_root);
+        let _ = builder.add(exclude);
+        if let Ok(matcher) = builder.build() {
+            layers.push(Arc::new(Layer { matcher, anchor }));
+        }
+    }
+
+    layers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_root_gitignore() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join(".gitignore"), "*.log\nbuild/\n").unwrap();
+
+        let stack = IgnoreStack::for_root(dir.path()).enter_dir(Path::new(""));
+        assert!(stack.is_ignored(Path::new("debug.log"), false));
+        assert!(stack.is_ignored(Path::new("build"), true));
+        assert!(!stack.is_ignored(Path::new("build"), false));
+        assert!(!stack.is_ignored(Path::new("main.rs"), false));
+    }
+
+    #[test]
+    fn test_nested_negation_and_anchoring() {
+        let dir = TempDir::new().unwrap();
+        std::fs::create_dir_all(dir.path().join("sub")).unwrap();
+        std::fs::write(dir.path().join(".gitignore"), "*.gen\n/top.txt\n").unwrap();
+        std::fs::write(dir.path().join("sub/.gitignore"), "!keep.gen\n").unwrap();
+
+        let root = IgnoreStack::for_root(dir.path()).enter_dir(Path::new(""));
+        let sub = root.enter_dir(Path::new("sub"));
+
+        assert!(sub.is_ignored(Path::new("sub/drop.gen"), false));
+        assert!(!sub.is_ignored(Path::new("sub/keep.gen"), false));
+
+        // Anchored pattern only applies at the root
+        assert!(root.is_ignored(Path::new("top.txt"), false));
+        assert!(!sub.is_ignored(Path::new("sub/top.txt"), false));
+    }
+
+    #[test]
+    fn test_dot_ignore_overrides_gitignore() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join(".gitignore"), "*.txt\n").unwrap();
+        std::fs::write(dir.path().join(".ignore"), "!notes.txt\n").unwrap();
+
+        let stack = IgnoreStack::for_root(dir.path()).enter_dir(Path::new(""));
+        assert!(stack.is_ignored(Path::new("other.txt"), false));
+        assert!(!stack.is_ignored(Path::new("notes.txt"), false));
+    }
+
+    #[test]
+    fn test_git_info_exclude_and_parent_gitignore() {
+        let dir = TempDir::new().unwrap();
+        std::fs::create_dir_all(dir.path().join(".git/info")).unwrap();
+        std::fs::create_dir_all(dir.path().join("src")).unwrap();
+        std::fs::write(dir.path().join(".git/info/exclude"), "*.tmp\n").unwrap();
+        std::fs::write(dir.path().join(".gitignore"), "vendor/\n").unwrap();
+
+        // Walk rooted below the repository top level
+        let stack = IgnoreStack::for_root(&dir.path().join("src")).enter_dir(Path::new(""));
+        assert!(stack.is_ignored(Path::new("scratch.tmp"), false));
+        assert!(stack.is_ignored(Path::new("vendor"), true));
+        assert!(!stack.is_ignored(Path::new("lib.rs"), false));
+    }
+}
@@ -65,7 +65,11 @@ impl GlobIndex for PathIndex {
 /// - `*.rs` - matches all Rust files
 /// - `test_*.py` - matches Python test files
 /// - `*config*` - matches files containing "config"
-pub fn glob_files<I: GlobIndex>(index: &I, pattern: &str, options: &GlobOptions) -> Result<GlobResult> {
+pub fn glob_files<I: GlobIndex>(
+    index: &I,
+    pattern: &str,
+    options: &GlobOptions,
+) -> Result<GlobResult> {
     let glob = GlobBuilder::new(pattern)
         .case_insensitive(true)
         .build()
@@ -0,0 +1,449 @@
+//! Line-level matching over query candidates (indexed grep)
+//!
+//! The token indexes only say which files contain the query terms. This
+//! module re-reads just those files and reports where the terms occur,
+//! using the same token rules as the index that produced the candidates.
+
+use crate::bundle::{IndexFiles, Section};
+use crate::error::Result;
+use crate::persistence::validate_index_match;
+use crate::query::{query_exact, query_exact_lower, query_fuzzy, QueryOptions};
+use crate::tokenizer::{exact_token_spans, ExactTokenizer, Tokenizer};
+use crate::trigram::MIN_TRIGRAM_TOKEN_LENGTH;
+use crate::unicode::{char_count, char_spans, fold_token, for_each_folded_byte};
+use memmap2::Mmap;
+use rayon::prelude::*;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// How query terms are matched against file contents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineMatchMode {
+    /// Whole exact-mode tokens, case-sensitive (candidates from `query_exact`)
+    Exact,
+    /// Whole exact-mode tokens, case-insensitive (`query_exact_lower`)
+    ExactIgnoreCase,
+    /// Case-insensitive substrings of at least three characters (`query_fuzzy`)
+    Substring,
+}
+
+/// Options for line matching
+#[derive(Debug, Clone)]
+pub struct LineOptions {
+    /// Lines of context to include before each matching line
+    pub before_context: usize,
+
+    /// Lines of context to include after each matching line
+    pub after_context: usize,
+
+    /// Token rules of the exact index that produced the candidates
+    pub tokenizer: Arc<dyn Tokenizer>,
+}
+
+impl Default for LineOptions {
+    fn default() -> Self {
+        Self {
+            before_context: 0,
+            after_context: 0,
+            tokenizer: Arc::new(ExactTokenizer),
+        }
+    }
+}
+
+/// A matching or context line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchedLine {
+    /// 1-based line number
+    pub line_number: usize,
+
+    /// 1-based byte columns of every occurrence (empty for context lines)
+    pub columns: Vec<usize>,
+
+    /// Line text without the line terminator
+    pub text: String,
+}
+
+impl MatchedLine {
+    /// Check whether this line is only included as context
+    pub fn is_context(&self) -> bool {
+        self.columns.is_empty()
+    }
+}
+
+/// Matching lines of one file, in line order
+#[derive(Debug, Clone)]
+pub struct FileLines {
+    /// File path
+    pub path: PathBuf,
+
+    /// Matching lines interleaved with their context lines
+    pub lines: Vec<MatchedLine>,
+
+    /// Number of lines with at least one occurrence
+    pub match_count: usize,
+}
+
+/// Result of a line query
+#[derive(Debug, Clone, Default)]
+pub struct LinesResult {
+    /// Files with at least one occurrence, in candidate order
+    pub files: Vec<FileLines>,
+
+    /// Number of candidate files that were read
+    pub files_scanned: usize,
+}
+
+/// Find the lines of the candidate files that contain the query terms
+///
+/// `candidates` is normally the `files` of a `QueryResult`. Candidates with
+/// no real occurrence (e.g. trigram false positives) are dropped.
+pub fn query_lines(
+    candidates: &[PathBuf],
+    query_str: &str,
+    mode: LineMatchMode,
+    options: &LineOptions,
+) -> LinesResult {
+    let terms = query_terms(query_str, mode, options.tokenizer.as_ref());
+    if terms.is_empty() {
+        return LinesResult::default();
+    }
+
+    let files = candidates
+        .par_iter()
+        .filter_map(|path| match_file(path, &terms, mode, options))
+        .collect();
+
+    LinesResult {
+        files,
+        files_scanned: candidates.len(),
+    }
+}
+
+/// Find the lines that contain the query terms in the files of an index
+///
+/// Candidates come from the token index matching `mode`, opened as a view so
+/// only the bitmaps of the query terms are read. The token rules in
+/// `options` are replaced by those the index was built with.
+pub fn grep_index(
+    files: &IndexFiles,
+    query_str: &str,
+    mode: LineMatchMode,
+    query_options: &QueryOptions,
+    options: LineOptions,
+) -> Result<LinesResult> {
+    let path_index = files.load_paths()?;
+    let options = LineOptions {
+        tokenizer: path_index.header.builtin_tokenizer()?,
+        ..options
+    };
+    let candidates = match mode {
+        LineMatchMode::Substring => {
+            let trigram_view = files.load_trigram_view()?;
+            validate_index_match(&path_index.header, &trigram_view.header)?;
+            query_fuzzy(&path_index, &trigram_view, query_str, query_options)?
+        }
+        LineMatchMode::ExactIgnoreCase => {
+            let exact_view = files.load_exact_view(Section::ExactLower)?;
+            validate_index_match(&path_index.header, &exact_view.header)?;
+            query_exact_lower(&path_index, &exact_view, query_str, query_options)?
+        }
+        LineMatchMode::Exact => {
+            let exact_view = files.load_exact_view(Section::Exact)?;
+            validate_index_match(&path_index.header, &exact_view.header)?;
+            query_exact(&path_index, &exact_view, query_str, query_options)?
+        }
+    };
+
+    Ok(query_lines(&candidates.files, query_str, mode, &options))
+}
+
+/// Split a query into the terms that are matched in file contents
+fn query_terms(query_str: &str, mode: LineMatchMode, tokenizer: &dyn Tokenizer) -> Vec<Vec<u8>> {
+    let query = query_str.as_bytes();
+    let mut terms: Vec<Vec<u8>> = match mode {
+        LineMatchMode::Exact => tokenizer.tokens(query).map(<[u8]>::to_vec).collect(),
+        LineMatchMode::ExactIgnoreCase => tokenizer.tokens(query).map(fold_token).collect(),
+        LineMatchMode::Substring => exact_token_spans(query)
+            .map(|(_, token)| token)
+            .filter(|token| char_count(token) >= MIN_TRIGRAM_TOKEN_LENGTH)
+            .map(fold_token)
+            .collect(),
+    };
+    terms.sort();
+    terms.dedup();
+    terms
+}
+
+/// Byte offsets of every term occurrence in a line
+fn find_occurrences(
+    line: &[u8],
+    terms: &[Vec<u8>],
+    mode: LineMatchMode,
+    tokenizer: &dyn Tokenizer,
+) -> Vec<usize> {
+    match mode {
+        LineMatchMode::Exact => tokenizer
+            .token_spans(line)
+            .filter(|(_, token)| terms.iter().any(|t| t == token))
+            .map(|(offset, _)| offset)
+            .collect(),
+        LineMatchMode::ExactIgnoreCase => tokenizer
+            .token_spans(line)
+            .filter(|(_, token)| terms.contains(&fold_token(token)))
+            .map(|(offset, _)| offset)
+            .collect(),
+        LineMatchMode::Substring => {
+            // Fold the line, remembering the line offset of each folded
+            // character so matches only start on character boundaries
+            let mut folded = Vec::with_capacity(line.len());
+            let mut origins = Vec::with_capacity(line.len());
+            for (offset, _, len) in char_spans(line) {
+                origins.push(Some(offset));
+                for_each_folded_byte(&line[offset..offset + len], |b| folded.push(b));
+                origins.resize(folded.len(), None);
+            }
+            let folded = &folded;
+            let origins = &origins;
+            let mut offsets: Vec<usize> = terms
+                .iter()
+                .flat_map(|term| {
+                    folded
+                        .windows(term.len())
+                        .enumerate()
+                        .filter(move |(_, window)| *window == term.as_slice())
+                        .filter_map(|(start, _)| origins[start])
+                })
+                .collect();
+            offsets.sort_unstable();
+            offsets.dedup();
+            offsets
+        }
+    }
+}
+
+/// Scan one file; returns None when it cannot be read or has no occurrence
+fn match_file(
+    path: &Path,
+    terms: &[Vec<u8>],
+    mode: LineMatchMode,
+    options: &LineOptions,
+) -> Option<FileLines> {
+    let file = File::open(path).ok()?;
+    if file.metadata().ok()?.len() == 0 {
+        return None;
+    }
+    let mmap = unsafe { Mmap::map(&file).ok()? };
+
+    // Skip binary files (null bytes in first 8KB), as the indexer does
+    let check_len = std::cmp::min(8192, mmap.len());
+    if mmap[..check_len].contains(&0) {
+        return None;
+    }
+
+    // A final newline ends the last line rather than starting an empty one
+    let content = mmap.strip_suffix(b"\n").unwrap_or(&mmap);
+    let lines: Vec<&[u8]> = content
+        .split(|&b| b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .collect();
+
+    let hits: Vec<(usize, Vec<usize>)> = lines
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, line)| {
+            let offsets = find_occurrences(line, terms, mode, options.tokenizer.as_ref());
+            (!offsets.is_empty()).then_some((idx, offsets))
+        })
+        .collect();
+
+    if hits.is_empty() {
+        return None;
+    }
+
+    // Lines to report: every hit plus its context window
+    let mut included = vec![false; lines.len()];
+    for (idx, _) in &hits {
+        let start = idx.saturating_sub(options.before_context);
+        let end = (idx + options.after_context).min(lines.len() - 1);
+        included[start..=end].fill(true);
+    }
+
+    let mut hits = hits.into_iter().peekable();
+    let mut result = Vec::new();
+    for (idx, line) in lines.iter().enumerate() {
+        if !included[idx] {
+            continue;
+        }
+        let columns = match hits.peek() {
+            Some((hit_idx, _)) if *hit_idx == idx => {
+                hits.next().unwrap().1.iter().map(|o| o + 1).collect()
+            }
+            _ => Vec::new(),
+        };
+        result.push(MatchedLine {
+            line_number: idx + 1,
+            columns,
+            text: String::from_utf8_lossy(line).into_owned(),
+        });
+    }
+
+    let match_count = result.iter().filter(|l| !l.is_context()).count();
+    Some(FileLines {
+        path: path.to_path_buf(),
+        lines: result,
+        match_count,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bundle::pack_index;
+    use crate::persistence::save_sample_index;
+    use tempfile::TempDir;
+
+    fn write(dir: &TempDir, name: &str, content: &str) -> PathBuf {
+        let path = dir.path().join(name);
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn matches(result: &LinesResult) -> Vec<(usize, Vec<usize>)> {
+        result.files[0]
+            .lines
+            .iter()
+            .filter(|l| !l.is_context())
+            .map(|l| (l.line_number, l.columns.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_exact_matches_whole_tokens() {
+        let dir = TempDir::new().unwrap();
+        let path = write(
+            &dir,
+            "a.rs",
+            "let parse = 1;\nparse_header(parse);\nParse\n",
+        );
+
+        let result = query_lines(
+            &[path],
+            "parse",
+            LineMatchMode::Exact,
+            &LineOptions::default(),
+        );
+        assert_eq!(matches(&result), vec![(1, vec![5]), (2, vec![14])]);
+        assert_eq!(result.files[0].match_count, 2);
+    }
+
+    #[test]
+    fn test_ignore_case_matches_any_case() {
+        let dir = TempDir::new().unwrap();
+        let path = write(&dir, "a.rs", "HashMap\nhashmap hashmaps\n");
+
+        let options = LineOptions::default();
+        let result = query_lines(&[path], "HASHMAP", LineMatchMode::ExactIgnoreCase, &options);
+        assert_eq!(matches(&result), vec![(1, vec![1]), (2, vec![1])]);
+
+        let path = write(
+            &dir,
+            "b.txt",
+            "ΣΟΦΊΑ σοφία
+σοφίας
+",
+        );
+        let result = query_lines(&[path], "Σοφία", LineMatchMode::ExactIgnoreCase, &options);
+        assert_eq!(matches(&result), vec![(1, vec![1, 12])]);
+    }
+
+    #[test]
+    fn test_substring_mode() {
+        let dir = TempDir::new().unwrap();
+        let path = write(&dir, "a.rs", "fn ParseHeader() {}\r\nreparse\n");
+
+        let options = LineOptions::default();
+        let result = query_lines(&[path], "parse", LineMatchMode::Substring, &options);
+        assert_eq!(matches(&result), vec![(1, vec![4]), (2, vec![3])]);
+        // Carriage returns are not part of the line text
+        assert_eq!(result.files[0].lines[0].text, "fn ParseHeader() {}");
+
+        // Columns are byte offsets into the original, unfolded line
+        let path = write(&dir, "b.txt", "x \u{212A}elvin KELVIN\n");
+        let result = query_lines(&[path], "kelvin", LineMatchMode::Substring, &options);
+        assert_eq!(matches(&result), vec![(1, vec![3, 12])]);
+    }
+
+    #[test]
+    fn test_trigram_false_positive_dropped() {
+        let dir = TempDir::new().unwrap();
+        // Has trigrams "abc" and "bcd" but never the substring "abcd"
+        let path = write(&dir, "a.txt", "abc bcd\n");
+
+        let options = LineOptions::default();
+        let result = query_lines(&[path], "abcd", LineMatchMode::Substring, &options);
+        assert!(result.files.is_empty());
+        assert_eq!(result.files_scanned, 1);
+    }
+
+    #[test]
+    fn test_context_lines() {
+        let dir = TempDir::new().unwrap();
+        let content = "one\ntwo\nneedle\nthree\nfour\nfive\nneedle\n";
+        let path = write(&dir, "a.txt", content);
+
+        let options = LineOptions {
+            before_context: 1,
+            after_context: 1,
+            ..Default::default()
+        };
+        let result = query_lines(&[path], "needle", LineMatchMode::Exact, &options);
+        let lines: Vec<(usize, bool)> = result.files[0]
+            .lines
+            .iter()
+            .map(|l| (l.line_number, l.is_context()))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![(2, true), (3, false), (4, true), (6, true), (7, false)]
+        );
+    }
+
+    #[test]
+    fn test_binary_and_missing_files_skipped() {
+        let dir = TempDir::new().unwrap();
+        let binary = dir.path().join("bin");
+        std::fs::write(&binary, b"needle\0needle").unwrap();
+        let missing = dir.path().join("missing");
+
+        let options = LineOptions::default();
+        let result = query_lines(&[binary, missing], "needle", LineMatchMode::Exact, &options);
+        assert!(result.files.is_empty());
+    }
+
+    #[test]
+    fn test_grep_index_split_and_bundle() {
+        let src = TempDir::new().unwrap();
+        let out = TempDir::new().unwrap();
+        let base = out.path().join("index.tkix");
+        let bundle_path = out.path().join("index.tkb");
+        save_sample_index(src.path(), &base);
+        pack_index(&base, &bundle_path).unwrap();
+
+        for path in [&base, &bundle_path] {
+            let files = IndexFiles::open(path).unwrap();
+            for (query, mode, column) in [
+                ("parse_header", LineMatchMode::Exact, 4),
+                ("PARSE_HEADER", LineMatchMode::ExactIgnoreCase, 4),
+                ("header", LineMatchMode::Substring, 10),
+            ] {
+                let options = QueryOptions::default();
+                let result =
+                    grep_index(&files, query, mode, &options, LineOptions::default()).unwrap();
+                assert_eq!(result.files.len(), 1, "{} in {}", query, path.display());
+                assert_eq!(result.files[0].path, src.path().join("a.rs"));
+                assert_eq!(matches(&result), vec![(1, vec![column])]);
+            }
+        }
+    }
+}
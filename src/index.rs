@@ -1,11 +1,21 @@
+use crate::dictionary::TokenDictionary;
+use crate::error::{Result, TokenizerError};
+use crate::positions::PositionIndex;
+use crate::provenance::BuildInfo;
+use crate::ranking::TermFrequencies;
+use crate::subword::SubwordIndex;
+use crate::tokenizer::{
+    builtin_tokenizer_by_fingerprint, describe_tokenizer, ExactTokenizer, Tokenizer,
+};
 use roaring::RoaringBitmap;
 use rustc_hash::FxHashMap;
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Current format version for the new split index format
-pub const FORMAT_VERSION: u16 = 3;
+pub const FORMAT_VERSION: u16 = 4;
 
 /// Header present in all index files for consistency checking
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -16,11 +26,18 @@ pub struct IndexHeader {
     pub index_id: [u8; 16],
     /// Timestamp when index was created (unix seconds)
     pub created_at: u64,
+    /// Fingerprint of the tokenizer the exact indexes were built with
+    pub tokenizer: u64,
 }
 
 impl IndexHeader {
-    /// Generate a new header with unique ID
+    /// Generate a new header with unique ID, for the default tokenizer
     pub fn new() -> Self {
+        Self::with_tokenizer(&ExactTokenizer)
+    }
+
+    /// Generate a new header with unique ID for an index built with `tokenizer`
+    pub fn with_tokenizer(tokenizer: &dyn Tokenizer) -> Self {
         Self {
             version: FORMAT_VERSION,
             index_id: generate_index_id(),
@@ -28,7 +45,33 @@ impl IndexHeader {
                 .duration_since(UNIX_EPOCH)
                 .unwrap()
                 .as_secs(),
+            tokenizer: tokenizer.fingerprint(),
+        }
+    }
+
+    /// Check that an index with this header was built with `tokenizer`
+    pub fn check_tokenizer(&self, tokenizer: &dyn Tokenizer) -> Result<()> {
+        if tokenizer.fingerprint() != self.tokenizer {
+            return Err(TokenizerError::TokenizerMismatch(format!(
+                "index was built with the {} tokenizer, not {}",
+                describe_tokenizer(self.tokenizer),
+                tokenizer.name()
+            )));
         }
+        Ok(())
+    }
+
+    /// Get the built-in tokenizer this header names
+    ///
+    /// Fails for an index built with a custom tokenizer, which has to be
+    /// supplied by the caller.
+    pub fn builtin_tokenizer(&self) -> Result<Arc<dyn Tokenizer>> {
+        builtin_tokenizer_by_fingerprint(self.tokenizer).ok_or_else(|| {
+            TokenizerError::TokenizerMismatch(format!(
+                "index was built with a {} tokenizer; open it with that tokenizer",
+                describe_tokenizer(self.tokenizer)
+            ))
+        })
     }
 }
 
@@ -79,11 +122,55 @@ pub struct PathIndex {
     /// Files as (directory_id, filename) pairs
     pub(crate) files: Vec<(u32, String)>,
 
+    /// Per-file change-detection metadata, parallel to `files`
+    pub(crate) file_meta: Vec<FileMeta>,
+
+    /// File IDs whose files were deleted; these slots are reused by new files
+    pub(crate) removed: RoaringBitmap,
+
+    /// How the last full scan built the index (None when it was assembled
+    /// some other way); incremental updates leave it as it is
+    pub build: Option<BuildInfo>,
+
     /// Transient lookup for directory deduplication during indexing
     #[serde(skip)]
     dir_lookup: FxHashMap<PathBuf, u32>,
 }
 
+/// Metadata recorded per file so incremental updates can detect changes
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FileMeta {
+    /// Modification time (nanoseconds since the unix epoch)
+    pub modified: u64,
+    /// File size in bytes
+    pub size: u64,
+    /// Optional hash of the file contents
+    pub content_hash: Option<u64>,
+}
+
+impl FileMeta {
+    /// Build metadata from filesystem metadata (no content hash)
+    pub fn from_metadata(metadata: &std::fs::Metadata) -> Self {
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+
+        Self {
+            modified,
+            size: metadata.len(),
+            content_hash: None,
+        }
+    }
+
+    /// Check whether the modification time or size differ
+    pub fn differs_from(&self, other: &FileMeta) -> bool {
+        self.modified != other.modified || self.size != other.size
+    }
+}
+
 impl PathIndex {
     /// Create a new empty path index
     pub fn new(header: IndexHeader, root_path: PathBuf) -> Self {
@@ -92,11 +179,16 @@ impl PathIndex {
             root_path,
             directories: Vec::new(),
             files: Vec::new(),
+            file_meta: Vec::new(),
+            removed: RoaringBitmap::new(),
+            build: None,
             dir_lookup: FxHashMap::default(),
         }
     }
 
     /// Register a file and return its ID
+    ///
+    /// Reuses the lowest removed file ID if one is available.
     pub fn register_file(&mut self, path: PathBuf) -> u32 {
         let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
         let filename = path
@@ -104,19 +196,82 @@ impl PathIndex {
             .map(|s| s.to_string_lossy().into_owned())
             .unwrap_or_default();
 
-        let dir_id = *self.dir_lookup.entry(dir.clone()).or_insert_with(|| {
-            let id = self.directories.len() as u32;
-            self.directories.push(dir);
-            id
-        });
+        let dir_id = self.directory_id(dir);
+
+        if let Some(file_id) = self.removed.min() {
+            self.removed.remove(file_id);
+            self.files[file_id as usize] = (dir_id, filename);
+            self.file_meta[file_id as usize] = FileMeta::default();
+            return file_id;
+        }
 
         let file_id = self.files.len() as u32;
         self.files.push((dir_id, filename));
+        self.file_meta.push(FileMeta::default());
         file_id
     }
 
+    /// Get the ID of a directory, adding it if it is new
+    fn directory_id(&mut self, dir: PathBuf) -> u32 {
+        *self.dir_lookup.entry(dir.clone()).or_insert_with(|| {
+            let id = self.directories.len() as u32;
+            self.directories.push(dir);
+            id
+        })
+    }
+
+    /// Append every file slot of another path index after the existing ones
+    ///
+    /// Returns the offset added to the other index's file IDs. Directories
+    /// both indexes have are stored once, and removed slots stay removed.
+    pub(crate) fn append(&mut self, other: &PathIndex) -> u32 {
+        let offset = self.files.len() as u32;
+        let dir_ids: Vec<u32> = other
+            .directories
+            .iter()
+            .map(|dir| self.directory_id(dir.clone()))
+            .collect();
+        self.files.extend(
+            other
+                .files
+                .iter()
+                .map(|(dir_id, filename)| (dir_ids[*dir_id as usize], filename.clone())),
+        );
+        self.file_meta.extend_from_slice(&other.file_meta);
+        self.removed
+            .extend(other.removed.iter().map(|file_id| file_id + offset));
+        offset
+    }
+
+    /// Mark a file as removed; its ID may be reused by a later registration
+    pub fn remove_file(&mut self, file_id: u32) {
+        if let Some(entry) = self.files.get_mut(file_id as usize) {
+            entry.1.clear();
+            self.file_meta[file_id as usize] = FileMeta::default();
+            self.removed.insert(file_id);
+        }
+    }
+
+    /// Record change-detection metadata for a file
+    pub fn set_file_meta(&mut self, file_id: u32, meta: FileMeta) {
+        if let Some(slot) = self.file_meta.get_mut(file_id as usize) {
+            *slot = meta;
+        }
+    }
+
+    /// Get change-detection metadata for a file
+    pub fn file_meta(&self, file_id: u32) -> Option<&FileMeta> {
+        if self.removed.contains(file_id) {
+            return None;
+        }
+        self.file_meta.get(file_id as usize)
+    }
+
     /// Get file path by ID (reconstructs from directory + filename)
     pub fn get_file_path(&self, file_id: u32) -> Option<PathBuf> {
+        if self.removed.contains(file_id) {
+            return None;
+        }
         let (dir_id, filename) = self.files.get(file_id as usize)?;
         let dir = self.directories.get(*dir_id as usize)?;
         Some(dir.join(filename))
@@ -132,11 +287,26 @@ impl PathIndex {
             .collect();
     }
 
-    /// Get total files
+    /// Get total files (excluding removed files)
     pub fn file_count(&self) -> usize {
+        self.files.len() - self.removed.len() as usize
+    }
+
+    /// Get the number of file ID slots, including removed files
+    ///
+    /// Every file ID stored in a bitmap is below this value.
+    pub fn slot_count(&self) -> usize {
         self.files.len()
     }
 
+    /// Get the IDs of all live files
+    pub fn file_ids(&self) -> RoaringBitmap {
+        let mut ids = RoaringBitmap::new();
+        ids.insert_range(0..self.files.len() as u32);
+        ids -= &self.removed;
+        ids
+    }
+
     /// Get total unique directories
     pub fn directory_count(&self) -> usize {
         self.directories.len()
@@ -147,6 +317,7 @@ impl PathIndex {
         self.files
             .iter()
             .enumerate()
+            .filter(|(idx, _)| !self.removed.contains(*idx as u32))
             .map(|(idx, (dir_id, filename))| {
                 let dir = &self.directories[*dir_id as usize];
                 (idx as u32, dir.join(filename))
@@ -158,6 +329,7 @@ impl PathIndex {
         self.files
             .iter()
             .enumerate()
+            .filter(|(idx, _)| !self.removed.contains(*idx as u32))
             .map(|(idx, (_, filename))| (idx as u32, filename.as_str()))
     }
 }
@@ -174,22 +346,158 @@ pub struct ExactTokenIndex {
 
     /// Maps token hash (u64) to bitmap of file IDs containing that token
     pub(crate) token_map: FxHashMap<u64, RoaringBitmap>,
+
+    /// Token strings behind the hashes, when a `.dict` file was built
+    /// (lowercased and keyed by `hash_token_lower` for the case-insensitive index)
+    #[serde(skip)]
+    pub(crate) dictionary: Option<TokenDictionary>,
+
+    /// Token positions per file, when a `.pos` file was built
+    /// (case-sensitive index only)
+    #[serde(skip)]
+    pub(crate) positions: Option<PositionIndex>,
+
+    /// Token counts per file for relevance ranking, when a `.tf`/`.tfi`
+    /// file was built
+    #[serde(skip)]
+    pub(crate) frequencies: Option<TermFrequencies>,
+
+    /// Identifier parts of the tokens, when a `.sub` file was built
+    /// (case-sensitive index only)
+    #[serde(skip)]
+    pub(crate) subwords: Option<SubwordIndex>,
+
+    /// Tokens stripped of `_` and `-` and case-folded, when a `.exactn`
+    /// file was built (case-sensitive index only)
+    #[serde(skip)]
+    pub(crate) normalized: Option<Box<ExactTokenIndex>>,
+
+    /// Tokenizer named by the header, used to tokenize queries
+    #[serde(skip, default = "default_tokenizer")]
+    pub(crate) tokenizer: Arc<dyn Tokenizer>,
+}
+
+pub(crate) fn default_tokenizer() -> Arc<dyn Tokenizer> {
+    Arc::new(ExactTokenizer)
 }
 
 impl ExactTokenIndex {
     /// Create a new empty exact token index
+    ///
+    /// Uses the built-in tokenizer the header names; an index for a custom
+    /// tokenizer needs `set_tokenizer`.
     pub fn new(header: IndexHeader) -> Self {
+        let tokenizer =
+            builtin_tokenizer_by_fingerprint(header.tokenizer).unwrap_or_else(default_tokenizer);
         Self {
             header,
             token_map: FxHashMap::default(),
+            dictionary: None,
+            positions: None,
+            frequencies: None,
+            subwords: None,
+            normalized: None,
+            tokenizer,
+        }
+    }
+
+    /// Get the tokenizer queries are split with
+    pub fn tokenizer(&self) -> &dyn Tokenizer {
+        self.tokenizer.as_ref()
+    }
+
+    /// Use `tokenizer` for queries, refusing one the index was not built with
+    pub fn set_tokenizer(&mut self, tokenizer: Arc<dyn Tokenizer>) -> Result<()> {
+        self.header.check_tokenizer(tokenizer.as_ref())?;
+        if let Some(normalized) = self.normalized.as_mut() {
+            normalized.set_tokenizer(tokenizer.clone())?;
+        }
+        self.tokenizer = tokenizer;
+        Ok(())
+    }
+
+    /// Get the token dictionary, if one was built or loaded
+    pub fn dictionary(&self) -> Option<&TokenDictionary> {
+        self.dictionary.as_ref()
+    }
+
+    /// Attach or drop the token dictionary
+    pub fn set_dictionary(&mut self, dictionary: Option<TokenDictionary>) {
+        self.dictionary = dictionary;
+    }
+
+    /// Get the positional index, if one was built or loaded
+    pub fn positions(&self) -> Option<&PositionIndex> {
+        self.positions.as_ref()
+    }
+
+    /// Attach or drop the positional index
+    pub fn set_positions(&mut self, positions: Option<PositionIndex>) {
+        self.positions = positions;
+    }
+
+    /// Get the term frequencies, if they were built or loaded
+    pub fn frequencies(&self) -> Option<&TermFrequencies> {
+        self.frequencies.as_ref()
+    }
+
+    /// Attach or drop the term frequencies
+    pub fn set_frequencies(&mut self, frequencies: Option<TermFrequencies>) {
+        self.frequencies = frequencies;
+    }
+
+    /// Get the subword index, if one was built or loaded
+    pub fn subwords(&self) -> Option<&SubwordIndex> {
+        self.subwords.as_ref()
+    }
+
+    /// Attach or drop the subword index
+    pub fn set_subwords(&mut self, subwords: Option<SubwordIndex>) {
+        self.subwords = subwords;
+    }
+
+    /// Get the normalized token index, if one was built or loaded
+    pub fn normalized(&self) -> Option<&ExactTokenIndex> {
+        self.normalized.as_deref()
+    }
+
+    /// Attach or drop the normalized token index
+    ///
+    /// The normalized index takes on this index's tokenizer.
+    pub fn set_normalized(&mut self, normalized: Option<ExactTokenIndex>) {
+        self.normalized = normalized.map(|mut normalized| {
+            normalized.tokenizer = self.tokenizer.clone();
+            Box::new(normalized)
+        });
+    }
+
+    /// Get the token string for a hash that is present in the index
+    ///
+    /// Returns None without a dictionary.
+    pub fn token_text(&self, token_hash: u64) -> Option<&[u8]> {
+        if !self.token_map.contains_key(&token_hash) {
+            return None;
         }
+        self.dictionary.as_ref()?.get(token_hash)
+    }
+
+    /// Iterate over `(token, document_frequency)` in bytewise token order
+    ///
+    /// Document frequency is the number of files containing the token's
+    /// hash, so tokens sharing a hash report the combined count. Yields
+    /// nothing without a dictionary.
+    pub fn iter_tokens(&self) -> impl Iterator<Item = (&[u8], u64)> + '_ {
+        self.dictionary
+            .iter()
+            .flat_map(|dictionary| dictionary.iter())
+            .filter_map(|(token, hash)| Some((token, self.token_map.get(&hash)?.len())))
     }
 
     /// Add a token hash for a file
     pub fn add_token(&mut self, token_hash: u64, file_id: u32) {
         self.token_map
             .entry(token_hash)
-            .or_insert_with(RoaringBitmap::new)
+            .or_default()
             .insert(file_id);
     }
 
@@ -198,6 +506,32 @@ impl ExactTokenIndex {
         self.token_map.get(&token_hash)
     }
 
+    /// Remove the given file IDs from every token bitmap (and their
+    /// positions, term frequencies, subword bitmaps and normalized tokens)
+    ///
+    /// Tokens left without any files are dropped.
+    pub fn remove_files(&mut self, file_ids: &RoaringBitmap) {
+        if file_ids.is_empty() {
+            return;
+        }
+        self.token_map.retain(|_, bitmap| {
+            *bitmap -= file_ids;
+            !bitmap.is_empty()
+        });
+        if let Some(positions) = self.positions.as_mut() {
+            positions.remove_files(file_ids);
+        }
+        if let Some(frequencies) = self.frequencies.as_mut() {
+            frequencies.remove_files(file_ids);
+        }
+        if let Some(subwords) = self.subwords.as_mut() {
+            subwords.remove_files(file_ids);
+        }
+        if let Some(normalized) = self.normalized.as_mut() {
+            normalized.remove_files(file_ids);
+        }
+    }
+
     /// Get total unique tokens
     pub fn token_count(&self) -> usize {
         self.token_map.len()
@@ -214,8 +548,8 @@ pub struct TrigramIndex {
     /// Header with version and index ID
     pub header: IndexHeader,
 
-    /// Maps trigram (packed as u32) to bitmap of file IDs
-    pub(crate) trigram_map: FxHashMap<u32, RoaringBitmap>,
+    /// Maps trigram (packed as u64) to bitmap of file IDs
+    pub(crate) trigram_map: FxHashMap<u64, RoaringBitmap>,
 }
 
 impl TrigramIndex {
@@ -228,24 +562,80 @@ impl TrigramIndex {
     }
 
     /// Add a trigram for a file
-    pub fn add_trigram(&mut self, trigram: u32, file_id: u32) {
-        self.trigram_map
-            .entry(trigram)
-            .or_insert_with(RoaringBitmap::new)
-            .insert(file_id);
+    pub fn add_trigram(&mut self, trigram: u64, file_id: u32) {
+        self.trigram_map.entry(trigram).or_default().insert(file_id);
     }
 
     /// Get bitmap for a trigram
-    pub fn get_bitmap(&self, trigram: u32) -> Option<&RoaringBitmap> {
+    pub fn get_bitmap(&self, trigram: u64) -> Option<&RoaringBitmap> {
         self.trigram_map.get(&trigram)
     }
 
+    /// Remove the given file IDs from every trigram bitmap
+    ///
+    /// Trigrams left without any files are dropped.
+    pub fn remove_files(&mut self, file_ids: &RoaringBitmap) {
+        if file_ids.is_empty() {
+            return;
+        }
+        self.trigram_map.retain(|_, bitmap| {
+            *bitmap -= file_ids;
+            !bitmap.is_empty()
+        });
+    }
+
     /// Get total unique trigrams
     pub fn trigram_count(&self) -> usize {
         self.trigram_map.len()
     }
 }
 
+// ============================================================================
+// IndexSet - all split indexes from one index run
+// ============================================================================
+
+/// The full set of split indexes produced by one index run
+#[derive(Debug, Clone)]
+pub struct IndexSet {
+    /// File paths shared by all token indexes
+    pub paths: PathIndex,
+    /// Case-sensitive exact tokens
+    pub exact: ExactTokenIndex,
+    /// Case-insensitive exact tokens
+    pub exact_lower: ExactTokenIndex,
+    /// Trigrams for fuzzy matching
+    pub trigram: TrigramIndex,
+}
+
+impl IndexSet {
+    /// Use `tokenizer` for queries, refusing one the indexes were not built with
+    pub fn set_tokenizer(&mut self, tokenizer: Arc<dyn Tokenizer>) -> Result<()> {
+        self.exact.set_tokenizer(tokenizer.clone())?;
+        self.exact_lower.set_tokenizer(tokenizer)
+    }
+
+    /// Stamp every index with the same header
+    pub fn set_header(&mut self, header: IndexHeader) {
+        self.paths.header = header.clone();
+        self.exact.header = header.clone();
+        if let Some(normalized) = self.exact.normalized.as_mut() {
+            normalized.header = header.clone();
+        }
+        self.exact_lower.header = header.clone();
+        self.trigram.header = header;
+    }
+}
+
+/// Shift every file ID of a bitmap up by `offset`; the caller checked that
+/// the results fit
+pub(crate) fn shift_bitmap(bitmap: &RoaringBitmap, offset: u32) -> RoaringBitmap {
+    if offset == 0 {
+        return bitmap.clone();
+    }
+    RoaringBitmap::from_sorted_iter(bitmap.iter().map(|file_id| file_id + offset))
+        .expect("shifted file IDs stay sorted")
+}
+
 // ============================================================================
 // Legacy TokenIndex - kept for compatibility during transition
 // ============================================================================
@@ -337,7 +727,7 @@ impl TokenIndex {
     pub fn add_token(&mut self, token_hash: u64, file_id: u32) {
         self.token_map
             .entry(token_hash)
-            .or_insert_with(RoaringBitmap::new)
+            .or_default()
             .insert(file_id);
     }
 
@@ -446,6 +836,43 @@ mod tests {
         assert_eq!(index.directory_count(), 2);
     }
 
+    #[test]
+    fn test_path_index_remove_and_reuse() {
+        let header = IndexHeader::default();
+        let mut index = PathIndex::new(header, PathBuf::from("/test"));
+        index.register_file(PathBuf::from("/test/a.txt"));
+        index.register_file(PathBuf::from("/test/b.txt"));
+
+        index.remove_file(0);
+        assert_eq!(index.file_count(), 1);
+        assert_eq!(index.slot_count(), 2);
+        assert_eq!(index.get_file_path(0), None);
+        assert_eq!(index.iter_files().count(), 1);
+
+        // Removed slot is reused before a new one is allocated
+        let id = index.register_file(PathBuf::from("/test/c.txt"));
+        assert_eq!(id, 0);
+        assert_eq!(index.file_count(), 2);
+        assert_eq!(index.get_file_path(0), Some(PathBuf::from("/test/c.txt")));
+    }
+
+    #[test]
+    fn test_remove_files_from_bitmaps() {
+        let header = IndexHeader::default();
+        let mut index = ExactTokenIndex::new(header);
+        index.add_token(1, 0);
+        index.add_token(1, 1);
+        index.add_token(2, 1);
+
+        let mut removed = RoaringBitmap::new();
+        removed.insert(1);
+        index.remove_files(&removed);
+
+        assert_eq!(index.token_count(), 1);
+        assert!(index.get_bitmap(1).unwrap().contains(0));
+        assert!(index.get_bitmap(2).is_none());
+    }
+
     #[test]
     fn test_exact_token_index() {
         let header = IndexHeader::default();
@@ -25,50 +25,138 @@
 //! }
 //! ```
 
+mod atomic;
+mod bundle;
+mod checksum;
+mod dictionary;
 mod error;
+mod gitignore;
 mod glob;
+mod grep;
 mod index;
+mod merge;
+mod migrate;
 mod persistence;
+mod positions;
+mod provenance;
 mod query;
+mod ranking;
+mod regex_search;
+mod rules;
 mod scanner;
+#[cfg(unix)]
+mod server;
+mod subword;
+mod table;
 mod tokenizer;
 mod trigram;
+mod typo;
+mod unicode;
+mod update;
+mod verify;
+mod view;
+#[cfg(target_os = "linux")]
+mod watch;
+mod wildcard;
 
 // Re-export public API
+pub use atomic::{orphaned_temp_files, BuildLock};
+pub use bundle::{is_bundle, pack_index, unpack_index, Bundle, IndexFiles, Section, MAGIC_BUNDLE};
+pub use dictionary::TokenDictionary;
 pub use error::{Result, TokenizerError};
 pub use glob::{glob_files, GlobOptions, GlobResult};
+pub use grep::{
+    grep_index, query_lines, FileLines, LineMatchMode, LineOptions, LinesResult, MatchedLine,
+};
 pub use index::{
-    ExactTokenIndex, IndexHeader, IndexMetadata, PathIndex, TokenIndex, TrigramIndex,
-    FORMAT_VERSION,
+    ExactTokenIndex, FileMeta, IndexHeader, IndexMetadata, IndexSet, PathIndex, TokenIndex,
+    TrigramIndex, FORMAT_VERSION,
 };
+pub use merge::{merge_index_sets, merge_indexes, DuplicatePolicy, MergeOptions, MergeReport};
+pub use migrate::{migrate_index, Migration, RebuildPlan};
 pub use persistence::{
     // New split index API
-    exact_file, exact_lower_file, load_exact, load_exact_mmap, load_paths, load_paths_mmap,
-    load_trigram, load_trigram_mmap, paths_file, save_all, save_exact, save_paths, save_trigram,
-    trigram_file, validate_index_match,
+    dict_file,
+    exact_file,
+    exact_lower_file,
+    exact_normalized_file,
     // Legacy single-file API (deprecated)
-    index_exists, load_index, load_index_mmap, save_index,
+    index_exists,
+    load_all,
+    load_all_with_tokenizer,
+    load_dictionary,
+    load_exact,
+    load_exact_mmap,
+    load_exact_view,
+    load_exact_view_with_tokenizer,
+    load_frequencies,
+    load_index,
+    load_index_mmap,
+    load_paths,
+    load_paths_mmap,
+    load_positions,
+    load_subwords,
+    load_trigram,
+    load_trigram_mmap,
+    load_trigram_view,
+    lock_file,
+    paths_file,
+    pos_file,
+    read_header,
+    save_all,
+    save_dictionary,
+    save_exact,
+    save_frequencies,
+    save_index,
+    save_paths,
+    save_positions,
+    save_subwords,
+    save_trigram,
+    sub_file,
+    tf_file,
+    tf_lower_file,
+    trigram_file,
+    validate_index_match,
 };
+pub use positions::PositionIndex;
+pub use provenance::{BuildInfo, SkipCounts};
 pub use query::{
-    query, query_exact, query_exact_lower, query_fuzzy, query_with_options, QueryOptions,
-    QueryResult,
+    has_positional_syntax, parse_query, query, query_boolean_exact, query_boolean_exact_lower,
+    query_boolean_exact_normalized, query_boolean_fuzzy, query_exact, query_exact_lower,
+    query_exact_normalized, query_fuzzy, query_subword, query_with_options, PatternExpansion,
+    QueryExpr, QueryOptions, QueryResult, SortOrder, TypoExpansion,
 };
+pub use ranking::{TermFrequencies, BM25_B, BM25_K1};
+pub use regex_search::{query_regex, RegexFileMatches, RegexMatch, RegexResult};
 pub use scanner::{scan_and_build_indexes, scan_and_index, ScanConfig};
+#[cfg(unix)]
+pub use server::{QueryMode, QueryServer, ServerClient, ServerRequest, ServerResponse};
+pub use subword::{split_subwords, SubwordIndex};
 pub use tokenizer::{
-    extract_exact_tokens_from_file, hash_token, tokenize, tokenize_exact, tokenize_query,
-    tokenize_query_exact, tokenize_query_exact_lower, MIN_TOKEN_LENGTH,
+    builtin_tokenizer, builtin_tokenizer_by_fingerprint, describe_tokenizer,
+    extract_exact_tokens_from_file, hash_token, hash_token_normalized, normalize_token, tokenize,
+    tokenize_exact, tokenize_query, tokenize_query_exact, tokenize_query_exact_lower,
+    tokenize_query_exact_normalized, ExactTokenizer, LegacyTokenizer, Tokenizer,
+    BUILTIN_TOKENIZERS, MIN_TOKEN_LENGTH,
 };
 pub use trigram::{
     extract_query_trigrams, extract_trigrams, extract_trigrams_from_file, pack_trigram,
     unpack_trigram, MIN_TRIGRAM_TOKEN_LENGTH,
 };
+pub use unicode::{fold_case, fold_token};
+pub use update::{apply_changes, detect_changes, update_indexes, FileChange, UpdateStats};
+pub use verify::{verify_index, FileCheck, VerifyReport};
+pub use view::{ExactTokenView, TokenLookup, TrigramLookup, TrigramView};
+#[cfg(target_os = "linux")]
+pub use watch::{IndexWatcher, WatchOptions};
+pub use wildcard::DEFAULT_MAX_EXPANSIONS;
 
 /// Format a number with thousand separators (e.g., 1234567 -> "1,234,567")
 pub fn fmt_num(n: impl std::fmt::Display) -> String {
     let s = n.to_string();
     let mut result = String::with_capacity(s.len() + s.len() / 3);
     for (i, c) in s.chars().enumerate() {
-        if i > 0 && (s.len() - i) % 3 == 0 {
+        if i > 0 && (s.len() - i).is_multiple_of(3) {
             result.push(',');
         }
         result.push(c);
@@ -1,13 +1,26 @@
-use clap::{Parser, Subcommand};
-use std::path::PathBuf;
+use clap::{Args, Parser, Subcommand};
+use std::path::{Path, PathBuf};
+#[cfg(target_os = "linux")]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(target_os = "linux")]
+use std::time::Duration;
 use std::time::Instant;
 use tokenizer::{
-    exact_file, exact_lower_file, fmt_num, glob_files, index_exists, load_exact, load_exact_mmap,
-    load_index, load_index_mmap, load_paths, load_paths_mmap, load_trigram, load_trigram_mmap,
-    paths_file, query_exact, query_exact_lower, query_fuzzy, query_with_options, save_all,
-    save_index, scan_and_build_indexes, scan_and_index, trigram_file, validate_index_match,
-    GlobOptions, QueryOptions, ScanConfig, TokenizerError,
+    builtin_tokenizer, describe_tokenizer, dict_file, exact_file, exact_lower_file,
+    exact_normalized_file, fmt_num, glob_files, grep_index, has_positional_syntax, index_exists,
+    is_bundle, load_index, load_index_mmap, load_paths, load_paths_mmap, merge_indexes,
+    migrate_index, orphaned_temp_files, pack_index, paths_file, pos_file, query_boolean_exact,
+    query_boolean_exact_lower, query_boolean_exact_normalized, query_boolean_fuzzy, query_exact,
+    query_exact_lower, query_exact_normalized, query_fuzzy, query_regex, query_subword,
+    query_with_options, save_all, save_index, scan_and_build_indexes, scan_and_index, sub_file,
+    tf_file, tf_lower_file, trigram_file, unpack_index, update_indexes, validate_index_match,
+    verify_index, BuildInfo, BuildLock, DuplicatePolicy, GlobOptions, IndexFiles, LineMatchMode,
+    LineOptions, MergeOptions, Migration, PathIndex, PatternExpansion, PositionIndex, QueryOptions,
+    QueryResult, ScanConfig, Section, SortOrder, TermFrequencies, TokenDictionary, TokenLookup,
+    TokenizerError, TrigramLookup, TypoExpansion, BUILTIN_TOKENIZERS, FORMAT_VERSION,
 };
+#[cfg(unix)]
+use tokenizer::{QueryMode, QueryServer, ServerClient, ServerRequest};
 
 #[derive(Parser)]
 #[command(name = "tokenizer")]
@@ -18,6 +31,108 @@ struct Cli {
     command: Commands,
 }
 
+/// File selection flags shared by `index` and `update`
+#[derive(Args)]
+struct ScanArgs {
+    /// File extensions to include (e.g., rs,py,js)
+    #[arg(short, long, value_delimiter = ',')]
+    extensions: Option<Vec<String>>,
+
+    /// Directory or file names to exclude
+    #[arg(short = 'x', long, value_delimiter = ',')]
+    exclude: Option<Vec<String>>,
+
+    /// Root-relative glob a file must match (repeatable, e.g. "src/**/*.{rs,toml}").
+    /// Prefix with ! to exclude instead (e.g. "!**/generated/**")
+    #[arg(short = 'I', long = "include")]
+    include: Vec<String>,
+
+    /// Root-relative glob to exclude (repeatable)
+    #[arg(long = "exclude-glob")]
+    exclude_glob: Vec<String>,
+
+    /// Maximum file size in MB
+    #[arg(long, default_value = "10")]
+    max_size: u64,
+
+    /// Record a content hash per file (lets `update` skip touched but unchanged files)
+    #[arg(long)]
+    hash: bool,
+
+    /// Don't respect .gitignore, .ignore and git exclude files
+    #[arg(long)]
+    no_ignore: bool,
+
+    /// Don't write the token dictionary (.dict) next to the index
+    #[arg(long)]
+    no_dict: bool,
+
+    /// Also write token positions (.pos) for "phrase" and NEAR/n queries
+    #[arg(long)]
+    positions: bool,
+
+    /// Don't write term frequencies (.tf, .tfi); results can't be ranked by relevance
+    #[arg(long)]
+    no_ranking: bool,
+
+    /// Don't write the subword index (.sub) used by --subword queries
+    #[arg(long)]
+    no_subwords: bool,
+
+    /// Don't write the normalized token index (.exactn) used by --normalize queries
+    #[arg(long)]
+    no_normalize: bool,
+
+    /// Token rules [default: exact, or the existing index's for update and watch]
+    #[arg(long, value_parser = BUILTIN_TOKENIZERS)]
+    tokenizer: Option<String>,
+
+    /// Wait for another build of the same index to finish instead of failing
+    #[arg(long)]
+    wait: bool,
+}
+
+impl ScanArgs {
+    /// Build the scan configuration for these flags
+    fn to_config(&self) -> ScanConfig {
+        let mut config = ScanConfig::default();
+
+        if let Some(ref exts) = self.extensions {
+            config.extensions = exts.clone();
+        }
+
+        if let Some(ref excl) = self.exclude {
+            config.exclude_patterns.extend(excl.iter().cloned());
+        }
+
+        config.include_globs = self.include.clone();
+        config.exclude_globs = self.exclude_glob.clone();
+        config.max_file_size = self.max_size * 1024 * 1024;
+        config.hash_contents = self.hash;
+        config.respect_ignore_files = !self.no_ignore;
+        config.build_dictionary = !self.no_dict;
+        config.build_positions = self.positions;
+        config.build_frequencies = !self.no_ranking;
+        config.build_subwords = !self.no_subwords;
+        config.build_normalized = !self.no_normalize;
+        if let Some(tokenizer) = self.tokenizer.as_deref().and_then(builtin_tokenizer) {
+            config.tokenizer = tokenizer;
+        }
+        config
+    }
+
+    /// Build the scan configuration for updating the index at `base`
+    ///
+    /// Without `--tokenizer` an existing index keeps its tokenizer.
+    fn to_update_config(&self, base: &Path) -> tokenizer::Result<ScanConfig> {
+        let mut config = self.to_config();
+        if self.tokenizer.is_none() && paths_file(base).exists() {
+            config.tokenizer = load_paths(&paths_file(base))?.header.builtin_tokenizer()?;
+        }
+        Ok(config)
+    }
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Build an index from a directory
@@ -30,34 +145,148 @@ enum Commands {
         #[arg(short, long, default_value = "index.tkix")]
         output: PathBuf,
 
-        /// File extensions to include (e.g., rs,py,js)
-        #[arg(short, long, value_delimiter = ',')]
-        extensions: Option<Vec<String>>,
+        /// Use legacy single-file format (deprecated)
+        #[arg(long)]
+        legacy: bool,
+
+        #[command(flatten)]
+        scan: ScanArgs,
+    },
+
+    /// Incrementally update an existing index (re-tokenizes only changed files)
+    Update {
+        /// Directory that was indexed (must match the index root)
+        #[arg(short, long, default_value = ".")]
+        dir: PathBuf,
+
+        /// Index file path (base name for .paths, .exact, .tri files)
+        #[arg(short, long, default_value = "index.tkix")]
+        output: PathBuf,
+
+        #[command(flatten)]
+        scan: ScanArgs,
+    },
 
-        /// Patterns to exclude
-        #[arg(short = 'x', long, value_delimiter = ',')]
-        exclude: Option<Vec<String>>,
+    /// Pack a split index into a single bundle file
+    Pack {
+        /// Index file path (base name for .paths, .exact, .tri files)
+        index: PathBuf,
 
-        /// Maximum file size in MB
-        #[arg(long, default_value = "10")]
-        max_size: u64,
+        /// Bundle file to write
+        #[arg(short, long, default_value = "index.tkb")]
+        output: PathBuf,
 
-        /// Use legacy single-file format (deprecated)
+        /// Wait for a build of the index to finish instead of failing
         #[arg(long)]
-        legacy: bool,
+        wait: bool,
+    },
+
+    /// Unpack a bundle file into a split index
+    Unpack {
+        /// Bundle file to read
+        bundle: PathBuf,
+
+        /// Index file path to write (base name for .paths, .exact, .tri files)
+        #[arg(short, long, default_value = "index.tkix")]
+        output: PathBuf,
+
+        /// Wait for another build of the same index to finish instead of failing
+        #[arg(long)]
+        wait: bool,
+    },
+
+    /// Rebuild an index from scratch with the root and settings it was built with
+    Reindex {
+        /// Index file path (base name for .paths, .exact, .tri files)
+        index: PathBuf,
+
+        /// Wait for another build of the same index to finish instead of failing
+        #[arg(long)]
+        wait: bool,
+    },
+
+    /// Merge several indexes into one without rescanning
+    Merge {
+        /// Indexes to merge (base names of split indexes, or bundles)
+        #[arg(required = true, num_args = 2..)]
+        inputs: Vec<PathBuf>,
+
+        /// Index file path to write (base name for .paths, .exact, .tri files)
+        #[arg(short, long, default_value = "index.tkix")]
+        output: PathBuf,
+
+        /// Copy to keep of a path found in more than one index: error, first,
+        /// last or newest (latest modification time)
+        #[arg(long, default_value = "error", value_name = "POLICY")]
+        duplicates: DuplicatePolicy,
+
+        /// Wait for another build of the output index to finish instead of failing
+        #[arg(long)]
+        wait: bool,
+    },
+
+    /// Check whether an index was written by an older version, and rebuild it if so
+    Migrate {
+        /// Index file path (base name for .paths, .exact, .tri files, or a legacy index)
+        index: PathBuf,
+
+        /// Rebuild an outdated index from the root it was built from
+        #[arg(long)]
+        rebuild: bool,
+
+        /// Wait for another build of the same index to finish instead of failing
+        #[arg(long)]
+        wait: bool,
+    },
+
+    /// Keep an index up to date by watching the directory for changes (Linux only)
+    #[cfg(target_os = "linux")]
+    Watch {
+        /// Directory to watch (must match the index root)
+        #[arg(short, long, default_value = ".")]
+        dir: PathBuf,
+
+        /// Index file path (built first if it does not exist)
+        #[arg(short, long, default_value = "index.tkix")]
+        output: PathBuf,
+
+        #[command(flatten)]
+        scan: ScanArgs,
+
+        /// Milliseconds a file must be quiet before it is re-tokenized
+        #[arg(long, default_value = "200")]
+        debounce_ms: u64,
+
+        /// Seconds between flushes of changes to the index files
+        #[arg(long, default_value = "5")]
+        flush_secs: u64,
     },
 
     /// Query an existing index
-    #[command(visible_alias = "q", after_help = "\
+    #[command(
+        visible_alias = "q",
+        after_help = "\
 Examples:
   tokenizer q Mannequin                      # exact match (default)
   tokenizer q Mannequin -i                   # case-insensitive exact match
   tokenizer q Mannequin -f                   # fuzzy match
+  tokenizer q Manequin -f --min-match 0.7    # fuzzy, 70% of trigrams, best first
   tokenizer q \"bob dog\" -o                   # OR mode (either token)
+  tokenizer q \"(bob OR dog) NOT cat\" -b      # boolean expression
+  tokenizer q '\"impl Display for\"'          # phrase (index built with --positions)
+  tokenizer q 'Display NEAR/3 fmt'           # tokens at most 3 apart (--positions)
+  tokenizer q 'fn\\s+parse_\\w+' -r            # regex, verified against file contents
+  tokenizer q recieve_buffer --typo 2        # also tokens within 2 edits
+  tokenizer q User --subword                 # identifier parts: getUserById, user_id
+  tokenizer q 'user id' --subword --in-order # both parts, in order, in one identifier
+  tokenizer q userId --normalize             # any spelling: user_id, USER_ID, user-id
   tokenizer q Mannequin -p src               # paths containing \"src\"
   tokenizer q Mannequin -g \"*.rs,*.h\"        # filter by glob
   tokenizer q Mannequin -x test              # exclude \"test\"
-  tokenizer q Mannequin -p src -x test -l 10 # combined")]
+  tokenizer q Mannequin -p src -x test -l 10 # combined
+  tokenizer q \"bob dog\" -o --sort path      # OR mode, sorted by path
+  tokenizer q Mannequin --server tokenizer.sock # use a running `tokenizer serve`"
+    )]
     Query {
         /// Search query
         query: String,
@@ -70,6 +299,22 @@ Examples:
         #[arg(short = 'f', long)]
         fuzzy: bool,
 
+        /// Match identifier parts split on camelCase, _, - and digits (case-insensitive)
+        #[arg(long, conflicts_with_all = ["fuzzy", "ignore_case", "boolean", "regex", "max_typos"])]
+        subword: bool,
+
+        /// Match tokens ignoring case, _ and - (userId also finds user_id and USER-ID)
+        #[arg(long, conflicts_with_all = ["fuzzy", "ignore_case", "subword", "regex"])]
+        normalize: bool,
+
+        /// Subword mode: all parts must occur in query order within one identifier
+        #[arg(long, requires = "subword")]
+        in_order: bool,
+
+        /// Fuzzy mode: keep files with at least this share (0.0-1.0) of the query's trigrams
+        #[arg(long, value_name = "RATIO", requires = "fuzzy", value_parser = parse_ratio)]
+        min_match: Option<f32>,
+
         /// Filter to paths containing substring (e.g., "src", "FortniteGame")
         #[arg(short = 'p', long)]
         path: Option<String>,
@@ -82,21 +327,118 @@ Examples:
         #[arg(short = 'x', long)]
         exclude: Option<String>,
 
-        /// Maximum results to return
+        /// Maximum results to return (the most relevant ones unless --sort says otherwise)
         #[arg(short, long)]
         limit: Option<usize>,
 
+        /// Result order: relevance (BM25, exact modes; id order when the index
+        /// has no term frequencies), path or id [default: relevance with --limit
+        /// or --fuzzy, otherwise id]
+        #[arg(long, value_name = "ORDER")]
+        sort: Option<SortOrder>,
+
         /// Match any token (OR) instead of all tokens (AND)
-        #[arg(short = 'o', long = "or")]
+        #[arg(short = 'o', long = "or", conflicts_with = "boolean")]
         or_mode: bool,
 
-        /// Index file path
+        /// Parse the query as a boolean expression: AND, OR, NOT, (...) and "quoted" terms
+        #[arg(short = 'b', long)]
+        boolean: bool,
+
+        /// Treat the query as a regex: trigram prefilter, then verify file contents
+        #[arg(short = 'r', long, conflicts_with_all = ["fuzzy", "boolean", "or_mode"])]
+        regex: bool,
+
+        /// Maximum tokens a wildcard (foo*, f?o) or /regex/ term may expand to
+        #[arg(long, value_name = "N")]
+        max_expansions: Option<usize>,
+
+        /// Exact modes: also match indexed tokens within N edits of each query token
+        #[arg(long = "typo", value_name = "N", conflicts_with_all = ["fuzzy", "regex"])]
+        max_typos: Option<u32>,
+
+        /// Index file path, or a bundle written by `tokenizer pack`
         #[arg(long, default_value = "index.tkix")]
         index: PathBuf,
 
-        /// Use memory-mapped loading (faster for repeated queries)
+        /// Query token files straight from a memory mapping instead of decoding them up front
+        /// (the paths file is still decoded in full)
         #[arg(long)]
         mmap: bool,
+
+        /// Ask a running `tokenizer serve` daemon first (falls back to loading the index)
+        #[cfg(unix)]
+        #[arg(long, value_name = "SOCKET")]
+        server: Option<PathBuf>,
+    },
+
+    /// Print matching lines of the files a query selects (indexed grep)
+    #[command(after_help = "\
+Examples:
+  tokenizer grep Mannequin                   # lines with the exact token
+  tokenizer grep mannequin -i                # case-insensitive exact match
+  tokenizer grep annequ -f                   # substring match via trigrams
+  tokenizer grep Mannequin -C 2              # two lines of context
+  tokenizer grep Mannequin --count           # matching lines per file")]
+    Grep {
+        /// Search query
+        query: String,
+
+        /// Case-insensitive exact matching
+        #[arg(short = 'i', long = "ignore-case", conflicts_with = "fuzzy")]
+        ignore_case: bool,
+
+        /// Substring matching (trigram candidates, case-insensitive)
+        #[arg(short = 'f', long)]
+        fuzzy: bool,
+
+        /// Lines of context before and after each match
+        #[arg(short = 'C', long, value_name = "NUM")]
+        context: Option<usize>,
+
+        /// Lines of context after each match
+        #[arg(short = 'A', long, value_name = "NUM")]
+        after_context: Option<usize>,
+
+        /// Lines of context before each match
+        #[arg(short = 'B', long, value_name = "NUM")]
+        before_context: Option<usize>,
+
+        /// Only print the number of matching lines per file
+        #[arg(short = 'c', long)]
+        count: bool,
+
+        /// Filter to paths containing substring
+        #[arg(short = 'p', long)]
+        path: Option<String>,
+
+        /// Filter by glob patterns (comma-separated, e.g., "*.rs,*.h")
+        #[arg(short = 'g', long, value_delimiter = ',')]
+        glob: Option<Vec<String>>,
+
+        /// Exclude files matching pattern
+        #[arg(short = 'x', long)]
+        exclude: Option<String>,
+
+        /// Match lines of files containing any token (OR) instead of all tokens (AND)
+        #[arg(short = 'o', long = "or")]
+        or_mode: bool,
+
+        /// Index file path
+        #[arg(long, default_value = "index.tkix")]
+        index: PathBuf,
+    },
+
+    /// Serve queries from memory over a Unix socket (newline-delimited JSON)
+    #[cfg(unix)]
+    Serve {
+        /// Index file path
+        #[arg(short, long, default_value = "index.tkix")]
+        index: PathBuf,
+
+        /// Socket path to listen on
+        #[arg(short, long, default_value = "tokenizer.sock")]
+        socket: PathBuf,
     },
 
     /// Show index statistics
@@ -104,6 +446,17 @@ Examples:
         /// Index file path
         #[arg(short, long, default_value = "index.tkix")]
         index: PathBuf,
+
+        /// List every normalized form with the spellings that collapse into it
+        #[arg(long)]
+        spellings: bool,
+    },
+
+    /// Check every index file against its checksums and the paths file
+    Verify {
+        /// Index file path
+        #[arg(short, long, default_value = "index.tkix")]
+        index: PathBuf,
     },
 
     /// Search for files by name using glob patterns
@@ -132,32 +485,185 @@ fn main() {
         Commands::Index {
             dir,
             output,
-            extensions,
-            exclude,
-            max_size,
             legacy,
+            scan,
         } => {
             if legacy {
-                cmd_index_legacy(dir, output, extensions, exclude, max_size)
+                cmd_index_legacy(dir, output, scan.to_config())
             } else {
-                cmd_index(dir, output, extensions, exclude, max_size)
+                cmd_index(dir, output, scan.to_config(), scan.wait)
             }
         }
 
+        Commands::Update { dir, output, scan } => scan
+            .to_update_config(&output)
+            .and_then(|config| cmd_update(dir, output, config, scan.wait)),
+
+        Commands::Reindex { index, wait } => cmd_reindex(index, wait),
+
+        Commands::Merge {
+            inputs,
+            output,
+            duplicates,
+            wait,
+        } => cmd_merge(inputs, output, duplicates, wait),
+
+        Commands::Migrate {
+            index,
+            rebuild,
+            wait,
+        } => cmd_migrate(index, rebuild, wait),
+
+        Commands::Pack {
+            index,
+            output,
+            wait,
+        } => cmd_pack(index, output, wait),
+
+        Commands::Unpack {
+            bundle,
+            output,
+            wait,
+        } => cmd_unpack(bundle, output, wait),
+
+        #[cfg(target_os = "linux")]
+        Commands::Watch {
+            dir,
+            output,
+            scan,
+            debounce_ms,
+            flush_secs,
+        } => {
+            let options = tokenizer::WatchOptions {
+                debounce: Duration::from_millis(debounce_ms),
+                flush_interval: Duration::from_secs(flush_secs),
+            };
+            scan.to_update_config(&output)
+                .and_then(|config| cmd_watch(dir, output, config, options, scan.wait))
+        }
+
         Commands::Query {
             query,
             ignore_case,
             fuzzy,
+            subword,
+            normalize,
+            in_order,
+            min_match,
             path,
             glob,
             exclude,
             limit,
+            sort,
             or_mode,
+            boolean,
+            regex,
+            max_expansions,
+            max_typos,
             index,
             mmap,
-        } => cmd_query(index, query, limit, or_mode, mmap, ignore_case, fuzzy, path, glob, exclude),
+            #[cfg(unix)]
+            server,
+        } => {
+            // Ranking exact results loads the term frequencies, which only
+            // pays off when a limit keeps the best ones
+            let sort = sort.unwrap_or(if fuzzy || limit.is_some() {
+                SortOrder::Relevance
+            } else {
+                SortOrder::Id
+            });
+            let options = QueryOptions {
+                limit,
+                match_all: !or_mode,
+                path_contains: path,
+                glob_patterns: glob,
+                exclude,
+                max_expansions,
+                sort,
+                min_match,
+                max_typos,
+                in_order,
+            };
+
+            // The daemon does not answer regex queries
+            if regex {
+                return finish(cmd_regex(index, query, mmap, ignore_case, options));
+            }
+
+            #[cfg(unix)]
+            if let Some(socket) = server {
+                let mode = if subword {
+                    QueryMode::Subword
+                } else if normalize {
+                    QueryMode::ExactN
+                } else if fuzzy {
+                    QueryMode::Fuzzy
+                } else if ignore_case {
+                    QueryMode::ExactI
+                } else {
+                    QueryMode::Exact
+                };
+                if let Some(result) = query_via_server(&socket, &query, mode, boolean, &options) {
+                    return finish(result);
+                }
+            }
+
+            if subword {
+                return finish(cmd_subword(index, query, mmap, options));
+            }
+            if normalize {
+                return finish(cmd_normalized(index, query, mmap, boolean, options));
+            }
+            cmd_query(index, query, mmap, ignore_case, fuzzy, boolean, options)
+        }
+
+        Commands::Grep {
+            query,
+            ignore_case,
+            fuzzy,
+            context,
+            after_context,
+            before_context,
+            count,
+            path,
+            glob,
+            exclude,
+            or_mode,
+            index,
+        } => {
+            let options = QueryOptions {
+                limit: None,
+                match_all: !or_mode,
+                path_contains: path,
+                glob_patterns: glob,
+                exclude,
+                max_expansions: None,
+                sort: SortOrder::Id,
+                min_match: None,
+                max_typos: None,
+                in_order: false,
+            };
+            let mode = if fuzzy {
+                LineMatchMode::Substring
+            } else if ignore_case {
+                LineMatchMode::ExactIgnoreCase
+            } else {
+                LineMatchMode::Exact
+            };
+            let line_options = LineOptions {
+                before_context: before_context.or(context).unwrap_or(0),
+                after_context: after_context.or(context).unwrap_or(0),
+                ..Default::default()
+            };
+            cmd_grep(index, query, mode, options, line_options, count)
+        }
+
+        #[cfg(unix)]
+        Commands::Serve { index, socket } => cmd_serve(index, socket),
 
-        Commands::Stats { index } => cmd_stats(index),
+        Commands::Stats { index, spellings } => cmd_stats(index, spellings),
+
+        Commands::Verify { index } => cmd_verify(index),
 
         Commands::Glob {
             pattern,
@@ -167,32 +673,39 @@ fn main() {
         } => cmd_glob(index, pattern, limit, mmap),
     };
 
+    finish(result);
+}
+
+/// Report a command's error and exit with a failure status
+fn finish(result: tokenizer::Result<()>) {
     if let Err(e) = result {
         eprintln!("Error: {}", e);
         std::process::exit(1);
     }
 }
 
+/// Take the build lock of an index, waiting for it or failing fast
+fn lock_index(base: &Path, wait: bool) -> tokenizer::Result<BuildLock> {
+    if wait {
+        BuildLock::acquire(base)
+    } else {
+        BuildLock::try_acquire(base)
+    }
+}
+
 fn cmd_index(
     dir: PathBuf,
     output: PathBuf,
-    extensions: Option<Vec<String>>,
-    exclude: Option<Vec<String>>,
-    max_size: u64,
+    config: ScanConfig,
+    wait: bool,
 ) -> tokenizer::Result<()> {
-    println!("Indexing directory: {}", dir.display());
-
-    let mut config = ScanConfig::default();
-
-    if let Some(exts) = extensions {
-        config.extensions = exts;
-    }
-
-    if let Some(excl) = exclude {
-        config.exclude_patterns.extend(excl);
-    }
+    let _lock = lock_index(&output, wait)?;
+    build_index(dir, output, config)
+}
 
-    config.max_file_size = max_size * 1024 * 1024;
+/// Build and save an index; the caller holds its build lock
+fn build_index(dir: PathBuf, output: PathBuf, config: ScanConfig) -> tokenizer::Result<()> {
+    println!("Indexing directory: {}", dir.display());
 
     let start = Instant::now();
     let (path_index, exact_index, exact_lower_index, trigram_index) =
@@ -227,10 +740,37 @@ fn cmd_index(
     let exact_lower_size = std::fs::metadata(exact_lower_file(&output))
         .map(|m| m.len())
         .unwrap_or(0);
+    let exact_normalized_size = std::fs::metadata(exact_normalized_file(&output))
+        .map(|m| m.len())
+        .unwrap_or(0);
     let trigram_size = std::fs::metadata(trigram_file(&output))
         .map(|m| m.len())
         .unwrap_or(0);
-    let total_size = paths_size + exact_size + exact_lower_size + trigram_size;
+    let dict_size = std::fs::metadata(dict_file(&output))
+        .map(|m| m.len())
+        .unwrap_or(0);
+    let sub_size = std::fs::metadata(sub_file(&output))
+        .map(|m| m.len())
+        .unwrap_or(0);
+    let pos_size = std::fs::metadata(pos_file(&output))
+        .map(|m| m.len())
+        .unwrap_or(0);
+    let tf_size = std::fs::metadata(tf_file(&output))
+        .map(|m| m.len())
+        .unwrap_or(0);
+    let tf_lower_size = std::fs::metadata(tf_lower_file(&output))
+        .map(|m| m.len())
+        .unwrap_or(0);
+    let total_size = paths_size
+        + exact_size
+        + exact_lower_size
+        + exact_normalized_size
+        + trigram_size
+        + dict_size
+        + sub_size
+        + pos_size
+        + tf_size
+        + tf_lower_size;
 
     println!("Saved index files in {:.2}s:", save_time.as_secs_f64());
     println!(
@@ -248,37 +788,59 @@ fn cmd_index(
         exact_lower_file(&output).display(),
         exact_lower_size as f64 / (1024.0 * 1024.0)
     );
+    if exact_index.normalized().is_some() {
+        println!(
+            "  {} ({:.2} MB)",
+            exact_normalized_file(&output).display(),
+            exact_normalized_size as f64 / (1024.0 * 1024.0)
+        );
+    }
     println!(
         "  {} ({:.2} MB)",
         trigram_file(&output).display(),
         trigram_size as f64 / (1024.0 * 1024.0)
     );
+    if exact_index.dictionary().is_some() {
+        println!(
+            "  {} ({:.2} MB)",
+            dict_file(&output).display(),
+            dict_size as f64 / (1024.0 * 1024.0)
+        );
+    }
+    if exact_index.subwords().is_some() {
+        println!(
+            "  {} ({:.2} MB)",
+            sub_file(&output).display(),
+            sub_size as f64 / (1024.0 * 1024.0)
+        );
+    }
+    if exact_index.positions().is_some() {
+        println!(
+            "  {} ({:.2} MB)",
+            pos_file(&output).display(),
+            pos_size as f64 / (1024.0 * 1024.0)
+        );
+    }
+    if exact_index.frequencies().is_some() {
+        println!(
+            "  {} ({:.2} MB)",
+            tf_file(&output).display(),
+            tf_size as f64 / (1024.0 * 1024.0)
+        );
+        println!(
+            "  {} ({:.2} MB)",
+            tf_lower_file(&output).display(),
+            tf_lower_size as f64 / (1024.0 * 1024.0)
+        );
+    }
     println!("  Total: {:.2} MB", total_size as f64 / (1024.0 * 1024.0));
 
     Ok(())
 }
 
-fn cmd_index_legacy(
-    dir: PathBuf,
-    output: PathBuf,
-    extensions: Option<Vec<String>>,
-    exclude: Option<Vec<String>>,
-    max_size: u64,
-) -> tokenizer::Result<()> {
+fn cmd_index_legacy(dir: PathBuf, output: PathBuf, config: ScanConfig) -> tokenizer::Result<()> {
     println!("Indexing directory (legacy mode): {}", dir.display());
 
-    let mut config = ScanConfig::default();
-
-    if let Some(exts) = extensions {
-        config.extensions = exts;
-    }
-
-    if let Some(excl) = exclude {
-        config.exclude_patterns.extend(excl);
-    }
-
-    config.max_file_size = max_size * 1024 * 1024;
-
     let start = Instant::now();
     let index = scan_and_index(&dir, &config)?;
     let index_time = start.elapsed();
@@ -306,24 +868,293 @@ fn cmd_index_legacy(
     Ok(())
 }
 
-fn cmd_query(
-    index_path: PathBuf,
-    query_str: String,
-    limit: Option<usize>,
-    or_mode: bool,
-    use_mmap: bool,
-    ignore_case: bool,
-    fuzzy: bool,
-    path: Option<String>,
-    glob: Option<Vec<String>>,
-    exclude: Option<String>,
+fn cmd_update(
+    dir: PathBuf,
+    output: PathBuf,
+    config: ScanConfig,
+    wait: bool,
 ) -> tokenizer::Result<()> {
-    // Default to exact mode (fuzzy = false means exact)
-    // ignore_case uses the lowercase exact index
+    let _lock = lock_index(&output, wait)?;
+    println!("Updating index {} from {}", output.display(), dir.display());
 
-    // Check if we have the new split format or legacy format
-    let has_split_format = paths_file(&index_path).exists();
-    let has_legacy_format = index_path.exists() && !has_split_format;
+    let start = Instant::now();
+    let stats = update_indexes(&output, &dir, &config)?;
+
+    println!(
+        "{} added, {} modified, {} removed, {} unchanged in {:.2}s",
+        fmt_num(stats.added),
+        fmt_num(stats.modified),
+        fmt_num(stats.removed),
+        fmt_num(stats.unchanged),
+        start.elapsed().as_secs_f64()
+    );
+
+    if !stats.has_changes() {
+        println!("Index is up to date");
+    }
+
+    Ok(())
+}
+
+fn cmd_reindex(index_path: PathBuf, wait: bool) -> tokenizer::Result<()> {
+    if !paths_file(&index_path).exists() {
+        return Err(TokenizerError::IndexNotFound(
+            index_path.display().to_string(),
+        ));
+    }
+
+    let _lock = lock_index(&index_path, wait)?;
+    let path_index = load_paths(&paths_file(&index_path))?;
+    let build = path_index.build.ok_or_else(|| {
+        TokenizerError::InvalidIndexFormat(
+            "Index has no build record; rebuild it with `tokenizer index`".to_string(),
+        )
+    })?;
+    let config = build.rebuild_config(&path_index.header)?;
+
+    build_index(build.root, index_path, config)
+}
+
+fn cmd_merge(
+    inputs: Vec<PathBuf>,
+    output: PathBuf,
+    duplicates: DuplicatePolicy,
+    wait: bool,
+) -> tokenizer::Result<()> {
+    let _lock = lock_index(&output, wait)?;
+    let start = Instant::now();
+    let options = MergeOptions {
+        duplicates,
+        ..Default::default()
+    };
+    let report = merge_indexes(&inputs, &output, &options)?;
+
+    for (outer, inner) in &report.overlapping_roots {
+        eprintln!(
+            "Warning: root {} contains root {}",
+            outer.display(),
+            inner.display()
+        );
+    }
+    if !report.duplicates.is_empty() {
+        println!(
+            "Kept one copy of {} paths found in more than one index",
+            fmt_num(report.duplicates.len())
+        );
+    }
+    for section in &report.dropped {
+        println!(
+            "Left out .{} files: not every index has one",
+            section.extension()
+        );
+    }
+    println!(
+        "Merged {} indexes into {} ({} files) in {:.2?}",
+        inputs.len(),
+        output.display(),
+        fmt_num(report.file_count),
+        start.elapsed()
+    );
+    Ok(())
+}
+
+fn cmd_migrate(index_path: PathBuf, rebuild: bool, wait: bool) -> tokenizer::Result<()> {
+    let _lock = lock_index(&index_path, wait)?;
+    let plan = match migrate_index(&index_path)? {
+        Migration::UpToDate => {
+            println!(
+                "{} is already at format version {}",
+                index_path.display(),
+                FORMAT_VERSION
+            );
+            return Ok(());
+        }
+        Migration::RebuildRequired(plan) => plan,
+    };
+
+    println!(
+        "{} has to be rebuilt: {}",
+        index_path.display(),
+        plan.reason
+    );
+    let Some(root) = plan.root else {
+        return Err(TokenizerError::InvalidIndexFormat(
+            "Index does not record its root directory; rebuild it with `tokenizer index`"
+                .to_string(),
+        ));
+    };
+    if !rebuild {
+        return Err(TokenizerError::InvalidIndexFormat(format!(
+            "Index has to be rebuilt; run `tokenizer migrate --rebuild {}` to rebuild it from {}",
+            index_path.display(),
+            root.display()
+        )));
+    }
+    if !root.is_dir() {
+        return Err(TokenizerError::IndexMismatch(format!(
+            "Index root {} no longer exists",
+            root.display()
+        )));
+    }
+
+    build_index(root, index_path.clone(), plan.config)?;
+    if plan.legacy {
+        std::fs::remove_file(&index_path).map_err(|e| TokenizerError::Io(e.to_string()))?;
+        println!("Removed legacy index {}", index_path.display());
+    }
+    Ok(())
+}
+
+fn cmd_pack(index_path: PathBuf, output: PathBuf, wait: bool) -> tokenizer::Result<()> {
+    let _lock = lock_index(&index_path, wait)?;
+    let start = Instant::now();
+    pack_index(&index_path, &output)?;
+
+    let size = std::fs::metadata(&output).map(|m| m.len()).unwrap_or(0);
+    println!(
+        "Packed {} into {} ({:.2} MB) in {:.2?}",
+        index_path.display(),
+        output.display(),
+        size as f64 / (1024.0 * 1024.0),
+        start.elapsed()
+    );
+    Ok(())
+}
+
+fn cmd_unpack(bundle: PathBuf, output: PathBuf, wait: bool) -> tokenizer::Result<()> {
+    let _lock = lock_index(&output, wait)?;
+    let start = Instant::now();
+    unpack_index(&bundle, &output)?;
+    println!(
+        "Unpacked {} into {} in {:.2?}",
+        bundle.display(),
+        output.display(),
+        start.elapsed()
+    );
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn cmd_watch(
+    dir: PathBuf,
+    output: PathBuf,
+    config: ScanConfig,
+    options: tokenizer::WatchOptions,
+    wait: bool,
+) -> tokenizer::Result<()> {
+    // Held while watching so no other build rewrites the index under us
+    let _lock = lock_index(&output, wait)?;
+    if !paths_file(&output).exists() {
+        build_index(dir.clone(), output.clone(), config.clone())?;
+    }
+
+    let mut watcher = tokenizer::IndexWatcher::new(&output, &dir, config, options)?;
+    println!(
+        "Watching {} ({} files); press Ctrl-C to stop",
+        dir.display(),
+        fmt_num(watcher.indexes().paths.file_count())
+    );
+
+    // Ctrl-C or SIGTERM ends the loop, which flushes pending changes on exit
+    static STOP: AtomicBool = AtomicBool::new(false);
+    ctrlc::set_handler(|| STOP.store(true, Ordering::Relaxed))
+        .map_err(|e| TokenizerError::Io(e.to_string()))?;
+
+    watcher.run(&STOP, |stats| {
+        println!(
+            "{} added, {} modified, {} removed",
+            fmt_num(stats.added),
+            fmt_num(stats.modified),
+            fmt_num(stats.removed)
+        );
+    })
+}
+
+/// Run a query through a `tokenizer serve` daemon
+///
+/// Returns None when no daemon is listening on the socket, so the caller can
+/// load the index itself.
+#[cfg(unix)]
+fn query_via_server(
+    socket: &std::path::Path,
+    query_str: &str,
+    mode: QueryMode,
+    boolean: bool,
+    options: &QueryOptions,
+) -> Option<tokenizer::Result<()>> {
+    let mut client = ServerClient::connect(socket).ok()?;
+
+    let request = ServerRequest {
+        mode,
+        query: query_str.to_string(),
+        boolean,
+        options: options.clone(),
+    };
+
+    let response = match client.request(&request) {
+        Ok(response) => response,
+        Err(e) => return Some(Err(e)),
+    };
+
+    let mode_str = match mode {
+        QueryMode::Fuzzy => "fuzzy",
+        QueryMode::ExactI => "exact-i",
+        QueryMode::ExactN => "exact-n",
+        QueryMode::Subword => "subword",
+        _ => "exact",
+    };
+    println!(
+        "Query ({}): \"{}\" ({} tokens, {} matched)",
+        mode_str,
+        query_str,
+        fmt_num(response.query_token_count),
+        fmt_num(response.matched_token_count)
+    );
+    println!(
+        "Found {} files in {:.3}ms (server: {})",
+        fmt_num(response.files.len()),
+        response.elapsed_us as f64 / 1000.0,
+        socket.display()
+    );
+    print_expansions(&response.expansions);
+    print_typos(&response.typos);
+    println!();
+
+    print_files(&response.files, &response.match_ratios, options);
+
+    Some(Ok(()))
+}
+
+#[cfg(unix)]
+fn cmd_serve(index_path: PathBuf, socket: PathBuf) -> tokenizer::Result<()> {
+    let start = Instant::now();
+    let server = QueryServer::new(&index_path)?;
+    println!(
+        "Loaded {} ({} files) in {:.2}s",
+        index_path.display(),
+        fmt_num(server.indexes().paths.file_count()),
+        start.elapsed().as_secs_f64()
+    );
+    println!("Listening on {}", socket.display());
+
+    server.serve(&socket)
+}
+
+fn cmd_query(
+    index_path: PathBuf,
+    query_str: String,
+    use_mmap: bool,
+    ignore_case: bool,
+    fuzzy: bool,
+    boolean: bool,
+    options: QueryOptions,
+) -> tokenizer::Result<()> {
+    // Default to exact mode (fuzzy = false means exact)
+    // ignore_case uses the lowercase exact index
+
+    // Check if we have the new split format (possibly bundled) or legacy format
+    let has_split_format = is_bundle(&index_path) || paths_file(&index_path).exists();
+    let has_legacy_format = index_path.exists() && !has_split_format;
 
     if !has_split_format && !has_legacy_format {
         return Err(TokenizerError::IndexNotFound(
@@ -333,7 +1164,14 @@ fn cmd_query(
 
     // If we have legacy format but trying to use new modes, error
     if has_legacy_format && !has_split_format {
-        eprintln!("Warning: Legacy index format detected. Re-index with `tokenizer index` for --exact/--fuzzy support.");
+        if boolean {
+            return Err(TokenizerError::InvalidIndexFormat(
+                "boolean queries need the split index format; convert it with `tokenizer migrate`"
+                    .to_string(),
+            ));
+        }
+
+        eprintln!("Warning: Legacy index format detected. Convert it with `tokenizer migrate` for --exact/--fuzzy support.");
         eprintln!("Falling back to legacy query...");
 
         // Fall back to legacy query
@@ -347,8 +1185,8 @@ fn cmd_query(
 
         let start = Instant::now();
         let options = QueryOptions {
-            limit,
-            match_all: !or_mode,
+            limit: options.limit,
+            match_all: options.match_all,
             ..Default::default()
         };
         let result = query_with_options(&index, &query_str, &options);
@@ -356,7 +1194,9 @@ fn cmd_query(
 
         println!(
             "Query: \"{}\" ({} tokens, {} matched)",
-            query_str, fmt_num(result.query_token_count), fmt_num(result.matched_token_count)
+            query_str,
+            fmt_num(result.query_token_count),
+            fmt_num(result.matched_token_count)
         );
         println!(
             "Found {} files in {:.3}ms (load: {:.3}ms)",
@@ -375,73 +1215,91 @@ fn cmd_query(
 
     // New split format query
     let start = Instant::now();
-    let path_index = if use_mmap {
-        load_paths_mmap(&paths_file(&index_path))?
-    } else {
-        load_paths(&paths_file(&index_path))?
+    let files = IndexFiles::open(&index_path)?;
+    let path_index = match &files {
+        IndexFiles::Split(base) if use_mmap => load_paths_mmap(&paths_file(base))?,
+        files => files.load_paths()?,
     };
     let paths_load_time = start.elapsed();
 
-    let options = QueryOptions {
-        limit,
-        match_all: !or_mode,
-        path_contains: path,
-        glob_patterns: glob,
-        exclude,
-    };
-
+    // With --mmap the token files are opened as views: only the header is
+    // read up front, bitmaps are decoded on lookup
+    let start = Instant::now();
     let (result, mode_str, tokens_load_time) = if fuzzy {
         // Fuzzy mode (trigrams)
-        let start = Instant::now();
-        let trigram_index = if use_mmap {
-            load_trigram_mmap(&trigram_file(&index_path))?
-        } else {
-            load_trigram(&trigram_file(&index_path))?
-        };
-        let load_time = start.elapsed();
-
-        // Validate index consistency
-        validate_index_match(&path_index.header, &trigram_index.header)?;
-
-        let result = query_fuzzy(&path_index, &trigram_index, &query_str, &options);
-        (result, "fuzzy", load_time)
-    } else if ignore_case {
-        // Case-insensitive exact mode
-        let start = Instant::now();
-        let exact_lower_index = if use_mmap {
-            load_exact_mmap(&exact_lower_file(&index_path))?
+        if use_mmap {
+            let trigram_view = files.load_trigram_view()?;
+            let load_time = start.elapsed();
+            validate_index_match(&path_index.header, &trigram_view.header)?;
+            let result = fuzzy_query(&path_index, &trigram_view, &query_str, &options, boolean)?;
+            (result, "fuzzy", load_time)
         } else {
-            load_exact(&exact_lower_file(&index_path))?
-        };
-        let load_time = start.elapsed();
-
-        // Validate index consistency
-        validate_index_match(&path_index.header, &exact_lower_index.header)?;
-
-        let result = query_exact_lower(&path_index, &exact_lower_index, &query_str, &options);
-        (result, "exact-i", load_time)
+            let trigram_index = files.load_trigram()?;
+            let load_time = start.elapsed();
+            validate_index_match(&path_index.header, &trigram_index.header)?;
+            let result = fuzzy_query(&path_index, &trigram_index, &query_str, &options, boolean)?;
+            (result, "fuzzy", load_time)
+        }
     } else {
-        // Case-sensitive exact mode (default)
-        let start = Instant::now();
-        let exact_index = if use_mmap {
-            load_exact_mmap(&exact_file(&index_path))?
+        // Exact mode; ignore_case uses the lowercase exact index
+        let (section, mode_str) = if ignore_case {
+            (Section::ExactLower, "exact-i")
         } else {
-            load_exact(&exact_file(&index_path))?
+            (Section::Exact, "exact")
         };
-        let load_time = start.elapsed();
-
-        // Validate index consistency
-        validate_index_match(&path_index.header, &exact_index.header)?;
-
-        let result = query_exact(&path_index, &exact_index, &query_str, &options);
-        (result, "exact", load_time)
+        let vocabulary =
+            load_query_vocabulary(&files, &path_index, &query_str, &options, ignore_case)?;
+        let positions = load_query_positions(&files, &path_index, &query_str, ignore_case)?;
+        let frequencies = load_query_frequencies(&files, &path_index, &options, ignore_case)?;
+        if use_mmap {
+            let mut exact_view = files.load_exact_view(section)?;
+            if let Some(vocabulary) = vocabulary {
+                exact_view.set_dictionary(vocabulary);
+            }
+            if let Some(positions) = positions {
+                exact_view.set_positions(positions);
+            }
+            if let Some(frequencies) = frequencies {
+                exact_view.set_frequencies(frequencies);
+            }
+            let load_time = start.elapsed();
+            validate_index_match(&path_index.header, &exact_view.header)?;
+            let result = exact_query(
+                &path_index,
+                &exact_view,
+                &query_str,
+                &options,
+                ignore_case,
+                boolean,
+            )?;
+            (result, mode_str, load_time)
+        } else {
+            let mut exact_index = files.load_exact(section)?;
+            exact_index.set_dictionary(vocabulary);
+            exact_index.set_positions(positions);
+            exact_index.set_frequencies(frequencies);
+            let load_time = start.elapsed();
+            validate_index_match(&path_index.header, &exact_index.header)?;
+            let result = exact_query(
+                &path_index,
+                &exact_index,
+                &query_str,
+                &options,
+                ignore_case,
+                boolean,
+            )?;
+            (result, mode_str, load_time)
+        }
     };
 
     let total_load_time = paths_load_time + tokens_load_time;
 
     println!(
         "Query ({}): \"{}\" ({} tokens, {} matched)",
-        mode_str, query_str, fmt_num(result.query_token_count), fmt_num(result.matched_token_count)
+        mode_str,
+        query_str,
+        fmt_num(result.query_token_count),
+        fmt_num(result.matched_token_count)
     );
     println!(
         "Found {} files in {:.3}ms (load: {:.3}ms)",
@@ -449,19 +1307,472 @@ fn cmd_query(
         0.0, // Query time is fast, not separately tracked
         total_load_time.as_secs_f64() * 1000.0
     );
+    print_expansions(&result.expansions);
+    print_typos(&result.typos);
+    println!();
+
+    print_files(&result.files, &result.match_ratios, &options);
+
+    Ok(())
+}
+
+fn cmd_subword(
+    index_path: PathBuf,
+    query_str: String,
+    use_mmap: bool,
+    options: QueryOptions,
+) -> tokenizer::Result<()> {
+    let files = IndexFiles::open(&index_path)?;
+    if !files.has(Section::Subwords) {
+        return Err(TokenizerError::InvalidIndexFormat(
+            "index has no subword index (.sub); re-index without --no-subwords".to_string(),
+        ));
+    }
+
+    let start = Instant::now();
+    let path_index = match &files {
+        IndexFiles::Split(base) if use_mmap => load_paths_mmap(&paths_file(base))?,
+        files => files.load_paths()?,
+    };
+    let (header, subwords) = files.load_subwords()?;
+    validate_index_match(&path_index.header, &header)?;
+    let load_time = start.elapsed();
+
+    // In-order matching unions the bitmaps of whole tokens
+    let start = Instant::now();
+    let result = if use_mmap {
+        let mut exact_view = files.load_exact_view(Section::Exact)?;
+        validate_index_match(&path_index.header, &exact_view.header)?;
+        exact_view.set_subwords(subwords);
+        query_subword(&path_index, &exact_view, &query_str, &options)?
+    } else {
+        let mut exact_index = files.load_exact(Section::Exact)?;
+        validate_index_match(&path_index.header, &exact_index.header)?;
+        exact_index.set_subwords(Some(subwords));
+        query_subword(&path_index, &exact_index, &query_str, &options)?
+    };
+    let query_time = start.elapsed();
+
+    println!(
+        "Query (subword{}): \"{}\" ({} parts, {} matched)",
+        if options.in_order { ", in order" } else { "" },
+        query_str,
+        fmt_num(result.query_token_count),
+        fmt_num(result.matched_token_count)
+    );
+    println!(
+        "Found {} files in {:.3}ms (load: {:.3}ms)",
+        fmt_num(result.files.len()),
+        query_time.as_secs_f64() * 1000.0,
+        load_time.as_secs_f64() * 1000.0
+    );
+    println!();
+
+    print_files(&result.files, &result.match_ratios, &options);
+
+    Ok(())
+}
+
+fn cmd_normalized(
+    index_path: PathBuf,
+    query_str: String,
+    use_mmap: bool,
+    boolean: bool,
+    options: QueryOptions,
+) -> tokenizer::Result<()> {
+    let files = IndexFiles::open(&index_path)?;
+    if !files.has(Section::ExactNormalized) {
+        return Err(TokenizerError::InvalidIndexFormat(
+            "index has no normalized token index (.exactn); re-index without --no-normalize"
+                .to_string(),
+        ));
+    }
+
+    let start = Instant::now();
+    let path_index = match &files {
+        IndexFiles::Split(base) if use_mmap => load_paths_mmap(&paths_file(base))?,
+        files => files.load_paths()?,
+    };
+    // Positions are only recorded for case-sensitive tokens
+    if has_positional_syntax(&query_str) {
+        eprintln!(
+            "Warning: phrases and NEAR/n can't be matched by position in normalized queries; \
+             matching all of their tokens instead"
+        );
+    }
+    let vocabulary = load_query_vocabulary(&files, &path_index, &query_str, &options, false)?
+        .map(|dictionary| dictionary.normalized());
+
+    let result = if use_mmap {
+        let mut normalized_view = files.load_exact_view(Section::ExactNormalized)?;
+        validate_index_match(&path_index.header, &normalized_view.header)?;
+        if let Some(vocabulary) = vocabulary {
+            normalized_view.set_dictionary(vocabulary);
+        }
+        normalized_query(&path_index, &normalized_view, &query_str, &options, boolean)?
+    } else {
+        let mut normalized_index = files.load_exact(Section::ExactNormalized)?;
+        validate_index_match(&path_index.header, &normalized_index.header)?;
+        normalized_index.set_dictionary(vocabulary);
+        normalized_query(
+            &path_index,
+            &normalized_index,
+            &query_str,
+            &options,
+            boolean,
+        )?
+    };
+    let elapsed = start.elapsed();
+
+    println!(
+        "Query (exact-n): \"{}\" ({} tokens, {} matched)",
+        query_str,
+        fmt_num(result.query_token_count),
+        fmt_num(result.matched_token_count)
+    );
+    println!(
+        "Found {} files in {:.3}ms",
+        fmt_num(result.files.len()),
+        elapsed.as_secs_f64() * 1000.0
+    );
+    print_expansions(&result.expansions);
+    print_typos(&result.typos);
+    println!();
+
+    print_files(&result.files, &result.match_ratios, &options);
+
+    Ok(())
+}
+
+fn cmd_regex(
+    index_path: PathBuf,
+    pattern: String,
+    use_mmap: bool,
+    ignore_case: bool,
+    options: QueryOptions,
+) -> tokenizer::Result<()> {
+    let files = IndexFiles::open(&index_path)?;
+
+    let start = Instant::now();
+    let path_index = match &files {
+        IndexFiles::Split(base) if use_mmap => load_paths_mmap(&paths_file(base))?,
+        files => files.load_paths()?,
+    };
+    let result = if use_mmap {
+        let trigram_view = files.load_trigram_view()?;
+        validate_index_match(&path_index.header, &trigram_view.header)?;
+        query_regex(&path_index, &trigram_view, &pattern, ignore_case, &options)?
+    } else {
+        let trigram_index = files.load_trigram()?;
+        validate_index_match(&path_index.header, &trigram_index.header)?;
+        query_regex(&path_index, &trigram_index, &pattern, ignore_case, &options)?
+    };
+    let elapsed = start.elapsed();
+
+    if result.full_scan {
+        eprintln!(
+            "Warning: regex has no usable trigrams; scanned all {} indexed files",
+            fmt_num(result.candidate_count)
+        );
+    }
+
+    let match_count: usize = result.files.iter().map(|f| f.matches.len()).sum();
+    println!("Query (regex): /{}/", pattern);
+    println!(
+        "Found {} matches in {} files ({} candidates) in {:.3}ms",
+        fmt_num(match_count),
+        fmt_num(result.files.len()),
+        fmt_num(result.candidate_count),
+        elapsed.as_secs_f64() * 1000.0
+    );
     println!();
 
     for file in &result.files {
-        println!("{}", file.display());
+        for m in &file.matches {
+            println!(
+                "{}:{}:{}-{}:{}",
+                file.path.display(),
+                m.line_number,
+                m.start,
+                m.end,
+                m.text.escape_debug()
+            );
+        }
     }
 
     Ok(())
 }
 
-fn cmd_stats(index_path: PathBuf) -> tokenizer::Result<()> {
-    // Check for new split format first
-    if paths_file(&index_path).exists() {
-        let path_index = load_paths(&paths_file(&index_path))?;
+/// Parse a ratio between 0.0 and 1.0
+fn parse_ratio(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(ratio) if (0.0..=1.0).contains(&ratio) => Ok(ratio),
+        _ => Err(format!("\"{}\" is not a ratio between 0.0 and 1.0", s)),
+    }
+}
+
+/// Load the token dictionary when the query has wildcard or regex terms or
+/// looks for typos
+///
+/// Returns None when the query needs no dictionary or the index was built
+/// without a `.dict` file; pattern terms and typo lookups then report the
+/// missing dictionary.
+fn load_query_vocabulary(
+    files: &IndexFiles,
+    path_index: &PathIndex,
+    query_str: &str,
+    options: &QueryOptions,
+    ignore_case: bool,
+) -> tokenizer::Result<Option<TokenDictionary>> {
+    let needed = options.max_typos.is_some() || query_str.contains(['*', '?', '/']);
+    if !needed || !files.has(Section::Dictionary) {
+        return Ok(None);
+    }
+
+    let (header, dictionary) = files.load_dictionary()?;
+    validate_index_match(&path_index.header, &header)?;
+    Ok(Some(if ignore_case {
+        dictionary.lowercased()
+    } else {
+        dictionary
+    }))
+}
+
+/// Load token positions when the query has phrases or `NEAR/n`
+///
+/// Positions are recorded for case-sensitive tokens only. Without them the
+/// query still runs, but phrases and `NEAR/n` only require all their tokens,
+/// so a warning is printed.
+fn load_query_positions(
+    files: &IndexFiles,
+    path_index: &PathIndex,
+    query_str: &str,
+    ignore_case: bool,
+) -> tokenizer::Result<Option<PositionIndex>> {
+    if !has_positional_syntax(query_str) {
+        return Ok(None);
+    }
+
+    if ignore_case || !files.has(Section::Positions) {
+        eprintln!(
+            "Warning: phrases and NEAR/n need token positions (index with --positions, \
+             case-sensitive queries only); matching all of their tokens instead"
+        );
+        return Ok(None);
+    }
+
+    let (header, positions) = files.load_positions()?;
+    validate_index_match(&path_index.header, &header)?;
+    Ok(Some(positions))
+}
+
+/// Load the term frequencies when results are sorted by relevance
+///
+/// Returns None for other orders or when the index was built without
+/// `.tf`/`.tfi` files; results then stay in file ID order.
+fn load_query_frequencies(
+    files: &IndexFiles,
+    path_index: &PathIndex,
+    options: &QueryOptions,
+    ignore_case: bool,
+) -> tokenizer::Result<Option<TermFrequencies>> {
+    let section = if ignore_case {
+        Section::FrequenciesLower
+    } else {
+        Section::Frequencies
+    };
+    if options.sort != SortOrder::Relevance || !files.has(section) {
+        return Ok(None);
+    }
+
+    let (header, frequencies) = files.load_frequencies(section)?;
+    validate_index_match(&path_index.header, &header)?;
+    Ok(Some(frequencies))
+}
+
+/// Print result paths, with each file's trigram match ratio for `--min-match`
+fn print_files(files: &[PathBuf], match_ratios: &[f32], options: &QueryOptions) {
+    if options.min_match.is_some() && match_ratios.len() == files.len() {
+        for (file, ratio) in files.iter().zip(match_ratios) {
+            println!("{:5.1}%  {}", ratio * 100.0, file.display());
+        }
+    } else {
+        for file in files {
+            println!("{}", file.display());
+        }
+    }
+}
+
+/// Print how each wildcard or regex term of a query was expanded
+fn print_expansions(expansions: &[PatternExpansion]) {
+    for expansion in expansions {
+        match &expansion.error {
+            Some(error) => eprintln!(
+                "Warning: pattern {} not expanded: {}",
+                expansion.pattern, error
+            ),
+            None if expansion.truncated => println!(
+                "Pattern {} expanded to {} tokens (truncated; raise --max-expansions)",
+                expansion.pattern,
+                fmt_num(expansion.token_count)
+            ),
+            None => println!(
+                "Pattern {} expanded to {} tokens",
+                expansion.pattern,
+                fmt_num(expansion.token_count)
+            ),
+        }
+    }
+}
+
+/// Print which indexed tokens stood in for query tokens with `--typo`
+fn print_typos(typos: &[TypoExpansion]) {
+    const SHOWN: usize = 5;
+    for typo in typos {
+        let mut substitutes: Vec<String> = typo
+            .substitutes
+            .iter()
+            .take(SHOWN)
+            .map(|token| format!("`{}`", token))
+            .collect();
+        if typo.truncated || typo.substitutes.len() > SHOWN {
+            substitutes.push("...".to_string());
+        }
+        let substitutes = substitutes.join(", ");
+
+        match &typo.error {
+            Some(error) => eprintln!("Warning: no typo lookup for {}: {}", typo.token, error),
+            None if typo.substitutes.is_empty() => {
+                println!("No close match for `{}`", typo.token)
+            }
+            None if typo.indexed => {
+                println!("`{}` also matched {}", typo.token, substitutes)
+            }
+            None => println!("`{}` not found; did you mean {}?", typo.token, substitutes),
+        }
+    }
+}
+
+/// Run a plain or boolean query against the normalized index
+fn normalized_query(
+    path_index: &PathIndex,
+    normalized_index: &impl TokenLookup,
+    query_str: &str,
+    options: &QueryOptions,
+    boolean: bool,
+) -> tokenizer::Result<QueryResult> {
+    if boolean {
+        query_boolean_exact_normalized(path_index, normalized_index, query_str, options)
+    } else {
+        query_exact_normalized(path_index, normalized_index, query_str, options)
+    }
+}
+
+/// Run an exact query against either the case-sensitive or lowercase index
+fn exact_query(
+    path_index: &PathIndex,
+    exact_index: &impl TokenLookup,
+    query_str: &str,
+    options: &QueryOptions,
+    ignore_case: bool,
+    boolean: bool,
+) -> tokenizer::Result<QueryResult> {
+    match (ignore_case, boolean) {
+        (true, true) => query_boolean_exact_lower(path_index, exact_index, query_str, options),
+        (false, true) => query_boolean_exact(path_index, exact_index, query_str, options),
+        (true, false) => query_exact_lower(path_index, exact_index, query_str, options),
+        (false, false) => query_exact(path_index, exact_index, query_str, options),
+    }
+}
+
+/// Run a fuzzy query, optionally parsing it as a boolean expression
+fn fuzzy_query(
+    path_index: &PathIndex,
+    trigram_index: &impl TrigramLookup,
+    query_str: &str,
+    options: &QueryOptions,
+    boolean: bool,
+) -> tokenizer::Result<QueryResult> {
+    if boolean {
+        query_boolean_fuzzy(path_index, trigram_index, query_str, options)
+    } else {
+        query_fuzzy(path_index, trigram_index, query_str, options)
+    }
+}
+
+fn cmd_grep(
+    index_path: PathBuf,
+    query_str: String,
+    mode: LineMatchMode,
+    options: QueryOptions,
+    line_options: LineOptions,
+    count_only: bool,
+) -> tokenizer::Result<()> {
+    // Candidate files come from the token index matching the line mode;
+    // only the header is read up front, bitmaps on lookup
+    let show_separators = line_options.before_context > 0 || line_options.after_context > 0;
+    let files = IndexFiles::open(&index_path)?;
+    let result = grep_index(&files, &query_str, mode, &options, line_options)?;
+
+    let mut printed_group = false;
+    for file in &result.files {
+        if count_only {
+            println!("{}:{}", file.path.display(), file.match_count);
+            continue;
+        }
+
+        let mut last_line = None;
+        for line in &file.lines {
+            let contiguous = last_line.is_some_and(|n| n + 1 == line.line_number);
+            if show_separators && printed_group && !contiguous {
+                println!("--");
+            }
+            if line.is_context() {
+                println!("{}-{}-{}", file.path.display(), line.line_number, line.text);
+            } else {
+                println!(
+                    "{}:{}:{}:{}",
+                    file.path.display(),
+                    line.line_number,
+                    line.columns[0],
+                    line.text
+                );
+            }
+            last_line = Some(line.line_number);
+            printed_group = true;
+        }
+    }
+
+    Ok(())
+}
+
+fn cmd_verify(index_path: PathBuf) -> tokenizer::Result<()> {
+    let report = verify_index(&index_path)?;
+
+    for file in &report.files {
+        if file.is_ok() {
+            println!("ok      {}", file.path.display());
+        }
+        for problem in &file.problems {
+            println!("FAILED  {}: {}", file.path.display(), problem);
+        }
+    }
+
+    if !report.is_ok() {
+        return Err(TokenizerError::Corrupted(format!(
+            "{} problems found; rebuild the index with `tokenizer reindex`",
+            fmt_num(report.problem_count())
+        )));
+    }
+    println!("\nAll {} files intact", fmt_num(report.files.len()));
+    Ok(())
+}
+
+fn cmd_stats(index_path: PathBuf, list_spellings: bool) -> tokenizer::Result<()> {
+    // Check for new split format or a bundle first
+    if paths_file(&index_path).exists() || is_bundle(&index_path) {
+        let files = IndexFiles::open(&index_path)?;
+        let path_index = files.load_paths()?;
 
         println!("Index Statistics (New Format)");
         println!("==============================");
@@ -473,44 +1784,116 @@ fn cmd_stats(index_path: PathBuf) -> tokenizer::Result<()> {
             path_index.header.created_at
         );
         println!("Index ID:      {:02x?}", &path_index.header.index_id[..8]);
+        println!(
+            "Tokenizer:     {}",
+            describe_tokenizer(path_index.header.tokenizer)
+        );
 
         // Load and show token counts
-        if let Ok(exact_index) = load_exact(&exact_file(&index_path)) {
+        if let Ok(exact_index) = files.load_exact_view(Section::Exact) {
             println!("Exact tokens:  {}", fmt_num(exact_index.token_count()));
         }
-        if let Ok(trigram_index) = load_trigram(&trigram_file(&index_path)) {
+        if let Ok(normalized_index) = files.load_exact_view(Section::ExactNormalized) {
+            println!(
+                "Normalized:    {} forms",
+                fmt_num(normalized_index.token_count())
+            );
+        }
+        if let Ok(trigram_index) = files.load_trigram_view() {
             println!("Trigrams:      {}", fmt_num(trigram_index.trigram_count()));
         }
+        if files.has(Section::Dictionary) {
+            let (header, dictionary) = files.load_dictionary()?;
+            validate_index_match(&path_index.header, &header)?;
+            println!(
+                "Dictionary:    {} tokens ({} hash collisions)",
+                fmt_num(dictionary.len()),
+                fmt_num(dictionary.collision_count())
+            );
+            if files.has(Section::ExactNormalized) {
+                print_spellings(&dictionary, list_spellings);
+            }
+        }
+        if files.has(Section::Subwords) {
+            let (header, subwords) = files.load_subwords()?;
+            validate_index_match(&path_index.header, &header)?;
+            println!(
+                "Subwords:      {} parts, {} multi-part tokens",
+                fmt_num(subwords.part_count()),
+                fmt_num(subwords.split_count())
+            );
+        }
+        if files.has(Section::Positions) {
+            let (header, positions) = files.load_positions()?;
+            validate_index_match(&path_index.header, &header)?;
+            println!(
+                "Positions:     {} occurrences in {} files",
+                fmt_num(positions.occurrence_count()),
+                fmt_num(positions.file_count())
+            );
+        }
+        if files.has(Section::Frequencies) {
+            let (header, frequencies) = files.load_frequencies(Section::Frequencies)?;
+            validate_index_match(&path_index.header, &header)?;
+            println!(
+                "Term freqs:    {} files, {:.1} tokens per file on average",
+                fmt_num(frequencies.file_count()),
+                frequencies.average_length()
+            );
+        }
 
         // File sizes
-        let paths_size = std::fs::metadata(paths_file(&index_path))
-            .map(|m| m.len())
-            .unwrap_or(0);
-        let exact_size = std::fs::metadata(exact_file(&index_path))
-            .map(|m| m.len())
-            .unwrap_or(0);
-        let trigram_size = std::fs::metadata(trigram_file(&index_path))
-            .map(|m| m.len())
-            .unwrap_or(0);
+        let paths_size = files.size(Section::Paths);
+        let exact_size = files.size(Section::Exact);
+        let trigram_size = files.size(Section::Trigram);
+        let dict_size = files.size(Section::Dictionary);
+        let sub_size = files.size(Section::Subwords);
+        let normalized_size = files.size(Section::ExactNormalized);
+        let pos_size = files.size(Section::Positions);
+        let tf_size = files.size(Section::Frequencies) + files.size(Section::FrequenciesLower);
 
         println!("\nFile sizes:");
-        println!(
-            "  Paths:   {:.2} MB",
-            paths_size as f64 / (1024.0 * 1024.0)
-        );
-        println!(
-            "  Exact:   {:.2} MB",
-            exact_size as f64 / (1024.0 * 1024.0)
-        );
+        println!("  Paths:   {:.2} MB", paths_size as f64 / (1024.0 * 1024.0));
+        println!("  Exact:   {:.2} MB", exact_size as f64 / (1024.0 * 1024.0));
         println!(
             "  Trigram: {:.2} MB",
             trigram_size as f64 / (1024.0 * 1024.0)
         );
+        println!("  Dict:    {:.2} MB", dict_size as f64 / (1024.0 * 1024.0));
+        println!("  Subword: {:.2} MB", sub_size as f64 / (1024.0 * 1024.0));
+        println!(
+            "  Normal:  {:.2} MB",
+            normalized_size as f64 / (1024.0 * 1024.0)
+        );
+        println!("  Pos:     {:.2} MB", pos_size as f64 / (1024.0 * 1024.0));
+        println!("  Freqs:   {:.2} MB", tf_size as f64 / (1024.0 * 1024.0));
         println!(
             "  Total:   {:.2} MB",
-            (paths_size + exact_size + trigram_size) as f64 / (1024.0 * 1024.0)
+            (paths_size
+                + exact_size
+                + trigram_size
+                + dict_size
+                + sub_size
+                + normalized_size
+                + pos_size
+                + tf_size) as f64
+                / (1024.0 * 1024.0)
         );
 
+        let orphans = orphaned_temp_files(&index_path)?;
+        if !orphans.is_empty() {
+            println!(
+                "\n{} temporary files left by an interrupted build (ignored; safe to delete)",
+                fmt_num(orphans.len())
+            );
+        }
+
+        println!("\nBuild:");
+        match &path_index.build {
+            Some(build) => print_build(build),
+            None => println!("  Not recorded"),
+        }
+
         return Ok(());
     }
 
@@ -524,9 +1907,7 @@ fn cmd_stats(index_path: PathBuf) -> tokenizer::Result<()> {
     let index = load_index(&index_path)?;
     let metadata = index.metadata();
 
-    let file_size = std::fs::metadata(&index_path)
-        .map(|m| m.len())
-        .unwrap_or(0);
+    let file_size = std::fs::metadata(&index_path).map(|m| m.len()).unwrap_or(0);
 
     println!("Index Statistics (Legacy Format)");
     println!("=================================");
@@ -566,7 +1947,8 @@ fn cmd_glob(
 
         println!(
             "Pattern: \"{}\" (scanned {} files)",
-            result.pattern, fmt_num(result.files_scanned)
+            result.pattern,
+            fmt_num(result.files_scanned)
         );
         println!(
             "Found {} files in {:.3}ms (load: {:.3}ms)",
@@ -605,7 +1987,8 @@ fn cmd_glob(
 
     println!(
         "Pattern: \"{}\" (scanned {} files)",
-        result.pattern, fmt_num(result.files_scanned)
+        result.pattern,
+        fmt_num(result.files_scanned)
     );
     println!(
         "Found {} files in {:.3}ms (load: {:.3}ms)",
@@ -621,3 +2004,125 @@ fn cmd_glob(
 
     Ok(())
 }
+
+/// Print how an index was built
+fn print_build(build: &BuildInfo) {
+    let list = |items: &[String], empty: &str| {
+        if items.is_empty() {
+            empty.to_string()
+        } else {
+            items.join(", ")
+        }
+    };
+    let config = &build.config;
+    let enabled: Vec<String> = [
+        (config.hash_contents, "content hashes"),
+        (config.respect_ignore_files, "ignore files"),
+        (config.build_dictionary, "dictionary"),
+        (config.build_positions, "positions"),
+        (config.build_frequencies, "ranking"),
+        (config.build_subwords, "subwords"),
+        (config.build_normalized, "normalized"),
+    ]
+    .into_iter()
+    .filter(|(enabled, _)| *enabled)
+    .map(|(_, name)| name.to_string())
+    .collect();
+    let skipped = &build.skipped;
+
+    println!("  Root:          {}", build.root.display());
+    println!("  Tool version:  {}", build.tool_version);
+    println!(
+        "  Built by:      {}@{}",
+        build.user.as_deref().unwrap_or("unknown"),
+        build.host.as_deref().unwrap_or("unknown")
+    );
+    println!("  Duration:      {:.2}s", build.duration().as_secs_f64());
+    println!("  Extensions:    {}", list(&config.extensions, "all"));
+    println!(
+        "  Excludes:      {}",
+        list(&config.exclude_patterns, "none")
+    );
+    println!("  Include globs: {}", list(&config.include_globs, "none"));
+    println!("  Exclude globs: {}", list(&config.exclude_globs, "none"));
+    println!(
+        "  Max file size: {:.2} MB",
+        config.max_file_size as f64 / (1024.0 * 1024.0)
+    );
+    println!("  Options:       {}", list(&enabled, "none"));
+    println!("  Skipped:       {} files", fmt_num(skipped.total()));
+    println!(
+        "    {} excluded, {} ignored, {} too large, {} binary, {} unreadable",
+        fmt_num(skipped.excluded),
+        fmt_num(skipped.ignored),
+        fmt_num(skipped.too_large),
+        fmt_num(skipped.binary),
+        fmt_num(skipped.unreadable)
+    );
+}
+
+/// Print how many distinct spellings collapse into each normalized form
+///
+/// Without `list_all` only a histogram and the forms with the most spellings
+/// are shown.
+fn print_spellings(dictionary: &TokenDictionary, list_all: bool) {
+    const TOP: usize = 10;
+    let mut forms = dictionary.spellings();
+    let merged = forms
+        .iter()
+        .filter(|(_, spellings)| spellings.len() > 1)
+        .count();
+    println!(
+        "Spellings:     {} normalized forms, {} with more than one spelling",
+        fmt_num(forms.len()),
+        fmt_num(merged)
+    );
+    if merged == 0 {
+        return;
+    }
+
+    let mut histogram: Vec<(usize, usize)> = Vec::new();
+    for (_, spellings) in &forms {
+        match histogram
+            .iter_mut()
+            .find(|(count, _)| *count == spellings.len())
+        {
+            Some((_, forms)) => *forms += 1,
+            None => histogram.push((spellings.len(), 1)),
+        }
+    }
+    histogram.sort_unstable();
+    for (count, form_count) in histogram.iter().filter(|(count, _)| *count > 1) {
+        println!("  {} spellings: {} forms", count, fmt_num(form_count));
+    }
+
+    // Most spellings first, then by form
+    forms.retain(|(_, spellings)| spellings.len() > 1);
+    forms.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then_with(|| a.0.cmp(&b.0)));
+    let shown = if list_all {
+        forms.len()
+    } else {
+        TOP.min(forms.len())
+    };
+    if !list_all {
+        println!("  Most spellings:");
+    }
+    for (form, spellings) in &forms[..shown] {
+        let spellings: Vec<_> = spellings
+            .iter()
+            .map(|s| String::from_utf8_lossy(s))
+            .collect();
+        println!(
+            "    {} ({}): {}",
+            String::from_utf8_lossy(form),
+            spellings.len(),
+            spellings.join(", ")
+        );
+    }
+    if shown < forms.len() {
+        println!(
+            "    ... {} more (--spellings lists all)",
+            fmt_num(forms.len() - shown)
+        );
+    }
+}
@@ -0,0 +1,569 @@
+//! Merge of several indexes into one, behind `tokenizer merge`
+//!
+//! The file slots of each input are appended after those of the inputs
+//! before it, so its file IDs are shifted by the number of slots before it
+//! and every bitmap and per-file companion of the input is shifted the same
+//! way. Nothing is rescanned.
+//!
+//! A path in more than one input is kept once, as `DuplicatePolicy` says;
+//! the other copies become removed slots. Optional files are merged when
+//! every input has them and left out otherwise. The merged index gets a new
+//! header with a fresh index ID and no build record, since no single scan
+//! built it.
+
+use crate::bundle::{IndexFiles, Section};
+use crate::error::{Result, TokenizerError};
+use crate::index::{shift_bitmap, ExactTokenIndex, IndexHeader, IndexSet, PathIndex, TrigramIndex};
+use crate::persistence::save_all;
+use crate::tokenizer::{describe_tokenizer, Tokenizer};
+use roaring::RoaringBitmap;
+use rustc_hash::FxHashMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+/// Which copy of a path found in more than one input to keep
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Fail the merge
+    #[default]
+    Error,
+    /// Keep the copy from the input listed first
+    First,
+    /// Keep the copy from the input listed last
+    Last,
+    /// Keep the copy with the latest modification time, the first on a tie
+    Newest,
+}
+
+impl std::str::FromStr for DuplicatePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(DuplicatePolicy::Error),
+            "first" => Ok(DuplicatePolicy::First),
+            "last" => Ok(DuplicatePolicy::Last),
+            "newest" => Ok(DuplicatePolicy::Newest),
+            _ => Err(format!(
+                "unknown duplicate policy \"{}\" (expected error, first, last or newest)",
+                s
+            )),
+        }
+    }
+}
+
+/// Options for `merge_indexes`
+#[derive(Debug, Clone, Default)]
+pub struct MergeOptions {
+    /// What to do with a path found in more than one input
+    pub duplicates: DuplicatePolicy,
+    /// Tokenizer the inputs were built with, needed when it is not built in
+    pub tokenizer: Option<Arc<dyn Tokenizer>>,
+}
+
+/// What `merge_indexes` found while merging
+#[derive(Debug, Clone, Default)]
+pub struct MergeReport {
+    /// Number of files in the merged index
+    pub file_count: usize,
+    /// Pairs of input roots where the first contains the second
+    pub overlapping_roots: Vec<(PathBuf, PathBuf)>,
+    /// Paths found in more than one input, of which one copy was kept
+    pub duplicates: Vec<PathBuf>,
+    /// Optional files left out because some inputs had them and others not
+    pub dropped: Vec<Section>,
+}
+
+/// Merge the indexes at `inputs`, split or bundled, into a split index at
+/// `output`
+///
+/// Every input is loaded before anything is written, so `output` may be one
+/// of them. Callers that race with builds of the output should hold its
+/// `BuildLock`.
+pub fn merge_indexes<P: AsRef<Path>>(
+    inputs: &[P],
+    output: &Path,
+    options: &MergeOptions,
+) -> Result<MergeReport> {
+    let sets = inputs
+        .iter()
+        .map(|input| {
+            let files = IndexFiles::open(input.as_ref())?;
+            match &options.tokenizer {
+                Some(tokenizer) => files.load_all_with_tokenizer(tokenizer.clone()),
+                None => files.load_all(),
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let (merged, report) = merge_index_sets(sets, options.duplicates)?;
+    save_all(
+        &merged.paths,
+        &merged.exact,
+        &merged.exact_lower,
+        &merged.trigram,
+        output,
+    )?;
+    Ok(report)
+}
+
+/// Merge loaded indexes in memory
+///
+/// Fails if the inputs were built with different tokenizers or would need
+/// more file IDs than fit in a `u32`.
+pub fn merge_index_sets(
+    sets: Vec<IndexSet>,
+    duplicates: DuplicatePolicy,
+) -> Result<(IndexSet, MergeReport)> {
+    let Some(first) = sets.first() else {
+        return Err(TokenizerError::IndexNotFound(
+            "no indexes to merge".to_string(),
+        ));
+    };
+    let tokenizer = first.exact.tokenizer.clone();
+    for set in &sets[1..] {
+        if set.paths.header.tokenizer != first.paths.header.tokenizer {
+            return Err(TokenizerError::TokenizerMismatch(format!(
+                "{} was built with the {} tokenizer, {} with the {} tokenizer",
+                first.paths.root_path.display(),
+                describe_tokenizer(first.paths.header.tokenizer),
+                set.paths.root_path.display(),
+                describe_tokenizer(set.paths.header.tokenizer)
+            )));
+        }
+    }
+
+    let slots: u64 = sets.iter().map(|set| set.paths.slot_count() as u64).sum();
+    if slots > u64::from(u32::MAX) {
+        return Err(TokenizerError::IndexMismatch(format!(
+            "merged index would have {} file slots, more than file IDs can address",
+            slots
+        )));
+    }
+
+    let mut report = MergeReport {
+        overlapping_roots: overlapping_roots(&sets),
+        ..Default::default()
+    };
+    let dropped = resolve_duplicates(&sets, duplicates, &mut report.duplicates)?;
+
+    let roots: Vec<&Path> = sets
+        .iter()
+        .map(|set| set.paths.root_path.as_path())
+        .collect();
+    let header = IndexHeader {
+        tokenizer: first.paths.header.tokenizer,
+        ..IndexHeader::new()
+    };
+    report.dropped = Section::ALL
+        .into_iter()
+        .filter(|section| {
+            let has = |set: &IndexSet| has_section(set, *section);
+            sets.iter().any(has) && !sets.iter().all(has)
+        })
+        .collect();
+
+    let mut merged = Merged::new(&sets, header.clone(), common_root(&roots));
+    for set in sets {
+        merged.append(set);
+    }
+    merged.remove_files(&dropped);
+
+    let mut set = merged.finish();
+    set.set_header(header);
+    set.exact.tokenizer = tokenizer.clone();
+    set.exact_lower.tokenizer = tokenizer;
+    report.file_count = set.paths.file_count();
+    Ok((set, report))
+}
+
+/// Check if a loaded index has the part stored in a section
+fn has_section(set: &IndexSet, section: Section) -> bool {
+    match section {
+        Section::ExactNormalized => set.exact.normalized.is_some(),
+        Section::Dictionary => set.exact.dictionary.is_some(),
+        Section::Positions => set.exact.positions.is_some(),
+        Section::Frequencies => set.exact.frequencies.is_some(),
+        Section::FrequenciesLower => set.exact_lower.frequencies.is_some(),
+        Section::Subwords => set.exact.subwords.is_some(),
+        Section::Paths | Section::Exact | Section::ExactLower | Section::Trigram => true,
+    }
+}
+
+/// Find the pairs of inputs where one root contains the other
+fn overlapping_roots(sets: &[IndexSet]) -> Vec<(PathBuf, PathBuf)> {
+    let mut overlaps = Vec::new();
+    for (i, a) in sets.iter().enumerate() {
+        for b in &sets[i + 1..] {
+            let (a, b) = (&a.paths.root_path, &b.paths.root_path);
+            if b.starts_with(a) {
+                overlaps.push((a.clone(), b.clone()));
+            } else if a.starts_with(b) {
+                overlaps.push((b.clone(), a.clone()));
+            }
+        }
+    }
+    overlaps
+}
+
+/// Pick one copy of every path found in more than one input
+///
+/// Returns the merged file IDs of the copies to drop, and lists the paths
+/// in `duplicates`.
+fn resolve_duplicates(
+    sets: &[IndexSet],
+    policy: DuplicatePolicy,
+    duplicates: &mut Vec<PathBuf>,
+) -> Result<RoaringBitmap> {
+    // Path -> merged file ID and modification time of the copy kept so far
+    let mut kept: FxHashMap<PathBuf, (u32, u64)> = FxHashMap::default();
+    let mut dropped = RoaringBitmap::new();
+    let mut offset = 0u32;
+    for set in sets {
+        for (file_id, path) in set.paths.iter_files() {
+            let modified = set.paths.file_meta(file_id).map_or(0, |meta| meta.modified);
+            let copy = (offset + file_id, modified);
+            let Some(existing) = kept.get_mut(&path) else {
+                kept.insert(path, copy);
+                continue;
+            };
+            let keep_new = match policy {
+                DuplicatePolicy::Error => {
+                    return Err(TokenizerError::IndexMismatch(format!(
+                        "{} is in more than one index; choose which copy to keep",
+                        path.display()
+                    )));
+                }
+                DuplicatePolicy::First => false,
+                DuplicatePolicy::Last => true,
+                DuplicatePolicy::Newest => copy.1 > existing.1,
+            };
+            if keep_new {
+                dropped.insert(existing.0);
+                *existing = copy;
+            } else {
+                dropped.insert(copy.0);
+            }
+            duplicates.push(path);
+        }
+        offset += set.paths.slot_count() as u32;
+    }
+    duplicates.sort();
+    duplicates.dedup();
+    Ok(dropped)
+}
+
+/// Deepest directory containing every root, or an empty path if they share
+/// none
+fn common_root(roots: &[&Path]) -> PathBuf {
+    let mut common: Vec<Component> = roots[0].components().collect();
+    for root in &roots[1..] {
+        let shared = common
+            .iter()
+            .zip(root.components())
+            .take_while(|(a, b)| **a == *b)
+            .count();
+        common.truncate(shared);
+    }
+    common.into_iter().collect()
+}
+
+/// The merged index while inputs are appended
+struct Merged {
+    paths: PathIndex,
+    exact: ExactTokenIndex,
+    exact_lower: ExactTokenIndex,
+    normalized: Option<ExactTokenIndex>,
+    trigram: TrigramIndex,
+}
+
+impl Merged {
+    /// Start an empty index with the optional parts every input has
+    fn new(sets: &[IndexSet], header: IndexHeader, root: PathBuf) -> Self {
+        let all = |section| sets.iter().all(|set| has_section(set, section));
+
+        let mut exact = ExactTokenIndex::new(header.clone());
+        let mut exact_lower = ExactTokenIndex::new(header.clone());
+        if all(Section::Dictionary) {
+            exact.dictionary = Some(Default::default());
+        }
+        if all(Section::Positions) {
+            exact.positions = Some(Default::default());
+        }
+        if all(Section::Subwords) {
+            exact.subwords = Some(Default::default());
+        }
+        if all(Section::Frequencies) {
+            exact.frequencies = Some(Default::default());
+        }
+        if all(Section::FrequenciesLower) {
+            exact_lower.frequencies = Some(Default::default());
+        }
+        let normalized =
+            all(Section::ExactNormalized).then(|| ExactTokenIndex::new(header.clone()));
+
+        Self {
+            paths: PathIndex::new(header.clone(), root),
+            exact,
+            exact_lower,
+            normalized,
+            trigram: TrigramIndex::new(header),
+        }
+    }
+
+    /// Append an input after the ones before it
+    fn append(&mut self, set: IndexSet) {
+        let offset = self.paths.append(&set.paths);
+        let IndexSet {
+            mut exact,
+            mut exact_lower,
+            trigram,
+            ..
+        } = set;
+
+        append_bitmaps(&mut self.exact.token_map, &exact.token_map, offset);
+        append_bitmaps(
+            &mut self.exact_lower.token_map,
+            &exact_lower.token_map,
+            offset,
+        );
+        append_bitmaps(&mut self.trigram.trigram_map, &trigram.trigram_map, offset);
+        if let (Some(merged), Some(normalized)) = (&mut self.normalized, &exact.normalized) {
+            append_bitmaps(&mut merged.token_map, &normalized.token_map, offset);
+        }
+
+        if let (Some(merged), Some(dictionary)) = (&mut self.exact.dictionary, &exact.dictionary) {
+            merged.extend(dictionary);
+        }
+        if let (Some(merged), Some(positions)) = (&mut self.exact.positions, exact.positions.take())
+        {
+            merged.append(positions, offset);
+        }
+        if let (Some(merged), Some(subwords)) = (&mut self.exact.subwords, exact.subwords.take()) {
+            merged.append(subwords, offset);
+        }
+        for (merged, index) in [
+            (&mut self.exact.frequencies, &mut exact),
+            (&mut self.exact_lower.frequencies, &mut exact_lower),
+        ] {
+            if let (Some(merged), Some(frequencies)) = (merged, index.frequencies.take()) {
+                merged.append(frequencies, offset);
+            }
+        }
+    }
+
+    /// Remove files from the paths and every index
+    fn remove_files(&mut self, file_ids: &RoaringBitmap) {
+        if file_ids.is_empty() {
+            return;
+        }
+        for file_id in file_ids {
+            self.paths.remove_file(file_id);
+        }
+        remove_bitmaps(&mut self.exact.token_map, file_ids);
+        remove_bitmaps(&mut self.exact_lower.token_map, file_ids);
+        remove_bitmaps(&mut self.trigram.trigram_map, file_ids);
+        if let Some(normalized) = self.normalized.as_mut() {
+            remove_bitmaps(&mut normalized.token_map, file_ids);
+        }
+        if let Some(positions) = self.exact.positions.as_mut() {
+            positions.remove_files(file_ids);
+        }
+        if let Some(subwords) = self.exact.subwords.as_mut() {
+            subwords.remove_files(file_ids);
+        }
+        for index in [&mut self.exact, &mut self.exact_lower] {
+            if let Some(frequencies) = index.frequencies.as_mut() {
+                frequencies.remove_files(file_ids);
+            }
+        }
+    }
+
+    /// Attach the derived dictionaries and the normalized index, as loading
+    /// the merged index would
+    fn finish(self) -> IndexSet {
+        let Merged {
+            paths,
+            mut exact,
+            mut exact_lower,
+            normalized,
+            trigram,
+        } = self;
+        exact_lower.dictionary = exact.dictionary.as_ref().map(|d| d.lowercased());
+        exact.normalized = normalized.map(|mut normalized| {
+            normalized.dictionary = exact.dictionary.as_ref().map(|d| d.normalized());
+            Box::new(normalized)
+        });
+        IndexSet {
+            paths,
+            exact,
+            exact_lower,
+            trigram,
+        }
+    }
+}
+
+fn append_bitmaps(
+    merged: &mut FxHashMap<u64, RoaringBitmap>,
+    from: &FxHashMap<u64, RoaringBitmap>,
+    offset: u32,
+) {
+    merged.reserve(from.len());
+    for (key, bitmap) in from {
+        *merged.entry(*key).or_default() |= shift_bitmap(bitmap, offset);
+    }
+}
+
+fn remove_bitmaps(map: &mut FxHashMap<u64, RoaringBitmap>, file_ids: &RoaringBitmap) {
+    map.retain(|_, bitmap| {
+        *bitmap -= file_ids;
+        !bitmap.is_empty()
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::{load_all, pos_file};
+    use crate::query::{query_exact, query_exact_lower, query_fuzzy, QueryOptions};
+    use crate::scanner::{scan_and_build_indexes, ScanConfig};
+    use crate::verify::verify_index;
+    use tempfile::tempdir;
+
+    fn build(dir: &Path, base: &Path, files: &[(&str, &str)], positions: bool) {
+        for (name, content) in files {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+        let config = ScanConfig {
+            build_positions: positions,
+            ..Default::default()
+        };
+        let (paths, exact, exact_lower, trigram) = scan_and_build_indexes(dir, &config).unwrap();
+        save_all(&paths, &exact, &exact_lower, &trigram, base).unwrap();
+    }
+
+    fn names(files: &[PathBuf]) -> Vec<String> {
+        let mut names: Vec<String> = files
+            .iter()
+            .map(|file| file.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_merge_disjoint_indexes() {
+        let src = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        let (a, b, all) = (
+            out.path().join("a.tkix"),
+            out.path().join("b.tkix"),
+            out.path().join("all.tkix"),
+        );
+        build(
+            &src.path().join("alpha"),
+            &a,
+            &[
+                ("lib.rs", "fn parse_header() {}"),
+                ("sub/x.rs", "let Shared = 1;"),
+            ],
+            true,
+        );
+        build(
+            &src.path().join("beta"),
+            &b,
+            &[
+                ("main.rs", "fn parse_body() {}"),
+                ("y.rs", "let shared = 2;"),
+            ],
+            false,
+        );
+
+        let report = merge_indexes(&[&a, &b], &all, &MergeOptions::default()).unwrap();
+        assert_eq!(report.file_count, 4);
+        assert!(report.overlapping_roots.is_empty());
+        assert!(report.duplicates.is_empty());
+        assert_eq!(report.dropped, [Section::Positions]);
+        assert!(verify_index(&all).unwrap().is_ok());
+        assert!(!pos_file(&all).exists());
+
+        let merged = load_all(&all).unwrap();
+        let first = load_all(&a).unwrap();
+        assert_ne!(merged.paths.header.index_id, first.paths.header.index_id);
+        assert!(merged.paths.build.is_none());
+        assert_eq!(
+            merged.paths.root_path,
+            first.paths.root_path.parent().unwrap()
+        );
+
+        let options = QueryOptions::default();
+        let result = query_exact(&merged.paths, &merged.exact, "parse_body", &options).unwrap();
+        assert_eq!(names(&result.files), ["main.rs"]);
+        let result =
+            query_exact_lower(&merged.paths, &merged.exact_lower, "SHARED", &options).unwrap();
+        assert_eq!(names(&result.files), ["x.rs", "y.rs"]);
+        let result = query_fuzzy(&merged.paths, &merged.trigram, "parse_", &options).unwrap();
+        assert_eq!(names(&result.files), ["lib.rs", "main.rs"]);
+        let dictionary = merged.exact.dictionary().unwrap();
+        assert!(dictionary.iter().any(|(token, _)| token == b"parse_header"));
+        assert!(dictionary.iter().any(|(token, _)| token == b"parse_body"));
+        assert_eq!(merged.exact.frequencies().unwrap().file_count(), 4);
+    }
+
+    #[test]
+    fn test_merge_overlapping_roots_and_duplicates() {
+        let src = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        let (outer, inner, all) = (
+            out.path().join("outer.tkix"),
+            out.path().join("inner.tkix"),
+            out.path().join("all.tkix"),
+        );
+        build(
+            src.path(),
+            &outer,
+            &[("top.rs", "fn top() {}"), ("lib/a.rs", "old_name")],
+            false,
+        );
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        build(
+            &src.path().join("lib"),
+            &inner,
+            &[("a.rs", "new_name")],
+            false,
+        );
+
+        let result = merge_indexes(&[&outer, &inner], &all, &MergeOptions::default());
+        assert!(matches!(result, Err(TokenizerError::IndexMismatch(_))));
+        assert!(!all.with_extension("paths").exists());
+
+        let options = QueryOptions::default();
+        for (policy, kept) in [
+            (DuplicatePolicy::First, "old_name"),
+            (DuplicatePolicy::Last, "new_name"),
+            (DuplicatePolicy::Newest, "new_name"),
+        ] {
+            let options_for = MergeOptions {
+                duplicates: policy,
+                ..Default::default()
+            };
+            let report = merge_indexes(&[&outer, &inner], &all, &options_for).unwrap();
+            assert_eq!(report.overlapping_roots.len(), 1);
+            assert_eq!(report.duplicates.len(), 1);
+            assert!(report.duplicates[0].ends_with("lib/a.rs"));
+            assert_eq!(report.file_count, 2);
+            assert!(verify_index(&all).unwrap().is_ok());
+
+            let merged = load_all(&all).unwrap();
+            assert_eq!(merged.paths.file_count(), 2);
+            assert_eq!(merged.paths.directory_count(), 2);
+            for token in ["old_name", "new_name"] {
+                let result = query_exact(&merged.paths, &merged.exact, token, &options).unwrap();
+                let expected: &[&str] = if token == kept { &["a.rs"] } else { &[] };
+                assert_eq!(names(&result.files), expected, "{:?} {}", policy, token);
+            }
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! Detection of indexes written by older format versions, and their
+//! rebuild, behind `tokenizer migrate`
+//!
+//! Split indexes from before format version 4 tokenize, hash and lay out
+//! their files differently, and legacy single-file indexes have no exact or
+//! trigram indexes at all. Neither can be converted: they can only be
+//! rebuilt from the directory they were built from, which both record.
+
+use crate::bundle::{is_bundle, Bundle};
+use crate::error::{Result, TokenizerError};
+use crate::index::FORMAT_VERSION;
+use crate::persistence::{load_index_metadata, paths_file, pos_file, MAGIC_LEGACY, MAGIC_PATHS};
+use crate::scanner::ScanConfig;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// What `migrate_index` found
+#[derive(Debug)]
+pub enum Migration {
+    /// The index already has the current format version
+    UpToDate,
+    /// The index was written by an older version and has to be rebuilt
+    RebuildRequired(RebuildPlan),
+}
+
+/// Why an index has to be rebuilt, and what to rebuild it from
+#[derive(Debug, Clone)]
+pub struct RebuildPlan {
+    /// Why the index can't be used as it is
+    pub reason: String,
+    /// Root directory the index was built from, if it could be read
+    pub root: Option<PathBuf>,
+    /// Scan configuration to rebuild with: the defaults, keeping positions
+    /// if the index had them
+    pub config: ScanConfig,
+    /// Whether the index is a legacy single-file index; rebuilding writes
+    /// split files next to it and leaves the legacy file to the caller
+    pub legacy: bool,
+}
+
+/// Check whether the index at `base` has the current format version
+///
+/// An index from an older version is left alone and reported with a
+/// `RebuildPlan`; nothing is written.
+pub fn migrate_index(base: &Path) -> Result<Migration> {
+    if is_bundle(base) {
+        // Bundles were introduced with the current format
+        Bundle::open(base)?;
+        return Ok(Migration::UpToDate);
+    }
+
+    let paths_path = paths_file(base);
+    if !paths_path.exists() {
+        if is_legacy_index(base) {
+            return Ok(Migration::RebuildRequired(legacy_plan(base)));
+        }
+        return Err(TokenizerError::IndexNotFound(base.display().to_string()));
+    }
+
+    let data = std::fs::read(&paths_path).map_err(|e| TokenizerError::Io(e.to_string()))?;
+    let version = paths_version(&data, &paths_path)?;
+    match version {
+        FORMAT_VERSION => Ok(Migration::UpToDate),
+        version if version > FORMAT_VERSION => Err(TokenizerError::InvalidIndexFormat(format!(
+            "{} has format version {}, newer than this build of tokenizer supports ({})",
+            paths_path.display(),
+            version,
+            FORMAT_VERSION
+        ))),
+        version => {
+            // Every older paths file starts with this header and the root
+            let root = bincode::serde::decode_from_slice::<(HeaderV3, PathBuf), _>(
+                &data[4..],
+                bincode::config::standard(),
+            )
+            .ok()
+            .map(|((_, root), _)| root);
+            Ok(Migration::RebuildRequired(RebuildPlan {
+                reason: format!(
+                    "format version {} indexes can't be converted to version {}, only rebuilt",
+                    version, FORMAT_VERSION
+                ),
+                root,
+                config: ScanConfig {
+                    build_positions: pos_file(base).exists(),
+                    ..Default::default()
+                },
+                legacy: false,
+            }))
+        }
+    }
+}
+
+fn is_legacy_index(base: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    File::open(base)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok_and(|_| &magic == MAGIC_LEGACY)
+}
+
+fn legacy_plan(base: &Path) -> RebuildPlan {
+    RebuildPlan {
+        reason: "legacy single-file indexes have no exact or trigram indexes".to_string(),
+        root: load_index_metadata(base)
+            .ok()
+            .map(|metadata| metadata.root_path),
+        config: ScanConfig::default(),
+        legacy: true,
+    }
+}
+
+/// Read the format version at the start of a paths file of any version
+fn paths_version(data: &[u8], path: &Path) -> Result<u16> {
+    if data.get(..4) != Some(&MAGIC_PATHS[..]) {
+        return Err(TokenizerError::InvalidIndexFormat(format!(
+            "Invalid magic bytes for {}",
+            path.display()
+        )));
+    }
+    bincode::serde::decode_from_slice::<u16, _>(&data[4..], bincode::config::standard())
+        .map(|(version, _)| version)
+        .map_err(|e| TokenizerError::Serialization(e.to_string()))
+}
+
+/// Header of every file before version 4, which had no tokenizer field
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct HeaderV3 {
+    version: u16,
+    index_id: [u8; 16],
+    created_at: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::{save_index, save_sample_index, LEGACY_V2_INDEX};
+    use crate::scanner::scan_and_index;
+    use tempfile::tempdir;
+
+    /// The paths file of a split index of `src`, as the first release wrote it
+    const SPLIT_V3_PATHS: &[u8] = &[
+        0x54, 0x4b, 0x49, 0x50, 0x03, 0xf2, 0xa4, 0x74, 0x64, 0x9e, 0x2c, 0xdf, 0x18, 0x12, 0x39,
+        0x00, 0x00, 0xe0, 0x6c, 0x2c, 0x83, 0xfc, 0x2b, 0xce, 0xd2, 0x6a, 0x03, 0x73, 0x72, 0x63,
+        0x01, 0x03, 0x73, 0x72, 0x63, 0x02, 0x00, 0x05, 0x62, 0x2e, 0x74, 0x78, 0x74, 0x00, 0x04,
+        0x61, 0x2e, 0x72, 0x73,
+    ];
+
+    #[test]
+    fn test_current_index_up_to_date() {
+        let src = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        let base = out.path().join("index.tkix");
+        save_sample_index(src.path(), &base);
+        assert!(matches!(migrate_index(&base).unwrap(), Migration::UpToDate));
+    }
+
+    #[test]
+    fn test_rebuild_required() {
+        let out = tempdir().unwrap();
+        let base = out.path().join("split.tkix");
+        std::fs::write(paths_file(&base), SPLIT_V3_PATHS).unwrap();
+        let Migration::RebuildRequired(plan) = migrate_index(&base).unwrap() else {
+            panic!("version 3 index reported up to date");
+        };
+        assert!(plan.reason.contains("format version 3"), "{}", plan.reason);
+        assert_eq!(plan.root, Some(PathBuf::from("src")));
+        assert!(!plan.config.build_positions);
+        assert!(!plan.legacy);
+
+        let legacy = out.path().join("legacy.tkix");
+        std::fs::write(&legacy, LEGACY_V2_INDEX).unwrap();
+        let Migration::RebuildRequired(plan) = migrate_index(&legacy).unwrap() else {
+            panic!("legacy index reported up to date");
+        };
+        assert!(plan.legacy);
+        assert!(plan.reason.contains("no exact or trigram"));
+        assert_eq!(plan.root, Some(PathBuf::from("src")));
+
+        let src = tempdir().unwrap();
+        std::fs::write(src.path().join("a.rs"), "fn main() {}").unwrap();
+        let index = scan_and_index(src.path(), &ScanConfig::default()).unwrap();
+        save_index(&index, &legacy).unwrap();
+        let Migration::RebuildRequired(plan) = migrate_index(&legacy).unwrap() else {
+            panic!("legacy index reported up to date");
+        };
+        assert_eq!(plan.root.as_ref(), Some(&index.metadata().root_path));
+
+        assert!(matches!(
+            migrate_index(&out.path().join("missing.tkix")),
+            Err(TokenizerError::IndexNotFound(_))
+        ));
+    }
+}
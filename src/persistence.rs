@@ -1,77 +1,62 @@
+use crate::atomic::{write_atomic, StagedWrites};
+use crate::bundle::{IndexFiles, Section};
+use crate::checksum::{verify_trailer, ChecksumWriter};
+use crate::dictionary::{decode_dictionary, write_dictionary, TokenDictionary, MAGIC_DICT};
 use crate::error::{Result, TokenizerError};
 use crate::index::{
-    ExactTokenIndex, IndexHeader, PathIndex, TokenIndex, TrigramIndex, FORMAT_VERSION,
+    ExactTokenIndex, IndexHeader, IndexMetadata, IndexSet, PathIndex, TokenIndex, TrigramIndex,
+    FORMAT_VERSION,
 };
+use crate::positions::{decode_positions, write_positions, PositionIndex, MAGIC_POS};
+use crate::ranking::{decode_frequencies, write_frequencies, TermFrequencies, MAGIC_TF};
+use crate::subword::{decode_subwords, write_subwords, SubwordIndex, MAGIC_SUB};
+use crate::table::{
+    parse_header as parse_table_header, write_table, BitmapTable, HEADER_LEN as TABLE_HEADER_LEN,
+};
+use crate::tokenizer::Tokenizer;
+use crate::view::{ExactTokenView, TrigramView};
 use memmap2::Mmap;
 use std::fs::File;
-use std::io::{BufReader, BufWriter, Read, Write};
+use std::io::{BufReader, Read, Write};
+use std::ops::Range;
 use std::path::Path;
+use std::sync::Arc;
 
 // ============================================================================
 // Legacy single-file index (for backward compatibility during transition)
 // ============================================================================
 
 /// Legacy magic bytes
-const MAGIC_LEGACY: &[u8; 4] = b"TKIX";
+pub(crate) const MAGIC_LEGACY: &[u8; 4] = b"TKIX";
 
 /// Save legacy index to disk (DEPRECATED)
 pub fn save_index(index: &TokenIndex, path: &Path) -> Result<()> {
-    let file = File::create(path).map_err(|e| TokenizerError::Io(e.to_string()))?;
-    let mut writer = BufWriter::new(file);
-
-    writer
-        .write_all(MAGIC_LEGACY)
-        .map_err(|e| TokenizerError::Io(e.to_string()))?;
-
-    let config = bincode::config::standard();
-    let encoded = bincode::serde::encode_to_vec(index, config)
-        .map_err(|e| TokenizerError::Serialization(e.to_string()))?;
-
-    writer
-        .write_all(&encoded)
-        .map_err(|e| TokenizerError::Io(e.to_string()))?;
-
-    writer
-        .flush()
-        .map_err(|e| TokenizerError::Io(e.to_string()))?;
-
-    Ok(())
+    write_atomic(path, |writer| {
+        writer
+            .write_all(MAGIC_LEGACY)
+            .map_err(|e| TokenizerError::Io(e.to_string()))?;
+
+        let config = bincode::config::standard();
+        let encoded = bincode::serde::encode_to_vec(index, config)
+            .map_err(|e| TokenizerError::Serialization(e.to_string()))?;
+
+        writer
+            .write_all(&encoded)
+            .map_err(|e| TokenizerError::Io(e.to_string()))
+    })
 }
 
 /// Load legacy index from disk (DEPRECATED)
 pub fn load_index(path: &Path) -> Result<TokenIndex> {
     let file = File::open(path).map_err(|e| TokenizerError::Io(e.to_string()))?;
     let mut reader = BufReader::new(file);
-
-    let mut magic = [0u8; 4];
-    reader
-        .read_exact(&mut magic)
-        .map_err(|e| TokenizerError::Io(e.to_string()))?;
-
-    if &magic != MAGIC_LEGACY {
-        return Err(TokenizerError::InvalidIndexFormat(
-            "Invalid magic bytes".to_string(),
-        ));
-    }
-
     let mut data = Vec::new();
     reader
         .read_to_end(&mut data)
         .map_err(|e| TokenizerError::Io(e.to_string()))?;
 
-    let config = bincode::config::standard();
-    let (mut index, _): (TokenIndex, _) = bincode::serde::decode_from_slice(&data, config)
-        .map_err(|e| TokenizerError::Serialization(e.to_string()))?;
-
-    if index.metadata().version != TokenIndex::CURRENT_VERSION {
-        return Err(TokenizerError::InvalidIndexFormat(format!(
-            "Index version mismatch: expected {}, got {}",
-            TokenIndex::CURRENT_VERSION,
-            index.metadata().version
-        )));
-    }
-
-    index.rebuild_dir_lookup();
+    let index = decode_index(&data)?;
+    check_index_version(&index)?;
     Ok(index)
 }
 
@@ -80,16 +65,33 @@ pub fn load_index_mmap(path: &Path) -> Result<TokenIndex> {
     let file = File::open(path).map_err(|e| TokenizerError::Io(e.to_string()))?;
     let mmap = unsafe { Mmap::map(&file).map_err(|e| TokenizerError::Io(e.to_string()))? };
 
-    if mmap.len() < 4 || &mmap[..4] != MAGIC_LEGACY {
+    let index = decode_index(&mmap)?;
+    check_index_version(&index)?;
+    Ok(index)
+}
+
+/// Read the metadata of a legacy index of any version
+pub(crate) fn load_index_metadata(path: &Path) -> Result<IndexMetadata> {
+    let data = std::fs::read(path).map_err(|e| TokenizerError::Io(e.to_string()))?;
+    decode_index(&data).map(|index| index.metadata().clone())
+}
+
+/// Decode a legacy index file without checking its version
+fn decode_index(data: &[u8]) -> Result<TokenIndex> {
+    if data.len() < 4 || &data[..4] != MAGIC_LEGACY {
         return Err(TokenizerError::InvalidIndexFormat(
             "Invalid magic bytes".to_string(),
         ));
     }
 
     let config = bincode::config::standard();
-    let (mut index, _): (TokenIndex, _) = bincode::serde::decode_from_slice(&mmap[4..], config)
+    let (mut index, _): (TokenIndex, _) = bincode::serde::decode_from_slice(&data[4..], config)
         .map_err(|e| TokenizerError::Serialization(e.to_string()))?;
+    index.rebuild_dir_lookup();
+    Ok(index)
+}
 
+fn check_index_version(index: &TokenIndex) -> Result<()> {
     if index.metadata().version != TokenIndex::CURRENT_VERSION {
         return Err(TokenizerError::InvalidIndexFormat(format!(
             "Index version mismatch: expected {}, got {}",
@@ -97,9 +99,7 @@ pub fn load_index_mmap(path: &Path) -> Result<TokenIndex> {
             index.metadata().version
         )));
     }
-
-    index.rebuild_dir_lookup();
-    Ok(index)
+    Ok(())
 }
 
 /// Magic bytes for each file type
@@ -111,7 +111,14 @@ pub const MAGIC_TRIGRAM: &[u8; 4] = b"TKIT";
 pub const EXT_PATHS: &str = "paths";
 pub const EXT_EXACT: &str = "exact";
 pub const EXT_EXACT_LOWER: &str = "exacti";
+pub const EXT_EXACT_NORMALIZED: &str = "exactn";
 pub const EXT_TRIGRAM: &str = "tri";
+pub const EXT_DICT: &str = "dict";
+pub const EXT_POS: &str = "pos";
+pub const EXT_TF: &str = "tf";
+pub const EXT_TF_LOWER: &str = "tfi";
+pub const EXT_SUB: &str = "sub";
+pub const EXT_LOCK: &str = "lock";
 
 /// Get the paths file path from base path
 pub fn paths_file(base: &Path) -> std::path::PathBuf {
@@ -128,19 +135,57 @@ pub fn exact_lower_file(base: &Path) -> std::path::PathBuf {
     base.with_extension(EXT_EXACT_LOWER)
 }
 
+/// Get the normalized exact tokens file path from base path
+pub fn exact_normalized_file(base: &Path) -> std::path::PathBuf {
+    base.with_extension(EXT_EXACT_NORMALIZED)
+}
+
 /// Get the trigram file path from base path
 pub fn trigram_file(base: &Path) -> std::path::PathBuf {
     base.with_extension(EXT_TRIGRAM)
 }
 
+/// Get the token dictionary file path from base path
+pub fn dict_file(base: &Path) -> std::path::PathBuf {
+    base.with_extension(EXT_DICT)
+}
+
+/// Get the token positions file path from base path
+pub fn pos_file(base: &Path) -> std::path::PathBuf {
+    base.with_extension(EXT_POS)
+}
+
+/// Get the term frequency file path from base path
+pub fn tf_file(base: &Path) -> std::path::PathBuf {
+    base.with_extension(EXT_TF)
+}
+
+/// Get the case-insensitive term frequency file path from base path
+pub fn tf_lower_file(base: &Path) -> std::path::PathBuf {
+    base.with_extension(EXT_TF_LOWER)
+}
+
+/// Get the subword index file path from base path
+pub fn sub_file(base: &Path) -> std::path::PathBuf {
+    base.with_extension(EXT_SUB)
+}
+
+/// Get the build lock file path from base path
+pub fn lock_file(base: &Path) -> std::path::PathBuf {
+    base.with_extension(EXT_LOCK)
+}
+
 // ============================================================================
 // Save functions
 // ============================================================================
 
 /// Save path index to disk
 pub fn save_paths(index: &PathIndex, path: &Path) -> Result<()> {
-    let file = File::create(path).map_err(|e| TokenizerError::Io(e.to_string()))?;
-    let mut writer = BufWriter::new(file);
+    write_atomic(path, |writer| write_paths_file(writer, index))
+}
+
+fn write_paths_file(writer: &mut impl Write, index: &PathIndex) -> Result<()> {
+    let mut writer = ChecksumWriter::new(writer);
 
     // Write magic bytes
     writer
@@ -154,64 +199,125 @@ pub fn save_paths(index: &PathIndex, path: &Path) -> Result<()> {
 
     writer
         .write_all(&encoded)
-        .map_err(|e| TokenizerError::Io(e.to_string()))?;
-
-    writer
-        .flush()
-        .map_err(|e| TokenizerError::Io(e.to_string()))?;
-
-    Ok(())
+        .and_then(|_| writer.finish())
+        .map(drop)
+        .map_err(|e| TokenizerError::Io(e.to_string()))
 }
 
 /// Save exact token index to disk
 pub fn save_exact(index: &ExactTokenIndex, path: &Path) -> Result<()> {
-    let file = File::create(path).map_err(|e| TokenizerError::Io(e.to_string()))?;
-    let mut writer = BufWriter::new(file);
+    write_atomic(path, |writer| write_exact_file(writer, index))
+}
 
-    writer
-        .write_all(MAGIC_EXACT)
-        .map_err(|e| TokenizerError::Io(e.to_string()))?;
+fn write_exact_file(writer: &mut impl Write, index: &ExactTokenIndex) -> Result<()> {
+    let entries = index.token_map.iter().map(|(k, v)| (*k, v)).collect();
+    write_table(writer, MAGIC_EXACT, &index.header, entries)
+        .map_err(|e| TokenizerError::Io(e.to_string()))
+}
 
-    let config = bincode::config::standard();
-    let encoded = bincode::serde::encode_to_vec(index, config)
-        .map_err(|e| TokenizerError::Serialization(e.to_string()))?;
+/// Save trigram index to disk
+pub fn save_trigram(index: &TrigramIndex, path: &Path) -> Result<()> {
+    write_atomic(path, |writer| write_trigram_file(writer, index))
+}
 
-    writer
-        .write_all(&encoded)
-        .map_err(|e| TokenizerError::Io(e.to_string()))?;
+fn write_trigram_file(writer: &mut impl Write, index: &TrigramIndex) -> Result<()> {
+    let entries = index.trigram_map.iter().map(|(k, v)| (*k, v)).collect();
+    write_table(writer, MAGIC_TRIGRAM, &index.header, entries)
+        .map_err(|e| TokenizerError::Io(e.to_string()))
+}
 
-    writer
-        .flush()
-        .map_err(|e| TokenizerError::Io(e.to_string()))?;
+/// Save the token dictionary of an exact index to disk
+///
+/// Only tokens still present in the index are written. Fails if the index
+/// has no dictionary.
+pub fn save_dictionary(index: &ExactTokenIndex, path: &Path) -> Result<()> {
+    write_atomic(path, |writer| write_dictionary_file(writer, index))
+}
 
-    Ok(())
+fn write_dictionary_file(writer: &mut impl Write, index: &ExactTokenIndex) -> Result<()> {
+    let dictionary = index.dictionary.as_ref().ok_or_else(|| {
+        TokenizerError::InvalidIndexFormat("Exact index has no token dictionary".to_string())
+    })?;
+
+    let mut writer = ChecksumWriter::new(writer);
+    write_dictionary(&mut writer, &index.header, dictionary, |hash| {
+        index.token_map.contains_key(&hash)
+    })
+    .and_then(|_| writer.finish())
+    .map(drop)
+    .map_err(|e| TokenizerError::Io(e.to_string()))
 }
 
-/// Save trigram index to disk
-pub fn save_trigram(index: &TrigramIndex, path: &Path) -> Result<()> {
-    let file = File::create(path).map_err(|e| TokenizerError::Io(e.to_string()))?;
-    let mut writer = BufWriter::new(file);
+/// Save the positional index of an exact index to disk
+///
+/// Fails if the index has no positions.
+pub fn save_positions(index: &ExactTokenIndex, path: &Path) -> Result<()> {
+    write_atomic(path, |writer| write_positions_file(writer, index))
+}
 
-    writer
-        .write_all(MAGIC_TRIGRAM)
-        .map_err(|e| TokenizerError::Io(e.to_string()))?;
+fn write_positions_file(writer: &mut impl Write, index: &ExactTokenIndex) -> Result<()> {
+    let positions = index.positions.as_ref().ok_or_else(|| {
+        TokenizerError::InvalidIndexFormat("Exact index has no token positions".to_string())
+    })?;
 
-    let config = bincode::config::standard();
-    let encoded = bincode::serde::encode_to_vec(index, config)
-        .map_err(|e| TokenizerError::Serialization(e.to_string()))?;
+    let mut writer = ChecksumWriter::new(writer);
+    write_positions(&mut writer, &index.header, positions)
+        .and_then(|_| writer.finish())
+        .map(drop)
+        .map_err(|e| TokenizerError::Io(e.to_string()))
+}
 
-    writer
-        .write_all(&encoded)
-        .map_err(|e| TokenizerError::Io(e.to_string()))?;
+/// Save the term frequencies of an exact index to disk
+///
+/// Fails if the index has no term frequencies.
+pub fn save_frequencies(index: &ExactTokenIndex, path: &Path) -> Result<()> {
+    write_atomic(path, |writer| write_frequencies_file(writer, index))
+}
 
-    writer
-        .flush()
-        .map_err(|e| TokenizerError::Io(e.to_string()))?;
+fn write_frequencies_file(writer: &mut impl Write, index: &ExactTokenIndex) -> Result<()> {
+    let frequencies = index.frequencies.as_ref().ok_or_else(|| {
+        TokenizerError::InvalidIndexFormat("Exact index has no term frequencies".to_string())
+    })?;
 
-    Ok(())
+    let mut writer = ChecksumWriter::new(writer);
+    write_frequencies(&mut writer, &index.header, frequencies)
+        .and_then(|_| writer.finish())
+        .map(drop)
+        .map_err(|e| TokenizerError::Io(e.to_string()))
+}
+
+/// Save the subword index of an exact index to disk
+///
+/// Only the splits of tokens still present in the index are written. Fails
+/// if the index has no subword index.
+pub fn save_subwords(index: &ExactTokenIndex, path: &Path) -> Result<()> {
+    write_atomic(path, |writer| write_subwords_file(writer, index))
 }
 
-/// Save all three index files at once
+fn write_subwords_file(writer: &mut impl Write, index: &ExactTokenIndex) -> Result<()> {
+    let subwords = index.subwords.as_ref().ok_or_else(|| {
+        TokenizerError::InvalidIndexFormat("Exact index has no subword index".to_string())
+    })?;
+
+    let mut writer = ChecksumWriter::new(writer);
+    write_subwords(&mut writer, &index.header, subwords, |hash| {
+        index.token_map.contains_key(&hash)
+    })
+    .and_then(|_| writer.finish())
+    .map(drop)
+    .map_err(|e| TokenizerError::Io(e.to_string()))
+}
+
+/// Save all index files at once
+///
+/// The optional `.exactn`, `.dict`, `.sub`, `.pos`, `.tf` and `.tfi` files
+/// are written when the exact indexes have normalized tokens, a dictionary,
+/// subwords, positions or term frequencies and removed otherwise, so a stale
+/// one never outlives its index.
+///
+/// Every file is staged in a temporary sibling and fsynced; nothing is
+/// replaced unless all of them were written. Concurrent builders of the same
+/// index should hold its `BuildLock`.
 pub fn save_all(
     paths: &PathIndex,
     exact: &ExactTokenIndex,
@@ -219,11 +325,49 @@ pub fn save_all(
     trigram: &TrigramIndex,
     base_path: &Path,
 ) -> Result<()> {
-    save_paths(paths, &paths_file(base_path))?;
-    save_exact(exact, &exact_file(base_path))?;
-    save_exact(exact_lower, &exact_lower_file(base_path))?;
-    save_trigram(trigram, &trigram_file(base_path))?;
-    Ok(())
+    let mut staged = StagedWrites::default();
+    staged.stage(&exact_file(base_path), |w| write_exact_file(w, exact))?;
+    staged.stage(&exact_lower_file(base_path), |w| {
+        write_exact_file(w, exact_lower)
+    })?;
+    staged.stage(&trigram_file(base_path), |w| write_trigram_file(w, trigram))?;
+
+    if let Some(normalized) = exact.normalized() {
+        staged.stage(&exact_normalized_file(base_path), |w| {
+            write_exact_file(w, normalized)
+        })?;
+    } else {
+        staged.remove(&exact_normalized_file(base_path));
+    }
+    if exact.dictionary.is_some() {
+        staged.stage(&dict_file(base_path), |w| write_dictionary_file(w, exact))?;
+    } else {
+        staged.remove(&dict_file(base_path));
+    }
+    if exact.subwords.is_some() {
+        staged.stage(&sub_file(base_path), |w| write_subwords_file(w, exact))?;
+    } else {
+        staged.remove(&sub_file(base_path));
+    }
+    if exact.positions.is_some() {
+        staged.stage(&pos_file(base_path), |w| write_positions_file(w, exact))?;
+    } else {
+        staged.remove(&pos_file(base_path));
+    }
+    for (index, path) in [
+        (exact, tf_file(base_path)),
+        (exact_lower, tf_lower_file(base_path)),
+    ] {
+        if index.frequencies.is_some() {
+            staged.stage(&path, |w| write_frequencies_file(w, index))?;
+        } else {
+            staged.remove(&path);
+        }
+    }
+
+    // Renamed last: a new paths file means every other file is in place
+    staged.stage(&paths_file(base_path), |w| write_paths_file(w, paths))?;
+    staged.commit()
 }
 
 // ============================================================================
@@ -232,184 +376,444 @@ pub fn save_all(
 
 /// Load path index from disk
 pub fn load_paths(path: &Path) -> Result<PathIndex> {
-    let file = File::open(path).map_err(|e| TokenizerError::Io(e.to_string()))?;
-    let mut reader = BufReader::new(file);
-
-    let mut magic = [0u8; 4];
-    reader
-        .read_exact(&mut magic)
-        .map_err(|e| TokenizerError::Io(e.to_string()))?;
+    let data = std::fs::read(path).map_err(|e| TokenizerError::Io(e.to_string()))?;
+    paths_from(&data, path)
+}
 
-    if &magic != MAGIC_PATHS {
-        return Err(TokenizerError::InvalidIndexFormat(
-            "Invalid magic bytes for paths file".to_string(),
-        ));
-    }
+/// Load path index using memory mapping
+///
+/// This only avoids copying the file: the path index is still decoded in
+/// full. Only the token files can be queried in place, through
+/// `load_exact_view` and `load_trigram_view`.
+pub fn load_paths_mmap(path: &Path) -> Result<PathIndex> {
+    paths_from(map_file(path)?.as_ref(), path)
+}
 
-    let mut data = Vec::new();
-    reader
-        .read_to_end(&mut data)
-        .map_err(|e| TokenizerError::Io(e.to_string()))?;
+/// Decode a paths file; `name` names it in errors
+pub(crate) fn paths_from(data: &[u8], name: &Path) -> Result<PathIndex> {
+    let payload = verify_file(data, MAGIC_PATHS, name)?;
+    decode_paths(&payload[4..]).map_err(in_file(name))
+}
 
+/// Decode a path index serialized after the paths magic
+///
+/// The header is decoded first so that files from other format versions
+/// fail with a version mismatch rather than a decoding error.
+fn decode_paths(data: &[u8]) -> Result<PathIndex> {
     let config = bincode::config::standard();
-    let (mut index, _): (PathIndex, _) = bincode::serde::decode_from_slice(&data, config)
+    let (header, _): (IndexHeader, _) = bincode::serde::decode_from_slice(data, config)
         .map_err(|e| TokenizerError::Serialization(e.to_string()))?;
-
-    if index.header.version != FORMAT_VERSION {
-        return Err(TokenizerError::InvalidIndexFormat(format!(
-            "Version mismatch: expected {}, got {}",
-            FORMAT_VERSION, index.header.version
-        )));
+    if header.version != FORMAT_VERSION {
+        return Err(version_mismatch(header.version));
     }
 
+    let (mut index, _): (PathIndex, _) = bincode::serde::decode_from_slice(data, config)
+        .map_err(|e| TokenizerError::Serialization(e.to_string()))?;
     index.rebuild_dir_lookup();
     Ok(index)
 }
 
-/// Load path index using memory mapping
-pub fn load_paths_mmap(path: &Path) -> Result<PathIndex> {
-    let file = File::open(path).map_err(|e| TokenizerError::Io(e.to_string()))?;
-    let mmap = unsafe { Mmap::map(&file).map_err(|e| TokenizerError::Io(e.to_string()))? };
+/// Load exact token index from disk
+///
+/// Fails for an index built with a custom tokenizer; see `load_all_with_tokenizer`.
+pub fn load_exact(path: &Path) -> Result<ExactTokenIndex> {
+    let data = std::fs::read(path).map_err(|e| TokenizerError::Io(e.to_string()))?;
+    exact_from(&data[..], path, None)
+}
 
-    if mmap.len() < 4 || &mmap[..4] != MAGIC_PATHS {
-        return Err(TokenizerError::InvalidIndexFormat(
-            "Invalid magic bytes for paths file".to_string(),
-        ));
-    }
+/// Load exact token index using memory mapping
+///
+/// Still decodes every bitmap; use `load_exact_view` to query straight from
+/// the mapping.
+pub fn load_exact_mmap(path: &Path) -> Result<ExactTokenIndex> {
+    exact_from(map_file(path)?, path, None)
+}
 
-    let config = bincode::config::standard();
-    let (mut index, _): (PathIndex, _) = bincode::serde::decode_from_slice(&mmap[4..], config)
-        .map_err(|e| TokenizerError::Serialization(e.to_string()))?;
+/// Open a read-only exact token view without decoding any bitmaps
+///
+/// Only the header is read here; each bitmap is checked when it is read, and
+/// a lookup that reads a corrupted one fails with `TokenizerError::Corrupted`.
+///
+/// Fails for an index built with a custom tokenizer; see
+/// `load_exact_view_with_tokenizer`.
+pub fn load_exact_view(path: &Path) -> Result<ExactTokenView> {
+    exact_view_from(map_file(path)?, path, None)
+}
 
-    if index.header.version != FORMAT_VERSION {
-        return Err(TokenizerError::InvalidIndexFormat(format!(
-            "Version mismatch: expected {}, got {}",
-            FORMAT_VERSION, index.header.version
-        )));
-    }
+/// Open a read-only exact token view of an index built with `tokenizer`
+///
+/// Fails with `TokenizerError::TokenizerMismatch` if it was built with
+/// another tokenizer.
+pub fn load_exact_view_with_tokenizer(
+    path: &Path,
+    tokenizer: Arc<dyn Tokenizer>,
+) -> Result<ExactTokenView> {
+    exact_view_from(map_file(path)?, path, Some(&tokenizer))
+}
 
-    index.rebuild_dir_lookup();
-    Ok(index)
+/// Decode an exact index file in full; `name` names it in errors
+pub(crate) fn exact_from<B: AsRef<[u8]>>(
+    data: B,
+    name: &Path,
+    tokenizer: Option<&Arc<dyn Tokenizer>>,
+) -> Result<ExactTokenIndex> {
+    decode_exact(data, tokenizer).map_err(in_file(name))
 }
 
-/// Load exact token index from disk
-pub fn load_exact(path: &Path) -> Result<ExactTokenIndex> {
-    let file = File::open(path).map_err(|e| TokenizerError::Io(e.to_string()))?;
-    let mut reader = BufReader::new(file);
+/// Open an exact index file as a view, checking `tokenizer` against its
+/// header or resolving the built-in tokenizer the header names
+pub(crate) fn exact_view_from(
+    data: MappedBytes,
+    name: &Path,
+    tokenizer: Option<&Arc<dyn Tokenizer>>,
+) -> Result<ExactTokenView> {
+    let (header, table) = BitmapTable::parse(data, MAGIC_EXACT).map_err(in_file(name))?;
+    let tokenizer = resolve_tokenizer(&header, tokenizer)?;
+    Ok(ExactTokenView::new(header, table, tokenizer))
+}
 
-    let mut magic = [0u8; 4];
-    reader
-        .read_exact(&mut magic)
-        .map_err(|e| TokenizerError::Io(e.to_string()))?;
+/// Load trigram index from disk
+pub fn load_trigram(path: &Path) -> Result<TrigramIndex> {
+    let data = std::fs::read(path).map_err(|e| TokenizerError::Io(e.to_string()))?;
+    trigram_from(&data[..], path)
+}
 
-    if &magic != MAGIC_EXACT {
-        return Err(TokenizerError::InvalidIndexFormat(
-            "Invalid magic bytes for exact tokens file".to_string(),
-        ));
+/// Load trigram index using memory mapping
+///
+/// Still decodes every bitmap; use `load_trigram_view` to query straight from
+/// the mapping.
+pub fn load_trigram_mmap(path: &Path) -> Result<TrigramIndex> {
+    trigram_from(map_file(path)?, path)
+}
+
+/// Open a read-only trigram view without decoding any bitmaps
+///
+/// Bitmaps are checked against their checksums as they are read.
+pub fn load_trigram_view(path: &Path) -> Result<TrigramView> {
+    trigram_view_from(map_file(path)?, path)
+}
+
+/// Decode a trigram index file in full; `name` names it in errors
+pub(crate) fn trigram_from<B: AsRef<[u8]>>(data: B, name: &Path) -> Result<TrigramIndex> {
+    decode_trigram(data).map_err(in_file(name))
+}
+
+/// Open a trigram index file as a view; `name` names it in errors
+pub(crate) fn trigram_view_from(data: MappedBytes, name: &Path) -> Result<TrigramView> {
+    let (header, table) = BitmapTable::parse(data, MAGIC_TRIGRAM).map_err(in_file(name))?;
+    Ok(TrigramView::new(header, table))
+}
+
+/// Load a token dictionary from disk
+///
+/// Callers check the header against the exact index before attaching it.
+pub fn load_dictionary(path: &Path) -> Result<(IndexHeader, TokenDictionary)> {
+    let data = std::fs::read(path).map_err(|e| TokenizerError::Io(e.to_string()))?;
+    dictionary_from(&data, path)
+}
+
+pub(crate) fn dictionary_from(data: &[u8], name: &Path) -> Result<(IndexHeader, TokenDictionary)> {
+    decode_dictionary(verify_file(data, MAGIC_DICT, name)?).map_err(in_file(name))
+}
+
+/// Load a positional index from disk
+///
+/// Callers check the header against the exact index before attaching it.
+pub fn load_positions(path: &Path) -> Result<(IndexHeader, PositionIndex)> {
+    let data = std::fs::read(path).map_err(|e| TokenizerError::Io(e.to_string()))?;
+    positions_from(&data, path)
+}
+
+pub(crate) fn positions_from(data: &[u8], name: &Path) -> Result<(IndexHeader, PositionIndex)> {
+    decode_positions(verify_file(data, MAGIC_POS, name)?).map_err(in_file(name))
+}
+
+/// Load term frequencies from disk
+///
+/// Callers check the header against the exact index before attaching them.
+pub fn load_frequencies(path: &Path) -> Result<(IndexHeader, TermFrequencies)> {
+    let data = std::fs::read(path).map_err(|e| TokenizerError::Io(e.to_string()))?;
+    frequencies_from(&data, path)
+}
+
+pub(crate) fn frequencies_from(data: &[u8], name: &Path) -> Result<(IndexHeader, TermFrequencies)> {
+    decode_frequencies(verify_file(data, MAGIC_TF, name)?).map_err(in_file(name))
+}
+
+/// Load a subword index from disk
+///
+/// Callers check the header against the exact index before attaching it.
+pub fn load_subwords(path: &Path) -> Result<(IndexHeader, SubwordIndex)> {
+    let data = std::fs::read(path).map_err(|e| TokenizerError::Io(e.to_string()))?;
+    subwords_from(&data, path)
+}
+
+pub(crate) fn subwords_from(data: &[u8], name: &Path) -> Result<(IndexHeader, SubwordIndex)> {
+    decode_subwords(verify_file(data, MAGIC_SUB, name)?).map_err(in_file(name))
+}
+
+/// Bytes of a mapped file, or of one section of a mapped bundle
+#[derive(Debug, Clone)]
+pub(crate) struct MappedBytes {
+    map: Arc<Mmap>,
+    range: Range<usize>,
+}
+
+impl MappedBytes {
+    /// Share `range` of a mapping; the caller checked it is in bounds
+    pub(crate) fn new(map: Arc<Mmap>, range: Range<usize>) -> Self {
+        Self { map, range }
     }
 
-    let mut data = Vec::new();
-    reader
-        .read_to_end(&mut data)
-        .map_err(|e| TokenizerError::Io(e.to_string()))?;
+    /// Share a subrange; `range` is relative to these bytes and in bounds
+    pub(crate) fn slice(&self, range: Range<usize>) -> Self {
+        let start = self.range.start + range.start;
+        Self::new(self.map.clone(), start..self.range.start + range.end)
+    }
+}
 
-    let config = bincode::config::standard();
-    let (index, _): (ExactTokenIndex, _) = bincode::serde::decode_from_slice(&data, config)
-        .map_err(|e| TokenizerError::Serialization(e.to_string()))?;
+impl AsRef<[u8]> for MappedBytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.map[self.range.clone()]
+    }
+}
+
+pub(crate) fn map_file(path: &Path) -> Result<MappedBytes> {
+    let file = File::open(path).map_err(|e| TokenizerError::Io(e.to_string()))?;
+    let map = unsafe { Mmap::map(&file).map_err(|e| TokenizerError::Io(e.to_string()))? };
+    let len = map.len();
+    Ok(MappedBytes::new(Arc::new(map), 0..len))
+}
 
-    if index.header.version != FORMAT_VERSION {
+/// Check the magic and checksum trailer of a file whose checksum covers all
+/// of it, returning the bytes before the trailer
+///
+/// Older format versions had no trailer, so a file from another version fails
+/// with a version mismatch rather than as corrupted.
+fn verify_file<'a>(data: &'a [u8], magic: &[u8; 4], path: &Path) -> Result<&'a [u8]> {
+    if data.get(..4) != Some(&magic[..]) {
         return Err(TokenizerError::InvalidIndexFormat(format!(
-            "Version mismatch: expected {}, got {}",
-            FORMAT_VERSION, index.header.version
+            "Invalid magic bytes for {}",
+            path.display()
         )));
     }
+    verify_trailer(data).map_err(|e| match file_version(data) {
+        Some(version) if version != FORMAT_VERSION => version_mismatch(version),
+        _ => in_file(path)(e),
+    })
+}
 
-    Ok(index)
+/// Read the format version from the header of an index file, if it has one
+pub(crate) fn file_version(data: &[u8]) -> Option<u16> {
+    let magic: &[u8; 4] = data.get(..4)?.try_into().ok()?;
+    if magic == MAGIC_PATHS {
+        let config = bincode::config::standard();
+        let (header, _): (IndexHeader, _) =
+            bincode::serde::decode_from_slice(&data[4..], config).ok()?;
+        return Some(header.version);
+    }
+    parse_table_header(data, magic)
+        .ok()
+        .map(|(header, _)| header.version)
 }
 
-/// Load exact token index using memory mapping
-pub fn load_exact_mmap(path: &Path) -> Result<ExactTokenIndex> {
-    let file = File::open(path).map_err(|e| TokenizerError::Io(e.to_string()))?;
-    let mmap = unsafe { Mmap::map(&file).map_err(|e| TokenizerError::Io(e.to_string()))? };
+/// Report a file of another format version, pointing older ones to a rebuild
+pub(crate) fn version_mismatch(version: u16) -> TokenizerError {
+    let hint = if version < FORMAT_VERSION {
+        "; rebuild the index with `tokenizer migrate --rebuild`"
+    } else {
+        ""
+    };
+    TokenizerError::InvalidIndexFormat(format!(
+        "Version mismatch: expected {}, got {}{}",
+        FORMAT_VERSION, version, hint
+    ))
+}
 
-    if mmap.len() < 4 || &mmap[..4] != MAGIC_EXACT {
-        return Err(TokenizerError::InvalidIndexFormat(
-            "Invalid magic bytes for exact tokens file".to_string(),
-        ));
+/// Name the file in a `TokenizerError::Corrupted`
+pub(crate) fn in_file(path: &Path) -> impl Fn(TokenizerError) -> TokenizerError + '_ {
+    move |e| match e {
+        TokenizerError::Corrupted(problem) => {
+            TokenizerError::Corrupted(format!("{}: {}", path.display(), problem))
+        }
+        e => e,
     }
+}
 
-    let config = bincode::config::standard();
-    let (index, _): (ExactTokenIndex, _) = bincode::serde::decode_from_slice(&mmap[4..], config)
-        .map_err(|e| TokenizerError::Serialization(e.to_string()))?;
+/// Decode an exact index, checking `tokenizer` against its header or
+/// resolving the built-in tokenizer the header names
+fn decode_exact<B: AsRef<[u8]>>(
+    data: B,
+    tokenizer: Option<&Arc<dyn Tokenizer>>,
+) -> Result<ExactTokenIndex> {
+    let (header, table) = BitmapTable::parse(data, MAGIC_EXACT)?;
+    table.verify_entries()?;
+    let tokenizer = resolve_tokenizer(&header, tokenizer)?;
+    let mut index = ExactTokenIndex::new(header);
+    index.tokenizer = tokenizer;
+    index.token_map.reserve(table.len());
+    for entry in table.iter() {
+        let (key, bitmap) = entry?;
+        index.token_map.insert(key, bitmap);
+    }
+    Ok(index)
+}
 
-    if index.header.version != FORMAT_VERSION {
-        return Err(TokenizerError::InvalidIndexFormat(format!(
-            "Version mismatch: expected {}, got {}",
-            FORMAT_VERSION, index.header.version
-        )));
+/// Check `tokenizer` against a header, or resolve the built-in tokenizer
+/// the header names
+fn resolve_tokenizer(
+    header: &IndexHeader,
+    tokenizer: Option<&Arc<dyn Tokenizer>>,
+) -> Result<Arc<dyn Tokenizer>> {
+    match tokenizer {
+        Some(tokenizer) => {
+            header.check_tokenizer(tokenizer.as_ref())?;
+            Ok(tokenizer.clone())
+        }
+        None => header.builtin_tokenizer(),
     }
+}
 
+fn decode_trigram<B: AsRef<[u8]>>(data: B) -> Result<TrigramIndex> {
+    let (header, table) = BitmapTable::parse(data, MAGIC_TRIGRAM)?;
+    table.verify_entries()?;
+    let mut index = TrigramIndex::new(header);
+    index.trigram_map.reserve(table.len());
+    for entry in table.iter() {
+        let (key, bitmap) = entry?;
+        index.trigram_map.insert(key, bitmap);
+    }
     Ok(index)
 }
 
-/// Load trigram index from disk
-pub fn load_trigram(path: &Path) -> Result<TrigramIndex> {
-    let file = File::open(path).map_err(|e| TokenizerError::Io(e.to_string()))?;
-    let mut reader = BufReader::new(file);
+/// Load all split index files for a base path and check they belong together
+///
+/// Fails for an index built with a custom tokenizer; see `load_all_with_tokenizer`.
+pub fn load_all(base_path: &Path) -> Result<IndexSet> {
+    load_all_tokenized(base_path, None)
+}
 
-    let mut magic = [0u8; 4];
-    reader
-        .read_exact(&mut magic)
-        .map_err(|e| TokenizerError::Io(e.to_string()))?;
+/// Load all split index files of an index built with `tokenizer`
+///
+/// Fails with `TokenizerError::TokenizerMismatch` if it was built with
+/// another tokenizer.
+pub fn load_all_with_tokenizer(
+    base_path: &Path,
+    tokenizer: Arc<dyn Tokenizer>,
+) -> Result<IndexSet> {
+    load_all_tokenized(base_path, Some(&tokenizer))
+}
 
-    if &magic != MAGIC_TRIGRAM {
-        return Err(TokenizerError::InvalidIndexFormat(
-            "Invalid magic bytes for trigram file".to_string(),
-        ));
+fn load_all_tokenized(
+    base_path: &Path,
+    tokenizer: Option<&Arc<dyn Tokenizer>>,
+) -> Result<IndexSet> {
+    load_all_from(&IndexFiles::Split(base_path.to_path_buf()), tokenizer)
+}
+
+/// Load every file of an index in either layout and check they belong
+/// together
+pub(crate) fn load_all_from(
+    files: &IndexFiles,
+    tokenizer: Option<&Arc<dyn Tokenizer>>,
+) -> Result<IndexSet> {
+    let paths = files.load_paths()?;
+    let mut exact = files.load_exact_tokenized(Section::Exact, tokenizer)?;
+    let mut exact_lower = files.load_exact_tokenized(Section::ExactLower, tokenizer)?;
+    let trigram = files.load_trigram()?;
+
+    validate_index_match(&paths.header, &exact.header)?;
+    validate_index_match(&paths.header, &exact_lower.header)?;
+    validate_index_match(&paths.header, &trigram.header)?;
+
+    // The dictionary is optional, but one from another run is an error
+    if files.has(Section::Dictionary) {
+        let (header, dictionary) = files.load_dictionary()?;
+        validate_index_match(&paths.header, &header)?;
+        exact_lower.dictionary = Some(dictionary.lowercased());
+        exact.dictionary = Some(dictionary);
     }
 
-    let mut data = Vec::new();
-    reader
-        .read_to_end(&mut data)
-        .map_err(|e| TokenizerError::Io(e.to_string()))?;
+    if files.has(Section::ExactNormalized) {
+        let mut normalized = files.load_exact_tokenized(Section::ExactNormalized, tokenizer)?;
+        validate_index_match(&paths.header, &normalized.header)?;
+        normalized.dictionary = exact.dictionary.as_ref().map(TokenDictionary::normalized);
+        exact.normalized = Some(Box::new(normalized));
+    }
 
-    let config = bincode::config::standard();
-    let (index, _): (TrigramIndex, _) = bincode::serde::decode_from_slice(&data, config)
-        .map_err(|e| TokenizerError::Serialization(e.to_string()))?;
+    if files.has(Section::Subwords) {
+        let (header, subwords) = files.load_subwords()?;
+        validate_index_match(&paths.header, &header)?;
+        exact.subwords = Some(subwords);
+    }
 
-    if index.header.version != FORMAT_VERSION {
-        return Err(TokenizerError::InvalidIndexFormat(format!(
-            "Version mismatch: expected {}, got {}",
-            FORMAT_VERSION, index.header.version
-        )));
+    if files.has(Section::Positions) {
+        let (header, positions) = files.load_positions()?;
+        validate_index_match(&paths.header, &header)?;
+        exact.positions = Some(positions);
     }
 
-    Ok(index)
+    for (index, section) in [
+        (&mut exact, Section::Frequencies),
+        (&mut exact_lower, Section::FrequenciesLower),
+    ] {
+        if files.has(section) {
+            let (header, frequencies) = files.load_frequencies(section)?;
+            validate_index_match(&paths.header, &header)?;
+            index.frequencies = Some(frequencies);
+        }
+    }
+
+    Ok(IndexSet {
+        paths,
+        exact,
+        exact_lower,
+        trigram,
+    })
 }
 
-/// Load trigram index using memory mapping
-pub fn load_trigram_mmap(path: &Path) -> Result<TrigramIndex> {
+/// Read only the header of a split index file
+///
+/// Much cheaper than a full load; used to detect that the files on disk were
+/// rewritten by another process.
+pub fn read_header(path: &Path) -> Result<IndexHeader> {
     let file = File::open(path).map_err(|e| TokenizerError::Io(e.to_string()))?;
-    let mmap = unsafe { Mmap::map(&file).map_err(|e| TokenizerError::Io(e.to_string()))? };
+    let mut reader = BufReader::new(file);
+
+    let mut magic = [0u8; 4];
+    reader
+        .read_exact(&mut magic)
+        .map_err(|e| TokenizerError::Io(e.to_string()))?;
+
+    // Token tables have a fixed-size header
+    for table_magic in [
+        MAGIC_EXACT,
+        MAGIC_TRIGRAM,
+        MAGIC_DICT,
+        MAGIC_SUB,
+        MAGIC_POS,
+        MAGIC_TF,
+    ] {
+        if &magic == table_magic {
+            let mut data = [0u8; TABLE_HEADER_LEN];
+            data[..4].copy_from_slice(&magic);
+            reader
+                .read_exact(&mut data[4..])
+                .map_err(|e| TokenizerError::Io(e.to_string()))?;
+            return Ok(parse_table_header(&data, table_magic)?.0);
+        }
+    }
 
-    if mmap.len() < 4 || &mmap[..4] != MAGIC_TRIGRAM {
+    if &magic != MAGIC_PATHS {
         return Err(TokenizerError::InvalidIndexFormat(
-            "Invalid magic bytes for trigram file".to_string(),
+            "Invalid magic bytes for index file".to_string(),
         ));
     }
 
+    // The header is the first field of the paths index
     let config = bincode::config::standard();
-    let (index, _): (TrigramIndex, _) = bincode::serde::decode_from_slice(&mmap[4..], config)
-        .map_err(|e| TokenizerError::Serialization(e.to_string()))?;
-
-    if index.header.version != FORMAT_VERSION {
-        return Err(TokenizerError::InvalidIndexFormat(format!(
-            "Version mismatch: expected {}, got {}",
-            FORMAT_VERSION, index.header.version
-        )));
-    }
-
-    Ok(index)
+    bincode::serde::decode_from_std_read(&mut reader, config)
+        .map_err(|e| TokenizerError::Serialization(e.to_string()))
 }
 
 /// Validate that two index files have matching index IDs
@@ -451,6 +855,37 @@ pub fn index_exists(base_path: &Path) -> bool {
     false
 }
 
+/// Index two small Rust files in `dir`, with positions, and save them at `base`
+#[cfg(test)]
+pub(crate) fn save_sample_index(dir: &Path, base: &Path) {
+    use crate::scanner::{scan_and_build_indexes, ScanConfig};
+
+    std::fs::write(dir.join("a.rs"), "fn parse_header() { let value = 1; }").unwrap();
+    std::fs::write(dir.join("b.rs"), "fn parse_body() { let value = 2; }").unwrap();
+    let config = ScanConfig {
+        build_positions: true,
+        ..Default::default()
+    };
+    let (paths, exact, exact_lower, trigram) = scan_and_build_indexes(dir, &config).unwrap();
+    save_all(&paths, &exact, &exact_lower, &trigram, base).unwrap();
+}
+
+/// A legacy index of `src/a.rs` ("fn hello() { world(); }") and `src/b.txt`
+/// ("hello there"), as written by the first release's `tokenizer index --legacy`
+#[cfg(test)]
+pub(crate) const LEGACY_V2_INDEX: &[u8] = &[
+    0x54, 0x4b, 0x49, 0x58, 0x04, 0xfd, 0x22, 0xf7, 0x37, 0xe0, 0xfe, 0x39, 0xde, 0xf1, 0x12, 0x3a,
+    0x30, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0xfd, 0x83, 0x01, 0x50, 0xbe, 0xe7, 0xad, 0x67, 0x9d, 0x14, 0x3a, 0x30, 0x00, 0x00, 0x01,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0xfd,
+    0x90, 0x1f, 0xa5, 0xa9, 0x98, 0x55, 0x74, 0x8f, 0x12, 0x3a, 0x30, 0x00, 0x00, 0x01, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x01, 0x00, 0xfd, 0x1f, 0x8e, 0x3d, 0xb0,
+    0x29, 0xa1, 0x1a, 0x1c, 0x12, 0x3a, 0x30, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x10, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01, 0x03, 0x73, 0x72, 0x63, 0x02, 0x00, 0x05, 0x62,
+    0x2e, 0x74, 0x78, 0x74, 0x00, 0x04, 0x61, 0x2e, 0x72, 0x73, 0x02, 0xfc, 0x2b, 0xce, 0xd2, 0x6a,
+    0x03, 0x73, 0x72, 0x63, 0x02, 0x04,
+];
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -472,6 +907,22 @@ mod tests {
         assert_ne!(header1.index_id, header2.index_id);
     }
 
+    #[test]
+    fn test_read_header() {
+        let dir = tempdir().unwrap();
+        let paths_path = dir.path().join("test.paths");
+        let exact_path = dir.path().join("test.exact");
+
+        let header = IndexHeader::new();
+        let mut index = PathIndex::new(header.clone(), dir.path().to_path_buf());
+        index.register_file(PathBuf::from("/test/file1.txt"));
+        save_paths(&index, &paths_path).unwrap();
+        save_exact(&ExactTokenIndex::new(header.clone()), &exact_path).unwrap();
+
+        assert_eq!(read_header(&paths_path).unwrap().index_id, header.index_id);
+        assert_eq!(read_header(&exact_path).unwrap().index_id, header.index_id);
+    }
+
     #[test]
     fn test_paths_roundtrip() {
         let dir = tempdir().unwrap();
@@ -531,6 +982,286 @@ mod tests {
         assert!(validate_index_match(&header1, &header3).is_err());
     }
 
+    #[test]
+    fn test_dictionary_saved_and_validated() {
+        use crate::scanner::{scan_and_build_indexes, ScanConfig};
+        use crate::tokenizer::hash_token;
+
+        let src = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        let base = out.path().join("index.tkix");
+        std::fs::write(src.path().join("a.rs"), "fn parse_header() {}").unwrap();
+        std::fs::write(src.path().join("b.rs"), "fn parse_body() {}").unwrap();
+
+        let (paths, exact, exact_lower, trigram) =
+            scan_and_build_indexes(src.path(), &ScanConfig::default()).unwrap();
+        save_all(&paths, &exact, &exact_lower, &trigram, &base).unwrap();
+        assert_eq!(read_header(&dict_file(&base)).unwrap(), paths.header);
+
+        let indexes = load_all(&base).unwrap();
+        let tokens: Vec<(&[u8], u64)> = indexes.exact.iter_tokens().collect();
+        assert_eq!(
+            tokens,
+            vec![(&b"fn"[..], 2), (b"parse_body", 1), (b"parse_header", 1)]
+        );
+        assert_eq!(
+            indexes.exact.token_text(hash_token(b"parse_body")),
+            Some(&b"parse_body"[..])
+        );
+
+        // A dictionary from another index run is rejected
+        let mut other = ExactTokenIndex::new(IndexHeader::new());
+        other.dictionary = Some(TokenDictionary::new());
+        save_dictionary(&other, &dict_file(&base)).unwrap();
+        assert!(matches!(
+            load_all(&base),
+            Err(TokenizerError::IndexMismatch(_))
+        ));
+
+        // Saving without a dictionary removes the stale file
+        let config = ScanConfig {
+            build_dictionary: false,
+            ..Default::default()
+        };
+        let (paths, exact, exact_lower, trigram) =
+            scan_and_build_indexes(src.path(), &config).unwrap();
+        save_all(&paths, &exact, &exact_lower, &trigram, &base).unwrap();
+        assert!(!dict_file(&base).exists());
+        assert!(load_all(&base).unwrap().exact.dictionary().is_none());
+    }
+
+    #[test]
+    fn test_positions_saved_and_removed() {
+        use crate::scanner::{scan_and_build_indexes, ScanConfig};
+        use crate::tokenizer::hash_token;
+
+        let src = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        let base = out.path().join("index.tkix");
+        std::fs::write(src.path().join("a.rs"), "impl Display for Foo").unwrap();
+
+        let config = ScanConfig {
+            build_positions: true,
+            ..Default::default()
+        };
+        let (paths, exact, exact_lower, trigram) =
+            scan_and_build_indexes(src.path(), &config).unwrap();
+        save_all(&paths, &exact, &exact_lower, &trigram, &base).unwrap();
+        assert_eq!(read_header(&pos_file(&base)).unwrap(), paths.header);
+
+        let indexes = load_all(&base).unwrap();
+        let positions = indexes.exact.positions().unwrap();
+        assert_eq!(positions.positions(0, hash_token(b"Display")), &[1]);
+        assert_eq!(positions.positions(0, hash_token(b"Foo")), &[3]);
+
+        let (paths, exact, exact_lower, trigram) =
+            scan_and_build_indexes(src.path(), &ScanConfig::default()).unwrap();
+        save_all(&paths, &exact, &exact_lower, &trigram, &base).unwrap();
+        assert!(!pos_file(&base).exists());
+        assert!(load_all(&base).unwrap().exact.positions().is_none());
+    }
+
+    #[test]
+    fn test_frequencies_saved_and_removed() {
+        use crate::scanner::{scan_and_build_indexes, ScanConfig};
+        use crate::tokenizer::{hash_token, hash_token_lower};
+
+        let src = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        let base = out.path().join("index.tkix");
+        std::fs::write(src.path().join("a.rs"), "Foo foo FOO bar").unwrap();
+
+        let (paths, exact, exact_lower, trigram) =
+            scan_and_build_indexes(src.path(), &ScanConfig::default()).unwrap();
+        save_all(&paths, &exact, &exact_lower, &trigram, &base).unwrap();
+        assert_eq!(read_header(&tf_file(&base)).unwrap(), paths.header);
+
+        let indexes = load_all(&base).unwrap();
+        let frequencies = indexes.exact.frequencies().unwrap();
+        assert_eq!(frequencies.term_frequency(0, hash_token(b"Foo")), 1);
+        assert_eq!(frequencies.document_length(0), 4);
+        let lower = indexes.exact_lower.frequencies().unwrap();
+        assert_eq!(lower.term_frequency(0, hash_token_lower(b"foo")), 3);
+
+        let config = ScanConfig {
+            build_frequencies: false,
+            ..Default::default()
+        };
+        let (paths, exact, exact_lower, trigram) =
+            scan_and_build_indexes(src.path(), &config).unwrap();
+        save_all(&paths, &exact, &exact_lower, &trigram, &base).unwrap();
+        assert!(!tf_file(&base).exists());
+        assert!(!tf_lower_file(&base).exists());
+        assert!(load_all(&base).unwrap().exact_lower.frequencies().is_none());
+    }
+
+    #[test]
+    fn test_subwords_saved_and_removed() {
+        use crate::scanner::{scan_and_build_indexes, ScanConfig};
+        use crate::tokenizer::{hash_token, hash_token_lower};
+
+        let src = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        let base = out.path().join("index.tkix");
+        std::fs::write(src.path().join("a.rs"), "getUserById(user_id)").unwrap();
+
+        let (paths, exact, exact_lower, trigram) =
+            scan_and_build_indexes(src.path(), &ScanConfig::default()).unwrap();
+        save_all(&paths, &exact, &exact_lower, &trigram, &base).unwrap();
+        assert_eq!(read_header(&sub_file(&base)).unwrap(), paths.header);
+
+        let indexes = load_all(&base).unwrap();
+        let subwords = indexes.exact.subwords().unwrap();
+        assert!(subwords.part_bitmap(hash_token_lower(b"by")).is_some());
+        let parts = [hash_token_lower(b"user"), hash_token_lower(b"id")];
+        assert_eq!(subwords.tokens_in_order(&parts).len(), 2);
+        assert!(subwords
+            .tokens_in_order(&parts)
+            .contains(&hash_token(b"user_id")));
+
+        let config = ScanConfig {
+            build_subwords: false,
+            ..Default::default()
+        };
+        let (paths, exact, exact_lower, trigram) =
+            scan_and_build_indexes(src.path(), &config).unwrap();
+        save_all(&paths, &exact, &exact_lower, &trigram, &base).unwrap();
+        assert!(!sub_file(&base).exists());
+        assert!(load_all(&base).unwrap().exact.subwords().is_none());
+    }
+
+    #[test]
+    fn test_normalized_saved_and_removed() {
+        use crate::scanner::{scan_and_build_indexes, ScanConfig};
+        use crate::tokenizer::hash_token_normalized;
+
+        let src = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        let base = out.path().join("index.tkix");
+        std::fs::write(src.path().join("a.rs"), "let userId = 1;").unwrap();
+        std::fs::write(src.path().join("b.py"), "USER_ID = get_user_id()").unwrap();
+
+        let (paths, exact, exact_lower, trigram) =
+            scan_and_build_indexes(src.path(), &ScanConfig::default()).unwrap();
+        save_all(&paths, &exact, &exact_lower, &trigram, &base).unwrap();
+        assert_eq!(
+            read_header(&exact_normalized_file(&base)).unwrap(),
+            paths.header
+        );
+
+        let indexes = load_all(&base).unwrap();
+        let normalized = indexes.exact.normalized().unwrap();
+        let hash = hash_token_normalized(b"userid");
+        assert_eq!(normalized.get_bitmap(hash).unwrap().len(), 2);
+        assert_eq!(normalized.token_text(hash), Some(&b"userid"[..]));
+
+        let config = ScanConfig {
+            build_normalized: false,
+            ..Default::default()
+        };
+        let (paths, exact, exact_lower, trigram) =
+            scan_and_build_indexes(src.path(), &config).unwrap();
+        save_all(&paths, &exact, &exact_lower, &trigram, &base).unwrap();
+        assert!(!exact_normalized_file(&base).exists());
+        assert!(load_all(&base).unwrap().exact.normalized().is_none());
+    }
+
+    /// Keeps `$` on PHP variables
+    struct PhpTokenizer;
+
+    impl Tokenizer for PhpTokenizer {
+        fn name(&self) -> &str {
+            "php-test"
+        }
+
+        fn token_spans<'a>(
+            &self,
+            content: &'a [u8],
+        ) -> Box<dyn Iterator<Item = (usize, &'a [u8])> + 'a> {
+            Box::new(
+                content
+                    .split(|b| b.is_ascii_whitespace() || b"=;()".contains(b))
+                    .filter_map(move |token| {
+                        let offset = token.as_ptr() as usize - content.as_ptr() as usize;
+                        (token.len() >= 2).then_some((offset, token))
+                    }),
+            )
+        }
+    }
+
+    #[test]
+    fn test_custom_tokenizer_recorded_and_enforced() {
+        use crate::query::{query_exact, query_exact_lower, QueryOptions};
+        use crate::scanner::{scan_and_build_indexes, ScanConfig};
+        use crate::tokenizer::ExactTokenizer;
+
+        let src = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        let base = out.path().join("index.tkix");
+        std::fs::write(src.path().join("a.php"), "$name = name();").unwrap();
+        std::fs::write(src.path().join("b.php"), "echo $Name;").unwrap();
+
+        let php: Arc<dyn Tokenizer> = Arc::new(PhpTokenizer);
+        let config = ScanConfig {
+            tokenizer: php.clone(),
+            ..Default::default()
+        };
+        let (paths, exact, exact_lower, trigram) =
+            scan_and_build_indexes(src.path(), &config).unwrap();
+        save_all(&paths, &exact, &exact_lower, &trigram, &base).unwrap();
+        assert_eq!(
+            read_header(&exact_file(&base)).unwrap().tokenizer,
+            php.fingerprint()
+        );
+        assert_eq!(
+            read_header(&dict_file(&base)).unwrap().tokenizer,
+            php.fingerprint()
+        );
+
+        // The custom rules can't be recovered from the header
+        assert!(matches!(
+            load_all(&base),
+            Err(TokenizerError::TokenizerMismatch(_))
+        ));
+        assert!(matches!(
+            load_all_with_tokenizer(&base, Arc::new(ExactTokenizer)),
+            Err(TokenizerError::TokenizerMismatch(_))
+        ));
+
+        // Queries split with the index's tokenizer
+        let indexes = load_all_with_tokenizer(&base, php.clone()).unwrap();
+        let options = QueryOptions::default();
+        let result = query_exact(&indexes.paths, &indexes.exact, "$name", &options).unwrap();
+        assert_eq!(result.files.len(), 1);
+        assert!(result.files[0].ends_with("a.php"));
+        let result =
+            query_exact_lower(&indexes.paths, &indexes.exact_lower, "$NAME", &options).unwrap();
+        assert_eq!(result.files.len(), 2);
+
+        let view = load_exact_view_with_tokenizer(&exact_file(&base), php).unwrap();
+        let result = query_exact(&indexes.paths, &view, "name()", &options).unwrap();
+        assert_eq!(result.files.len(), 1);
+        assert!(matches!(
+            load_exact_view(&exact_file(&base)),
+            Err(TokenizerError::TokenizerMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_load_legacy_v2_index() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("legacy.tkix");
+        std::fs::write(&path, LEGACY_V2_INDEX).unwrap();
+
+        for index in [load_index(&path).unwrap(), load_index_mmap(&path).unwrap()] {
+            assert_eq!(index.metadata().version, 2);
+            assert_eq!(index.metadata().root_path, Path::new("src"));
+            assert_eq!(index.file_count(), 2);
+            let hello = index.get_bitmap(crate::tokenizer::hash_token(b"hello"));
+            assert_eq!(hello.map(|bitmap| bitmap.len()), Some(2));
+        }
+    }
+
     #[test]
     fn test_file_path_helpers() {
         let base = Path::new("/tmp/myindex.tkix");
@@ -0,0 +1,352 @@
+//! Positional postings: where each exact token occurs inside each file
+//!
+//! Optional companion of the exact index, stored as a `.pos` file:
+//!
+//! ```text
+//! magic        [u8; 4]  "TKPO"
+//! version      u16
+//! reserved     u16
+//! index_id     [u8; 16]
+//! created_at   u64
+//! tokenizer    u64
+//! file_count   u64
+//! files        file_count x {
+//!                  file_id: varint, token_count: varint,
+//!                  tokens: token_count x { hash: u64, count: varint, deltas: count x varint }
+//!              }
+//! ```
+//!
+//! Positions are token ordinals: the n-th exact token of a file (tokens
+//! shorter than `MIN_TOKEN_LENGTH` are not counted) has position n. Files
+//! are written in ID order, tokens in hash order, and positions as deltas
+//! from the previous position of the same token.
+
+use crate::dictionary::{read_varint, write_varint};
+use crate::error::{Result, TokenizerError};
+use crate::index::{IndexHeader, FORMAT_VERSION};
+use crate::table::{parse_header, write_header, HEADER_LEN};
+use roaring::RoaringBitmap;
+use rustc_hash::FxHashMap;
+use std::io::Write;
+
+/// Magic bytes of the positions file
+pub(crate) const MAGIC_POS: &[u8; 4] = b"TKPO";
+
+/// A run of token ordinals `(first, last)` inside one file
+pub(crate) type Span = (u32, u32);
+
+/// Token ordinal positions per file, keyed by exact token hash
+#[derive(Debug, Clone, Default)]
+pub struct PositionIndex {
+    files: FxHashMap<u32, FxHashMap<u64, Vec<u32>>>,
+}
+
+impl PositionIndex {
+    /// Create an empty positional index
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the positions of every token of a file, replacing earlier ones
+    pub fn insert_file(&mut self, file_id: u32, postings: Vec<(u64, Vec<u32>)>) {
+        if postings.is_empty() {
+            self.files.remove(&file_id);
+        } else {
+            self.files.insert(file_id, postings.into_iter().collect());
+        }
+    }
+
+    /// Drop the positions of the given files
+    pub fn remove_files(&mut self, file_ids: &RoaringBitmap) {
+        for file_id in file_ids {
+            self.files.remove(&file_id);
+        }
+    }
+
+    /// Take over the positions of another index, shifting its file IDs by
+    /// `offset`
+    pub(crate) fn append(&mut self, other: PositionIndex, offset: u32) {
+        self.files.extend(
+            other
+                .files
+                .into_iter()
+                .map(|(file_id, tokens)| (file_id + offset, tokens)),
+        );
+    }
+
+    /// Get the sorted positions of a token in a file
+    pub fn positions(&self, file_id: u32, token_hash: u64) -> &[u32] {
+        self.files
+            .get(&file_id)
+            .and_then(|tokens| tokens.get(&token_hash))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Get the number of files with positions
+    pub fn file_count(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Get the IDs of the files with positions
+    pub(crate) fn file_ids(&self) -> RoaringBitmap {
+        self.files.keys().copied().collect()
+    }
+
+    /// Get the total number of recorded token occurrences
+    pub fn occurrence_count(&self) -> usize {
+        self.files
+            .values()
+            .flat_map(|tokens| tokens.values())
+            .map(Vec::len)
+            .sum()
+    }
+
+    /// Find where the slots occur one after another in a file
+    ///
+    /// Each slot lists alternative token hashes (one for a plain token, many
+    /// for an expanded wildcard).
+    pub(crate) fn sequence_spans(&self, file_id: u32, slots: &[Vec<u64>]) -> Vec<Span> {
+        let Some(first) = slots.first() else {
+            return Vec::new();
+        };
+        let rest: Vec<Vec<u32>> = slots[1..]
+            .iter()
+            .map(|slot| self.slot_positions(file_id, slot))
+            .collect();
+
+        let last_offset = rest.len() as u32;
+        self.slot_positions(file_id, first)
+            .into_iter()
+            .filter(|&start| {
+                rest.iter().enumerate().all(|(i, positions)| {
+                    start
+                        .checked_add(i as u32 + 1)
+                        .is_some_and(|p| positions.binary_search(&p).is_ok())
+                })
+            })
+            .map(|start| (start, start + last_offset))
+            .collect()
+    }
+
+    /// Sorted positions of any of the slot's tokens
+    fn slot_positions(&self, file_id: u32, slot: &[u64]) -> Vec<u32> {
+        let mut positions: Vec<u32> = slot
+            .iter()
+            .flat_map(|hash| self.positions(file_id, *hash).iter().copied())
+            .collect();
+        if slot.len() > 1 {
+            positions.sort_unstable();
+            positions.dedup();
+        }
+        positions
+    }
+}
+
+/// Combine spans from both sides that are at most `distance` tokens apart
+///
+/// Adjacent spans are one token apart. Each result covers both spans.
+pub(crate) fn near_spans(lhs: &[Span], rhs: &[Span], distance: u32) -> Vec<Span> {
+    let mut rhs: Vec<Span> = rhs.to_vec();
+    rhs.sort_unstable();
+    let max_len = rhs
+        .iter()
+        .map(|(first, last)| last - first)
+        .max()
+        .unwrap_or(0);
+
+    let mut result = Vec::new();
+    for &(l_first, l_last) in lhs {
+        // Earliest right span that can still end within `distance` of lhs
+        let lowest = l_first.saturating_sub(distance.saturating_add(max_len));
+        let begin = rhs.partition_point(|(first, _)| *first < lowest);
+        for &(r_first, r_last) in &rhs[begin..] {
+            if r_first > l_last.saturating_add(distance) {
+                break;
+            }
+            let gap = if r_first > l_last {
+                r_first - l_last
+            } else {
+                l_first.saturating_sub(r_last)
+            };
+            if gap <= distance {
+                result.push((l_first.min(r_first), l_last.max(r_last)));
+            }
+        }
+    }
+    result.sort_unstable();
+    result.dedup();
+    result
+}
+
+/// Write a positions file
+pub(crate) fn write_positions<W: Write>(
+    writer: &mut W,
+    header: &IndexHeader,
+    index: &PositionIndex,
+) -> std::io::Result<()> {
+    write_header(writer, MAGIC_POS, header, index.files.len())?;
+
+    let mut file_ids: Vec<u32> = index.files.keys().copied().collect();
+    file_ids.sort_unstable();
+    for file_id in file_ids {
+        let tokens = &index.files[&file_id];
+        let mut hashes: Vec<u64> = tokens.keys().copied().collect();
+        hashes.sort_unstable();
+
+        write_varint(writer, u64::from(file_id))?;
+        write_varint(writer, hashes.len() as u64)?;
+        for hash in hashes {
+            let positions = &tokens[&hash];
+            writer.write_all(&hash.to_le_bytes())?;
+            write_varint(writer, positions.len() as u64)?;
+            let mut previous = 0;
+            for &position in positions {
+                write_varint(writer, u64::from(position - previous))?;
+                previous = position;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Decode a positions file
+pub(crate) fn decode_positions(data: &[u8]) -> Result<(IndexHeader, PositionIndex)> {
+    let (header, count) = parse_header(data, MAGIC_POS)?;
+    if header.version != FORMAT_VERSION {
+        return Err(TokenizerError::InvalidIndexFormat(format!(
+            "Version mismatch: expected {}, got {}",
+            FORMAT_VERSION, header.version
+        )));
+    }
+
+    let corrupt = || TokenizerError::InvalidIndexFormat("Positions file is corrupt".to_string());
+    let mut position = HEADER_LEN;
+    let read_u32 = |position: &mut usize| {
+        read_varint(data, position)
+            .and_then(|value| u32::try_from(value).ok())
+            .ok_or_else(corrupt)
+    };
+
+    let mut index = PositionIndex::new();
+    for _ in 0..count {
+        let file_id = read_u32(&mut position)?;
+        let token_count = read_u32(&mut position)?;
+        let mut tokens = FxHashMap::default();
+        for _ in 0..token_count {
+            let hash = data
+                .get(position..position + 8)
+                .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+                .ok_or_else(corrupt)?;
+            position += 8;
+
+            let occurrences = read_u32(&mut position)?;
+            let mut positions = Vec::with_capacity(occurrences.min(1 << 16) as usize);
+            let mut current = 0u32;
+            for i in 0..occurrences {
+                let delta = read_u32(&mut position)?;
+                if i > 0 && delta == 0 {
+                    return Err(corrupt());
+                }
+                current = current.checked_add(delta).ok_or_else(corrupt)?;
+                positions.push(current);
+            }
+            tokens.insert(hash, positions);
+        }
+        index.files.insert(file_id, tokens);
+    }
+
+    if position != data.len() {
+        return Err(corrupt());
+    }
+
+    Ok((header, index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::hash_token;
+
+    fn index_of(file_id: u32, text: &str) -> PositionIndex {
+        let mut postings: FxHashMap<u64, Vec<u32>> = FxHashMap::default();
+        for (i, token) in text.split_whitespace().enumerate() {
+            postings
+                .entry(hash_token(token.as_bytes()))
+                .or_default()
+                .push(i as u32);
+        }
+        let mut index = PositionIndex::new();
+        index.insert_file(file_id, postings.into_iter().collect());
+        index
+    }
+
+    fn slots(tokens: &[&str]) -> Vec<Vec<u64>> {
+        tokens
+            .iter()
+            .map(|t| vec![hash_token(t.as_bytes())])
+            .collect()
+    }
+
+    #[test]
+    fn test_sequence_spans() {
+        let index = index_of(3, "impl Display for Foo impl Debug for Foo");
+        assert_eq!(
+            index.sequence_spans(3, &slots(&["impl", "Display", "for"])),
+            vec![(0, 2)]
+        );
+        assert_eq!(
+            index.sequence_spans(3, &slots(&["for", "Foo"])),
+            vec![(2, 3), (6, 7)]
+        );
+        assert!(index
+            .sequence_spans(3, &slots(&["Display", "impl"]))
+            .is_empty());
+        assert!(index.sequence_spans(4, &slots(&["impl"])).is_empty());
+
+        // A slot may hold several alternatives
+        let either = vec![vec![hash_token(b"Display"), hash_token(b"Debug")]];
+        assert_eq!(index.sequence_spans(3, &either), vec![(1, 1), (5, 5)]);
+    }
+
+    #[test]
+    fn test_near_spans() {
+        let lhs = [(0, 0), (10, 11)];
+        let rhs = [(3, 3), (14, 14)];
+        assert_eq!(near_spans(&lhs, &rhs, 3), vec![(0, 3), (10, 14)]);
+        assert_eq!(near_spans(&lhs, &rhs, 2), Vec::<Span>::new());
+        // Order does not matter
+        assert_eq!(near_spans(&rhs, &lhs, 3), vec![(0, 3), (10, 14)]);
+        assert_eq!(near_spans(&[(5, 5)], &[(6, 6)], 1), vec![(5, 6)]);
+    }
+
+    #[test]
+    fn test_roundtrip_and_corruption() {
+        let mut index = index_of(7, "alpha beta alpha gamma alpha");
+        index.insert_file(2, vec![(hash_token(b"beta"), vec![0, 300, 70000])]);
+
+        let header = IndexHeader::new();
+        let mut data = Vec::new();
+        write_positions(&mut data, &header, &index).unwrap();
+        let (decoded_header, decoded) = decode_positions(&data).unwrap();
+        assert_eq!(decoded_header, header);
+        assert_eq!(decoded.file_count(), 2);
+        assert_eq!(decoded.occurrence_count(), 8);
+        assert_eq!(decoded.positions(7, hash_token(b"alpha")), &[0, 2, 4]);
+        assert_eq!(decoded.positions(2, hash_token(b"beta")), &[0, 300, 70000]);
+
+        assert!(decode_positions(&data[..data.len() - 1]).is_err());
+        let mut trailing = data.clone();
+        trailing.push(0);
+        assert!(decode_positions(&trailing).is_err());
+    }
+
+    #[test]
+    fn test_remove_files() {
+        let mut index = index_of(1, "alpha beta");
+        index.insert_file(2, vec![(hash_token(b"alpha"), vec![0])]);
+        index.remove_files(&RoaringBitmap::from_iter([1]));
+        assert_eq!(index.file_count(), 1);
+        assert!(index.positions(1, hash_token(b"alpha")).is_empty());
+    }
+}
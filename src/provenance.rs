@@ -0,0 +1,226 @@
+//! Build provenance: how, where and by whom an index was built
+//!
+//! A full scan records its effective `ScanConfig`, the canonical root, the
+//! tool version, host and user, the files it skipped and how long it took.
+//! The record is stored once, in the paths file, and lets an index be
+//! described by `tokenizer stats` and rebuilt by `tokenizer reindex`.
+
+use crate::error::{Result, TokenizerError};
+use crate::index::IndexHeader;
+use crate::scanner::ScanConfig;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// How a full scan built an index
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BuildInfo {
+    /// Effective scan configuration; the tokenizer is named by the header
+    pub config: ScanConfig,
+    /// Canonical path of the indexed root
+    pub root: PathBuf,
+    /// Version of the tool that built the index
+    pub tool_version: String,
+    /// Host the index was built on, when known
+    pub host: Option<String>,
+    /// User who built the index, when known
+    pub user: Option<String>,
+    /// Files left out of the index, or indexed without their contents
+    pub skipped: SkipCounts,
+    /// Wall-clock time of the scan (milliseconds)
+    pub duration_ms: u64,
+}
+
+impl BuildInfo {
+    /// Describe a scan of `root` with `config` run on this host
+    pub fn new(root: &Path, config: &ScanConfig) -> Self {
+        Self {
+            config: config.clone(),
+            root: root.canonicalize().unwrap_or_else(|_| root.to_path_buf()),
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            host: host_name(),
+            user: user_name(),
+            skipped: SkipCounts::default(),
+            duration_ms: 0,
+        }
+    }
+
+    /// Build duration
+    pub fn duration(&self) -> Duration {
+        Duration::from_millis(self.duration_ms)
+    }
+
+    /// Get the scan configuration to rebuild the index with
+    ///
+    /// The tokenizer comes from `header`; an index built with a custom
+    /// tokenizer has to be rebuilt by the caller that owns it.
+    pub fn rebuild_config(&self, header: &IndexHeader) -> Result<ScanConfig> {
+        if !self.root.is_dir() {
+            return Err(TokenizerError::IndexMismatch(format!(
+                "Index root {} no longer exists",
+                self.root.display()
+            )));
+        }
+        Ok(ScanConfig {
+            tokenizer: header.builtin_tokenizer()?,
+            ..self.config.clone()
+        })
+    }
+
+    /// Record the file filters an incremental update applied
+    ///
+    /// Updates keep the sections the index was built with, so only the
+    /// filters change. Returns true if the stored configuration changed.
+    pub(crate) fn record_update(&mut self, config: &ScanConfig) -> bool {
+        let stored = &self.config;
+        let changed = stored.extensions != config.extensions
+            || stored.exclude_patterns != config.exclude_patterns
+            || stored.include_globs != config.include_globs
+            || stored.exclude_globs != config.exclude_globs
+            || stored.max_file_size != config.max_file_size
+            || stored.hash_contents != config.hash_contents
+            || stored.respect_ignore_files != config.respect_ignore_files;
+        if changed {
+            self.config = ScanConfig {
+                extensions: config.extensions.clone(),
+                exclude_patterns: config.exclude_patterns.clone(),
+                include_globs: config.include_globs.clone(),
+                exclude_globs: config.exclude_globs.clone(),
+                max_file_size: config.max_file_size,
+                hash_contents: config.hash_contents,
+                respect_ignore_files: config.respect_ignore_files,
+                ..self.config.clone()
+            };
+        }
+        changed
+    }
+}
+
+/// Number of files skipped by a scan, by reason
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SkipCounts {
+    /// Left out by extension, excluded name or glob (files in pruned
+    /// directories are not counted)
+    pub excluded: u64,
+    /// Left out by `.gitignore` and the other ignore files
+    pub ignored: u64,
+    /// Left out for exceeding `max_file_size`
+    pub too_large: u64,
+    /// Indexed without contents because they look binary
+    pub binary: u64,
+    /// Indexed without contents because they could not be read
+    pub unreadable: u64,
+}
+
+impl SkipCounts {
+    /// Total number of skipped files
+    pub fn total(&self) -> u64 {
+        self.excluded + self.ignored + self.too_large + self.binary + self.unreadable
+    }
+}
+
+/// Why a walked file was skipped or indexed without its contents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SkipReason {
+    Excluded,
+    Ignored,
+    TooLarge,
+    Binary,
+    Unreadable,
+}
+
+/// `SkipCounts` that the walker threads can update concurrently
+#[derive(Debug, Default)]
+pub(crate) struct SkipCounters {
+    excluded: AtomicU64,
+    ignored: AtomicU64,
+    too_large: AtomicU64,
+    binary: AtomicU64,
+    unreadable: AtomicU64,
+}
+
+impl SkipCounters {
+    /// Count one skipped file
+    pub(crate) fn add(&self, reason: SkipReason) {
+        let counter = match reason {
+            SkipReason::Excluded => &self.excluded,
+            SkipReason::Ignored => &self.ignored,
+            SkipReason::TooLarge => &self.too_large,
+            SkipReason::Binary => &self.binary,
+            SkipReason::Unreadable => &self.unreadable,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Read the counts
+    pub(crate) fn counts(&self) -> SkipCounts {
+        SkipCounts {
+            excluded: self.excluded.load(Ordering::Relaxed),
+            ignored: self.ignored.load(Ordering::Relaxed),
+            too_large: self.too_large.load(Ordering::Relaxed),
+            binary: self.binary.load(Ordering::Relaxed),
+            unreadable: self.unreadable.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Name of this host, when it can be found
+fn host_name() -> Option<String> {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .or_else(|_| std::fs::read_to_string("/etc/hostname"))
+        .ok()
+        .map(|name| name.trim().to_string())
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .filter(|name| !name.is_empty())
+}
+
+/// Name of the current user, when it can be found
+fn user_name() -> Option<String> {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .ok()
+        .filter(|name| !name.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::LegacyTokenizer;
+    use std::sync::Arc;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_skip_counters() {
+        let counters = SkipCounters::default();
+        counters.add(SkipReason::Binary);
+        counters.add(SkipReason::Binary);
+        counters.add(SkipReason::TooLarge);
+        let counts = counters.counts();
+        assert_eq!(counts.binary, 2);
+        assert_eq!(counts.too_large, 1);
+        assert_eq!(counts.total(), 3);
+    }
+
+    #[test]
+    fn test_rebuild_config() {
+        let dir = tempdir().unwrap();
+        let config = ScanConfig {
+            extensions: vec!["rs".to_string()],
+            tokenizer: Arc::new(LegacyTokenizer),
+            ..Default::default()
+        };
+        let build = BuildInfo::new(&dir.path().join("."), &config);
+        assert_eq!(build.root, dir.path().canonicalize().unwrap());
+        assert_eq!(build.tool_version, env!("CARGO_PKG_VERSION"));
+
+        let header = IndexHeader::with_tokenizer(&LegacyTokenizer);
+        let rebuilt = build.rebuild_config(&header).unwrap();
+        assert_eq!(rebuilt.extensions, ["rs"]);
+        assert_eq!(rebuilt.tokenizer.name(), "legacy");
+
+        drop(dir);
+        assert!(build.rebuild_config(&header).is_err());
+    }
+}
@@ -1,7 +1,21 @@
-use crate::index::{ExactTokenIndex, PathIndex, TokenIndex, TrigramIndex};
-use crate::tokenizer::{tokenize_query, tokenize_query_exact, tokenize_query_exact_lower};
+use crate::error::{Result, TokenizerError};
+use crate::index::{PathIndex, TokenIndex};
+use crate::positions::{near_spans, PositionIndex, Span};
+use crate::ranking::TermFrequencies;
+use crate::subword::subword_hashes;
+use crate::tokenizer::{
+    hash_token, hash_token_lower, hash_token_normalized, hash_tokens_normalized, normalize_token,
+    tokenize_query, Tokenizer,
+};
 use crate::trigram::extract_query_trigrams;
+use crate::typo::similar_tokens;
+use crate::unicode::fold_token;
+use crate::view::{TokenLookup, TrigramLookup};
+use crate::wildcard::{TokenPattern, DEFAULT_MAX_EXPANSIONS};
 use roaring::RoaringBitmap;
+use rustc_hash::FxHashSet;
+use serde::{Deserialize, Serialize};
+use std::borrow::{Borrow, Cow};
 use std::path::PathBuf;
 
 /// Result of a query operation
@@ -15,15 +29,70 @@ pub struct QueryResult {
 
     /// Number of tokens that had matches in the index
     pub matched_token_count: usize,
+
+    /// How each wildcard or regex pattern in the query was expanded
+    pub expansions: Vec<PatternExpansion>,
+
+    /// Which indexed tokens stood in for each query token (`max_typos` only)
+    pub typos: Vec<TypoExpansion>,
+
+    /// BM25 score of each file, parallel to `files` (empty unless an exact
+    /// query ran against an index with term frequencies)
+    pub scores: Vec<f32>,
+
+    /// Share of the query's distinct trigrams found in each file, parallel
+    /// to `files` (fuzzy queries only)
+    pub match_ratios: Vec<f32>,
+}
+
+/// How one wildcard or regex pattern expanded against the token dictionary
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PatternExpansion {
+    /// Pattern as written in the query
+    pub pattern: String,
+
+    /// Number of indexed tokens the pattern matched
+    pub token_count: usize,
+
+    /// More tokens matched than the expansion limit allowed
+    pub truncated: bool,
+
+    /// Why the pattern could not be expanded (invalid regex, no dictionary)
+    pub error: Option<String>,
+}
+
+/// Indexed tokens that stood in for a possibly misspelled query token
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TypoExpansion {
+    /// Token as written in the query
+    pub token: String,
+
+    /// The token itself is in the index
+    pub indexed: bool,
+
+    /// Indexed tokens within the edit distance, closest first
+    pub substitutes: Vec<String>,
+
+    /// More tokens were close enough than the expansion limit allowed
+    pub truncated: bool,
+
+    /// Why close tokens could not be looked up (no dictionary)
+    pub error: Option<String>,
 }
 
 /// Query options
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct QueryOptions {
     /// Maximum number of results to return
     pub limit: Option<usize>,
 
     /// Require all tokens to match (AND) vs any token (OR)
+    ///
+    /// Defaults to AND when deserialized, as on the command line.
+    #[serde(default = "default_match_all")]
     pub match_all: bool,
 
     /// Filter to paths containing this substring
@@ -34,6 +103,60 @@ pub struct QueryOptions {
 
     /// Exclude files with paths containing this substring
     pub exclude: Option<String>,
+
+    /// Maximum tokens one wildcard pattern may expand to
+    /// (None = `DEFAULT_MAX_EXPANSIONS`)
+    pub max_expansions: Option<usize>,
+
+    /// Order of the results; `limit` keeps the first files in this order
+    pub sort: SortOrder,
+
+    /// Fuzzy queries: keep files containing at least this share (0.0-1.0)
+    /// of the query's distinct trigrams, instead of all (`match_all`) or any
+    pub min_match: Option<f32>,
+
+    /// Exact queries: also match indexed tokens within this many edits
+    /// (Levenshtein distance) of each query token
+    pub max_typos: Option<u32>,
+
+    /// Subword queries: all parts of the query must occur in this order
+    /// within a single identifier
+    pub in_order: bool,
+}
+
+fn default_match_all() -> bool {
+    true
+}
+
+/// Order of query results
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    /// File ID order, which is the order files were indexed in
+    #[default]
+    Id,
+    /// Path order
+    Path,
+    /// Highest BM25 score first for exact queries against an index with
+    /// term frequencies, highest trigram match ratio first for fuzzy
+    /// queries; other queries fall back to file ID order
+    Relevance,
+}
+
+impl std::str::FromStr for SortOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "id" => Ok(SortOrder::Id),
+            "path" => Ok(SortOrder::Path),
+            "relevance" => Ok(SortOrder::Relevance),
+            _ => Err(format!(
+                "unknown sort order \"{}\" (expected relevance, path or id)",
+                s
+            )),
+        }
+    }
 }
 
 /// Execute a query against the index (AND mode by default)
@@ -63,6 +186,10 @@ pub fn query_with_options(
             files: vec![],
             query_token_count: 0,
             matched_token_count: 0,
+            expansions: vec![],
+            typos: vec![],
+            scores: vec![],
+            match_ratios: vec![],
         };
     }
 
@@ -79,6 +206,10 @@ pub fn query_with_options(
             files: vec![],
             query_token_count,
             matched_token_count: 0,
+            expansions: vec![],
+            typos: vec![],
+            scores: vec![],
+            match_ratios: vec![],
         };
     }
 
@@ -108,6 +239,10 @@ pub fn query_with_options(
         files,
         query_token_count,
         matched_token_count,
+        expansions: vec![],
+        typos: vec![],
+        scores: vec![],
+        match_ratios: vec![],
     }
 }
 
@@ -116,37 +251,113 @@ pub fn query_with_options(
 // ============================================================================
 
 /// Execute an exact mode query (case-sensitive, preserves _ and -)
+///
+/// Query words with `*`/`?` wildcards or wrapped in `/.../` are expanded
+/// against the index's token dictionary; see `QueryResult::expansions`.
+/// `"quoted phrases"` and `a NEAR/n b` are matched by token position when
+/// the index has a positional index, and otherwise only need all their
+/// tokens.
+///
+/// When the index has term frequencies every file gets a BM25 score, and
+/// `SortOrder::Relevance` returns the highest scoring files first.
 pub fn query_exact(
     path_index: &PathIndex,
-    exact_index: &ExactTokenIndex,
+    exact_index: &impl TokenLookup,
     query_str: &str,
     options: &QueryOptions,
-) -> QueryResult {
-    let token_hashes = tokenize_query_exact(query_str);
-    let query_token_count = token_hashes.len();
+) -> Result<QueryResult> {
+    query_exact_terms(
+        path_index,
+        exact_index,
+        query_str,
+        options,
+        TokenForm::Exact,
+    )
+}
 
-    if token_hashes.is_empty() {
-        return QueryResult {
+/// Execute a case-insensitive exact mode query
+///
+/// Like `query_exact`, results are scored by BM25 when the index has term
+/// frequencies, and ranked by score with `SortOrder::Relevance`.
+pub fn query_exact_lower(
+    path_index: &PathIndex,
+    exact_lower_index: &impl TokenLookup,
+    query_str: &str,
+    options: &QueryOptions,
+) -> Result<QueryResult> {
+    query_exact_terms(
+        path_index,
+        exact_lower_index,
+        query_str,
+        options,
+        TokenForm::Lower,
+    )
+}
+
+/// Execute a style-insensitive exact mode query against the normalized index
+///
+/// Query tokens are stripped of `_` and `-` and case-folded like the
+/// indexed ones, so `userId` also finds `user_id` and `USER-ID`. Wildcards
+/// are matched against the normalized tokens.
+pub fn query_exact_normalized(
+    path_index: &PathIndex,
+    normalized_index: &impl TokenLookup,
+    query_str: &str,
+    options: &QueryOptions,
+) -> Result<QueryResult> {
+    query_exact_terms(
+        path_index,
+        normalized_index,
+        query_str,
+        options,
+        TokenForm::Normalized,
+    )
+}
+
+fn query_exact_terms(
+    path_index: &PathIndex,
+    index: &impl TokenLookup,
+    query_str: &str,
+    options: &QueryOptions,
+    form: TokenForm,
+) -> Result<QueryResult> {
+    let ExactQuery {
+        terms,
+        expansions,
+        typos,
+    } = parse_exact_terms(index, query_str, form, options)?;
+    let query_token_count = terms.len();
+
+    if terms.is_empty() {
+        return Ok(QueryResult {
             files: vec![],
             query_token_count: 0,
             matched_token_count: 0,
-        };
+            expansions,
+            typos,
+            scores: vec![],
+            match_ratios: vec![],
+        });
     }
 
-    // Collect bitmaps for each token
-    let bitmaps: Vec<&RoaringBitmap> = token_hashes
-        .iter()
-        .filter_map(|hash| exact_index.get_bitmap(*hash))
-        .collect();
+    // Collect bitmaps for each token or pattern
+    let mut bitmaps: Vec<Cow<RoaringBitmap>> = Vec::with_capacity(terms.len());
+    for term in &terms {
+        bitmaps.extend(term.evaluate(index)?);
+    }
 
     let matched_token_count = bitmaps.len();
 
     if bitmaps.is_empty() {
-        return QueryResult {
+        return Ok(QueryResult {
             files: vec![],
             query_token_count,
             matched_token_count: 0,
-        };
+            expansions,
+            typos,
+            scores: vec![],
+            match_ratios: vec![],
+        });
     }
 
     let result = if options.match_all {
@@ -155,62 +366,495 @@ pub fn query_exact(
         union_bitmaps(&bitmaps)
     };
 
-    let files = resolve_file_ids(path_index, &result, options);
+    let ranking = match index.frequencies() {
+        Some(frequencies) => Some((frequencies, weigh_tokens(frequencies, index, &terms)?)),
+        None => None,
+    };
+    let score = ranking
+        .as_ref()
+        .map(|(frequencies, tokens)| |file_id| frequencies.bm25(file_id, tokens));
+    let (files, scores) = select_files(path_index, &result, options, score);
 
-    QueryResult {
+    Ok(QueryResult {
         files,
         query_token_count,
         matched_token_count,
+        expansions,
+        typos,
+        scores,
+        match_ratios: vec![],
+    })
+}
+
+/// Pair every distinct token of the query terms with its inverse document
+/// frequency, for BM25 scoring
+fn weigh_tokens(
+    frequencies: &TermFrequencies,
+    index: &impl TokenLookup,
+    terms: &[ExactTerm],
+) -> Result<Vec<(u64, f32)>> {
+    let mut hashes = Vec::new();
+    for term in terms {
+        term.collect_hashes(&mut hashes);
+    }
+    hashes.sort_unstable();
+    hashes.dedup();
+
+    let mut weights = Vec::with_capacity(hashes.len());
+    for hash in hashes {
+        if let Some(bitmap) = index.token_bitmap(hash)? {
+            weights.push((hash, frequencies.idf(bitmap.len())));
+        }
     }
+    Ok(weights)
 }
 
-/// Execute a case-insensitive exact mode query
-pub fn query_exact_lower(
-    path_index: &PathIndex,
-    exact_lower_index: &ExactTokenIndex,
+/// One term of an exact-mode query
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ExactTerm {
+    /// Token slots that must occur one after another: a single token or
+    /// pattern has one slot, a quoted phrase one per token. Each slot lists
+    /// the token hashes that may fill it.
+    Sequence(Vec<Vec<u64>>),
+    /// Two terms at most `distance` tokens apart (`a NEAR/n b`)
+    Near(Box<ExactTerm>, Box<ExactTerm>, u32),
+}
+
+impl ExactTerm {
+    /// Add the hash of every token the term can match
+    fn collect_hashes(&self, hashes: &mut Vec<u64>) {
+        match self {
+            ExactTerm::Sequence(slots) => hashes.extend(slots.iter().flatten()),
+            ExactTerm::Near(lhs, rhs, _) => {
+                lhs.collect_hashes(hashes);
+                rhs.collect_hashes(hashes);
+            }
+        }
+    }
+
+    /// Check whether matching the term needs token positions
+    fn is_positional(&self) -> bool {
+        match self {
+            ExactTerm::Sequence(slots) => slots.len() > 1,
+            ExactTerm::Near(..) => true,
+        }
+    }
+
+    /// Get the files containing every token of the term, in any order
+    fn candidates<'a>(
+        &self,
+        index: &'a impl TokenLookup,
+    ) -> Result<Option<Cow<'a, RoaringBitmap>>> {
+        match self {
+            ExactTerm::Sequence(slots) => {
+                let mut bitmaps = Vec::with_capacity(slots.len());
+                for slot in slots {
+                    let mut slot_bitmaps: Vec<Cow<RoaringBitmap>> = Vec::new();
+                    for hash in slot {
+                        slot_bitmaps.extend(index.token_bitmap(*hash)?);
+                    }
+                    bitmaps.push(match slot_bitmaps.len() {
+                        0 => return Ok(None),
+                        1 => slot_bitmaps.pop().unwrap(),
+                        _ => Cow::Owned(union_bitmaps(&slot_bitmaps)),
+                    });
+                }
+                if bitmaps.len() == 1 {
+                    Ok(bitmaps.pop())
+                } else {
+                    Ok(Some(Cow::Owned(intersect_bitmaps(&bitmaps))))
+                }
+            }
+            ExactTerm::Near(lhs, rhs, _) => {
+                let (Some(lhs), Some(rhs)) = (lhs.candidates(index)?, rhs.candidates(index)?)
+                else {
+                    return Ok(None);
+                };
+                Ok(Some(Cow::Owned(lhs.as_ref() & rhs.as_ref())))
+            }
+        }
+    }
+
+    /// Get where the term occurs in a file
+    fn spans(&self, positions: &PositionIndex, file_id: u32) -> Vec<Span> {
+        match self {
+            ExactTerm::Sequence(slots) => positions.sequence_spans(file_id, slots),
+            ExactTerm::Near(lhs, rhs, distance) => near_spans(
+                &lhs.spans(positions, file_id),
+                &rhs.spans(positions, file_id),
+                *distance,
+            ),
+        }
+    }
+
+    /// Get the files matching the term
+    ///
+    /// Without a positional index, phrases and `NEAR/n` only require all
+    /// their tokens to be present.
+    fn evaluate<'a>(&self, index: &'a impl TokenLookup) -> Result<Option<Cow<'a, RoaringBitmap>>> {
+        let Some(candidates) = self.candidates(index)? else {
+            return Ok(None);
+        };
+        Ok(Some(match index.positions() {
+            Some(positions) if self.is_positional() => Cow::Owned(
+                candidates
+                    .iter()
+                    .filter(|file_id| !self.spans(positions, *file_id).is_empty())
+                    .collect(),
+            ),
+            _ => candidates,
+        }))
+    }
+}
+
+/// Split an exact-mode query into `"quoted phrases"` and bare words
+///
+/// An unterminated quote runs to the end of the query. The flag is true
+/// for phrases.
+fn lex_exact_query(query_str: &str) -> Vec<(&str, bool)> {
+    let mut lexemes = Vec::new();
+    let mut rest = query_str;
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return lexemes;
+        }
+        if let Some(after) = rest.strip_prefix('"') {
+            let end = after.find('"').unwrap_or(after.len());
+            lexemes.push((&after[..end], true));
+            rest = after.get(end + 1..).unwrap_or("");
+        } else {
+            let end = rest
+                .find(|c: char| c.is_whitespace() || c == '"')
+                .unwrap_or(rest.len());
+            lexemes.push((&rest[..end], false));
+            rest = &rest[end..];
+        }
+    }
+}
+
+/// Check whether a query uses phrase or `NEAR/n` syntax
+///
+/// Those need a positional index (`ScanConfig::build_positions`) to be
+/// matched exactly.
+pub fn has_positional_syntax(query_str: &str) -> bool {
+    let lexemes = lex_exact_query(query_str);
+    lexemes.iter().enumerate().any(|(i, (text, phrase))| {
+        if *phrase {
+            text.split_whitespace().nth(1).is_some()
+        } else {
+            i > 0 && parse_near(text).is_some()
+        }
+    })
+}
+
+fn parse_near(word: &str) -> Option<u32> {
+    word.strip_prefix("NEAR/")?.parse().ok()
+}
+
+/// Bitmaps of the terms of a query, with how its words were expanded
+struct TermBitmaps<'a> {
+    /// One bitmap per term, None when no file matched
+    bitmaps: Vec<Option<Cow<'a, RoaringBitmap>>>,
+    expansions: Vec<PatternExpansion>,
+    typos: Vec<TypoExpansion>,
+}
+
+/// Look up every term of an exact-mode query
+///
+/// See `parse_exact_terms` for how the query is split into terms.
+fn lookup_exact_terms<'a>(
+    index: &'a impl TokenLookup,
     query_str: &str,
+    form: TokenForm,
     options: &QueryOptions,
-) -> QueryResult {
-    let token_hashes = tokenize_query_exact_lower(query_str);
-    let query_token_count = token_hashes.len();
+) -> Result<TermBitmaps<'a>> {
+    let query = parse_exact_terms(index, query_str, form, options)?;
+    Ok(TermBitmaps {
+        bitmaps: query
+            .terms
+            .iter()
+            .map(|term| term.evaluate(index))
+            .collect::<Result<_>>()?,
+        expansions: query.expansions,
+        typos: query.typos,
+    })
+}
 
-    if token_hashes.is_empty() {
-        return QueryResult {
-            files: vec![],
-            query_token_count: 0,
-            matched_token_count: 0,
-        };
+/// How the tokens of an exact index were folded before hashing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TokenForm {
+    /// As written (`hash_token`)
+    Exact,
+    /// ASCII-lowercased (`hash_token_lower`)
+    Lower,
+    /// Stripped of `_` and `-` and lowercased (`hash_token_normalized`)
+    Normalized,
+}
+
+impl TokenForm {
+    /// Split query text with the index's tokenizer and hash the tokens
+    fn tokenize(self, tokenizer: &dyn Tokenizer, text: &str) -> Vec<u64> {
+        let tokens = tokenizer.tokens(text.as_bytes());
+        match self {
+            TokenForm::Exact => tokens.map(hash_token).collect(),
+            TokenForm::Lower => tokens.map(hash_token_lower).collect(),
+            TokenForm::Normalized => hash_tokens_normalized(tokens).collect(),
+        }
     }
 
-    // Collect bitmaps for each token
-    let bitmaps: Vec<&RoaringBitmap> = token_hashes
-        .iter()
-        .filter_map(|hash| exact_lower_index.get_bitmap(*hash))
-        .collect();
+    /// Fold a single token, returning it with its hash
+    fn fold(self, token: &[u8]) -> (Cow<'_, [u8]>, u64) {
+        match self {
+            TokenForm::Exact => (Cow::Borrowed(token), hash_token(token)),
+            TokenForm::Lower => (Cow::Owned(fold_token(token)), hash_token_lower(token)),
+            TokenForm::Normalized => (
+                Cow::Owned(normalize_token(token)),
+                hash_token_normalized(token),
+            ),
+        }
+    }
 
-    let matched_token_count = bitmaps.len();
+    /// Parse a query word as a wildcard or regex pattern over folded tokens
+    fn pattern(self, word: &str) -> Option<std::result::Result<TokenPattern, String>> {
+        match self {
+            TokenForm::Exact => TokenPattern::parse(word, false),
+            TokenForm::Lower => TokenPattern::parse(word, true),
+            // Regexes are left as written; wildcards are folded like tokens
+            TokenForm::Normalized if word.starts_with('/') => TokenPattern::parse(word, true),
+            TokenForm::Normalized => {
+                let folded = normalize_token(word.as_bytes());
+                TokenPattern::parse(&String::from_utf8_lossy(&folded), true)
+            }
+        }
+    }
+}
 
-    if bitmaps.is_empty() {
-        return QueryResult {
-            files: vec![],
-            query_token_count,
-            matched_token_count: 0,
-        };
+/// An exact-mode query split into terms
+struct ExactQuery {
+    terms: Vec<ExactTerm>,
+    /// Expansion of each wildcard or regex word
+    expansions: Vec<PatternExpansion>,
+    /// Substitutes found for query tokens (`max_typos` only)
+    typos: Vec<TypoExpansion>,
+}
+
+/// Split an exact-mode query into terms
+///
+/// Bare words are split into tokens, each its own term; a wildcard or regex
+/// word is one term matching any token it expands to. A quoted phrase is one
+/// term whose tokens must be adjacent, and `a NEAR/n b` joins the terms on
+/// either side. Phrases and `NEAR/n` are only checked when the index has
+/// token positions.
+///
+/// With `options.max_typos` each token of a bare word also matches the
+/// indexed tokens within that edit distance.
+fn parse_exact_terms(
+    index: &impl TokenLookup,
+    query_str: &str,
+    form: TokenForm,
+    options: &QueryOptions,
+) -> Result<ExactQuery> {
+    let mut terms: Vec<ExactTerm> = Vec::new();
+    let mut expansions = Vec::new();
+    let mut typos = Vec::new();
+    let mut near: Option<u32> = None;
+
+    for (text, phrase) in lex_exact_query(query_str) {
+        let mut new_terms = Vec::new();
+        if phrase {
+            let slots: Vec<Vec<u64>> = form
+                .tokenize(index.tokenizer(), text)
+                .into_iter()
+                .map(|h| vec![h])
+                .collect();
+            if !slots.is_empty() {
+                new_terms.push(ExactTerm::Sequence(slots));
+            }
+        } else if let (Some(distance), false) = (parse_near(text), terms.is_empty()) {
+            near = Some(distance);
+            continue;
+        } else if let Some(pattern) = form.pattern(text) {
+            let (hashes, expansion) = expand_pattern(index, text, pattern, options)?;
+            new_terms.push(ExactTerm::Sequence(vec![hashes]));
+            expansions.push(expansion);
+        } else if let Some(max_typos) = options.max_typos {
+            for token in index.tokenizer().tokens(text.as_bytes()) {
+                let (hashes, typo) = widen_token(index, token, form, max_typos, options)?;
+                new_terms.push(ExactTerm::Sequence(vec![hashes]));
+                typos.extend(typo);
+            }
+        } else {
+            new_terms.extend(
+                form.tokenize(index.tokenizer(), text)
+                    .into_iter()
+                    .map(|hash| ExactTerm::Sequence(vec![vec![hash]])),
+            );
+        }
+
+        // NEAR binds the last term before it to the first term after it
+        let mut new_terms = new_terms.into_iter();
+        if let Some(distance) = near {
+            if let (Some(rhs), Some(lhs)) = (new_terms.next(), terms.pop()) {
+                terms.push(ExactTerm::Near(Box::new(lhs), Box::new(rhs), distance));
+                near = None;
+            }
+        }
+        terms.extend(new_terms);
     }
 
-    let result = if options.match_all {
+    Ok(ExactQuery {
+        terms,
+        expansions,
+        typos,
+    })
+}
+
+/// Get the hashes of a query token and of the indexed tokens within
+/// `max_typos` edits of it
+///
+/// The report is left out when the token is indexed and nothing else was
+/// close enough.
+fn widen_token(
+    index: &impl TokenLookup,
+    token: &[u8],
+    form: TokenForm,
+    max_typos: u32,
+    options: &QueryOptions,
+) -> Result<(Vec<u64>, Option<TypoExpansion>)> {
+    let (token, hash) = form.fold(token);
+    let mut typo = TypoExpansion {
+        token: String::from_utf8_lossy(&token).into_owned(),
+        indexed: index.token_bitmap(hash)?.is_some(),
+        ..Default::default()
+    };
+    let mut hashes = vec![hash];
+
+    let Some(vocabulary) = index.vocabulary() else {
+        typo.error = Some("index has no token dictionary".to_string());
+        return Ok((hashes, Some(typo)));
+    };
+    let limit = options.max_expansions.unwrap_or(DEFAULT_MAX_EXPANSIONS);
+    for (similar_token, similar_hash, _) in similar_tokens(vocabulary, &token, max_typos) {
+        if similar_hash == hash || index.token_bitmap(similar_hash)?.is_none() {
+            continue;
+        }
+        if typo.substitutes.len() == limit {
+            typo.truncated = true;
+            break;
+        }
+        hashes.push(similar_hash);
+        typo.substitutes
+            .push(String::from_utf8_lossy(similar_token).into_owned());
+    }
+
+    let report = !typo.indexed || !typo.substitutes.is_empty();
+    Ok((hashes, report.then_some(typo)))
+}
+
+/// Expand a wildcard or regex word into the hashes of the indexed tokens it
+/// matches
+fn expand_pattern(
+    index: &impl TokenLookup,
+    word: &str,
+    pattern: std::result::Result<TokenPattern, String>,
+    options: &QueryOptions,
+) -> Result<(Vec<u64>, PatternExpansion)> {
+    let mut expansion = PatternExpansion {
+        pattern: word.to_string(),
+        ..Default::default()
+    };
+    let pattern = match (pattern, index.vocabulary()) {
+        (Ok(pattern), Some(vocabulary)) => (pattern, vocabulary),
+        (Err(e), _) => {
+            expansion.error = Some(e);
+            return Ok((Vec::new(), expansion));
+        }
+        (_, None) => {
+            expansion.error = Some("index has no token dictionary".to_string());
+            return Ok((Vec::new(), expansion));
+        }
+    };
+
+    let (vocabulary_pattern, vocabulary) = pattern;
+    let limit = options.max_expansions.unwrap_or(DEFAULT_MAX_EXPANSIONS);
+    let (expanded, truncated) = vocabulary_pattern.expand(vocabulary, limit);
+    let mut hashes = Vec::with_capacity(expanded.len());
+    for hash in expanded {
+        if index.token_bitmap(hash)?.is_some() {
+            hashes.push(hash);
+        }
+    }
+    expansion.token_count = hashes.len();
+    expansion.truncated = truncated;
+    Ok((hashes, expansion))
+}
+
+// ============================================================================
+// Subword Mode Query (uses SubwordIndex)
+// ============================================================================
+
+/// Execute a subword query: identifier parts instead of whole tokens
+///
+/// Query tokens are split into parts like the indexed ones (`getUser` is
+/// `get` and `user`), ignoring case. Each part is a term combined by
+/// `options.match_all`, so `User` finds `getUserById`. With
+/// `options.in_order` a file instead needs one identifier holding all the
+/// parts in query order: `user id` finds `getUserById` but not `idOfUser`.
+///
+/// `exact_index` must be the case-sensitive exact index with its subword
+/// index attached; without one this fails.
+pub fn query_subword(
+    path_index: &PathIndex,
+    exact_index: &impl TokenLookup,
+    query_str: &str,
+    options: &QueryOptions,
+) -> Result<QueryResult> {
+    let subwords = exact_index.subwords().ok_or_else(|| {
+        TokenizerError::InvalidIndexFormat("index has no subword index (.sub file)".to_string())
+    })?;
+
+    let mut parts: Vec<u64> = exact_index
+        .tokenizer()
+        .tokens(query_str.as_bytes())
+        .flat_map(subword_hashes)
+        .collect();
+    if !options.in_order {
+        parts.sort_unstable();
+        parts.dedup();
+    }
+    let query_token_count = parts.len();
+
+    let bitmaps: Vec<&RoaringBitmap> = parts
+        .iter()
+        .filter_map(|part| subwords.part_bitmap(*part))
+        .collect();
+    let matched_token_count = bitmaps.len();
+
+    let result = if bitmaps.is_empty() {
+        RoaringBitmap::new()
+    } else if options.in_order && parts.len() > 1 {
+        let mut tokens: Vec<Cow<RoaringBitmap>> = Vec::new();
+        for token in subwords.tokens_in_order(&parts) {
+            tokens.extend(exact_index.token_bitmap(token)?);
+        }
+        union_bitmaps(&tokens)
+    } else if options.match_all || options.in_order {
         intersect_bitmaps(&bitmaps)
     } else {
         union_bitmaps(&bitmaps)
     };
-
     let files = resolve_file_ids(path_index, &result, options);
 
-    QueryResult {
+    Ok(QueryResult {
         files,
         query_token_count,
         matched_token_count,
-    }
+        expansions: vec![],
+        typos: vec![],
+        scores: vec![],
+        match_ratios: vec![],
+    })
 }
 
 // ============================================================================
@@ -218,62 +862,528 @@ pub fn query_exact_lower(
 // ============================================================================
 
 /// Execute a fuzzy mode query (case-insensitive trigrams)
+///
+/// With `options.min_match` a file needs that share of the query's distinct
+/// trigrams; otherwise all of them (`match_all`) or any. Each file's share
+/// is returned in `QueryResult::match_ratios`, and `SortOrder::Relevance`
+/// puts the best matches first.
 pub fn query_fuzzy(
     path_index: &PathIndex,
-    trigram_index: &TrigramIndex,
+    trigram_index: &impl TrigramLookup,
     query_str: &str,
     options: &QueryOptions,
-) -> QueryResult {
+) -> Result<QueryResult> {
     let trigrams = extract_query_trigrams(query_str);
     let query_token_count = trigrams.len();
 
     if trigrams.is_empty() {
-        return QueryResult {
+        return Ok(QueryResult {
             files: vec![],
             query_token_count: 0,
             matched_token_count: 0,
-        };
+            expansions: vec![],
+            typos: vec![],
+            scores: vec![],
+            match_ratios: vec![],
+        });
     }
 
     // Collect bitmaps for each trigram
-    let bitmaps: Vec<&RoaringBitmap> = trigrams
-        .iter()
-        .filter_map(|trigram| trigram_index.get_bitmap(*trigram))
-        .collect();
+    let mut matched_trigrams = Vec::new();
+    let mut bitmaps: Vec<Cow<RoaringBitmap>> = Vec::new();
+    for trigram in &trigrams {
+        if let Some(bitmap) = trigram_index.trigram_bitmap(*trigram)? {
+            matched_trigrams.push(*trigram);
+            bitmaps.push(bitmap);
+        }
+    }
 
     let matched_token_count = bitmaps.len();
 
     if bitmaps.is_empty() {
-        return QueryResult {
+        return Ok(QueryResult {
             files: vec![],
             query_token_count,
             matched_token_count: 0,
-        };
+            expansions: vec![],
+            typos: vec![],
+            scores: vec![],
+            match_ratios: vec![],
+        });
+    }
+
+    let distinct = trigrams.iter().collect::<FxHashSet<_>>().len();
+
+    // AND: every file holds all trigrams that are in the index
+    if options.match_all && options.min_match.is_none() {
+        let result = intersect_bitmaps(&bitmaps);
+        let files = resolve_file_ids(path_index, &result, options);
+        let matched = matched_trigrams.iter().collect::<FxHashSet<_>>().len();
+        let match_ratios = vec![matched as f32 / distinct as f32; files.len()];
+        return Ok(QueryResult {
+            files,
+            query_token_count,
+            matched_token_count,
+            expansions: vec![],
+            typos: vec![],
+            scores: vec![],
+            match_ratios,
+        });
     }
 
     // For fuzzy search, we typically want files that match MOST trigrams
     // but not necessarily ALL (since partial matches are useful)
-    let result = if options.match_all {
-        intersect_bitmaps(&bitmaps)
-    } else {
-        union_bitmaps(&bitmaps)
+    let needed = options.min_match.map_or(1, |ratio| {
+        // The small slack keeps e.g. 0.3 of 10 trigrams at 3, not 4
+        let needed = (f64::from(ratio.clamp(0.0, 1.0)) * distinct as f64 - 1e-6).ceil();
+        (needed as u32).max(1)
+    });
+    let hits = trigram_hits(&matched_trigrams, &bitmaps);
+    let result: RoaringBitmap = hits
+        .iter()
+        .enumerate()
+        .filter(|(_, count)| **count >= needed)
+        .map(|(file_id, _)| file_id as u32)
+        .collect();
+
+    let ratio = |file_id: u32| hits[file_id as usize] as f32 / distinct as f32;
+    let (files, match_ratios) = select_files(path_index, &result, options, Some(ratio));
+
+    Ok(QueryResult {
+        files,
+        query_token_count,
+        matched_token_count,
+        expansions: vec![],
+        typos: vec![],
+        scores: vec![],
+        match_ratios,
+    })
+}
+
+/// Count how many distinct query trigrams each file contains, by file ID
+fn trigram_hits(trigrams: &[u64], bitmaps: &[Cow<RoaringBitmap>]) -> Vec<u32> {
+    let len = bitmaps
+        .iter()
+        .filter_map(|bitmap| bitmap.max())
+        .max()
+        .map_or(0, |max| max as usize + 1);
+    let mut hits = vec![0u32; len];
+    let mut seen = FxHashSet::default();
+    for (trigram, bitmap) in trigrams.iter().zip(bitmaps) {
+        if seen.insert(*trigram) {
+            for file_id in bitmap.iter() {
+                hits[file_id as usize] += 1;
+            }
+        }
+    }
+    hits
+}
+
+// ============================================================================
+// Boolean Query Language
+// ============================================================================
+
+/// Parsed boolean query, e.g. `(async OR await) AND tokio NOT deprecated`
+///
+/// Operators are the uppercase words `AND`, `OR` and `NOT`; adjacent terms
+/// are implicitly ANDed and `NOT` binds tighter than `AND`, which binds
+/// tighter than `OR`. Double quotes make a term literal, so `"OR"` searches
+/// for the token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryExpr {
+    /// A bare or quoted term; files must contain all of its tokens
+    Term {
+        /// Term text without quotes
+        text: String,
+        /// Byte offset of the term in the query string
+        position: usize,
+    },
+    /// Files matching both sides
+    And(Box<QueryExpr>, Box<QueryExpr>),
+    /// Files matching either side
+    Or(Box<QueryExpr>, Box<QueryExpr>),
+    /// Files not matching the inner expression
+    Not(Box<QueryExpr>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Lexeme {
+    LParen,
+    RParen,
+    And,
+    Or,
+    Not,
+    Word(String),
+    Quoted(String),
+}
+
+/// Split a boolean query into lexemes with their byte offsets
+fn lex_query(query_str: &str) -> Result<Vec<(usize, Lexeme)>> {
+    let mut lexemes = Vec::new();
+    let mut chars = query_str.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                lexemes.push((start, Lexeme::LParen));
+            }
+            ')' => {
+                chars.next();
+                lexemes.push((start, Lexeme::RParen));
+            }
+            '"' => {
+                chars.next();
+                let mut text = String::new();
+                let mut closed = false;
+                while let Some((_, c)) = chars.next() {
+                    match c {
+                        '"' => {
+                            closed = true;
+                            break;
+                        }
+                        '\\' => {
+                            if let Some((_, escaped)) = chars.next() {
+                                text.push(escaped);
+                            }
+                        }
+                        c => text.push(c),
+                    }
+                }
+                if !closed {
+                    return Err(syntax_error(start, "unterminated quoted term"));
+                }
+                lexemes.push((start, Lexeme::Quoted(text)));
+            }
+            _ => {
+                let mut end = query_str.len();
+                while let Some(&(idx, c)) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, '(' | ')' | '"') {
+                        end = idx;
+                        break;
+                    }
+                    chars.next();
+                }
+                let lexeme = match &query_str[start..end] {
+                    "AND" => Lexeme::And,
+                    "OR" => Lexeme::Or,
+                    "NOT" => Lexeme::Not,
+                    word => Lexeme::Word(word.to_string()),
+                };
+                lexemes.push((start, lexeme));
+            }
+        }
+    }
+
+    Ok(lexemes)
+}
+
+fn syntax_error(position: usize, message: impl Into<String>) -> TokenizerError {
+    TokenizerError::QuerySyntax {
+        position,
+        message: message.into(),
+    }
+}
+
+/// Recursive descent parser over the lexemes of one query
+struct QueryParser {
+    lexemes: Vec<(usize, Lexeme)>,
+    pos: usize,
+    end: usize,
+}
+
+impl QueryParser {
+    fn peek(&self) -> Option<&Lexeme> {
+        self.lexemes.get(self.pos).map(|(_, lexeme)| lexeme)
+    }
+
+    /// Byte offset of the next lexeme, or the end of the query
+    fn offset(&self) -> usize {
+        self.lexemes
+            .get(self.pos)
+            .map_or(self.end, |(offset, _)| *offset)
+    }
+
+    fn parse_or(&mut self) -> Result<QueryExpr> {
+        let mut expr = self.parse_and()?;
+        while self.peek() == Some(&Lexeme::Or) {
+            self.pos += 1;
+            let rhs = self.parse_and()?;
+            expr = QueryExpr::Or(Box::new(expr), Box::new(rhs));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<QueryExpr> {
+        let mut expr = self.parse_unary()?;
+        loop {
+            match self.peek() {
+                Some(Lexeme::And) => self.pos += 1,
+                // Adjacent terms and `a NOT b` are implicit ANDs
+                Some(Lexeme::Not | Lexeme::LParen | Lexeme::Word(_) | Lexeme::Quoted(_)) => {}
+                _ => break,
+            }
+            let rhs = self.parse_unary()?;
+            expr = QueryExpr::And(Box::new(expr), Box::new(rhs));
+        }
+        Ok(expr)
+    }
+
+    fn parse_unary(&mut self) -> Result<QueryExpr> {
+        if self.peek() == Some(&Lexeme::Not) {
+            self.pos += 1;
+            return Ok(QueryExpr::Not(Box::new(self.parse_unary()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<QueryExpr> {
+        let offset = self.offset();
+        let Some((_, lexeme)) = self.lexemes.get(self.pos).cloned() else {
+            return Err(syntax_error(offset, "expected a term"));
+        };
+
+        match lexeme {
+            Lexeme::Word(text) | Lexeme::Quoted(text) => {
+                self.pos += 1;
+                Ok(QueryExpr::Term {
+                    text,
+                    position: offset,
+                })
+            }
+            Lexeme::LParen => {
+                self.pos += 1;
+                let expr = self.parse_or()?;
+                if self.peek() != Some(&Lexeme::RParen) {
+                    return Err(syntax_error(offset, "unclosed '('"));
+                }
+                self.pos += 1;
+                Ok(expr)
+            }
+            Lexeme::RParen => Err(syntax_error(offset, "unexpected ')'")),
+            Lexeme::And => Err(syntax_error(offset, "expected a term before AND")),
+            Lexeme::Or => Err(syntax_error(offset, "expected a term before OR")),
+            Lexeme::Not => unreachable!("NOT is handled by parse_unary"),
+        }
+    }
+}
+
+/// Parse a boolean query string
+pub fn parse_query(query_str: &str) -> Result<QueryExpr> {
+    let mut parser = QueryParser {
+        lexemes: lex_query(query_str)?,
+        pos: 0,
+        end: query_str.len(),
+    };
+
+    if parser.lexemes.is_empty() {
+        return Err(syntax_error(0, "empty query"));
+    }
+
+    let expr = parser.parse_or()?;
+    if parser.pos < parser.lexemes.len() {
+        // parse_and stops only at OR, ')' or the end, so this is a stray ')'
+        return Err(syntax_error(parser.offset(), "unmatched ')'"));
+    }
+    Ok(expr)
+}
+
+impl QueryExpr {
+    /// Evaluate to the set of matching file IDs
+    ///
+    /// `term_bitmap` resolves a term to the files containing it; `all_files`
+    /// is the set `NOT` is taken against.
+    fn evaluate<F>(&self, all_files: &RoaringBitmap, term_bitmap: &mut F) -> Result<RoaringBitmap>
+    where
+        F: FnMut(&str, usize) -> Result<RoaringBitmap>,
+    {
+        match self {
+            QueryExpr::Term { text, position } => term_bitmap(text, *position),
+            // `a AND NOT b` is a difference; no need to complement b
+            QueryExpr::And(lhs, rhs) => match (lhs.as_ref(), rhs.as_ref()) {
+                (expr, QueryExpr::Not(negated)) | (QueryExpr::Not(negated), expr) => {
+                    let mut result = expr.evaluate(all_files, term_bitmap)?;
+                    if !result.is_empty() {
+                        result -= negated.evaluate(all_files, term_bitmap)?;
+                    }
+                    Ok(result)
+                }
+                _ => {
+                    let mut result = lhs.evaluate(all_files, term_bitmap)?;
+                    if !result.is_empty() {
+                        result &= rhs.evaluate(all_files, term_bitmap)?;
+                    }
+                    Ok(result)
+                }
+            },
+            QueryExpr::Or(lhs, rhs) => {
+                let mut result = lhs.evaluate(all_files, term_bitmap)?;
+                result |= rhs.evaluate(all_files, term_bitmap)?;
+                Ok(result)
+            }
+            QueryExpr::Not(inner) => Ok(all_files - inner.evaluate(all_files, term_bitmap)?),
+        }
+    }
+}
+
+/// Evaluate a boolean query, resolving each term with `term_lookup`
+///
+/// `term_lookup` returns one bitmap per key of the term (token, trigram or
+/// expanded pattern) plus any pattern and typo expansions. A term matches the files
+/// containing all of its keys. Terms without any indexable key are a syntax
+/// error, since they would otherwise silently match nothing (or everything
+/// under `NOT`).
+fn query_boolean<'a, T>(
+    path_index: &PathIndex,
+    query_str: &str,
+    options: &QueryOptions,
+    term_lookup: T,
+) -> Result<QueryResult>
+where
+    T: Fn(&str) -> Result<TermBitmaps<'a>>,
+{
+    let expr = parse_query(query_str)?;
+    let mut query_token_count = 0;
+    let mut matched_token_count = 0;
+    let mut expansions = Vec::new();
+    let mut typos = Vec::new();
+
+    let mut term_bitmap = |text: &str, position: usize| {
+        let term = term_lookup(text)?;
+        expansions.extend(term.expansions);
+        typos.extend(term.typos);
+        let keys = term.bitmaps;
+        if keys.is_empty() {
+            return Err(syntax_error(
+                position,
+                format!("term \"{}\" has no indexable tokens", text),
+            ));
+        }
+        let key_count = keys.len();
+        query_token_count += key_count;
+
+        let bitmaps: Vec<Cow<RoaringBitmap>> = keys.into_iter().flatten().collect();
+        matched_token_count += bitmaps.len();
+        if bitmaps.len() < key_count {
+            return Ok(RoaringBitmap::new());
+        }
+        Ok(intersect_bitmaps(&bitmaps))
     };
 
+    let result = expr.evaluate(&path_index.file_ids(), &mut term_bitmap)?;
     let files = resolve_file_ids(path_index, &result, options);
 
-    QueryResult {
+    Ok(QueryResult {
         files,
         query_token_count,
         matched_token_count,
+        expansions,
+        typos,
+        scores: vec![],
+        match_ratios: vec![],
+    })
+}
+
+/// Execute a boolean query against the case-sensitive exact index
+///
+/// `options.match_all` is ignored; the expression decides how terms combine.
+/// Terms may be wildcard or regex patterns, as in `query_exact`, and quoted
+/// terms of several tokens are phrases.
+pub fn query_boolean_exact(
+    path_index: &PathIndex,
+    exact_index: &impl TokenLookup,
+    query_str: &str,
+    options: &QueryOptions,
+) -> Result<QueryResult> {
+    query_boolean(path_index, query_str, options, |text| {
+        lookup_exact_terms(exact_index, &boolean_term(text), TokenForm::Exact, options)
+    })
+}
+
+/// Write a boolean-mode term in exact-mode syntax
+///
+/// Only quoted terms can contain whitespace; they become phrases.
+fn boolean_term(text: &str) -> Cow<'_, str> {
+    if text.contains(char::is_whitespace) {
+        Cow::Owned(format!("\"{}\"", text.replace('"', " ")))
+    } else {
+        Cow::Borrowed(text)
     }
 }
 
-/// Resolve file IDs to paths with optional filtering
-fn resolve_file_ids(
+/// Execute a boolean query against the case-insensitive exact index
+pub fn query_boolean_exact_lower(
+    path_index: &PathIndex,
+    exact_lower_index: &impl TokenLookup,
+    query_str: &str,
+    options: &QueryOptions,
+) -> Result<QueryResult> {
+    query_boolean(path_index, query_str, options, |text| {
+        lookup_exact_terms(
+            exact_lower_index,
+            &boolean_term(text),
+            TokenForm::Lower,
+            options,
+        )
+    })
+}
+
+/// Execute a boolean query against the normalized exact index
+pub fn query_boolean_exact_normalized(
+    path_index: &PathIndex,
+    normalized_index: &impl TokenLookup,
+    query_str: &str,
+    options: &QueryOptions,
+) -> Result<QueryResult> {
+    query_boolean(path_index, query_str, options, |text| {
+        let term = boolean_term(text);
+        lookup_exact_terms(normalized_index, &term, TokenForm::Normalized, options)
+    })
+}
+
+/// Execute a boolean query against the trigram index
+pub fn query_boolean_fuzzy(
+    path_index: &PathIndex,
+    trigram_index: &impl TrigramLookup,
+    query_str: &str,
+    options: &QueryOptions,
+) -> Result<QueryResult> {
+    query_boolean(path_index, query_str, options, |text| {
+        let bitmaps = extract_query_trigrams(text)
+            .into_iter()
+            .map(|trigram| trigram_index.trigram_bitmap(trigram))
+            .collect::<Result<_>>()?;
+        Ok(TermBitmaps {
+            bitmaps,
+            expansions: vec![],
+            typos: vec![],
+        })
+    })
+}
+
+/// Resolve file IDs to paths with optional filtering, in `options.sort` order
+pub(crate) fn resolve_file_ids(
+    path_index: &PathIndex,
+    bitmap: &RoaringBitmap,
+    options: &QueryOptions,
+) -> Vec<PathBuf> {
+    select_files(path_index, bitmap, options, None::<fn(u32) -> f32>).0
+}
+
+/// Resolve file IDs to paths with optional filtering, in `options.sort` order
+///
+/// `score` rates each file; it ranks the files for `SortOrder::Relevance`
+/// (file ID order without it) and the scores of the returned files come back
+/// alongside them.
+fn select_files(
     path_index: &PathIndex,
     bitmap: &RoaringBitmap,
     options: &QueryOptions,
-) -> Vec<PathBuf> {
+    score: Option<impl Fn(u32) -> f32>,
+) -> (Vec<PathBuf>, Vec<f32>) {
     // Build glob matcher if patterns provided
     let glob_matcher = options.glob_patterns.as_ref().and_then(|patterns| {
         let mut builder = globset::GlobSetBuilder::new();
@@ -320,14 +1430,42 @@ fn resolve_file_ids(
             }
         }
 
-        Some(path)
+        Some((id, path))
     });
 
-    if let Some(limit) = options.limit {
-        iter.take(limit).collect()
-    } else {
-        iter.collect()
-    }
+    let limit = options.limit.unwrap_or(usize::MAX);
+    let selected: Vec<(u32, PathBuf)> = match (options.sort, &score) {
+        (SortOrder::Relevance, Some(score)) => {
+            // Top-k by score, ties in file ID order
+            let mut scored: Vec<(f32, u32, PathBuf)> =
+                iter.map(|(id, path)| (score(id), id, path)).collect();
+            let by_score = |a: &(f32, u32, PathBuf), b: &(f32, u32, PathBuf)| {
+                b.0.total_cmp(&a.0).then(a.1.cmp(&b.1))
+            };
+            if limit < scored.len() {
+                scored.select_nth_unstable_by(limit, by_score);
+                scored.truncate(limit);
+            }
+            scored.sort_unstable_by(by_score);
+            let scores = scored.iter().map(|(score, _, _)| *score).collect();
+            let files = scored.into_iter().map(|(_, _, path)| path).collect();
+            return (files, scores);
+        }
+        (SortOrder::Path, _) => {
+            let mut files: Vec<(u32, PathBuf)> = iter.collect();
+            files.sort_unstable_by(|a, b| a.1.cmp(&b.1));
+            files.truncate(limit);
+            files
+        }
+        _ => iter.take(limit).collect(),
+    };
+
+    let scores = match score {
+        Some(score) => selected.iter().map(|(id, _)| score(*id)).collect(),
+        None => Vec::new(),
+    };
+    let files = selected.into_iter().map(|(_, path)| path).collect();
+    (files, scores)
 }
 
 // ============================================================================
@@ -335,19 +1473,19 @@ fn resolve_file_ids(
 // ============================================================================
 
 /// Intersect bitmaps, sorting by cardinality for efficiency
-fn intersect_bitmaps(bitmaps: &[&RoaringBitmap]) -> RoaringBitmap {
+fn intersect_bitmaps<B: Borrow<RoaringBitmap>>(bitmaps: &[B]) -> RoaringBitmap {
     if bitmaps.is_empty() {
         return RoaringBitmap::new();
     }
 
     // Sort by cardinality (smallest first) for early termination
-    let mut sorted: Vec<_> = bitmaps.iter().collect();
+    let mut sorted: Vec<&RoaringBitmap> = bitmaps.iter().map(Borrow::borrow).collect();
     sorted.sort_by_key(|b| b.len());
 
-    let mut result = (*sorted[0]).clone();
+    let mut result = sorted[0].clone();
 
     for bitmap in &sorted[1..] {
-        result &= **bitmap;
+        result &= *bitmap;
 
         // Early exit if intersection is empty
         if result.is_empty() {
@@ -359,11 +1497,11 @@ fn intersect_bitmaps(bitmaps: &[&RoaringBitmap]) -> RoaringBitmap {
 }
 
 /// Union all bitmaps
-fn union_bitmaps(bitmaps: &[&RoaringBitmap]) -> RoaringBitmap {
+fn union_bitmaps<B: Borrow<RoaringBitmap>>(bitmaps: &[B]) -> RoaringBitmap {
     let mut result = RoaringBitmap::new();
 
     for bitmap in bitmaps {
-        result |= *bitmap;
+        result |= bitmap.borrow();
     }
 
     result
@@ -372,6 +1510,8 @@ fn union_bitmaps(bitmaps: &[&RoaringBitmap]) -> RoaringBitmap {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::dictionary::TokenDictionary;
+    use crate::index::ExactTokenIndex;
 
     #[test]
     fn test_empty_query() {
@@ -473,11 +1613,11 @@ mod tests {
         let mut path_index = PathIndex::new(header, PathBuf::from("/project"));
 
         // Register test files: IDs 0-5
-        path_index.register_file(PathBuf::from("/project/src/main.rs"));      // 0
-        path_index.register_file(PathBuf::from("/project/src/lib.rs"));       // 1
-        path_index.register_file(PathBuf::from("/project/test/unit.rs"));     // 2
-        path_index.register_file(PathBuf::from("/project/src/util.py"));      // 3
-        path_index.register_file(PathBuf::from("/project/docs/readme.md"));   // 4
+        path_index.register_file(PathBuf::from("/project/src/main.rs")); // 0
+        path_index.register_file(PathBuf::from("/project/src/lib.rs")); // 1
+        path_index.register_file(PathBuf::from("/project/test/unit.rs")); // 2
+        path_index.register_file(PathBuf::from("/project/src/util.py")); // 3
+        path_index.register_file(PathBuf::from("/project/docs/readme.md")); // 4
         path_index.register_file(PathBuf::from("/project/src/test_helper.h")); // 5
 
         path_index
@@ -655,7 +1795,10 @@ mod tests {
         // Should match: /project/src/main.rs, /project/src/lib.rs
         // Excluded: /project/src/test_helper.h (has "test"), /project/src/util.py (not .rs)
         assert_eq!(result.len(), 2);
-        let paths: Vec<_> = result.iter().map(|p| p.to_string_lossy().to_string()).collect();
+        let paths: Vec<_> = result
+            .iter()
+            .map(|p| p.to_string_lossy().to_string())
+            .collect();
         assert!(paths.iter().any(|p| p.contains("main.rs")));
         assert!(paths.iter().any(|p| p.contains("lib.rs")));
     }
@@ -722,7 +1865,7 @@ mod tests {
             match_all: true, // AND mode
             ..Default::default()
         };
-        let result = query_exact(&path_index, &exact_index, "alpha beta", &options);
+        let result = query_exact(&path_index, &exact_index, "alpha beta", &options).unwrap();
 
         // Only file 2 has both tokens
         assert_eq!(result.files.len(), 1);
@@ -738,7 +1881,7 @@ mod tests {
             match_all: false, // OR mode
             ..Default::default()
         };
-        let result = query_exact(&path_index, &exact_index, "alpha beta", &options);
+        let result = query_exact(&path_index, &exact_index, "alpha beta", &options).unwrap();
 
         // Files 0, 1, and 2 have at least one token
         assert_eq!(result.files.len(), 3);
@@ -767,11 +1910,708 @@ mod tests {
             ..Default::default()
         };
 
-        let and_result = query_exact(&path_index, &exact_index, "alpha", &and_options);
-        let or_result = query_exact(&path_index, &exact_index, "alpha", &or_options);
+        let and_result = query_exact(&path_index, &exact_index, "alpha", &and_options).unwrap();
+        let or_result = query_exact(&path_index, &exact_index, "alpha", &or_options).unwrap();
 
         // Both should find the same 2 files
         assert_eq!(and_result.files.len(), 2);
         assert_eq!(or_result.files.len(), 2);
     }
+
+    // ========================================================================
+    // Tests for wildcard and regex patterns
+    // ========================================================================
+
+    fn create_test_exact_index_with_dictionary() -> (PathIndex, ExactTokenIndex) {
+        let (path_index, mut exact_index) = create_test_exact_index_with_tokens();
+        let mut dictionary = TokenDictionary::new();
+        dictionary.insert(b"alpha");
+        dictionary.insert(b"beta");
+        exact_index.set_dictionary(Some(dictionary));
+        (path_index, exact_index)
+    }
+
+    #[test]
+    fn test_query_exact_wildcard_patterns() {
+        let (path_index, exact_index) = create_test_exact_index_with_dictionary();
+        let options = QueryOptions {
+            match_all: true,
+            ..Default::default()
+        };
+
+        let result = query_exact(&path_index, &exact_index, "al*", &options).unwrap();
+        assert_eq!(result.files.len(), 2);
+        assert_eq!(result.expansions.len(), 1);
+        assert_eq!(result.expansions[0].pattern, "al*");
+        assert_eq!(result.expansions[0].token_count, 1);
+
+        // One pattern matching both tokens is a union, not an intersection
+        let result = query_exact(&path_index, &exact_index, "/(alpha|beta)/", &options).unwrap();
+        assert_eq!(result.files.len(), 3);
+        assert_eq!(result.expansions[0].token_count, 2);
+
+        let result = query_exact(&path_index, &exact_index, "*eta alph?", &options).unwrap();
+        assert_eq!(result.files.len(), 1);
+        assert_eq!(result.matched_token_count, 2);
+
+        let options = QueryOptions {
+            max_expansions: Some(1),
+            ..options
+        };
+        let result = query_exact(&path_index, &exact_index, "*a", &options).unwrap();
+        assert!(result.expansions[0].truncated);
+        assert_eq!(result.expansions[0].token_count, 1);
+    }
+
+    #[test]
+    fn test_query_pattern_errors() {
+        let (path_index, exact_index) = create_test_exact_index_with_tokens();
+        let options = QueryOptions {
+            match_all: true,
+            ..Default::default()
+        };
+
+        // Like a token missing from the index, the pattern is left out
+        let result = query_exact(&path_index, &exact_index, "alpha be*", &options).unwrap();
+        assert_eq!(result.files.len(), 2);
+        assert_eq!(result.query_token_count, 2);
+        assert_eq!(result.matched_token_count, 1);
+        assert_eq!(
+            result.expansions[0].error.as_deref(),
+            Some("index has no token dictionary")
+        );
+
+        let (path_index, exact_index) = create_test_exact_index_with_dictionary();
+        let result = query_exact(&path_index, &exact_index, "/(/", &options).unwrap();
+        assert!(result.expansions[0].error.is_some());
+    }
+
+    #[test]
+    fn test_query_exact_lower_patterns() {
+        use crate::tokenizer::hash_token_lower;
+
+        let (path_index, _) = create_test_exact_index_with_tokens();
+        let mut exact_lower = ExactTokenIndex::new(path_index.header.clone());
+        let mut bitmap = RoaringBitmap::new();
+        bitmap.insert(3);
+        exact_lower
+            .token_map
+            .insert(hash_token_lower(b"HttpRequest"), bitmap);
+        let mut dictionary = TokenDictionary::new();
+        dictionary.insert(b"HttpRequest");
+        exact_lower.set_dictionary(Some(dictionary.lowercased()));
+
+        let options = QueryOptions::default();
+        let result = query_exact_lower(&path_index, &exact_lower, "*REQUEST", &options).unwrap();
+        assert_eq!(result.files, vec![PathBuf::from("/project/file_c.rs")]);
+        let result = query_exact_lower(&path_index, &exact_lower, "/http\\w+/", &options).unwrap();
+        assert_eq!(result.files.len(), 1);
+
+        // Non-ASCII letters fold too, in plain words and patterns
+        let mut bitmap = RoaringBitmap::new();
+        bitmap.insert(0);
+        exact_lower
+            .token_map
+            .insert(hash_token_lower("ÜberSicht".as_bytes()), bitmap);
+        dictionary.insert("ÜberSicht".as_bytes());
+        exact_lower.set_dictionary(Some(dictionary.lowercased()));
+        for query in ["übersicht", "ÜBERSICHT", "Über*", "?bersicht"] {
+            let result = query_exact_lower(&path_index, &exact_lower, query, &options).unwrap();
+            assert_eq!(result.files.len(), 1, "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_query_exact_typos() {
+        let (path_index, exact_index) = create_test_exact_index_with_dictionary();
+        let options = QueryOptions {
+            match_all: true,
+            max_typos: Some(1),
+            ..Default::default()
+        };
+
+        let result = query_exact(&path_index, &exact_index, "alpa bet", &options).unwrap();
+        assert_eq!(result.files, vec![PathBuf::from("/project/file_ab.rs")]);
+        assert_eq!(result.matched_token_count, 2);
+        assert_eq!(result.typos.len(), 2);
+        assert_eq!(result.typos[0].token, "alpa");
+        assert!(!result.typos[0].indexed);
+        assert_eq!(result.typos[0].substitutes, vec!["alpha".to_string()]);
+
+        // Indexed tokens with nothing close are not reported
+        let result = query_exact(&path_index, &exact_index, "alpha", &options).unwrap();
+        assert_eq!(result.files.len(), 2);
+        assert!(result.typos.is_empty());
+
+        let result = query_exact(&path_index, &exact_index, "alpah", &options).unwrap();
+        assert!(result.files.is_empty());
+        assert!(result.typos[0].substitutes.is_empty());
+        let options = QueryOptions {
+            max_typos: Some(2),
+            ..options
+        };
+        let result = query_exact(&path_index, &exact_index, "alpah", &options).unwrap();
+        assert_eq!(result.files.len(), 2);
+
+        // Without a typo budget a misspelling matches nothing
+        let result =
+            query_exact(&path_index, &exact_index, "alpa", &QueryOptions::default()).unwrap();
+        assert!(result.files.is_empty());
+        assert!(result.typos.is_empty());
+    }
+
+    #[test]
+    fn test_query_typos_lower_and_errors() {
+        use crate::tokenizer::hash_token_lower;
+
+        let (path_index, exact_index) = create_test_exact_index_with_tokens();
+        let options = QueryOptions {
+            max_typos: Some(1),
+            ..Default::default()
+        };
+        let result = query_exact(&path_index, &exact_index, "alpa", &options).unwrap();
+        assert!(result.files.is_empty());
+        assert_eq!(
+            result.typos[0].error.as_deref(),
+            Some("index has no token dictionary")
+        );
+
+        let mut exact_lower = ExactTokenIndex::new(path_index.header.clone());
+        exact_lower.token_map.insert(
+            hash_token_lower(b"ReceiveBuffer"),
+            RoaringBitmap::from_iter([3]),
+        );
+        let mut dictionary = TokenDictionary::new();
+        dictionary.insert(b"ReceiveBuffer");
+        exact_lower.set_dictionary(Some(dictionary.lowercased()));
+        let result =
+            query_exact_lower(&path_index, &exact_lower, "RecieveBuffer", &options).unwrap();
+        assert!(result.files.is_empty());
+        let options = QueryOptions {
+            max_typos: Some(2),
+            ..options
+        };
+        let result =
+            query_exact_lower(&path_index, &exact_lower, "RecieveBuffer", &options).unwrap();
+        assert_eq!(result.files, vec![PathBuf::from("/project/file_c.rs")]);
+        assert_eq!(result.typos[0].token, "recievebuffer");
+        assert_eq!(
+            result.typos[0].substitutes,
+            vec!["receivebuffer".to_string()]
+        );
+
+        // Boolean terms are widened too
+        let (path_index, exact_index) = create_test_exact_index_with_dictionary();
+        let result =
+            query_boolean_exact(&path_index, &exact_index, "alpa AND NOT bet", &options).unwrap();
+        assert_eq!(result.files, vec![PathBuf::from("/project/file_a.rs")]);
+        assert_eq!(result.typos.len(), 2);
+    }
+
+    // ========================================================================
+    // Tests for the boolean query language
+    // ========================================================================
+
+    fn term(text: &str, position: usize) -> Box<QueryExpr> {
+        Box::new(QueryExpr::Term {
+            text: text.to_string(),
+            position,
+        })
+    }
+
+    fn boolean_files(query_str: &str) -> Vec<String> {
+        let (path_index, exact_index) = create_test_exact_index_with_tokens();
+        let result = query_boolean_exact(
+            &path_index,
+            &exact_index,
+            query_str,
+            &QueryOptions::default(),
+        )
+        .unwrap();
+        let mut names: Vec<String> = result
+            .files
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_parse_query_precedence() {
+        // NOT binds tighter than AND, AND tighter than OR
+        let expr = parse_query("a OR b c NOT d").unwrap();
+        let expected = QueryExpr::Or(
+            term("a", 0),
+            Box::new(QueryExpr::And(
+                Box::new(QueryExpr::And(term("b", 5), term("c", 7))),
+                Box::new(QueryExpr::Not(term("d", 13))),
+            )),
+        );
+        assert_eq!(expr, expected);
+
+        let expr = parse_query("(a OR \"OR\") AND b").unwrap();
+        let expected = QueryExpr::And(
+            Box::new(QueryExpr::Or(term("a", 1), term("OR", 6))),
+            term("b", 16),
+        );
+        assert_eq!(expr, expected);
+    }
+
+    #[test]
+    fn test_parse_query_errors() {
+        let position = |query_str: &str| match parse_query(query_str) {
+            Err(TokenizerError::QuerySyntax { position, .. }) => position,
+            other => panic!("expected syntax error for {:?}, got {:?}", query_str, other),
+        };
+
+        assert_eq!(position(""), 0);
+        assert_eq!(position("(alpha OR beta"), 0);
+        assert_eq!(position("alpha) beta"), 5);
+        assert_eq!(position("alpha AND"), 9);
+        assert_eq!(position("OR beta"), 0);
+        assert_eq!(position("alpha \"beta"), 6);
+        assert_eq!(position("alpha NOT ()"), 11);
+    }
+
+    #[test]
+    fn test_query_boolean_set_operations() {
+        assert_eq!(
+            boolean_files("alpha OR beta"),
+            ["file_a.rs", "file_ab.rs", "file_b.rs"]
+        );
+        assert_eq!(boolean_files("alpha AND beta"), ["file_ab.rs"]);
+        assert_eq!(boolean_files("alpha NOT beta"), ["file_a.rs"]);
+        assert_eq!(boolean_files("NOT alpha"), ["file_b.rs", "file_c.rs"]);
+        assert_eq!(boolean_files("NOT (alpha OR beta)"), ["file_c.rs"]);
+        assert_eq!(boolean_files("(alpha OR missing) beta"), ["file_ab.rs"]);
+        assert!(boolean_files("alpha missing").is_empty());
+    }
+
+    #[test]
+    fn test_query_boolean_unindexable_term() {
+        let (path_index, exact_index) = create_test_exact_index_with_tokens();
+        let options = QueryOptions::default();
+        let err = query_boolean_exact(&path_index, &exact_index, "alpha OR x", &options);
+        assert!(matches!(
+            err,
+            Err(TokenizerError::QuerySyntax { position: 9, .. })
+        ));
+
+        let result =
+            query_boolean_exact(&path_index, &exact_index, "alpha OR gamma", &options).unwrap();
+        assert_eq!(result.query_token_count, 2);
+        assert_eq!(result.matched_token_count, 1);
+    }
+
+    #[test]
+    fn test_query_boolean_patterns() {
+        let (path_index, exact_index) = create_test_exact_index_with_dictionary();
+        let options = QueryOptions::default();
+        let result =
+            query_boolean_exact(&path_index, &exact_index, "NOT b* AND al*", &options).unwrap();
+        assert_eq!(result.files, vec![PathBuf::from("/project/file_a.rs")]);
+        assert_eq!(result.expansions.len(), 2);
+    }
+
+    // ========================================================================
+    // Tests for phrases and NEAR/n
+    // ========================================================================
+
+    fn create_test_exact_index_with_positions() -> (PathIndex, ExactTokenIndex) {
+        use crate::tokenizer::hash_token;
+
+        let (path_index, mut exact_index) = create_test_exact_index_with_tokens();
+        let mut positions = PositionIndex::new();
+        positions.insert_file(0, vec![(hash_token(b"alpha"), vec![0])]);
+        positions.insert_file(1, vec![(hash_token(b"beta"), vec![0])]);
+        positions.insert_file(
+            2,
+            vec![
+                (hash_token(b"alpha"), vec![3]),
+                (hash_token(b"beta"), vec![4, 10]),
+            ],
+        );
+        exact_index.set_positions(Some(positions));
+        (path_index, exact_index)
+    }
+
+    #[test]
+    fn test_query_exact_phrases() {
+        let (path_index, mut exact_index) = create_test_exact_index_with_positions();
+        let options = QueryOptions::default();
+
+        let result = query_exact(&path_index, &exact_index, "\"alpha beta\"", &options).unwrap();
+        assert_eq!(result.files, vec![PathBuf::from("/project/file_ab.rs")]);
+        // A phrase is a single term
+        assert_eq!(result.query_token_count, 1);
+        assert_eq!(result.matched_token_count, 1);
+
+        let result = query_exact(&path_index, &exact_index, "\"beta alpha\"", &options).unwrap();
+        assert!(result.files.is_empty());
+
+        let result = query_boolean_exact(
+            &path_index,
+            &exact_index,
+            "\"beta alpha\" OR beta",
+            &options,
+        )
+        .unwrap();
+        assert_eq!(result.files.len(), 2);
+
+        // Without positions a phrase only needs all of its tokens
+        exact_index.set_positions(None);
+        let result = query_exact(&path_index, &exact_index, "\"beta alpha\"", &options).unwrap();
+        assert_eq!(result.files, vec![PathBuf::from("/project/file_ab.rs")]);
+    }
+
+    #[test]
+    fn test_query_exact_near() {
+        let (path_index, exact_index) = create_test_exact_index_with_positions();
+        let options = QueryOptions {
+            match_all: true,
+            ..Default::default()
+        };
+
+        let result = query_exact(&path_index, &exact_index, "beta NEAR/1 alpha", &options).unwrap();
+        assert_eq!(result.files, vec![PathBuf::from("/project/file_ab.rs")]);
+
+        let result = query_exact(&path_index, &exact_index, "beta NEAR/0 alpha", &options).unwrap();
+        assert!(result.files.is_empty());
+
+        // NEAR/ without a left-hand term is an ordinary word
+        assert!(!has_positional_syntax("NEAR/2 alpha"));
+        assert!(has_positional_syntax("alpha NEAR/2 beta"));
+        assert!(has_positional_syntax("\"alpha beta\""));
+        assert!(!has_positional_syntax("\"alpha\""));
+    }
+
+    // ========================================================================
+    // Tests for relevance ranking and sort orders
+    // ========================================================================
+
+    fn create_test_exact_index_with_frequencies() -> (PathIndex, ExactTokenIndex) {
+        use crate::tokenizer::hash_token;
+
+        let (path_index, mut exact_index) = create_test_exact_index_with_tokens();
+        let (alpha, beta) = (hash_token(b"alpha"), hash_token(b"beta"));
+        let mut frequencies = TermFrequencies::new();
+        frequencies.insert_file(0, vec![(alpha, 1), (hash_token(b"other"), 20)]);
+        frequencies.insert_file(1, vec![(beta, 2)]);
+        frequencies.insert_file(2, vec![(alpha, 4), (beta, 1)]);
+        frequencies.insert_file(3, vec![(hash_token(b"other"), 5)]);
+        exact_index.set_frequencies(Some(frequencies));
+        (path_index, exact_index)
+    }
+
+    #[test]
+    fn test_query_exact_relevance() {
+        let (path_index, exact_index) = create_test_exact_index_with_frequencies();
+        let options = QueryOptions {
+            sort: SortOrder::Relevance,
+            ..Default::default()
+        };
+
+        let result = query_exact(&path_index, &exact_index, "alpha beta", &options).unwrap();
+        assert_eq!(
+            result.files,
+            vec![
+                PathBuf::from("/project/file_ab.rs"),
+                PathBuf::from("/project/file_b.rs"),
+                PathBuf::from("/project/file_a.rs"),
+            ]
+        );
+        assert_eq!(result.scores.len(), 3);
+        assert!(result.scores.windows(2).all(|pair| pair[0] >= pair[1]));
+
+        // The limit keeps the best files, not the first IDs
+        let top = query_exact(
+            &path_index,
+            &exact_index,
+            "alpha beta",
+            &QueryOptions {
+                limit: Some(2),
+                ..options.clone()
+            },
+        )
+        .unwrap();
+        assert_eq!(top.files, result.files[..2]);
+        assert_eq!(top.scores, result.scores[..2]);
+    }
+
+    #[test]
+    fn test_query_sort_orders() {
+        let (path_index, exact_index) = create_test_exact_index_with_frequencies();
+        let by_id = QueryOptions::default();
+        let result = query_exact(&path_index, &exact_index, "alpha beta", &by_id).unwrap();
+        assert_eq!(result.files[0], PathBuf::from("/project/file_a.rs"));
+        // Scores are reported in any order
+        assert_eq!(result.scores.len(), 3);
+
+        let by_path = QueryOptions {
+            sort: SortOrder::Path,
+            limit: Some(2),
+            ..Default::default()
+        };
+        let result = query_exact(&path_index, &exact_index, "alpha beta", &by_path).unwrap();
+        assert_eq!(
+            result.files,
+            vec![
+                PathBuf::from("/project/file_a.rs"),
+                PathBuf::from("/project/file_ab.rs"),
+            ]
+        );
+
+        // Without term frequencies relevance falls back to file ID order
+        let (path_index, exact_index) = create_test_exact_index_with_tokens();
+        let by_relevance = QueryOptions {
+            sort: SortOrder::Relevance,
+            ..Default::default()
+        };
+        let result = query_exact(&path_index, &exact_index, "beta alpha", &by_relevance).unwrap();
+        assert_eq!(result.files[0], PathBuf::from("/project/file_a.rs"));
+        assert!(result.scores.is_empty());
+
+        assert_eq!("path".parse::<SortOrder>(), Ok(SortOrder::Path));
+        assert!("score".parse::<SortOrder>().is_err());
+    }
+
+    // ========================================================================
+    // Tests for fuzzy match ratios
+    // ========================================================================
+
+    fn create_test_trigram_index(contents: &[&str]) -> (PathIndex, crate::index::TrigramIndex) {
+        use crate::index::{IndexHeader, TrigramIndex};
+        use crate::trigram::extract_trigrams;
+
+        let header = IndexHeader::new();
+        let mut path_index = PathIndex::new(header.clone(), PathBuf::from("/project"));
+        let mut trigram_index = TrigramIndex::new(header);
+        for (i, content) in contents.iter().enumerate() {
+            let file_id = path_index.register_file(PathBuf::from(format!("/project/{}.rs", i)));
+            for trigram in extract_trigrams(content.as_bytes()) {
+                trigram_index.add_trigram(trigram, file_id);
+            }
+        }
+        (path_index, trigram_index)
+    }
+
+    #[test]
+    fn test_query_fuzzy_min_match() {
+        // "mannequin" has 7 distinct trigrams
+        let (path_index, trigram_index) =
+            create_test_trigram_index(&["mannequin", "manequin", "mann", "quinoa"]);
+        let options = QueryOptions {
+            min_match: Some(0.5),
+            sort: SortOrder::Relevance,
+            ..Default::default()
+        };
+
+        let result = query_fuzzy(&path_index, &trigram_index, "mannequin", &options).unwrap();
+        assert_eq!(
+            result.files,
+            vec![
+                PathBuf::from("/project/0.rs"),
+                PathBuf::from("/project/1.rs")
+            ]
+        );
+        assert_eq!(result.match_ratios[0], 1.0);
+        assert!((result.match_ratios[1] - 5.0 / 7.0).abs() < 1e-6);
+
+        // A lower threshold admits weaker matches, ranked last
+        let options = QueryOptions {
+            min_match: Some(0.25),
+            ..options
+        };
+        let result = query_fuzzy(&path_index, &trigram_index, "mannequin", &options).unwrap();
+        assert_eq!(result.files.len(), 4);
+        assert!(result
+            .match_ratios
+            .windows(2)
+            .all(|pair| pair[0] >= pair[1]));
+    }
+
+    #[test]
+    fn test_query_fuzzy_unicode() {
+        let (path_index, trigram_index) =
+            create_test_trigram_index(&["ΣΥΝΆΡΤΗΣΗ", "Größenänderung", "東京都庁", "plain"]);
+        let options = QueryOptions::default();
+        let files = |query: &str| {
+            query_fuzzy(&path_index, &trigram_index, query, &options)
+                .unwrap()
+                .files
+        };
+
+        assert_eq!(files("συνάρτηση"), vec![PathBuf::from("/project/0.rs")]);
+        assert_eq!(files("größen"), vec![PathBuf::from("/project/1.rs")]);
+        assert_eq!(files("京都庁"), vec![PathBuf::from("/project/2.rs")]);
+    }
+
+    #[test]
+    fn test_query_fuzzy_match_ratios_without_min_match() {
+        let (path_index, trigram_index) =
+            create_test_trigram_index(&["mannequin", "manequin", "quinoa"]);
+
+        let and_options = QueryOptions {
+            match_all: true,
+            ..Default::default()
+        };
+        let and_result =
+            query_fuzzy(&path_index, &trigram_index, "mannequin", &and_options).unwrap();
+        assert_eq!(and_result.files, vec![PathBuf::from("/project/0.rs")]);
+        assert_eq!(and_result.match_ratios, vec![1.0]);
+
+        // OR mode keeps file ID order unless asked to rank
+        let or_options = QueryOptions::default();
+        let or_result = query_fuzzy(&path_index, &trigram_index, "mannequin", &or_options).unwrap();
+        assert_eq!(or_result.files.len(), 3);
+        assert_eq!(or_result.files[0], PathBuf::from("/project/0.rs"));
+        assert_eq!(or_result.match_ratios.len(), 3);
+    }
+
+    // ========================================================================
+    // Tests for subword queries
+    // ========================================================================
+
+    fn create_test_subword_index(contents: &[&str]) -> (PathIndex, ExactTokenIndex) {
+        use crate::index::IndexHeader;
+        use crate::subword::SubwordIndex;
+        use crate::tokenizer::hash_token;
+
+        let header = IndexHeader::new();
+        let mut path_index = PathIndex::new(header.clone(), PathBuf::from("/project"));
+        let mut exact_index = ExactTokenIndex::new(header);
+        let mut subwords = SubwordIndex::new();
+        for (i, content) in contents.iter().enumerate() {
+            let file_id = path_index.register_file(PathBuf::from(format!("/project/{}.rs", i)));
+            for token in content.split_whitespace() {
+                let hash = hash_token(token.as_bytes());
+                exact_index.add_token(hash, file_id);
+                subwords.add_token(hash, token.as_bytes(), file_id);
+            }
+        }
+        exact_index.set_subwords(Some(subwords));
+        (path_index, exact_index)
+    }
+
+    #[test]
+    fn test_query_subword() {
+        let (path_index, exact_index) =
+            create_test_subword_index(&["getUserById", "user_id other", "IdOfUser", "userName"]);
+        let options = QueryOptions {
+            match_all: true,
+            ..Default::default()
+        };
+        let files = |query: &str, options: &QueryOptions| -> Vec<String> {
+            query_subword(&path_index, &exact_index, query, options)
+                .unwrap()
+                .files
+                .iter()
+                .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+
+        assert_eq!(files("User", &options), ["0.rs", "1.rs", "2.rs", "3.rs"]);
+        assert_eq!(files("userId", &options), ["0.rs", "1.rs", "2.rs"]);
+        assert_eq!(files("user ID", &options), ["0.rs", "1.rs", "2.rs"]);
+        let or_options = QueryOptions {
+            match_all: false,
+            ..Default::default()
+        };
+        assert_eq!(files("name of", &or_options), ["2.rs", "3.rs"]);
+
+        let in_order = QueryOptions {
+            in_order: true,
+            ..options.clone()
+        };
+        assert_eq!(files("user id", &in_order), ["0.rs", "1.rs"]);
+        assert_eq!(files("getId", &in_order), ["0.rs"]);
+        assert_eq!(files("id user", &in_order), ["2.rs"]);
+        assert_eq!(files("name", &in_order), ["3.rs"]);
+        // Parts from different identifiers are not in one token
+        assert!(files("user other", &in_order).is_empty());
+
+        // Like a token missing from an exact query, a missing part is left out
+        let result = query_subword(&path_index, &exact_index, "user missing", &options).unwrap();
+        assert_eq!(result.query_token_count, 2);
+        assert_eq!(result.matched_token_count, 1);
+        assert_eq!(result.files.len(), 4);
+        assert!(files("user missing", &in_order).is_empty());
+    }
+
+    #[test]
+    fn test_query_subword_needs_index() {
+        let (path_index, exact_index) = create_test_exact_index_with_tokens();
+        let result = query_subword(&path_index, &exact_index, "alpha", &QueryOptions::default());
+        assert!(matches!(result, Err(TokenizerError::InvalidIndexFormat(_))));
+    }
+
+    // ========================================================================
+    // Normalized Query Tests
+    // ========================================================================
+
+    fn create_test_normalized_index(contents: &[&str]) -> (PathIndex, ExactTokenIndex) {
+        use crate::dictionary::TokenDictionary;
+        use crate::index::IndexHeader;
+        use crate::tokenizer::tokenize_exact_normalized;
+
+        let header = IndexHeader::new();
+        let mut path_index = PathIndex::new(header.clone(), PathBuf::from("/project"));
+        let mut normalized = ExactTokenIndex::new(header);
+        let mut dictionary = TokenDictionary::new();
+        for (i, content) in contents.iter().enumerate() {
+            let file_id = path_index.register_file(PathBuf::from(format!("/project/{}.rs", i)));
+            for hash in tokenize_exact_normalized(content.as_bytes()) {
+                normalized.add_token(hash, file_id);
+            }
+            for token in content.split_whitespace() {
+                dictionary.insert_normalized(token.as_bytes());
+            }
+        }
+        normalized.set_dictionary(Some(dictionary));
+        (path_index, normalized)
+    }
+
+    #[test]
+    fn test_query_exact_normalized() {
+        let (path_index, normalized) = create_test_normalized_index(&[
+            "let userId = 1",
+            "user_id = None",
+            "USER-ID is set",
+            "username only",
+        ]);
+        let files = |query: &str, options: &QueryOptions| -> Vec<String> {
+            query_exact_normalized(&path_index, &normalized, query, options)
+                .unwrap()
+                .files
+                .iter()
+                .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+        let options = QueryOptions::default();
+
+        for spelling in ["userId", "user_id", "USERID", "User-Id"] {
+            assert_eq!(files(spelling, &options), ["0.rs", "1.rs", "2.rs"]);
+        }
+        assert_eq!(files("user_*", &options), ["0.rs", "1.rs", "2.rs", "3.rs"]);
+        assert_eq!(files("user_nam", &options), Vec::<String>::new());
+
+        let typos = QueryOptions {
+            max_typos: Some(1),
+            ..Default::default()
+        };
+        let result = query_exact_normalized(&path_index, &normalized, "user_nam", &typos).unwrap();
+        assert_eq!(result.files.len(), 1);
+        assert_eq!(result.typos[0].token, "usernam");
+        assert_eq!(result.typos[0].substitutes, ["username"]);
+
+        let result = query_boolean_exact_normalized(
+            &path_index,
+            &normalized,
+            "user_id AND NOT is",
+            &options,
+        )
+        .unwrap();
+        assert_eq!(result.files.len(), 2);
+    }
 }
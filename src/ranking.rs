@@ -0,0 +1,289 @@
+//! Term frequencies and BM25 relevance scoring
+//!
+//! Optional companion of an exact index, stored as a `.tf` file for the
+//! case-sensitive index and a `.tfi` file for the lowercase one:
+//!
+//! ```text
+//! magic        [u8; 4]  "TKTF"
+//! version      u16
+//! reserved     u16
+//! index_id     [u8; 16]
+//! created_at   u64
+//! tokenizer    u64
+//! file_count   u64
+//! files        file_count x {
+//!                  file_id: varint, token_count: varint,
+//!                  tokens: token_count x { hash: u64, count: varint }
+//!              }
+//! ```
+//!
+//! Files are written in ID order and tokens in hash order. A file's length,
+//! which BM25 normalizes by, is the sum of its token counts.
+
+use crate::dictionary::{read_varint, write_varint};
+use crate::error::{Result, TokenizerError};
+use crate::index::{IndexHeader, FORMAT_VERSION};
+use crate::table::{parse_header, write_header, HEADER_LEN};
+use roaring::RoaringBitmap;
+use rustc_hash::FxHashMap;
+use std::io::Write;
+
+/// Magic bytes of the term frequency file
+pub(crate) const MAGIC_TF: &[u8; 4] = b"TKTF";
+
+/// BM25 term frequency saturation
+pub const BM25_K1: f32 = 1.2;
+
+/// BM25 document length normalization
+pub const BM25_B: f32 = 0.75;
+
+/// Token counts of one file, sorted by hash
+#[derive(Debug, Clone, Default)]
+struct FileTerms {
+    length: u32,
+    counts: Vec<(u64, u32)>,
+}
+
+/// How often each token occurs in each file, for relevance ranking
+#[derive(Debug, Clone, Default)]
+pub struct TermFrequencies {
+    files: FxHashMap<u32, FileTerms>,
+    total_length: u64,
+}
+
+impl TermFrequencies {
+    /// Create an empty set of term frequencies
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the token counts of a file, replacing earlier ones
+    pub fn insert_file(&mut self, file_id: u32, mut counts: Vec<(u64, u32)>) {
+        self.remove_file(file_id);
+        if counts.is_empty() {
+            return;
+        }
+        counts.sort_unstable();
+        let length = counts
+            .iter()
+            .fold(0u32, |sum, (_, count)| sum.saturating_add(*count));
+        self.total_length += u64::from(length);
+        self.files.insert(file_id, FileTerms { length, counts });
+    }
+
+    /// Drop the token counts of the given files
+    pub fn remove_files(&mut self, file_ids: &RoaringBitmap) {
+        for file_id in file_ids {
+            self.remove_file(file_id);
+        }
+    }
+
+    fn remove_file(&mut self, file_id: u32) {
+        if let Some(terms) = self.files.remove(&file_id) {
+            self.total_length -= u64::from(terms.length);
+        }
+    }
+
+    /// Take over the token counts of another set, shifting its file IDs by
+    /// `offset`
+    pub(crate) fn append(&mut self, other: TermFrequencies, offset: u32) {
+        self.total_length += other.total_length;
+        self.files.extend(
+            other
+                .files
+                .into_iter()
+                .map(|(file_id, terms)| (file_id + offset, terms)),
+        );
+    }
+
+    /// Get how often a token occurs in a file
+    pub fn term_frequency(&self, file_id: u32, token_hash: u64) -> u32 {
+        self.files.get(&file_id).map_or(0, |terms| {
+            terms
+                .counts
+                .binary_search_by_key(&token_hash, |(hash, _)| *hash)
+                .map_or(0, |i| terms.counts[i].1)
+        })
+    }
+
+    /// Get the number of tokens in a file, counting repeats
+    pub fn document_length(&self, file_id: u32) -> u32 {
+        self.files.get(&file_id).map_or(0, |terms| terms.length)
+    }
+
+    /// Get the number of files with at least one token
+    pub fn file_count(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Get the IDs of the files with at least one token
+    pub(crate) fn file_ids(&self) -> RoaringBitmap {
+        self.files.keys().copied().collect()
+    }
+
+    /// Get the mean document length
+    pub fn average_length(&self) -> f32 {
+        if self.files.is_empty() {
+            0.0
+        } else {
+            self.total_length as f32 / self.files.len() as f32
+        }
+    }
+
+    /// Inverse document frequency of a token found in `document_frequency` files
+    pub fn idf(&self, document_frequency: u64) -> f32 {
+        let n = self.files.len() as f32;
+        let df = document_frequency as f32;
+        ((n - df + 0.5) / (df + 0.5) + 1.0).ln()
+    }
+
+    /// Score a file against query tokens given as `(hash, idf)` pairs
+    pub fn bm25(&self, file_id: u32, weighted_tokens: &[(u64, f32)]) -> f32 {
+        let average = self.average_length();
+        if average == 0.0 {
+            return 0.0;
+        }
+        let norm =
+            BM25_K1 * (1.0 - BM25_B + BM25_B * self.document_length(file_id) as f32 / average);
+        weighted_tokens
+            .iter()
+            .map(|(hash, idf)| {
+                let tf = self.term_frequency(file_id, *hash) as f32;
+                idf * tf * (BM25_K1 + 1.0) / (tf + norm)
+            })
+            .sum()
+    }
+}
+
+/// Write a term frequency file
+pub(crate) fn write_frequencies<W: Write>(
+    writer: &mut W,
+    header: &IndexHeader,
+    frequencies: &TermFrequencies,
+) -> std::io::Result<()> {
+    write_header(writer, MAGIC_TF, header, frequencies.files.len())?;
+
+    let mut file_ids: Vec<u32> = frequencies.files.keys().copied().collect();
+    file_ids.sort_unstable();
+    for file_id in file_ids {
+        let terms = &frequencies.files[&file_id];
+        write_varint(writer, u64::from(file_id))?;
+        write_varint(writer, terms.counts.len() as u64)?;
+        for (hash, count) in &terms.counts {
+            writer.write_all(&hash.to_le_bytes())?;
+            write_varint(writer, u64::from(*count))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Decode a term frequency file
+pub(crate) fn decode_frequencies(data: &[u8]) -> Result<(IndexHeader, TermFrequencies)> {
+    let (header, count) = parse_header(data, MAGIC_TF)?;
+    if header.version != FORMAT_VERSION {
+        return Err(TokenizerError::InvalidIndexFormat(format!(
+            "Version mismatch: expected {}, got {}",
+            FORMAT_VERSION, header.version
+        )));
+    }
+
+    let corrupt =
+        || TokenizerError::InvalidIndexFormat("Term frequency file is corrupt".to_string());
+    let mut position = HEADER_LEN;
+    let read_u32 = |position: &mut usize| {
+        read_varint(data, position)
+            .and_then(|value| u32::try_from(value).ok())
+            .ok_or_else(corrupt)
+    };
+
+    let mut frequencies = TermFrequencies::new();
+    for _ in 0..count {
+        let file_id = read_u32(&mut position)?;
+        let token_count = read_u32(&mut position)?;
+        let mut counts = Vec::with_capacity(token_count.min(1 << 16) as usize);
+        for _ in 0..token_count {
+            let hash = data
+                .get(position..position + 8)
+                .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+                .ok_or_else(corrupt)?;
+            position += 8;
+            counts.push((hash, read_u32(&mut position)?));
+        }
+        frequencies.insert_file(file_id, counts);
+    }
+
+    if position != data.len() {
+        return Err(corrupt());
+    }
+
+    Ok((header, frequencies))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lengths_and_removal() {
+        let mut frequencies = TermFrequencies::new();
+        frequencies.insert_file(0, vec![(7, 3), (2, 1)]);
+        frequencies.insert_file(1, vec![(7, 2)]);
+        assert_eq!(frequencies.document_length(0), 4);
+        assert_eq!(frequencies.term_frequency(0, 7), 3);
+        assert_eq!(frequencies.term_frequency(0, 9), 0);
+        assert_eq!(frequencies.average_length(), 3.0);
+
+        // Replacing a file updates the total length
+        frequencies.insert_file(0, vec![(2, 2)]);
+        assert_eq!(frequencies.average_length(), 2.0);
+
+        frequencies.remove_files(&RoaringBitmap::from_iter([0]));
+        assert_eq!(frequencies.file_count(), 1);
+        assert_eq!(frequencies.average_length(), 2.0);
+        assert_eq!(frequencies.document_length(0), 0);
+    }
+
+    #[test]
+    fn test_bm25_ordering() {
+        let mut frequencies = TermFrequencies::new();
+        // Token 1 is rare, token 2 is in every file
+        frequencies.insert_file(0, vec![(1, 1), (2, 9)]);
+        frequencies.insert_file(1, vec![(1, 5), (2, 5)]);
+        frequencies.insert_file(2, vec![(2, 10)]);
+        frequencies.insert_file(3, vec![(2, 9), (3, 40)]);
+
+        let rare = frequencies.idf(2);
+        let common = frequencies.idf(4);
+        assert!(rare > common && common > 0.0);
+
+        let query = [(1, rare), (2, common)];
+        let scores: Vec<f32> = (0..4).map(|id| frequencies.bm25(id, &query)).collect();
+        assert!(scores[1] > scores[0]);
+        assert!(scores[0] > scores[2]);
+        // The same count weighs less in a longer file
+        assert!(frequencies.bm25(0, &[(2, common)]) > frequencies.bm25(3, &[(2, common)]));
+        assert_eq!(frequencies.bm25(9, &query), 0.0);
+    }
+
+    #[test]
+    fn test_roundtrip_and_corruption() {
+        let mut frequencies = TermFrequencies::new();
+        frequencies.insert_file(4, vec![(u64::MAX, 300), (5, 1)]);
+        frequencies.insert_file(1, vec![(5, 70000)]);
+
+        let header = IndexHeader::new();
+        let mut data = Vec::new();
+        write_frequencies(&mut data, &header, &frequencies).unwrap();
+        let (decoded_header, decoded) = decode_frequencies(&data).unwrap();
+        assert_eq!(decoded_header, header);
+        assert_eq!(decoded.file_count(), 2);
+        assert_eq!(decoded.term_frequency(4, u64::MAX), 300);
+        assert_eq!(decoded.document_length(1), 70000);
+
+        assert!(decode_frequencies(&data[..data.len() - 1]).is_err());
+        let mut trailing = data.clone();
+        trailing.push(0);
+        assert!(decode_frequencies(&trailing).is_err());
+    }
+}
@@ -0,0 +1,723 @@
+//! Regex search with trigram prefiltering and verification
+//!
+//! A regex is compiled into a boolean query over the trigrams every match
+//! must contain (the approach of Russ Cox's codesearch). That query selects
+//! candidate files from the trigram index, and each candidate is then
+//! scanned with the real regex so only true matches are returned.
+//!
+//! The trigram index only holds lowercased trigrams made of token
+//! characters (`[A-Za-z0-9_-]`), so strings derived from the regex are
+//! lowercased and only their token-character windows become trigrams.
+
+use crate::error::{Result, TokenizerError};
+use crate::index::PathIndex;
+use crate::query::{resolve_file_ids, QueryOptions};
+use crate::trigram::{is_trigram_token_char, pack_trigram};
+use crate::unicode::{char_count, char_spans, fold_case, fold_token};
+use crate::view::TrigramLookup;
+use memmap2::Mmap;
+use rayon::prelude::*;
+use regex::bytes::{Regex, RegexBuilder};
+use regex_syntax::hir::{Class, Hir, HirKind};
+use regex_syntax::ParserBuilder;
+use roaring::RoaringBitmap;
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// Largest string set tracked before it is reduced to trigrams
+const MAX_SET_SIZE: usize = 16;
+
+/// Largest character class expanded into single-character strings
+const MAX_CLASS_SIZE: usize = 8;
+
+/// One regex match in a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegexMatch {
+    /// Byte offset of the match start
+    pub start: usize,
+
+    /// Byte offset just past the match end
+    pub end: usize,
+
+    /// 1-based line number of the match start
+    pub line_number: usize,
+
+    /// Matched text (lossy UTF-8)
+    pub text: String,
+}
+
+/// Regex matches of one file, in file order
+#[derive(Debug, Clone)]
+pub struct RegexFileMatches {
+    /// File path
+    pub path: PathBuf,
+
+    /// Every non-overlapping match
+    pub matches: Vec<RegexMatch>,
+}
+
+/// Result of a regex query
+#[derive(Debug, Clone, Default)]
+pub struct RegexResult {
+    /// Files with at least one verified match, in candidate order
+    pub files: Vec<RegexFileMatches>,
+
+    /// Number of candidate files that were scanned
+    pub candidate_count: usize,
+
+    /// The regex had no usable trigrams, so every indexed file was scanned
+    pub full_scan: bool,
+}
+
+/// Find the files matching a regex, with match offsets
+///
+/// Candidates come from the trigram index; the filters in `options` apply
+/// to the candidates and `options.limit` caps the number of matching files.
+/// `options.match_all` is ignored.
+pub fn query_regex(
+    path_index: &PathIndex,
+    trigram_index: &impl TrigramLookup,
+    pattern: &str,
+    ignore_case: bool,
+    options: &QueryOptions,
+) -> Result<RegexResult> {
+    let hir = ParserBuilder::new()
+        .case_insensitive(ignore_case)
+        .utf8(false)
+        .build()
+        .parse(pattern)
+        .map_err(regex_syntax_error)?;
+    let regex = RegexBuilder::new(pattern)
+        .case_insensitive(ignore_case)
+        .build()
+        .map_err(|e| TokenizerError::QuerySyntax {
+            position: 0,
+            message: e.to_string(),
+        })?;
+
+    let trigram_query = regex_trigram_query(&hir);
+    let full_scan = trigram_query == TrigramQuery::All;
+    let bitmap = trigram_query.evaluate(&path_index.file_ids(), trigram_index)?;
+
+    // The limit applies to verified matches, not to candidates
+    let candidate_options = QueryOptions {
+        limit: None,
+        ..options.clone()
+    };
+    let candidates = resolve_file_ids(path_index, &bitmap, &candidate_options);
+
+    let mut files: Vec<RegexFileMatches> = candidates
+        .par_iter()
+        .filter_map(|path| match_file(path, &regex))
+        .collect();
+    if let Some(limit) = options.limit {
+        files.truncate(limit);
+    }
+
+    Ok(RegexResult {
+        files,
+        candidate_count: candidates.len(),
+        full_scan,
+    })
+}
+
+fn regex_syntax_error(error: regex_syntax::Error) -> TokenizerError {
+    let (position, message) = match &error {
+        regex_syntax::Error::Parse(e) => (e.span().start.offset, e.kind().to_string()),
+        regex_syntax::Error::Translate(e) => (e.span().start.offset, e.kind().to_string()),
+        _ => (0, error.to_string()),
+    };
+    TokenizerError::QuerySyntax { position, message }
+}
+
+/// Scan one file; returns None when it cannot be read or has no match
+fn match_file(path: &Path, regex: &Regex) -> Option<RegexFileMatches> {
+    let file = File::open(path).ok()?;
+    if file.metadata().ok()?.len() == 0 {
+        return None;
+    }
+    let mmap = unsafe { Mmap::map(&file).ok()? };
+
+    // Skip binary files (null bytes in first 8KB), as the indexer does
+    let check_len = std::cmp::min(8192, mmap.len());
+    if mmap[..check_len].contains(&0) {
+        return None;
+    }
+
+    let mut line_number = 1;
+    let mut counted_to = 0;
+    let matches: Vec<RegexMatch> = regex
+        .find_iter(&mmap)
+        .map(|m| {
+            line_number += mmap[counted_to..m.start()]
+                .iter()
+                .filter(|&&b| b == b'\n')
+                .count();
+            counted_to = m.start();
+            RegexMatch {
+                start: m.start(),
+                end: m.end(),
+                line_number,
+                text: String::from_utf8_lossy(m.as_bytes()).into_owned(),
+            }
+        })
+        .collect();
+
+    if matches.is_empty() {
+        return None;
+    }
+    Some(RegexFileMatches {
+        path: path.to_path_buf(),
+        matches,
+    })
+}
+
+// ============================================================================
+// Regex to trigram query
+// ============================================================================
+
+/// Boolean query over trigrams that a file must satisfy to possibly match
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum TrigramQuery {
+    /// Every file may match
+    All,
+    /// No file can match
+    None,
+    /// Files containing one trigram
+    Trigram(u64),
+    /// Files satisfying every subquery
+    And(Vec<TrigramQuery>),
+    /// Files satisfying any subquery
+    Or(Vec<TrigramQuery>),
+}
+
+impl TrigramQuery {
+    fn and(self, other: TrigramQuery) -> TrigramQuery {
+        match (self, other) {
+            (TrigramQuery::None, _) | (_, TrigramQuery::None) => TrigramQuery::None,
+            (TrigramQuery::All, q) | (q, TrigramQuery::All) => q,
+            (a, b) if a == b => a,
+            (TrigramQuery::And(mut a), TrigramQuery::And(b)) => {
+                for q in b {
+                    if !a.contains(&q) {
+                        a.push(q);
+                    }
+                }
+                TrigramQuery::And(a)
+            }
+            (TrigramQuery::And(mut a), q) | (q, TrigramQuery::And(mut a)) => {
+                if !a.contains(&q) {
+                    a.push(q);
+                }
+                TrigramQuery::And(a)
+            }
+            (a, b) => TrigramQuery::And(vec![a, b]),
+        }
+    }
+
+    fn or(self, other: TrigramQuery) -> TrigramQuery {
+        match (self, other) {
+            (TrigramQuery::All, _) | (_, TrigramQuery::All) => TrigramQuery::All,
+            (TrigramQuery::None, q) | (q, TrigramQuery::None) => q,
+            (a, b) if a == b => a,
+            (TrigramQuery::Or(mut a), TrigramQuery::Or(b)) => {
+                for q in b {
+                    if !a.contains(&q) {
+                        a.push(q);
+                    }
+                }
+                TrigramQuery::Or(a)
+            }
+            (TrigramQuery::Or(mut a), q) | (q, TrigramQuery::Or(mut a)) => {
+                if !a.contains(&q) {
+                    a.push(q);
+                }
+                TrigramQuery::Or(a)
+            }
+            (a, b) => TrigramQuery::Or(vec![a, b]),
+        }
+    }
+
+    /// Get the files that satisfy the query
+    fn evaluate(
+        &self,
+        all_files: &RoaringBitmap,
+        index: &impl TrigramLookup,
+    ) -> Result<RoaringBitmap> {
+        match self {
+            TrigramQuery::All => Ok(all_files.clone()),
+            TrigramQuery::None => Ok(RoaringBitmap::new()),
+            TrigramQuery::Trigram(trigram) => Ok(index
+                .trigram_bitmap(*trigram)?
+                .map(|bitmap| bitmap.into_owned())
+                .unwrap_or_default()),
+            TrigramQuery::And(queries) => {
+                let mut queries = queries.iter();
+                let Some(first) = queries.next() else {
+                    return Ok(all_files.clone());
+                };
+                let mut result = first.evaluate(all_files, index)?;
+                for query in queries {
+                    if result.is_empty() {
+                        break;
+                    }
+                    result &= query.evaluate(all_files, index)?;
+                }
+                Ok(result)
+            }
+            TrigramQuery::Or(queries) => {
+                let mut result = RoaringBitmap::new();
+                for query in queries {
+                    result |= query.evaluate(all_files, index)?;
+                }
+                Ok(result)
+            }
+        }
+    }
+}
+
+type StringSet = BTreeSet<Vec<u8>>;
+
+/// What is known about the strings a (sub)regex can match
+#[derive(Debug, Clone)]
+struct Info {
+    /// Whether the empty string can match
+    can_empty: bool,
+    /// Every string that can match, when the set is small
+    exact: Option<StringSet>,
+    /// Possible beginnings of a match (when `exact` is None)
+    prefix: StringSet,
+    /// Possible endings of a match (when `exact` is None)
+    suffix: StringSet,
+    /// Trigram query every match satisfies
+    query: TrigramQuery,
+}
+
+impl Info {
+    fn exact(set: StringSet) -> Info {
+        Info {
+            can_empty: set.contains(&[][..]),
+            exact: Some(set),
+            prefix: StringSet::new(),
+            suffix: StringSet::new(),
+            query: TrigramQuery::All,
+        }
+        .simplify(false)
+    }
+
+    fn empty_string() -> Info {
+        Info::exact(StringSet::from([Vec::new()]))
+    }
+
+    /// Any single character
+    fn any_char() -> Info {
+        Info {
+            can_empty: false,
+            exact: None,
+            prefix: StringSet::from([Vec::new()]),
+            suffix: StringSet::from([Vec::new()]),
+            query: TrigramQuery::All,
+        }
+    }
+
+    /// Any string, including the empty one
+    fn any_string() -> Info {
+        Info {
+            can_empty: true,
+            ..Info::any_char()
+        }
+    }
+
+    fn prefixes(&self) -> &StringSet {
+        self.exact.as_ref().unwrap_or(&self.prefix)
+    }
+
+    fn suffixes(&self) -> &StringSet {
+        self.exact.as_ref().unwrap_or(&self.suffix)
+    }
+
+    /// The query with the exact strings folded in
+    fn exact_query(&self) -> TrigramQuery {
+        match &self.exact {
+            Some(exact) => self.query.clone().and(strings_query(exact)),
+            None => self.query.clone(),
+        }
+    }
+
+    /// Move oversized or long exact sets into the query and keep the
+    /// prefix and suffix sets small
+    fn simplify(mut self, force: bool) -> Info {
+        if let Some(exact) = &self.exact {
+            let min_len = exact.iter().map(|s| char_count(s)).min().unwrap_or(0);
+            if exact.len() > MAX_SET_SIZE || min_len >= 4 || (force && min_len >= 3) {
+                self.query = self.exact_query();
+                let exact = self.exact.take().unwrap_or_default();
+                self.prefix = exact.clone();
+                self.suffix = exact;
+            }
+        }
+
+        if self.exact.is_none() {
+            simplify_set(&mut self.prefix, &mut self.query, false);
+            simplify_set(&mut self.suffix, &mut self.query, true);
+        }
+        self
+    }
+}
+
+/// AND the strings of a prefix or suffix set into the query, then cut them
+/// down to at most two characters (fewer if the set is still too large)
+fn simplify_set(set: &mut StringSet, query: &mut TrigramQuery, suffix: bool) {
+    *query = std::mem::replace(query, TrigramQuery::All).and(strings_query(set));
+
+    let mut keep = 2;
+    loop {
+        *set = set
+            .iter()
+            .map(|s| {
+                let starts: Vec<usize> = char_spans(s).map(|(offset, _, _)| offset).collect();
+                if starts.len() <= keep {
+                    s.clone()
+                } else if suffix {
+                    s[starts[starts.len() - keep]..].to_vec()
+                } else {
+                    s[..starts[keep]].to_vec()
+                }
+            })
+            .collect();
+        if set.len() <= MAX_SET_SIZE || keep == 0 {
+            break;
+        }
+        keep -= 1;
+    }
+}
+
+/// Query matching files that contain any string of the set
+fn strings_query(set: &StringSet) -> TrigramQuery {
+    set.iter()
+        .map(|s| string_query(s))
+        .fold(TrigramQuery::None, TrigramQuery::or)
+}
+
+/// Query matching files that contain a string
+fn string_query(s: &[u8]) -> TrigramQuery {
+    // Bytes cut off from their character decode to None and match nothing
+    let chars: Vec<Option<char>> = char_spans(s).map(|(_, c, _)| c).collect();
+    chars
+        .windows(3)
+        .filter_map(|w| match w {
+            [Some(a), Some(b), Some(c)]
+                if is_trigram_token_char(*a)
+                    && is_trigram_token_char(*b)
+                    && is_trigram_token_char(*c) =>
+            {
+                Some(TrigramQuery::Trigram(pack_trigram(*a, *b, *c)))
+            }
+            _ => None,
+        })
+        .fold(TrigramQuery::All, TrigramQuery::and)
+}
+
+fn cross(xs: &StringSet, ys: &StringSet) -> StringSet {
+    xs.iter()
+        .flat_map(|x| ys.iter().map(move |y| [x.as_slice(), y].concat()))
+        .collect()
+}
+
+/// Compile a parsed regex into the trigram query its matches satisfy
+pub(crate) fn regex_trigram_query(hir: &Hir) -> TrigramQuery {
+    analyze(hir).simplify(true).exact_query()
+}
+
+fn analyze(hir: &Hir) -> Info {
+    match hir.kind() {
+        HirKind::Empty | HirKind::Look(_) => Info::empty_string(),
+        HirKind::Literal(literal) => Info::exact(StringSet::from([fold_token(&literal.0)])),
+        HirKind::Class(class) => match class_strings(class) {
+            Some(set) => Info::exact(set),
+            None => Info::any_char(),
+        },
+        HirKind::Capture(capture) => analyze(&capture.sub),
+        HirKind::Repetition(repetition) => {
+            match (repetition.min, repetition.max) {
+                (0, Some(1)) => alternate(analyze(&repetition.sub), Info::empty_string()),
+                (0, _) => Info::any_string(),
+                (_, Some(1)) => analyze(&repetition.sub),
+                // One or more copies: the strings of one copy are prefixes
+                // and suffixes of a match
+                _ => {
+                    let mut info = analyze(&repetition.sub);
+                    if let Some(exact) = info.exact.take() {
+                        info.prefix = exact.clone();
+                        info.suffix = exact;
+                    }
+                    info.simplify(false)
+                }
+            }
+        }
+        HirKind::Concat(subs) => subs
+            .iter()
+            .map(analyze)
+            .reduce(concat)
+            .unwrap_or_else(Info::empty_string),
+        HirKind::Alternation(subs) => subs
+            .iter()
+            .map(analyze)
+            .reduce(alternate)
+            .unwrap_or_else(Info::empty_string),
+    }
+}
+
+/// The case-folded single-character strings of a small class
+fn class_strings(class: &Class) -> Option<StringSet> {
+    match class {
+        Class::Unicode(class) => {
+            let size: usize = class
+                .ranges()
+                .iter()
+                .map(|r| r.end() as usize - r.start() as usize + 1)
+                .sum();
+            if size > MAX_CLASS_SIZE {
+                return None;
+            }
+            let mut buf = [0u8; 4];
+            Some(
+                class
+                    .ranges()
+                    .iter()
+                    .flat_map(|r| r.start()..=r.end())
+                    .map(|c| fold_case(c).encode_utf8(&mut buf).as_bytes().to_vec())
+                    .collect(),
+            )
+        }
+        Class::Bytes(class) => {
+            let size: usize = class
+                .ranges()
+                .iter()
+                .map(|r| r.end() as usize - r.start() as usize + 1)
+                .sum();
+            if size > MAX_CLASS_SIZE {
+                return None;
+            }
+            Some(
+                class
+                    .ranges()
+                    .iter()
+                    .flat_map(|r| r.start()..=r.end())
+                    .map(|b| vec![b.to_ascii_lowercase()])
+                    .collect(),
+            )
+        }
+    }
+}
+
+fn concat(x: Info, y: Info) -> Info {
+    let query = x.query.clone().and(y.query.clone());
+
+    if let (Some(xe), Some(ye)) = (&x.exact, &y.exact) {
+        if xe.len() * ye.len() <= MAX_SET_SIZE {
+            let mut info = Info::exact(cross(xe, ye));
+            info.query = info.query.and(query);
+            return info;
+        }
+    }
+
+    let mut prefix = match &x.exact {
+        Some(xe) => cross(xe, y.prefixes()),
+        None => x.prefix.clone(),
+    };
+    if x.can_empty {
+        prefix.extend(y.prefixes().iter().cloned());
+    }
+    let mut suffix = match &y.exact {
+        Some(ye) => cross(x.suffixes(), ye),
+        None => y.suffix.clone(),
+    };
+    if y.can_empty {
+        suffix.extend(x.suffixes().iter().cloned());
+    }
+
+    // Trigrams spanning the boundary are not covered by either side
+    let mut query = query;
+    if x.exact.is_none()
+        && y.exact.is_none()
+        && x.suffix.len() <= MAX_SET_SIZE
+        && y.prefix.len() <= MAX_SET_SIZE
+    {
+        query = query.and(strings_query(&cross(&x.suffix, &y.prefix)));
+    }
+
+    Info {
+        can_empty: x.can_empty && y.can_empty,
+        exact: None,
+        prefix,
+        suffix,
+        query,
+    }
+    .simplify(false)
+}
+
+fn alternate(x: Info, y: Info) -> Info {
+    let can_empty = x.can_empty || y.can_empty;
+
+    if let (Some(xe), Some(ye)) = (&x.exact, &y.exact) {
+        let mut info = Info::exact(xe.union(ye).cloned().collect());
+        info.query = info.query.and(x.query.or(y.query));
+        return info;
+    }
+
+    Info {
+        can_empty,
+        exact: None,
+        prefix: x.prefixes().union(y.prefixes()).cloned().collect(),
+        suffix: x.suffixes().union(y.suffixes()).cloned().collect(),
+        query: x.exact_query().or(y.exact_query()),
+    }
+    .simplify(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::{scan_and_build_indexes, ScanConfig};
+    use crate::trigram::unpack_trigram;
+    use tempfile::TempDir;
+
+    fn trigram_query(pattern: &str) -> TrigramQuery {
+        let hir = ParserBuilder::new().build().parse(pattern).unwrap();
+        regex_trigram_query(&hir)
+    }
+
+    fn trigram(s: &str) -> TrigramQuery {
+        let c: Vec<char> = s.chars().collect();
+        TrigramQuery::Trigram(pack_trigram(c[0], c[1], c[2]))
+    }
+
+    fn trigrams(query: &TrigramQuery) -> BTreeSet<String> {
+        match query {
+            TrigramQuery::Trigram(t) => {
+                let (a, b, c) = unpack_trigram(*t);
+                BTreeSet::from([[a, b, c].iter().collect()])
+            }
+            TrigramQuery::And(qs) | TrigramQuery::Or(qs) => qs.iter().flat_map(trigrams).collect(),
+            _ => BTreeSet::new(),
+        }
+    }
+
+    #[test]
+    fn test_literal_and_alternation_queries() {
+        assert_eq!(
+            trigram_query("Parse_"),
+            TrigramQuery::And(vec![
+                trigram("par"),
+                trigram("ars"),
+                trigram("rse"),
+                trigram("se_")
+            ])
+        );
+        assert_eq!(
+            trigram_query("foo|bar"),
+            TrigramQuery::Or(vec![trigram("bar"), trigram("foo")])
+        );
+        // Trigrams never span delimiters, so "a b" gives nothing
+        assert_eq!(trigram_query("a bc"), TrigramQuery::All);
+        // Trigrams are over folded characters, not bytes
+        assert_eq!(trigram_query("ÉTÉ"), trigram("été"));
+        assert_eq!(
+            trigrams(&trigram_query("(?i)Σοφ")),
+            BTreeSet::from(["σοφ".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_queries_across_operators() {
+        let query = trigram_query(r"fn\s+parse_\w+");
+        assert_eq!(
+            trigrams(&query),
+            BTreeSet::from(["ars", "par", "rse", "se_"].map(String::from))
+        );
+
+        // Case-insensitive classes fold to one lowercase string
+        let query = trigram_query("(?i)HashMap");
+        assert!(trigrams(&query).contains("map"));
+
+        // A small class is expanded: [cb]at needs "cat" or "bat"
+        assert_eq!(
+            trigram_query("[cb]at"),
+            TrigramQuery::Or(vec![trigram("bat"), trigram("cat")])
+        );
+
+        assert_eq!(trigram_query(r"\w+"), TrigramQuery::All);
+        assert_eq!(trigram_query("(abc)?"), TrigramQuery::All);
+        assert_eq!(trigram_query("x.*y"), TrigramQuery::All);
+    }
+
+    #[test]
+    fn test_query_regex_verifies_candidates() {
+        let src = TempDir::new().unwrap();
+        std::fs::write(
+            src.path().join("a.rs"),
+            "fn main() {}\nfn parse_header() {}\n",
+        )
+        .unwrap();
+        // Has every trigram of "parse_" but no match
+        std::fs::write(src.path().join("b.rs"), "parse_ fn x\n").unwrap();
+        std::fs::write(src.path().join("c.rs"), "nothing here\n").unwrap();
+        let (paths, _, _, trigram) =
+            scan_and_build_indexes(src.path(), &ScanConfig::default()).unwrap();
+
+        let options = QueryOptions::default();
+        let result = query_regex(&paths, &trigram, r"fn\s+parse_\w+", false, &options).unwrap();
+        assert!(!result.full_scan);
+        assert_eq!(result.candidate_count, 2);
+        assert_eq!(result.files.len(), 1);
+        assert!(result.files[0].path.ends_with("a.rs"));
+        assert_eq!(
+            result.files[0].matches,
+            vec![RegexMatch {
+                start: 13,
+                end: 28,
+                line_number: 2,
+                text: "fn parse_header".to_string(),
+            }]
+        );
+
+        let result = query_regex(&paths, &trigram, "PARSE_H", true, &options).unwrap();
+        assert_eq!(result.files.len(), 1);
+        assert_eq!(result.files[0].matches[0].text, "parse_h");
+    }
+
+    #[test]
+    fn test_query_regex_full_scan_fallback() {
+        let src = TempDir::new().unwrap();
+        std::fs::write(src.path().join("a.txt"), "x = 1\n").unwrap();
+        std::fs::write(src.path().join("b.txt"), "y = 2\n").unwrap();
+        let (paths, _, _, trigram) =
+            scan_and_build_indexes(src.path(), &ScanConfig::default()).unwrap();
+
+        let options = QueryOptions::default();
+        let result = query_regex(&paths, &trigram, r"\w = \d", false, &options).unwrap();
+        assert!(result.full_scan);
+        assert_eq!(result.candidate_count, 2);
+        assert_eq!(result.files.len(), 2);
+
+        let options = QueryOptions {
+            limit: Some(1),
+            ..Default::default()
+        };
+        let result = query_regex(&paths, &trigram, r"\w = \d", false, &options).unwrap();
+        assert_eq!(result.files.len(), 1);
+    }
+
+    #[test]
+    fn test_invalid_regex() {
+        let paths = PathIndex::new(Default::default(), PathBuf::from("/"));
+        let trigram = crate::index::TrigramIndex::new(Default::default());
+        let result = query_regex(&paths, &trigram, "ab(c", false, &QueryOptions::default());
+        assert!(matches!(
+            result,
+            Err(TokenizerError::QuerySyntax { position: 2, .. })
+        ));
+    }
+}
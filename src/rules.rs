@@ -0,0 +1,188 @@
+//! File selection rules shared by every directory walk
+//!
+//! Compiles the filters in `ScanConfig` (extensions, excluded directory
+//! names and include/exclude globs) once so the streaming jwalk pipeline and
+//! the legacy walkdir path make identical decisions.
+
+use crate::error::{Result, TokenizerError};
+use crate::scanner::ScanConfig;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use std::path::Path;
+
+/// Compiled include/exclude rules for a scan
+#[derive(Debug, Clone)]
+pub(crate) struct ScanRules {
+    /// File extensions to include (empty = all files)
+    extensions: Vec<String>,
+    /// Directory or file names to exclude (case-insensitive)
+    exclude_names: Vec<String>,
+    /// Root-relative globs a file must match (None = all files)
+    include: Option<GlobSet>,
+    /// Root-relative globs that exclude files and directories
+    exclude: GlobSet,
+    /// Directory prefixes of `.../**` exclude globs, used to prune whole subtrees
+    exclude_dirs: GlobSet,
+}
+
+impl ScanRules {
+    /// Compile the rules from a scan configuration
+    ///
+    /// Entries in `include_globs` starting with `!` are treated as excludes.
+    pub(crate) fn new(config: &ScanConfig) -> Result<Self> {
+        let mut include = GlobSetBuilder::new();
+        let mut has_include = false;
+        let mut exclude_patterns: Vec<&str> = Vec::new();
+
+        for pattern in &config.include_globs {
+            if let Some(negated) = pattern.strip_prefix('!') {
+                exclude_patterns.push(negated);
+            } else {
+                include.add(compile_glob(pattern)?);
+                has_include = true;
+            }
+        }
+        exclude_patterns.extend(config.exclude_globs.iter().map(String::as_str));
+
+        let mut exclude = GlobSetBuilder::new();
+        let mut exclude_dirs = GlobSetBuilder::new();
+        for pattern in exclude_patterns {
+            exclude.add(compile_glob(pattern)?);
+            if let Some(dir) = pattern.strip_suffix("/**") {
+                exclude_dirs.add(compile_glob(dir)?);
+            }
+        }
+
+        Ok(Self {
+            extensions: config.extensions.clone(),
+            exclude_names: config.exclude_patterns.clone(),
+            include: if has_include {
+                Some(build_set(include)?)
+            } else {
+                None
+            },
+            exclude: build_set(exclude)?,
+            exclude_dirs: build_set(exclude_dirs)?,
+        })
+    }
+
+    /// Check whether a directory (given relative to the walk root) should be skipped
+    pub(crate) fn skip_dir(&self, rel_path: &Path) -> bool {
+        self.excluded_by_name(rel_path)
+            || self.exclude.is_match(rel_path)
+            || self.exclude_dirs.is_match(rel_path)
+    }
+
+    /// Check whether a file (given relative to the walk root) should be skipped
+    pub(crate) fn skip_file(&self, rel_path: &Path) -> bool {
+        if self.excluded_by_name(rel_path) {
+            return true;
+        }
+
+        if !self.extensions.is_empty() {
+            match rel_path.extension().and_then(|e| e.to_str()) {
+                Some(ext) if self.extensions.iter().any(|e| e == ext) => {}
+                _ => return true,
+            }
+        }
+
+        if let Some(ref include) = self.include {
+            if !include.is_match(rel_path) {
+                return true;
+            }
+        }
+
+        self.exclude.is_match(rel_path)
+    }
+
+    /// Check the entry's own name against the excluded names
+    fn excluded_by_name(&self, rel_path: &Path) -> bool {
+        match rel_path.file_name() {
+            Some(name) => crate::scanner::should_exclude(Path::new(name), &self.exclude_names),
+            None => false,
+        }
+    }
+}
+
+/// Compile a root-relative glob (`*` stays within one path component, `**` spans many)
+fn compile_glob(pattern: &str) -> Result<globset::Glob> {
+    GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .build()
+        .map_err(|e| TokenizerError::InvalidPattern(e.to_string()))
+}
+
+fn build_set(builder: GlobSetBuilder) -> Result<GlobSet> {
+    builder
+        .build()
+        .map_err(|e| TokenizerError::InvalidPattern(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(include: &[&str], exclude: &[&str]) -> ScanRules {
+        let config = ScanConfig {
+            include_globs: include.iter().map(|s| s.to_string()).collect(),
+            exclude_globs: exclude.iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        };
+        ScanRules::new(&config).unwrap()
+    }
+
+    #[test]
+    fn test_include_globs() {
+        let rules = rules(&["src/**/*.{rs,toml}"], &[]);
+        assert!(!rules.skip_file(Path::new("src/main.rs")));
+        assert!(!rules.skip_file(Path::new("src/a/b/Cargo.toml")));
+        assert!(rules.skip_file(Path::new("src/readme.md")));
+        assert!(rules.skip_file(Path::new("main.rs")));
+    }
+
+    #[test]
+    fn test_negated_include_excludes() {
+        let rules = rules(&["**/*.ts", "!**/generated/**"], &[]);
+        assert!(!rules.skip_file(Path::new("web/app.ts")));
+        assert!(rules.skip_file(Path::new("web/generated/api.ts")));
+        assert!(rules.skip_dir(Path::new("web/generated")));
+        assert!(!rules.skip_dir(Path::new("web")));
+    }
+
+    #[test]
+    fn test_multi_part_extension() {
+        let rules = rules(&[], &["**/*.d.ts"]);
+        assert!(rules.skip_file(Path::new("types/index.d.ts")));
+        assert!(!rules.skip_file(Path::new("types/index.ts")));
+    }
+
+    #[test]
+    fn test_star_does_not_cross_directories() {
+        let rules = rules(&["*.rs"], &[]);
+        assert!(!rules.skip_file(Path::new("lib.rs")));
+        assert!(rules.skip_file(Path::new("src/lib.rs")));
+    }
+
+    #[test]
+    fn test_excluded_names_and_extensions() {
+        let config = ScanConfig {
+            extensions: vec!["rs".to_string()],
+            ..Default::default()
+        };
+        let rules = ScanRules::new(&config).unwrap();
+        assert!(rules.skip_dir(Path::new("a/node_modules")));
+        assert!(!rules.skip_file(Path::new("a/lib.rs")));
+        assert!(rules.skip_file(Path::new("a/lib.py")));
+    }
+
+    #[test]
+    fn test_invalid_glob() {
+        let config = ScanConfig {
+            include_globs: vec!["src/[".to_string()],
+            ..Default::default()
+        };
+        assert!(matches!(
+            ScanRules::new(&config),
+            Err(TokenizerError::InvalidPattern(_))
+        ));
+    }
+}
@@ -1,40 +1,112 @@
+use crate::dictionary::TokenDictionary;
 use crate::error::{Result, TokenizerError};
 use crate::fmt_num;
-use crate::index::{ExactTokenIndex, IndexHeader, PathIndex, TokenIndex, TrigramIndex};
-use crate::tokenizer::{extract_exact_tokens_from_file, extract_exact_tokens_lower_from_file, extract_tokens_from_file};
-use crate::trigram::extract_trigrams_from_file;
+use crate::gitignore::IgnoreStack;
+use crate::index::{
+    default_tokenizer, ExactTokenIndex, FileMeta, IndexHeader, IndexSet, PathIndex, TokenIndex,
+    TrigramIndex,
+};
+use crate::positions::PositionIndex;
+use crate::provenance::{BuildInfo, SkipCounters, SkipReason};
+use crate::ranking::TermFrequencies;
+use crate::rules::ScanRules;
+use crate::subword::SubwordIndex;
+use crate::tokenizer::{
+    extract_tokens_from_file, hash_token, hash_token_lower, hash_tokens_normalized, ExactTokenizer,
+    Tokenizer,
+};
+use crate::trigram::extract_trigrams;
+use jwalk::WalkDirGeneric as JWalkDirGeneric;
+use memmap2::Mmap;
 use rayon::prelude::*;
 use roaring::RoaringBitmap;
-use rustc_hash::FxHashMap;
+use rustc_hash::{FxHashMap, FxHashSet, FxHasher};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::hash::Hasher;
 use std::path::{Path, PathBuf};
-use std::sync::mpsc;
+use std::sync::{mpsc, Arc};
 use std::thread;
 use std::time::Instant;
-use jwalk::WalkDir as JWalkDir;
 use walkdir::WalkDir;
 
 /// Result from processing a single file in the streaming pipeline
-struct FileProcessingResult {
+pub(crate) struct FileProcessingResult {
     file_id: u32,
     exact_tokens: Vec<u64>,
+    /// Token strings parallel to `exact_tokens` (empty unless a dictionary
+    /// or subword index is built)
+    exact_token_texts: Vec<Box<[u8]>>,
     exact_lower_tokens: Vec<u64>,
-    trigrams: Vec<u32>,
+    /// Normalized token hashes (empty unless a normalized index is built)
+    exact_normalized_tokens: Vec<u64>,
+    /// Token ordinal positions (empty unless a positional index is built)
+    exact_token_positions: Vec<(u64, Vec<u32>)>,
+    /// Token counts for relevance ranking (empty unless term frequencies are built)
+    exact_token_counts: Vec<(u64, u32)>,
+    exact_lower_token_counts: Vec<(u64, u32)>,
+    trigrams: Vec<u64>,
+    content_hash: Option<u64>,
+    /// Set when the contents were not indexed
+    skipped: Option<SkipReason>,
 }
 
 /// Configuration for scanning
-#[derive(Debug, Clone)]
+///
+/// Stored with the index as part of its `BuildInfo`; the tokenizer is not
+/// serialized since the index header already names it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ScanConfig {
     /// File extensions to include (empty = all files)
     pub extensions: Vec<String>,
 
-    /// Patterns to exclude
+    /// Directory or file names to exclude
     pub exclude_patterns: Vec<String>,
 
+    /// Globs matched against root-relative paths that a file must match
+    /// (empty = all files). Entries starting with `!` exclude instead.
+    pub include_globs: Vec<String>,
+
+    /// Globs matched against root-relative paths that exclude files and directories
+    pub exclude_globs: Vec<String>,
+
     /// Maximum file size to index (in bytes)
     pub max_file_size: u64,
 
     /// Number of files per batch for parallel processing
     pub batch_size: usize,
+
+    /// Record a content hash per file so incremental updates can skip
+    /// files whose mtime changed but whose contents did not
+    pub hash_contents: bool,
+
+    /// Skip files matched by `.gitignore`, `.ignore`, `.git/info/exclude`
+    /// and the global git excludes file
+    pub respect_ignore_files: bool,
+
+    /// Record the token strings behind exact token hashes (the `.dict` file)
+    pub build_dictionary: bool,
+
+    /// Record where each exact token occurs in each file (the `.pos` file),
+    /// for phrase and `NEAR/n` queries
+    pub build_positions: bool,
+
+    /// Count how often each token occurs in each file (the `.tf` and `.tfi`
+    /// files), for ranking results by relevance
+    pub build_frequencies: bool,
+
+    /// Split exact tokens into identifier parts (the `.sub` file), for
+    /// subword queries
+    pub build_subwords: bool,
+
+    /// Index tokens with `_` and `-` stripped and case folded (the `.exactn`
+    /// file), for style-insensitive queries
+    pub build_normalized: bool,
+
+    /// Token rules for the exact indexes and every index derived from
+    /// them; queries against the index use the same tokenizer
+    #[serde(skip, default = "default_tokenizer")]
+    pub tokenizer: Arc<dyn Tokenizer>,
 }
 
 impl Default for ScanConfig {
@@ -48,8 +120,18 @@ impl Default for ScanConfig {
                 ".cache".to_string(),
                 "__pycache__".to_string(),
             ],
+            include_globs: vec![],
+            exclude_globs: vec![],
             max_file_size: 10 * 1024 * 1024, // 10 MB
             batch_size: 1000,
+            hash_contents: false,
+            respect_ignore_files: true,
+            build_dictionary: true,
+            build_positions: false,
+            build_frequencies: true,
+            build_subwords: true,
+            build_normalized: true,
+            tokenizer: Arc::new(ExactTokenizer),
         }
     }
 }
@@ -74,24 +156,63 @@ pub fn scan_and_index(root: &Path, config: &ScanConfig) -> Result<TokenIndex> {
 fn walk_and_send(
     root: PathBuf,
     config: ScanConfig,
-    tx: mpsc::SyncSender<PathBuf>,
+    tx: mpsc::SyncSender<(PathBuf, FileMeta)>,
+    skipped: Arc<SkipCounters>,
 ) -> Result<()> {
-    let exclude_patterns = config.exclude_patterns.clone();
-    let extensions = config.extensions.clone();
+    let rules = ScanRules::new(&config)?;
     let max_file_size = config.max_file_size;
+    let respect_ignore_files = config.respect_ignore_files;
+    let walk_root = root.clone();
+    let walk_skipped = skipped.clone();
+
+    let root_ignores = if respect_ignore_files {
+        IgnoreStack::for_root(&root)
+    } else {
+        IgnoreStack::default()
+    };
 
-    for entry in JWalkDir::new(&root)
+    for entry in JWalkDirGeneric::<(IgnoreStack, ())>::new(&root)
         .skip_hidden(false)
         .follow_links(false)
-        .process_read_dir(move |_depth, _path, _state, children| {
-            // Filter out excluded directories in parallel (runs on rayon threads)
+        // Own pool: the coordinator keeps the global rayon pool busy with tokenization
+        .parallelism(jwalk::Parallelism::RayonNewPool(0))
+        .root_read_dir_state(root_ignores)
+        .process_read_dir(move |depth, path, ignores, children| {
+            // Load this directory's ignore files; children inherit the stack
+            if respect_ignore_files && depth.is_some() {
+                let rel_dir = path.strip_prefix(&walk_root).unwrap_or(Path::new(""));
+                *ignores = ignores.enter_dir(rel_dir);
+            }
+
+            // The root entry itself is always walked
+            if depth.is_none() {
+                return;
+            }
+
+            // Filter out excluded and ignored entries in parallel (runs on rayon threads)
             children.retain(|entry_result| {
                 if let Ok(entry) = entry_result {
-                    // Check if this is a directory we should exclude
-                    if let Some(file_name) = entry.file_name.to_str() {
-                        if exclude_patterns.iter().any(|p| file_name.eq_ignore_ascii_case(p)) {
-                            return false;
+                    let entry_path = entry.path();
+                    let rel = entry_path.strip_prefix(&walk_root).unwrap_or(&entry_path);
+                    let is_dir = entry.file_type.is_dir();
+
+                    let skip = if is_dir {
+                        rules.skip_dir(rel)
+                    } else {
+                        rules.skip_file(rel)
+                    };
+                    if skip {
+                        if !is_dir {
+                            walk_skipped.add(SkipReason::Excluded);
                         }
+                        return false;
+                    }
+
+                    if respect_ignore_files && ignores.is_ignored(rel, is_dir) {
+                        if !is_dir {
+                            walk_skipped.add(SkipReason::Ignored);
+                        }
+                        return false;
                     }
                 }
                 true
@@ -107,26 +228,20 @@ fn walk_and_send(
 
         let path = entry.path();
 
-        // Check extension filter
-        if !extensions.is_empty() {
-            if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
-                if !extensions.iter().any(|e| e == ext) {
+        // Check file size (metadata already fetched by jwalk)
+        let meta = match entry.metadata() {
+            Ok(metadata) => {
+                if metadata.len() > max_file_size {
+                    skipped.add(SkipReason::TooLarge);
                     continue;
                 }
-            } else {
-                continue;
+                FileMeta::from_metadata(&metadata)
             }
-        }
-
-        // Check file size (metadata already fetched by jwalk)
-        if let Ok(metadata) = entry.metadata() {
-            if metadata.len() > max_file_size {
-                continue;
-            }
-        }
+            Err(_) => FileMeta::default(),
+        };
 
         // Send to coordinator (blocks if channel full = backpressure)
-        if tx.send(path.to_path_buf()).is_err() {
+        if tx.send((path.to_path_buf(), meta)).is_err() {
             // Receiver dropped, stop walking
             break;
         }
@@ -135,57 +250,336 @@ fn walk_and_send(
     Ok(())
 }
 
+/// Walk a directory and collect every file that passes the scan filters
+pub(crate) fn walk_files(root: &Path, config: &ScanConfig) -> Result<Vec<(PathBuf, FileMeta)>> {
+    let (tx, rx) = mpsc::sync_channel::<(PathBuf, FileMeta)>(1024);
+    let walker_config = config.clone();
+    let walker_root = root.to_path_buf();
+    let skipped = Arc::new(SkipCounters::default());
+    let walker_handle =
+        thread::spawn(move || walk_and_send(walker_root, walker_config, tx, skipped));
+
+    let files: Vec<_> = rx.into_iter().collect();
+
+    walker_handle
+        .join()
+        .map_err(|_| TokenizerError::WalkDir("Walker thread panicked".to_string()))??;
+
+    Ok(files)
+}
+
+/// Hash a file's contents (used for change detection)
+pub(crate) fn hash_file_contents(path: &Path) -> std::io::Result<u64> {
+    let file = File::open(path)?;
+    let mut hasher = FxHasher::default();
+
+    if file.metadata()?.len() > 0 {
+        let mmap = unsafe { memmap2::Mmap::map(&file)? };
+        hasher.write(&mmap);
+    }
+
+    Ok(hasher.finish())
+}
+
+/// What `process_single_file` extracts from a file besides its exact,
+/// lowercase and trigram hashes
+#[derive(Debug, Clone)]
+pub(crate) struct FileOutputs {
+    /// Splits the contents into exact tokens
+    pub(crate) tokenizer: Arc<dyn Tokenizer>,
+    /// Hash the contents for change detection
+    pub(crate) content_hash: bool,
+    /// Keep the token strings for the dictionary and subword index
+    pub(crate) token_texts: bool,
+    /// Record token ordinal positions for the positional index
+    pub(crate) token_positions: bool,
+    /// Count tokens for the term frequencies
+    pub(crate) token_counts: bool,
+    /// Hash the normalized tokens for the normalized index
+    pub(crate) normalized_tokens: bool,
+}
+
+impl FileOutputs {
+    /// Everything a full scan with `config` builds
+    fn for_config(config: &ScanConfig) -> Self {
+        Self {
+            tokenizer: config.tokenizer.clone(),
+            content_hash: config.hash_contents,
+            token_texts: config.build_dictionary || config.build_subwords,
+            token_positions: config.build_positions,
+            token_counts: config.build_frequencies,
+            normalized_tokens: config.build_normalized,
+        }
+    }
+}
+
+/// Map a file for tokenizing; empty files give None
+///
+/// Fails with the reason when the contents can't be indexed.
+fn map_text_file(path: &Path) -> std::result::Result<Option<Mmap>, SkipReason> {
+    let file = File::open(path).map_err(|_| SkipReason::Unreadable)?;
+    if file.metadata().map_err(|_| SkipReason::Unreadable)?.len() == 0 {
+        return Ok(None);
+    }
+
+    let mmap = unsafe { Mmap::map(&file).map_err(|_| SkipReason::Unreadable)? };
+
+    // Check for binary file (null bytes in first 8KB)
+    let check_len = std::cmp::min(8192, mmap.len());
+    if mmap[..check_len].contains(&0) {
+        return Err(SkipReason::Binary);
+    }
+    Ok(Some(mmap))
+}
+
 /// Process a single file and extract tokens + trigrams
-fn process_single_file(file_id: u32, path: &Path) -> FileProcessingResult {
-    let exact_tokens = extract_exact_tokens_from_file(path).unwrap_or_default();
-    let exact_lower_tokens = extract_exact_tokens_lower_from_file(path).unwrap_or_default();
-    let trigrams = extract_trigrams_from_file(path).unwrap_or_default();
+///
+/// The file is tokenized once with `outputs.tokenizer`; every exact-family
+/// hash, position and count comes from that one pass.
+pub(crate) fn process_single_file(
+    file_id: u32,
+    path: &Path,
+    outputs: &FileOutputs,
+) -> FileProcessingResult {
+    let mapping = map_text_file(path);
+    let content: &[u8] = match &mapping {
+        Ok(Some(mmap)) => mmap,
+        _ => &[],
+    };
+
+    let mut unique_tokens: FxHashMap<u64, &[u8]> = FxHashMap::default();
+    let mut lower_counts: FxHashMap<u64, u32> = FxHashMap::default();
+    let mut counts: FxHashMap<u64, u32> = FxHashMap::default();
+    let mut positions: FxHashMap<u64, Vec<u32>> = FxHashMap::default();
+    for (ordinal, token) in outputs.tokenizer.tokens(content).enumerate() {
+        let hash = hash_token(token);
+        unique_tokens.entry(hash).or_insert(token);
+        *lower_counts.entry(hash_token_lower(token)).or_default() += 1;
+        if outputs.token_counts {
+            *counts.entry(hash).or_default() += 1;
+        }
+        if outputs.token_positions {
+            positions.entry(hash).or_default().push(ordinal as u32);
+        }
+    }
+
+    let exact_normalized_tokens = if outputs.normalized_tokens {
+        let normalized: FxHashSet<u64> =
+            hash_tokens_normalized(unique_tokens.values().copied()).collect();
+        normalized.into_iter().collect()
+    } else {
+        Vec::new()
+    };
+    let (exact_tokens, exact_token_texts) = if outputs.token_texts {
+        unique_tokens
+            .into_iter()
+            .map(|(hash, token)| (hash, Box::from(token)))
+            .unzip()
+    } else {
+        (unique_tokens.into_keys().collect(), Vec::new())
+    };
+    let exact_lower_tokens = lower_counts.keys().copied().collect();
+    let (exact_token_counts, exact_lower_token_counts) = if outputs.token_counts {
+        (
+            counts.into_iter().collect(),
+            lower_counts.into_iter().collect(),
+        )
+    } else {
+        (Vec::new(), Vec::new())
+    };
+    let trigrams: FxHashSet<u64> = extract_trigrams(content).collect();
+    let content_hash = if outputs.content_hash {
+        hash_file_contents(path).ok()
+    } else {
+        None
+    };
 
     FileProcessingResult {
         file_id,
         exact_tokens,
+        exact_token_texts,
         exact_lower_tokens,
-        trigrams,
+        exact_normalized_tokens,
+        exact_token_positions: positions.into_iter().collect(),
+        exact_token_counts,
+        exact_lower_token_counts,
+        trigrams: trigrams.into_iter().collect(),
+        content_hash,
+        skipped: mapping.err(),
+    }
+}
+
+/// Add processed files to an existing set of indexes
+///
+/// The files must already be registered in `indexes.paths`.
+pub(crate) fn apply_results(indexes: &mut IndexSet, results: Vec<FileProcessingResult>) {
+    for result in results {
+        if let Some(dictionary) = indexes.exact.dictionary.as_mut() {
+            for (hash, token) in result.exact_tokens.iter().zip(&result.exact_token_texts) {
+                dictionary.insert_hashed(*hash, token);
+            }
+        }
+        if let Some(dictionary) = indexes.exact_lower.dictionary.as_mut() {
+            for token in &result.exact_token_texts {
+                dictionary.insert_lower(token);
+            }
+        }
+        if let Some(normalized) = indexes.exact.normalized.as_mut() {
+            if let Some(dictionary) = normalized.dictionary.as_mut() {
+                for token in &result.exact_token_texts {
+                    dictionary.insert_normalized(token);
+                }
+            }
+            for token_hash in result.exact_normalized_tokens {
+                normalized.add_token(token_hash, result.file_id);
+            }
+        }
+        if let Some(subwords) = indexes.exact.subwords.as_mut() {
+            for (hash, token) in result.exact_tokens.iter().zip(&result.exact_token_texts) {
+                subwords.add_token(*hash, token, result.file_id);
+            }
+        }
+        if let Some(positions) = indexes.exact.positions.as_mut() {
+            positions.insert_file(result.file_id, result.exact_token_positions);
+        }
+        if let Some(frequencies) = indexes.exact.frequencies.as_mut() {
+            frequencies.insert_file(result.file_id, result.exact_token_counts);
+        }
+        if let Some(frequencies) = indexes.exact_lower.frequencies.as_mut() {
+            frequencies.insert_file(result.file_id, result.exact_lower_token_counts);
+        }
+
+        for token_hash in result.exact_tokens {
+            indexes.exact.add_token(token_hash, result.file_id);
+        }
+
+        for token_hash in result.exact_lower_tokens {
+            indexes.exact_lower.add_token(token_hash, result.file_id);
+        }
+
+        for trigram in result.trigrams {
+            indexes.trigram.add_trigram(trigram, result.file_id);
+        }
+
+        if let Some(hash) = result.content_hash {
+            if let Some(meta) = indexes.paths.file_meta.get_mut(result.file_id as usize) {
+                meta.content_hash = Some(hash);
+            }
+        }
     }
 }
 
 /// Merge all streaming results into final indexes
+///
+/// The optional companions are built as requested by `config`.
 fn merge_results(
     rx: mpsc::Receiver<FileProcessingResult>,
     header: IndexHeader,
+    path_index: &mut PathIndex,
+    config: &ScanConfig,
+    skipped: &SkipCounters,
 ) -> (ExactTokenIndex, ExactTokenIndex, TrigramIndex) {
+    let mut dictionary = config.build_dictionary.then(TokenDictionary::new);
+    let mut positions = config.build_positions.then(PositionIndex::new);
+    let mut subwords = config.build_subwords.then(SubwordIndex::new);
+    let mut frequencies = config.build_frequencies.then(TermFrequencies::new);
+    let mut lower_frequencies = config.build_frequencies.then(TermFrequencies::new);
     let mut exact_map: FxHashMap<u64, RoaringBitmap> = FxHashMap::default();
     let mut exact_lower_map: FxHashMap<u64, RoaringBitmap> = FxHashMap::default();
-    let mut trigram_map: FxHashMap<u32, RoaringBitmap> = FxHashMap::default();
+    let mut normalized_map = config
+        .build_normalized
+        .then(FxHashMap::<u64, RoaringBitmap>::default);
+    let mut trigram_map: FxHashMap<u64, RoaringBitmap> = FxHashMap::default();
 
     for result in rx {
+        if let Some(hash) = result.content_hash {
+            path_index.file_meta[result.file_id as usize].content_hash = Some(hash);
+        }
+        if let Some(reason) = result.skipped {
+            skipped.add(reason);
+        }
+
+        if let Some(dictionary) = dictionary.as_mut() {
+            for (hash, token) in result.exact_tokens.iter().zip(&result.exact_token_texts) {
+                dictionary.insert_hashed(*hash, token);
+            }
+        }
+
+        if let Some(subwords) = subwords.as_mut() {
+            for (hash, token) in result.exact_tokens.iter().zip(&result.exact_token_texts) {
+                subwords.add_token(*hash, token, result.file_id);
+            }
+        }
+
+        if let Some(positions) = positions.as_mut() {
+            positions.insert_file(result.file_id, result.exact_token_positions);
+        }
+
+        if let Some(frequencies) = frequencies.as_mut() {
+            frequencies.insert_file(result.file_id, result.exact_token_counts);
+        }
+        if let Some(frequencies) = lower_frequencies.as_mut() {
+            frequencies.insert_file(result.file_id, result.exact_lower_token_counts);
+        }
+
         for token_hash in result.exact_tokens {
             exact_map
                 .entry(token_hash)
-                .or_insert_with(RoaringBitmap::new)
+                .or_default()
                 .insert(result.file_id);
         }
 
         for token_hash in result.exact_lower_tokens {
             exact_lower_map
                 .entry(token_hash)
-                .or_insert_with(RoaringBitmap::new)
+                .or_default()
                 .insert(result.file_id);
         }
 
+        if let Some(normalized_map) = normalized_map.as_mut() {
+            for token_hash in result.exact_normalized_tokens {
+                normalized_map
+                    .entry(token_hash)
+                    .or_default()
+                    .insert(result.file_id);
+            }
+        }
+
         for trigram in result.trigrams {
             trigram_map
                 .entry(trigram)
-                .or_insert_with(RoaringBitmap::new)
+                .or_default()
                 .insert(result.file_id);
         }
     }
 
     let mut exact_index = ExactTokenIndex::new(header.clone());
+    exact_index.tokenizer = config.tokenizer.clone();
     exact_index.token_map = exact_map;
+    exact_index.dictionary = dictionary;
+    exact_index.positions = positions;
+    exact_index.frequencies = frequencies;
+    exact_index.subwords = subwords;
 
     let mut exact_lower_index = ExactTokenIndex::new(header.clone());
+    exact_lower_index.tokenizer = config.tokenizer.clone();
     exact_lower_index.token_map = exact_lower_map;
+    exact_lower_index.dictionary = exact_index
+        .dictionary
+        .as_ref()
+        .map(TokenDictionary::lowercased);
+    exact_lower_index.frequencies = lower_frequencies;
+
+    if let Some(normalized_map) = normalized_map {
+        let mut normalized_index = ExactTokenIndex::new(header.clone());
+        normalized_index.tokenizer = config.tokenizer.clone();
+        normalized_index.token_map = normalized_map;
+        normalized_index.dictionary = exact_index
+            .dictionary
+            .as_ref()
+            .map(TokenDictionary::normalized);
+        exact_index.normalized = Some(Box::new(normalized_index));
+    }
 
     let mut trigram_index = TrigramIndex::new(header);
     trigram_index.trigram_map = trigram_map;
@@ -202,11 +596,15 @@ pub fn scan_and_build_indexes(
     root: &Path,
     config: &ScanConfig,
 ) -> Result<(PathIndex, ExactTokenIndex, ExactTokenIndex, TrigramIndex)> {
+    let build_start = Instant::now();
+    let mut build = BuildInfo::new(root, config);
+    let skipped = Arc::new(SkipCounters::default());
+
     // Create shared header with same index_id for all three files
-    let header = IndexHeader::new();
+    let header = IndexHeader::with_tokenizer(config.tokenizer.as_ref());
 
     // Channel for discovered files (bounded for backpressure)
-    let (path_tx, path_rx) = mpsc::sync_channel::<PathBuf>(1024);
+    let (path_tx, path_rx) = mpsc::sync_channel::<(PathBuf, FileMeta)>(1024);
 
     // Channel for processing results
     let (result_tx, result_rx) = mpsc::channel::<FileProcessingResult>();
@@ -214,38 +612,48 @@ pub fn scan_and_build_indexes(
     // Clone config and root for the walker thread
     let walker_config = config.clone();
     let walker_root = root.to_path_buf();
+    let walker_skipped = skipped.clone();
 
     // Spawn walker thread - discovers files and sends through channel
-    let walker_handle = thread::spawn(move || walk_and_send(walker_root, walker_config, path_tx));
+    let walker_handle =
+        thread::spawn(move || walk_and_send(walker_root, walker_config, path_tx, walker_skipped));
 
     // Main thread: receive paths, assign IDs, dispatch to rayon workers
     let mut path_index = PathIndex::new(header.clone(), root.to_path_buf());
 
+    let outputs = FileOutputs::for_config(config);
+
     // Progress tracking
     let progress_start = Instant::now();
     let mut files_dispatched: u32 = 0;
 
     // Use rayon scope to spawn parallel workers
     rayon::scope(|s| {
-        for path in path_rx {
+        for (path, meta) in path_rx {
             // Sequential: register file and get canonical ID
             let file_id = path_index.register_file(path.clone());
+            path_index.set_file_meta(file_id, meta);
 
             // Clone sender for this task
             let tx = result_tx.clone();
+            let outputs = &outputs;
 
             // Spawn parallel work - processing starts immediately
             s.spawn(move |_| {
-                let result = process_single_file(file_id, &path);
+                let result = process_single_file(file_id, &path, outputs);
                 let _ = tx.send(result); // Ignore send errors if receiver dropped
             });
 
             // Progress reporting
             files_dispatched += 1;
-            if files_dispatched % 2500 == 0 {
+            if files_dispatched.is_multiple_of(2500) {
                 let elapsed = progress_start.elapsed().as_secs_f64();
                 let rate = files_dispatched as f64 / elapsed;
-                println!("Indexed {} files ({:.0} files/sec)", fmt_num(files_dispatched), rate);
+                println!(
+                    "Indexed {} files ({:.0} files/sec)",
+                    fmt_num(files_dispatched),
+                    rate
+                );
             }
         }
     });
@@ -259,7 +667,12 @@ pub fn scan_and_build_indexes(
         .map_err(|_| TokenizerError::WalkDir("Walker thread panicked".to_string()))??;
 
     // Collect and merge all results into final indexes
-    let (exact_index, exact_lower_index, trigram_index) = merge_results(result_rx, header);
+    let (exact_index, exact_lower_index, trigram_index) =
+        merge_results(result_rx, header, &mut path_index, config, &skipped);
+
+    build.skipped = skipped.counts();
+    build.duration_ms = build_start.elapsed().as_millis() as u64;
+    path_index.build = Some(build);
 
     Ok((path_index, exact_index, exact_lower_index, trigram_index))
 }
@@ -267,11 +680,45 @@ pub fn scan_and_build_indexes(
 /// Collect all files matching the configuration
 fn collect_files(root: &Path, config: &ScanConfig) -> Result<Vec<PathBuf>> {
     let mut files = Vec::new();
+    let rules = ScanRules::new(config)?;
+
+    // Ignore stacks for the directories on the current walk path, indexed by depth
+    let mut dir_ignores: Vec<IgnoreStack> = Vec::new();
+    let root_ignores = IgnoreStack::for_root(root);
 
     for entry in WalkDir::new(root)
         .follow_links(false)
         .into_iter()
-        .filter_entry(|e| !should_exclude(e.path(), &config.exclude_patterns))
+        .filter_entry(|e| {
+            let rel = e.path().strip_prefix(root).unwrap_or(e.path());
+            let is_dir = e.file_type().is_dir();
+
+            if e.depth() > 0 {
+                let skip = if is_dir {
+                    rules.skip_dir(rel)
+                } else {
+                    rules.skip_file(rel)
+                };
+                if skip {
+                    return false;
+                }
+            }
+            if !config.respect_ignore_files {
+                return true;
+            }
+
+            dir_ignores.truncate(e.depth());
+            let parent = dir_ignores.last().unwrap_or(&root_ignores);
+
+            if e.depth() > 0 && parent.is_ignored(rel, is_dir) {
+                return false;
+            }
+            if is_dir {
+                let entered = parent.enter_dir(rel);
+                dir_ignores.push(entered);
+            }
+            true
+        })
     {
         let entry = entry.map_err(|e| TokenizerError::WalkDir(e.to_string()))?;
 
@@ -281,17 +728,6 @@ fn collect_files(root: &Path, config: &ScanConfig) -> Result<Vec<PathBuf>> {
 
         let path = entry.path();
 
-        // Check extension filter
-        if !config.extensions.is_empty() {
-            if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
-                if !config.extensions.iter().any(|e| e == ext) {
-                    continue;
-                }
-            } else {
-                continue;
-            }
-        }
-
         // Check file size
         if let Ok(metadata) = entry.metadata() {
             if metadata.len() > config.max_file_size {
@@ -306,7 +742,7 @@ fn collect_files(root: &Path, config: &ScanConfig) -> Result<Vec<PathBuf>> {
 }
 
 /// Check if a path should be excluded
-fn should_exclude(path: &Path, patterns: &[String]) -> bool {
+pub(crate) fn should_exclude(path: &Path, patterns: &[String]) -> bool {
     for component in path.components() {
         if let std::path::Component::Normal(name) = component {
             if let Some(name_str) = name.to_str() {
@@ -320,11 +756,7 @@ fn should_exclude(path: &Path, patterns: &[String]) -> bool {
 }
 
 /// Build index using parallel processing
-fn build_index_parallel(
-    root: &Path,
-    files: Vec<PathBuf>,
-    batch_size: usize,
-) -> Result<TokenIndex> {
+fn build_index_parallel(root: &Path, files: Vec<PathBuf>, batch_size: usize) -> Result<TokenIndex> {
     // Process files in parallel and collect token -> file_id mappings
     let token_maps: Vec<FxHashMap<u64, Vec<u32>>> = files
         .par_chunks(batch_size)
@@ -338,10 +770,7 @@ fn build_index_parallel(
 
                 if let Ok(tokens) = extract_tokens_from_file(path) {
                     for token_hash in tokens {
-                        local_map
-                            .entry(token_hash)
-                            .or_insert_with(Vec::new)
-                            .push(file_id);
+                        local_map.entry(token_hash).or_default().push(file_id);
                     }
                 }
             }
@@ -355,9 +784,7 @@ fn build_index_parallel(
 
     for local_map in token_maps {
         for (token_hash, file_ids) in local_map {
-            let bitmap = merged
-                .entry(token_hash)
-                .or_insert_with(RoaringBitmap::new);
+            let bitmap = merged.entry(token_hash).or_default();
             for file_id in file_ids {
                 bitmap.insert(file_id);
             }
@@ -415,8 +842,8 @@ mod tests {
         };
 
         // Query for "alfred" using both modes
-        let exact_result = query_exact(&path_index, &exact_index, "alfred", &options);
-        let fuzzy_result = query_fuzzy(&path_index, &trigram_index, "alfred", &options);
+        let exact_result = query_exact(&path_index, &exact_index, "alfred", &options).unwrap();
+        let fuzzy_result = query_fuzzy(&path_index, &trigram_index, "alfred", &options).unwrap();
 
         // Both should find files
         assert!(!exact_result.files.is_empty(), "Exact should find files");
@@ -438,7 +865,52 @@ mod tests {
         }
 
         // Verify we found the expected files (file1 and file3 contain "alfred")
-        assert_eq!(exact_result.files.len(), 2, "Should find exactly 2 files with 'alfred'");
+        assert_eq!(
+            exact_result.files.len(),
+            2,
+            "Should find exactly 2 files with 'alfred'"
+        );
+    }
+
+    #[test]
+    fn test_build_info_recorded() {
+        use crate::persistence::{load_paths, save_paths};
+
+        let src = TempDir::new().unwrap();
+        std::fs::write(src.path().join("a.rs"), "fn main() {}").unwrap();
+        std::fs::write(src.path().join("b.md"), "notes").unwrap();
+        std::fs::write(src.path().join("c.rs"), b"\x00\x01binary").unwrap();
+        std::fs::write(src.path().join("d.rs"), "x".repeat(64)).unwrap();
+        std::fs::write(src.path().join("skip.rs"), "fn skip() {}").unwrap();
+        std::fs::write(src.path().join(".ignore"), "skip.rs\n").unwrap();
+
+        let config = ScanConfig {
+            extensions: vec!["rs".to_string()],
+            max_file_size: 32,
+            ..Default::default()
+        };
+        let (paths, ..) = scan_and_build_indexes(src.path(), &config).unwrap();
+        assert_eq!(paths.file_count(), 2);
+
+        let build = paths.build.as_ref().unwrap();
+        assert_eq!(build.root, src.path().canonicalize().unwrap());
+        assert_eq!(build.config.extensions, ["rs"]);
+        assert_eq!(build.config.max_file_size, 32);
+        // b.md and .ignore by extension, skip.rs by .ignore, d.rs by size;
+        // c.rs is indexed without contents
+        assert_eq!(build.skipped.excluded, 2);
+        assert_eq!(build.skipped.ignored, 1);
+        assert_eq!(build.skipped.too_large, 1);
+        assert_eq!(build.skipped.binary, 1);
+        assert_eq!(build.skipped.unreadable, 0);
+
+        let out = TempDir::new().unwrap();
+        let paths_path = out.path().join("index.paths");
+        save_paths(&paths, &paths_path).unwrap();
+        let loaded = load_paths(&paths_path).unwrap().build.unwrap();
+        assert_eq!(loaded.skipped, build.skipped);
+        assert_eq!(loaded.config.max_file_size, 32);
+        assert_eq!(loaded.tool_version, build.tool_version);
     }
 
     #[test]
@@ -462,38 +934,111 @@ mod tests {
         };
 
         // Exact search for "alfred" should find the file
-        let exact_result = query_exact(&path_index, &exact_index, "alfred", &options);
-        assert_eq!(exact_result.files.len(), 1, "Exact 'alfred' should find 1 file");
+        let exact_result = query_exact(&path_index, &exact_index, "alfred", &options).unwrap();
+        assert_eq!(
+            exact_result.files.len(),
+            1,
+            "Exact 'alfred' should find 1 file"
+        );
 
         // Fuzzy search for "lfred" (partial) should also find the file
         // because "alfred" contains trigrams: alf, lfr, fre, red
         // and "lfred" contains trigrams: lfr, fre, red
-        let fuzzy_result = query_fuzzy(&path_index, &trigram_index, "lfred", &options);
-        assert_eq!(fuzzy_result.files.len(), 1, "Fuzzy 'lfred' should find 1 file");
+        let fuzzy_result = query_fuzzy(&path_index, &trigram_index, "lfred", &options).unwrap();
+        assert_eq!(
+            fuzzy_result.files.len(),
+            1,
+            "Fuzzy 'lfred' should find 1 file"
+        );
 
         // Both should find the same file
         assert_eq!(exact_result.files[0], fuzzy_result.files[0]);
     }
 
+    #[test]
+    fn test_gitignore_respected_by_default() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::create_dir_all(temp_dir.path().join("gen")).unwrap();
+        std::fs::write(temp_dir.path().join(".gitignore"), "gen/\n*.log\n").unwrap();
+        std::fs::write(temp_dir.path().join("main.rs"), "fn marker() {}").unwrap();
+        std::fs::write(temp_dir.path().join("debug.log"), "marker").unwrap();
+        std::fs::write(temp_dir.path().join("gen/out.rs"), "marker").unwrap();
+
+        let options = QueryOptions {
+            match_all: true,
+            ..Default::default()
+        };
+
+        let config = ScanConfig::default();
+        let (path_index, exact_index, _, _) =
+            scan_and_build_indexes(temp_dir.path(), &config).unwrap();
+        let result = query_exact(&path_index, &exact_index, "marker", &options).unwrap();
+        assert_eq!(result.files.len(), 1);
+        assert!(result.files[0].ends_with("main.rs"));
+
+        let legacy = scan_and_index(temp_dir.path(), &config).unwrap();
+        assert_eq!(legacy.file_count(), 2); // main.rs and .gitignore
+
+        let config = ScanConfig {
+            respect_ignore_files: false,
+            ..Default::default()
+        };
+        let (path_index, exact_index, _, _) =
+            scan_and_build_indexes(temp_dir.path(), &config).unwrap();
+        let result = query_exact(&path_index, &exact_index, "marker", &options).unwrap();
+        assert_eq!(result.files.len(), 3);
+    }
+
+    #[test]
+    fn test_glob_rules_shared_by_both_walks() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::create_dir_all(temp_dir.path().join("src/generated")).unwrap();
+        std::fs::write(temp_dir.path().join("src/lib.rs"), "marker").unwrap();
+        std::fs::write(temp_dir.path().join("src/Cargo.toml"), "marker").unwrap();
+        std::fs::write(temp_dir.path().join("src/notes.md"), "marker").unwrap();
+        std::fs::write(temp_dir.path().join("src/generated/api.rs"), "marker").unwrap();
+        std::fs::write(temp_dir.path().join("top.rs"), "marker").unwrap();
+
+        let config = ScanConfig {
+            include_globs: vec![
+                "src/**/*.{rs,toml}".to_string(),
+                "!**/generated/**".to_string(),
+            ],
+            ..Default::default()
+        };
+
+        let (path_index, exact_index, _, _) =
+            scan_and_build_indexes(temp_dir.path(), &config).unwrap();
+        let options = QueryOptions::default();
+        let mut files: Vec<String> = query_exact(&path_index, &exact_index, "marker", &options)
+            .unwrap()
+            .files
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        assert_eq!(files, vec!["Cargo.toml", "lib.rs"]);
+
+        let legacy = scan_and_index(temp_dir.path(), &config).unwrap();
+        assert_eq!(legacy.file_count(), 2);
+    }
+
     #[test]
     fn test_should_exclude() {
         let patterns = vec![".git".to_string(), "node_modules".to_string()];
 
+        assert!(should_exclude(Path::new("/project/.git/config"), &patterns));
         assert!(should_exclude(
-            Path::new("/project/.git/config"),
+            Path::new("/project/node_modules/pkg"),
             &patterns
         ));
-        assert!(should_exclude(
-            Path::new("/project/node_modules/pkg"),
+        assert!(!should_exclude(
+            Path::new("/project/src/main.rs"),
             &patterns
         ));
-        assert!(!should_exclude(Path::new("/project/src/main.rs"), &patterns));
 
         // Case-insensitive matching
-        assert!(should_exclude(
-            Path::new("/project/.GIT/config"),
-            &patterns
-        ));
+        assert!(should_exclude(Path::new("/project/.GIT/config"), &patterns));
         assert!(should_exclude(
             Path::new("/project/Node_Modules/pkg"),
             &patterns
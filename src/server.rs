@@ -0,0 +1,533 @@
+//! Persistent query daemon over a Unix socket
+//!
+//! Loads the split index files once and answers newline-delimited JSON
+//! requests, one response line per request line. The paths file header is
+//! checked before every request and the whole index set is reloaded when its
+//! `index_id` changed on disk.
+
+use crate::error::{Result, TokenizerError};
+use crate::glob::{glob_files, GlobOptions};
+use crate::index::IndexSet;
+use crate::persistence::{load_all, paths_file, read_header};
+use crate::query::{
+    query_boolean_exact, query_boolean_exact_lower, query_boolean_exact_normalized,
+    query_boolean_fuzzy, query_exact, query_exact_lower, query_exact_normalized, query_fuzzy,
+    query_subword, PatternExpansion, QueryOptions, QueryResult, TypoExpansion,
+};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+/// Index a daemon request runs against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum QueryMode {
+    /// Case-sensitive exact tokens
+    Exact,
+    /// Case-insensitive exact tokens
+    ExactI,
+    /// Exact tokens with `_` and `-` stripped and case folded
+    ExactN,
+    /// Trigram fuzzy matching
+    Fuzzy,
+    /// Identifier parts (camelCase / snake_case)
+    Subword,
+    /// Filename glob search
+    Glob,
+}
+
+/// A single daemon request, e.g. `{"mode":"exact","query":"foo","limit":10}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerRequest {
+    /// Which index to query
+    pub mode: QueryMode,
+
+    /// Query string, or the filename pattern in glob mode
+    pub query: String,
+
+    /// Parse `query` as a boolean expression (`AND`/`OR`/`NOT`, parentheses)
+    #[serde(default)]
+    pub boolean: bool,
+
+    /// Query options; glob mode only uses `limit`
+    #[serde(flatten)]
+    pub options: QueryOptions,
+}
+
+/// Response to a daemon request
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerResponse {
+    /// Set when the request failed; the other fields are then empty
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    /// Matching file paths
+    pub files: Vec<PathBuf>,
+
+    /// Number of tokens in the query
+    pub query_token_count: usize,
+
+    /// Number of tokens that had matches in the index
+    pub matched_token_count: usize,
+
+    /// How each wildcard or regex pattern in the query was expanded
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub expansions: Vec<PatternExpansion>,
+
+    /// Indexed tokens that stood in for query tokens (`max_typos` only)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub typos: Vec<TypoExpansion>,
+
+    /// BM25 score of each file, parallel to `files` (exact modes only)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub scores: Vec<f32>,
+
+    /// Trigram match ratio of each file, parallel to `files` (fuzzy mode only)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub match_ratios: Vec<f32>,
+
+    /// Files scanned (glob mode only)
+    pub files_scanned: usize,
+
+    /// Time spent answering the request, in microseconds
+    pub elapsed_us: u64,
+}
+
+impl ServerResponse {
+    fn error(message: impl Into<String>) -> Self {
+        Self {
+            error: Some(message.into()),
+            ..Default::default()
+        }
+    }
+
+    fn from_query(result: QueryResult) -> Self {
+        Self {
+            files: result.files,
+            query_token_count: result.query_token_count,
+            matched_token_count: result.matched_token_count,
+            expansions: result.expansions,
+            typos: result.typos,
+            scores: result.scores,
+            match_ratios: result.match_ratios,
+            ..Default::default()
+        }
+    }
+
+    fn from_result(result: Result<QueryResult>) -> Self {
+        result.map_or_else(|e| Self::error(e.to_string()), Self::from_query)
+    }
+}
+
+/// Query daemon state: the loaded index set and where it came from
+pub struct QueryServer {
+    base: PathBuf,
+    indexes: RwLock<Arc<IndexSet>>,
+}
+
+impl QueryServer {
+    /// Load the split index files at `base`
+    pub fn new(base: &Path) -> Result<Self> {
+        Ok(Self {
+            base: base.to_path_buf(),
+            indexes: RwLock::new(Arc::new(load_all(base)?)),
+        })
+    }
+
+    /// Get the currently loaded index set
+    pub fn indexes(&self) -> Arc<IndexSet> {
+        self.indexes
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Reload the index set if the files on disk have a different index ID
+    ///
+    /// Returns true when a reload happened.
+    pub fn reload_if_changed(&self) -> Result<bool> {
+        let header = read_header(&paths_file(&self.base))?;
+        if header.index_id == self.indexes().paths.header.index_id {
+            return Ok(false);
+        }
+
+        let indexes = Arc::new(load_all(&self.base)?);
+        *self.indexes.write().unwrap_or_else(|e| e.into_inner()) = indexes;
+        Ok(true)
+    }
+
+    /// Answer a single request
+    pub fn handle(&self, request: &ServerRequest) -> ServerResponse {
+        // A failed reload (e.g. files caught mid-rewrite) keeps serving the
+        // loaded index; the next request tries again
+        let _ = self.reload_if_changed();
+
+        let start = Instant::now();
+        let indexes = self.indexes();
+        let query = request.query.as_str();
+        let options = &request.options;
+
+        let mut response = match request.mode {
+            QueryMode::Exact if request.boolean => ServerResponse::from_result(
+                query_boolean_exact(&indexes.paths, &indexes.exact, query, options),
+            ),
+            QueryMode::ExactI if request.boolean => ServerResponse::from_result(
+                query_boolean_exact_lower(&indexes.paths, &indexes.exact_lower, query, options),
+            ),
+            QueryMode::Fuzzy if request.boolean => ServerResponse::from_result(
+                query_boolean_fuzzy(&indexes.paths, &indexes.trigram, query, options),
+            ),
+            QueryMode::Exact => ServerResponse::from_result(query_exact(
+                &indexes.paths,
+                &indexes.exact,
+                query,
+                options,
+            )),
+            QueryMode::ExactI => ServerResponse::from_result(query_exact_lower(
+                &indexes.paths,
+                &indexes.exact_lower,
+                query,
+                options,
+            )),
+            QueryMode::Fuzzy => ServerResponse::from_result(query_fuzzy(
+                &indexes.paths,
+                &indexes.trigram,
+                query,
+                options,
+            )),
+            QueryMode::ExactN => match indexes.exact.normalized() {
+                None => ServerResponse::error("index has no normalized token index (.exactn file)"),
+                Some(normalized) if request.boolean => ServerResponse::from_result(
+                    query_boolean_exact_normalized(&indexes.paths, normalized, query, options),
+                ),
+                Some(normalized) => ServerResponse::from_result(query_exact_normalized(
+                    &indexes.paths,
+                    normalized,
+                    query,
+                    options,
+                )),
+            },
+            QueryMode::Subword if request.boolean => {
+                ServerResponse::error("subword mode does not support boolean queries")
+            }
+            QueryMode::Subword => ServerResponse::from_result(query_subword(
+                &indexes.paths,
+                &indexes.exact,
+                query,
+                options,
+            )),
+            QueryMode::Glob => {
+                let glob_options = GlobOptions {
+                    limit: options.limit,
+                };
+                match glob_files(&indexes.paths, query, &glob_options) {
+                    Ok(result) => ServerResponse {
+                        files: result.files,
+                        files_scanned: result.files_scanned,
+                        ..Default::default()
+                    },
+                    Err(e) => ServerResponse::error(e.to_string()),
+                }
+            }
+        };
+
+        response.elapsed_us = start.elapsed().as_micros() as u64;
+        response
+    }
+
+    /// Answer one raw request line
+    fn handle_line(&self, line: &str) -> ServerResponse {
+        match serde_json::from_str::<ServerRequest>(line) {
+            Ok(request) => self.handle(&request),
+            Err(e) => ServerResponse::error(format!("Invalid request: {}", e)),
+        }
+    }
+
+    /// Listen on `socket` and answer requests until the process exits
+    ///
+    /// Each connection gets its own thread and may send any number of
+    /// requests. A stale socket file left by a dead daemon is replaced.
+    pub fn serve(&self, socket: &Path) -> Result<()> {
+        if socket.exists() {
+            if UnixStream::connect(socket).is_ok() {
+                return Err(TokenizerError::Io(format!(
+                    "A server is already listening on {}",
+                    socket.display()
+                )));
+            }
+            std::fs::remove_file(socket).map_err(|e| TokenizerError::Io(e.to_string()))?;
+        }
+
+        let listener = UnixListener::bind(socket).map_err(|e| TokenizerError::Io(e.to_string()))?;
+
+        std::thread::scope(|scope| {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else {
+                    continue;
+                };
+                // A client that hangs up mid-request only ends its own connection
+                scope.spawn(move || {
+                    let _ = self.handle_connection(stream);
+                });
+            }
+        });
+
+        Ok(())
+    }
+
+    fn handle_connection(&self, stream: UnixStream) -> std::io::Result<()> {
+        let mut writer = stream.try_clone()?;
+        let reader = BufReader::new(stream);
+
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let response = self.handle_line(&line);
+            let mut encoded = serde_json::to_vec(&response)?;
+            encoded.push(b'\n');
+            writer.write_all(&encoded)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Client side of the query daemon protocol
+pub struct ServerClient {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl ServerClient {
+    /// Connect to a running daemon
+    pub fn connect(socket: &Path) -> Result<Self> {
+        let stream = UnixStream::connect(socket).map_err(|e| TokenizerError::Io(e.to_string()))?;
+        let writer = stream
+            .try_clone()
+            .map_err(|e| TokenizerError::Io(e.to_string()))?;
+
+        Ok(Self {
+            reader: BufReader::new(stream),
+            writer,
+        })
+    }
+
+    /// Send a request and wait for its response
+    ///
+    /// Errors reported by the daemon are returned as `TokenizerError::Server`.
+    pub fn request(&mut self, request: &ServerRequest) -> Result<ServerResponse> {
+        let mut encoded = serde_json::to_vec(request)
+            .map_err(|e| TokenizerError::Serialization(e.to_string()))?;
+        encoded.push(b'\n');
+        self.writer
+            .write_all(&encoded)
+            .map_err(|e| TokenizerError::Io(e.to_string()))?;
+
+        let mut line = String::new();
+        let read = self
+            .reader
+            .read_line(&mut line)
+            .map_err(|e| TokenizerError::Io(e.to_string()))?;
+        if read == 0 {
+            return Err(TokenizerError::Io(
+                "Server closed the connection".to_string(),
+            ));
+        }
+
+        let response: ServerResponse = serde_json::from_str(&line)
+            .map_err(|e| TokenizerError::Serialization(e.to_string()))?;
+
+        match response.error {
+            Some(message) => Err(TokenizerError::Server(message)),
+            None => Ok(response),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::save_all;
+    use crate::scanner::{scan_and_build_indexes, ScanConfig};
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn build(root: &Path, base: &Path) {
+        let (paths, exact, exact_lower, trigram) =
+            scan_and_build_indexes(root, &ScanConfig::default()).unwrap();
+        save_all(&paths, &exact, &exact_lower, &trigram, base).unwrap();
+    }
+
+    fn request(mode: QueryMode, query: &str) -> ServerRequest {
+        ServerRequest {
+            mode,
+            query: query.to_string(),
+            boolean: false,
+            options: QueryOptions {
+                match_all: true,
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_request_parsing() {
+        let request: ServerRequest = serde_json::from_str(
+            r#"{"mode":"exact-i","query":"foo bar","limit":5,"glob_patterns":["*.rs"]}"#,
+        )
+        .unwrap();
+        assert_eq!(request.mode, QueryMode::ExactI);
+        assert_eq!(request.options.limit, Some(5));
+        assert!(request.options.match_all);
+        assert_eq!(
+            request.options.glob_patterns,
+            Some(vec!["*.rs".to_string()])
+        );
+
+        let request: ServerRequest =
+            serde_json::from_str(r#"{"mode":"exact","query":"foo bar","match_all":false}"#)
+                .unwrap();
+        assert!(!request.options.match_all);
+    }
+
+    #[test]
+    fn test_handle_all_modes() {
+        let src = TempDir::new().unwrap();
+        let out = TempDir::new().unwrap();
+        let base = out.path().join("index.tkix");
+        std::fs::write(src.path().join("a.rs"), "HashMap insert").unwrap();
+        std::fs::write(src.path().join("b.txt"), "hashmap").unwrap();
+        build(src.path(), &base);
+
+        let server = QueryServer::new(&base).unwrap();
+        assert_eq!(
+            server
+                .handle(&request(QueryMode::Exact, "HashMap"))
+                .files
+                .len(),
+            1
+        );
+        assert_eq!(
+            server
+                .handle(&request(QueryMode::ExactI, "HASHMAP"))
+                .files
+                .len(),
+            2
+        );
+        assert_eq!(
+            server
+                .handle(&request(QueryMode::Fuzzy, "ashma"))
+                .files
+                .len(),
+            2
+        );
+        assert_eq!(
+            server.handle(&request(QueryMode::Glob, "*.rs")).files.len(),
+            1
+        );
+        assert_eq!(
+            server.handle(&request(QueryMode::Subword, "map")).files,
+            vec![src.path().join("a.rs")]
+        );
+        assert_eq!(
+            server
+                .handle(&request(QueryMode::ExactN, "hash_map"))
+                .files
+                .len(),
+            2
+        );
+
+        let response = server.handle(&request(QueryMode::Glob, "[invalid"));
+        assert!(response.error.is_some());
+
+        let mut boolean = request(QueryMode::ExactI, "hashmap NOT insert");
+        boolean.boolean = true;
+        assert_eq!(server.handle(&boolean).files.len(), 1);
+        boolean.query = "(hashmap".to_string();
+        assert!(server
+            .handle(&boolean)
+            .error
+            .unwrap()
+            .contains("position 0"));
+
+        let response = server.handle_line("not json");
+        assert!(response.error.unwrap().starts_with("Invalid request"));
+    }
+
+    #[test]
+    fn test_hot_reload() {
+        let src = TempDir::new().unwrap();
+        let out = TempDir::new().unwrap();
+        let base = out.path().join("index.tkix");
+        std::fs::write(src.path().join("a.txt"), "before").unwrap();
+        build(src.path(), &base);
+
+        let server = QueryServer::new(&base).unwrap();
+        assert!(!server.reload_if_changed().unwrap());
+        assert_eq!(
+            server
+                .handle(&request(QueryMode::Exact, "after"))
+                .files
+                .len(),
+            0
+        );
+
+        std::fs::write(src.path().join("a.txt"), "after").unwrap();
+        build(src.path(), &base);
+
+        assert_eq!(
+            server
+                .handle(&request(QueryMode::Exact, "after"))
+                .files
+                .len(),
+            1
+        );
+        assert!(!server.reload_if_changed().unwrap());
+    }
+
+    #[test]
+    fn test_socket_roundtrip() {
+        let src = TempDir::new().unwrap();
+        let out = TempDir::new().unwrap();
+        let base = out.path().join("index.tkix");
+        let socket = out.path().join("tokenizer.sock");
+        std::fs::write(src.path().join("a.txt"), "socket").unwrap();
+        build(src.path(), &base);
+
+        let server = QueryServer::new(&base).unwrap();
+        let serve_socket = socket.clone();
+        std::thread::spawn(move || server.serve(&serve_socket));
+
+        let mut client = None;
+        for _ in 0..100 {
+            if let Ok(c) = ServerClient::connect(&socket) {
+                client = Some(c);
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let mut client = client.expect("server did not start");
+
+        let response = client
+            .request(&request(QueryMode::Exact, "socket"))
+            .unwrap();
+        assert_eq!(response.files.len(), 1);
+        assert_eq!(response.query_token_count, 1);
+
+        // Several requests share one connection
+        let response = client
+            .request(&request(QueryMode::Exact, "missing"))
+            .unwrap();
+        assert!(response.files.is_empty());
+
+        let result = client.request(&request(QueryMode::Glob, "[invalid"));
+        assert!(matches!(result, Err(TokenizerError::Server(_))));
+    }
+}
@@ -0,0 +1,410 @@
+//! Subword index: identifier parts split on case, `_`, `-` and digits
+//!
+//! Optional companion of the exact index, stored as a `.sub` file. Every
+//! exact token is split into lowercase parts (`getUserById` is `get`,
+//! `user`, `by`, `id`). Each part maps to the files with a token containing
+//! it, and each token of two or more parts keeps its part sequence, so a
+//! query can ask for parts in order within one identifier:
+//!
+//! ```text
+//! magic        [u8; 4]  "TKSW"
+//! version      u16
+//! reserved     u16
+//! index_id     [u8; 16]
+//! created_at   u64
+//! tokenizer    u64
+//! part_count   u64
+//! parts        part_count x { hash: u64, size: varint, bitmap: Roaring }
+//! split_count  varint
+//! splits       split_count x { token_hash: u64, part_count: varint, parts: part_count x varint }
+//! ```
+//!
+//! Parts are keyed by `hash_token_lower` and written in hash order; a split
+//! refers to its parts by their ordinal in that list. Tokens are keyed by
+//! their case-sensitive exact hash.
+
+use crate::dictionary::{read_varint, write_varint};
+use crate::error::{Result, TokenizerError};
+use crate::index::{shift_bitmap, IndexHeader, FORMAT_VERSION};
+use crate::table::{parse_header, write_header, HEADER_LEN};
+use crate::tokenizer::hash_token_lower;
+use crate::unicode::char_spans;
+use roaring::RoaringBitmap;
+use rustc_hash::FxHashMap;
+use std::io::Write;
+
+/// Magic bytes of the subword file
+pub(crate) const MAGIC_SUB: &[u8; 4] = b"TKSW";
+
+/// Split an identifier into its parts
+///
+/// A part ends at `_` and `-`, before an uppercase letter that follows a
+/// lowercase one (`getUser`), before the last letter of an uppercase run
+/// that continues in lowercase (`HTTPServer` is `HTTP`, `Server`) and
+/// between letters and digits (`utf8` is `utf`, `8`). Letter case is
+/// Unicode's, so `ÉcoleNormale` is `École`, `Normale`.
+pub fn split_subwords(token: &[u8]) -> Vec<&[u8]> {
+    let chars: Vec<(usize, Option<char>)> = char_spans(token).map(|(i, c, _)| (i, c)).collect();
+    let is_upper = |k: usize| {
+        chars
+            .get(k)
+            .and_then(|(_, c)| *c)
+            .is_some_and(char::is_uppercase)
+    };
+    let is_lower = |k: usize| {
+        chars
+            .get(k)
+            .and_then(|(_, c)| *c)
+            .is_some_and(char::is_lowercase)
+    };
+    let is_digit = |k: usize| chars[k].1.is_some_and(char::is_numeric);
+
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut start_char = 0;
+    for (k, &(i, c)) in chars.iter().enumerate() {
+        if matches!(c, Some('_' | '-')) {
+            if start < i {
+                parts.push(&token[start..i]);
+            }
+            start = i + 1;
+            start_char = k + 1;
+            continue;
+        }
+        if k == start_char {
+            continue;
+        }
+
+        let boundary = if is_digit(k) != is_digit(k - 1) {
+            true
+        } else if is_upper(k - 1) {
+            // The last capital of a run starts the next word: HTTP|Server
+            is_upper(k) && is_lower(k + 1)
+        } else {
+            is_upper(k)
+        };
+        if boundary {
+            parts.push(&token[start..i]);
+            start = i;
+            start_char = k;
+        }
+    }
+    if start < token.len() {
+        parts.push(&token[start..]);
+    }
+    parts
+}
+
+/// Hash the parts of an identifier, in order
+pub(crate) fn subword_hashes(token: &[u8]) -> Vec<u64> {
+    split_subwords(token)
+        .into_iter()
+        .map(hash_token_lower)
+        .collect()
+}
+
+/// Identifier parts per file, plus the parts of every multi-part token
+#[derive(Debug, Clone, Default)]
+pub struct SubwordIndex {
+    /// Part hash -> files with a token containing the part
+    parts: FxHashMap<u64, RoaringBitmap>,
+    /// Exact token hash -> part hashes in order, for tokens of two or more parts
+    splits: FxHashMap<u64, Box<[u64]>>,
+}
+
+impl SubwordIndex {
+    /// Create an empty subword index
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that a file contains an exact token
+    pub fn add_token(&mut self, token_hash: u64, token: &[u8], file_id: u32) {
+        let hashes = subword_hashes(token);
+        for hash in &hashes {
+            self.parts.entry(*hash).or_default().insert(file_id);
+        }
+        if hashes.len() > 1 {
+            self.splits
+                .entry(token_hash)
+                .or_insert_with(|| hashes.into_boxed_slice());
+        }
+    }
+
+    /// Remove the given file IDs from every part bitmap
+    ///
+    /// Token splits are kept; the ones whose token left the exact index are
+    /// dropped when the file is written.
+    pub fn remove_files(&mut self, file_ids: &RoaringBitmap) {
+        self.parts.retain(|_, bitmap| {
+            *bitmap -= file_ids;
+            !bitmap.is_empty()
+        });
+    }
+
+    /// Take over the parts and splits of another index, shifting its file IDs
+    /// by `offset`
+    pub(crate) fn append(&mut self, other: SubwordIndex, offset: u32) {
+        for (hash, bitmap) in other.parts {
+            *self.parts.entry(hash).or_default() |= shift_bitmap(&bitmap, offset);
+        }
+        for (token_hash, split) in other.splits {
+            self.splits.entry(token_hash).or_insert(split);
+        }
+    }
+
+    /// Get the files with a token containing a part (`hash_token_lower` of
+    /// the lowercase part)
+    pub fn part_bitmap(&self, part_hash: u64) -> Option<&RoaringBitmap> {
+        self.parts.get(&part_hash)
+    }
+
+    /// Get the exact hashes of the tokens containing every part, in this
+    /// order
+    ///
+    /// Other parts may come between them. Only tokens of two or more parts
+    /// are recorded, so a single part matches no tokens; use `part_bitmap`.
+    pub fn tokens_in_order(&self, parts: &[u64]) -> Vec<u64> {
+        if parts.len() < 2 {
+            return Vec::new();
+        }
+        let mut tokens: Vec<u64> = self
+            .splits
+            .iter()
+            .filter(|(_, split)| {
+                let mut split = split.iter();
+                parts.iter().all(|part| split.any(|hash| hash == part))
+            })
+            .map(|(token_hash, _)| *token_hash)
+            .collect();
+        tokens.sort_unstable();
+        tokens
+    }
+
+    /// Get the number of distinct parts
+    pub fn part_count(&self) -> usize {
+        self.parts.len()
+    }
+
+    /// Get the number of tokens with two or more parts
+    pub fn split_count(&self) -> usize {
+        self.splits.len()
+    }
+
+    /// Get the IDs of the files with a token containing any part
+    pub(crate) fn file_ids(&self) -> RoaringBitmap {
+        self.parts
+            .values()
+            .fold(RoaringBitmap::new(), |ids, bitmap| ids | bitmap)
+    }
+}
+
+/// Write a subword file, keeping the splits of the tokens that pass `keep`
+pub(crate) fn write_subwords<W: Write>(
+    writer: &mut W,
+    header: &IndexHeader,
+    subwords: &SubwordIndex,
+    keep: impl Fn(u64) -> bool,
+) -> std::io::Result<()> {
+    let mut parts: Vec<(u64, &RoaringBitmap)> = subwords
+        .parts
+        .iter()
+        .map(|(hash, bitmap)| (*hash, bitmap))
+        .collect();
+    parts.sort_unstable_by_key(|(hash, _)| *hash);
+
+    write_header(writer, MAGIC_SUB, header, parts.len())?;
+    for (hash, bitmap) in &parts {
+        writer.write_all(&hash.to_le_bytes())?;
+        write_varint(writer, bitmap.serialized_size() as u64)?;
+        bitmap.serialize_into(&mut *writer)?;
+    }
+
+    let ordinals: FxHashMap<u64, usize> = parts
+        .iter()
+        .enumerate()
+        .map(|(ordinal, (hash, _))| (*hash, ordinal))
+        .collect();
+    let mut splits: Vec<(u64, Vec<usize>)> = subwords
+        .splits
+        .iter()
+        .filter(|(token_hash, _)| keep(**token_hash))
+        .filter_map(|(token_hash, split)| {
+            let split = split
+                .iter()
+                .map(|hash| ordinals.get(hash).copied())
+                .collect::<Option<Vec<usize>>>()?;
+            Some((*token_hash, split))
+        })
+        .collect();
+    splits.sort_unstable_by_key(|(token_hash, _)| *token_hash);
+
+    write_varint(writer, splits.len() as u64)?;
+    for (token_hash, split) in splits {
+        writer.write_all(&token_hash.to_le_bytes())?;
+        write_varint(writer, split.len() as u64)?;
+        for ordinal in split {
+            write_varint(writer, ordinal as u64)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Decode a subword file
+pub(crate) fn decode_subwords(data: &[u8]) -> Result<(IndexHeader, SubwordIndex)> {
+    let (header, count) = parse_header(data, MAGIC_SUB)?;
+    if header.version != FORMAT_VERSION {
+        return Err(TokenizerError::InvalidIndexFormat(format!(
+            "Version mismatch: expected {}, got {}",
+            FORMAT_VERSION, header.version
+        )));
+    }
+
+    let corrupt = || TokenizerError::InvalidIndexFormat("Subword file is corrupt".to_string());
+    let mut position = HEADER_LEN;
+    let read_usize = |position: &mut usize| {
+        read_varint(data, position)
+            .and_then(|value| usize::try_from(value).ok())
+            .ok_or_else(corrupt)
+    };
+    let read_hash = |position: &mut usize| {
+        let hash = data
+            .get(*position..*position + 8)
+            .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+            .ok_or_else(corrupt);
+        *position += 8;
+        hash
+    };
+
+    let mut index = SubwordIndex::new();
+    let mut part_hashes = Vec::with_capacity(count.min(1 << 16));
+    for _ in 0..count {
+        let hash = read_hash(&mut position)?;
+        let size = read_usize(&mut position)?;
+        let bytes = position
+            .checked_add(size)
+            .and_then(|end| data.get(position..end))
+            .ok_or_else(corrupt)?;
+        let bitmap = RoaringBitmap::deserialize_from(bytes).map_err(|_| corrupt())?;
+        position += size;
+        index.parts.insert(hash, bitmap);
+        part_hashes.push(hash);
+    }
+
+    let split_count = read_usize(&mut position)?;
+    for _ in 0..split_count {
+        let token_hash = read_hash(&mut position)?;
+        let length = read_usize(&mut position)?;
+        let mut split = Vec::with_capacity(length.min(1 << 8));
+        for _ in 0..length {
+            let ordinal = read_usize(&mut position)?;
+            split.push(*part_hashes.get(ordinal).ok_or_else(corrupt)?);
+        }
+        index.splits.insert(token_hash, split.into_boxed_slice());
+    }
+
+    if position != data.len() {
+        return Err(corrupt());
+    }
+
+    Ok((header, index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::hash_token;
+
+    fn split(token: &str) -> Vec<&str> {
+        split_subwords(token.as_bytes())
+            .into_iter()
+            .map(|part| std::str::from_utf8(part).unwrap())
+            .collect()
+    }
+
+    fn index_of(tokens: &[(&str, u32)]) -> SubwordIndex {
+        let mut index = SubwordIndex::new();
+        for (token, file_id) in tokens {
+            index.add_token(hash_token(token.as_bytes()), token.as_bytes(), *file_id);
+        }
+        index
+    }
+
+    fn parts(text: &str) -> Vec<u64> {
+        text.split(' ')
+            .map(|part| hash_token_lower(part.as_bytes()))
+            .collect()
+    }
+
+    #[test]
+    fn test_split_subwords() {
+        assert_eq!(split("getUserById"), ["get", "User", "By", "Id"]);
+        assert_eq!(split("get_user-by_id"), ["get", "user", "by", "id"]);
+        assert_eq!(split("HTTPServer"), ["HTTP", "Server"]);
+        assert_eq!(
+            split("parseHTTP2Response"),
+            ["parse", "HTTP", "2", "Response"]
+        );
+        assert_eq!(split("utf8_decode"), ["utf", "8", "decode"]);
+        assert_eq!(split("__init__"), ["init"]);
+        assert_eq!(split("MAX_SIZE"), ["MAX", "SIZE"]);
+        assert_eq!(split("x"), ["x"]);
+        assert!(split("--").is_empty());
+        assert_eq!(split("ÉcoleNormale"), ["École", "Normale"]);
+        assert_eq!(split("größeBerechnen2"), ["größe", "Berechnen", "2"]);
+        assert_eq!(split("ΑΒΓΔelta"), ["ΑΒΓ", "Δelta"]);
+    }
+
+    #[test]
+    fn test_parts_and_order() {
+        let mut index = index_of(&[("getUserById", 0), ("user_id", 1), ("IdOfUser", 2)]);
+        assert_eq!(index.part_count(), 5);
+        assert_eq!(index.split_count(), 3);
+        assert_eq!(
+            index.part_bitmap(hash_token_lower(b"user")).unwrap(),
+            &RoaringBitmap::from_iter([0, 1, 2])
+        );
+
+        let in_order = index.tokens_in_order(&parts("user id"));
+        let mut expected = vec![hash_token(b"getUserById"), hash_token(b"user_id")];
+        expected.sort_unstable();
+        assert_eq!(in_order, expected);
+        assert_eq!(
+            index.tokens_in_order(&parts("get id")),
+            vec![hash_token(b"getUserById")]
+        );
+        assert!(index.tokens_in_order(&parts("id get")).is_empty());
+
+        index.remove_files(&RoaringBitmap::from_iter([0, 2]));
+        assert!(index.part_bitmap(hash_token_lower(b"get")).is_none());
+        assert_eq!(index.part_count(), 2);
+    }
+
+    #[test]
+    fn test_roundtrip_and_corruption() {
+        let index = index_of(&[("getUserById", 3), ("user_id", 70000), ("plain", 1)]);
+        let header = IndexHeader::new();
+        let mut data = Vec::new();
+        let stale = hash_token(b"user_id");
+        write_subwords(&mut data, &header, &index, |hash| hash != stale).unwrap();
+
+        let (decoded_header, decoded) = decode_subwords(&data).unwrap();
+        assert_eq!(decoded_header, header);
+        assert_eq!(decoded.part_count(), index.part_count());
+        assert_eq!(decoded.split_count(), 1);
+        assert_eq!(
+            decoded.part_bitmap(hash_token_lower(b"user")).unwrap(),
+            &RoaringBitmap::from_iter([3, 70000])
+        );
+        assert_eq!(
+            decoded.tokens_in_order(&parts("user id")),
+            vec![hash_token(b"getUserById")]
+        );
+
+        assert!(decode_subwords(&data[..data.len() - 1]).is_err());
+        let mut trailing = data.clone();
+        trailing.push(0);
+        assert!(decode_subwords(&trailing).is_err());
+    }
+}
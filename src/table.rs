@@ -0,0 +1,355 @@
+//! Sorted bitmap table: the on-disk layout of the exact and trigram index files
+//!
+//! ```text
+//! magic        [u8; 4]
+//! version      u16
+//! reserved     u16
+//! index_id     [u8; 16]
+//! created_at   u64
+//! tokenizer    u64
+//! entry_count  u64
+//! entries      entry_count x { key: u64, offset: u64, len: u64 }, sorted by key
+//! bitmaps      entry_count x { bitmap: Roaring, checksum: u64 }
+//! trailer      { checksum: u64, magic: [u8; 4] "TKCK" }
+//! ```
+//!
+//! Integers are little-endian and offsets are from the start of the file.
+//! An entry's offset/len locate its serialized bitmap, which is followed by
+//! the checksum of the entry key and bitmap. The trailer checksum covers the
+//! header and the entry table.
+//!
+//! Opening a table only reads its header, so views open in constant time.
+//! Readers binary-search the entry table and deserialize and check only the
+//! bitmaps a query touches: a bitmap's checksum covers its entry's key, and
+//! a damaged offset or length lands on bytes that fail the check. The entry
+//! table as a whole is checked by full loads and `tokenizer verify`.
+
+use crate::checksum::{
+    checksum, corrupted, split_trailer, write_trailer, Checksum, ChecksumWriter,
+};
+use crate::error::{Result, TokenizerError};
+use crate::index::{IndexHeader, FORMAT_VERSION};
+use roaring::RoaringBitmap;
+use std::io::Write;
+
+/// Bytes before the entry table
+pub(crate) const HEADER_LEN: usize = 48;
+
+/// Bytes per entry in the table
+const ENTRY_LEN: usize = 24;
+
+/// Bytes of the checksum following each bitmap
+const BITMAP_CHECKSUM_LEN: usize = 8;
+
+/// Write a complete table file
+pub(crate) fn write_table<W: Write>(
+    writer: &mut W,
+    magic: &[u8; 4],
+    header: &IndexHeader,
+    mut entries: Vec<(u64, &RoaringBitmap)>,
+) -> std::io::Result<()> {
+    entries.sort_unstable_by_key(|(key, _)| *key);
+
+    let mut table = ChecksumWriter::new(&mut *writer);
+    write_header(&mut table, magic, header, entries.len())?;
+
+    let mut offset = (HEADER_LEN + entries.len() * ENTRY_LEN) as u64;
+    for (key, bitmap) in &entries {
+        let len = bitmap.serialized_size() as u64;
+        table.write_all(&key.to_le_bytes())?;
+        table.write_all(&offset.to_le_bytes())?;
+        table.write_all(&len.to_le_bytes())?;
+        offset += len + BITMAP_CHECKSUM_LEN as u64;
+    }
+    let (_, table_checksum) = table.into_parts();
+
+    let mut buf = Vec::new();
+    for (key, bitmap) in &entries {
+        buf.clear();
+        bitmap.serialize_into(&mut buf)?;
+        writer.write_all(&buf)?;
+        writer.write_all(&bitmap_checksum(*key, &buf).to_le_bytes())?;
+    }
+
+    write_trailer(writer, table_checksum)
+}
+
+/// Write the fixed header shared by table and dictionary files
+pub(crate) fn write_header<W: Write>(
+    writer: &mut W,
+    magic: &[u8; 4],
+    header: &IndexHeader,
+    count: usize,
+) -> std::io::Result<()> {
+    writer.write_all(magic)?;
+    writer.write_all(&header.version.to_le_bytes())?;
+    writer.write_all(&[0u8; 2])?;
+    writer.write_all(&header.index_id)?;
+    writer.write_all(&header.created_at.to_le_bytes())?;
+    writer.write_all(&header.tokenizer.to_le_bytes())?;
+    writer.write_all(&(count as u64).to_le_bytes())
+}
+
+/// Parse the fixed header of a table file
+///
+/// Returns the header and the number of entries. The version is not checked.
+pub(crate) fn parse_header(data: &[u8], magic: &[u8; 4]) -> Result<(IndexHeader, usize)> {
+    if data.len() < HEADER_LEN || &data[..4] != magic {
+        return Err(TokenizerError::InvalidIndexFormat(format!(
+            "Invalid magic bytes for {} file",
+            String::from_utf8_lossy(magic)
+        )));
+    }
+
+    let mut index_id = [0u8; 16];
+    index_id.copy_from_slice(&data[8..24]);
+    let header = IndexHeader {
+        version: u16::from_le_bytes([data[4], data[5]]),
+        index_id,
+        created_at: read_u64(data, 24),
+        tokenizer: read_u64(data, 32),
+    };
+
+    Ok((header, read_u64(data, 40) as usize))
+}
+
+/// Read-only view of a table file held in any byte buffer (usually a mapping)
+#[derive(Debug)]
+pub(crate) struct BitmapTable<B> {
+    data: B,
+    count: usize,
+    /// End of the bitmap region, where the trailer starts
+    end: usize,
+    /// Checksum of the header and entry table, from the trailer
+    table_checksum: u64,
+}
+
+impl<B: AsRef<[u8]>> BitmapTable<B> {
+    /// Validate the header of a table file
+    ///
+    /// Fails with `TokenizerError::Corrupted` if the file is truncated. The
+    /// entry table is not checked; see `verify_entries`.
+    pub(crate) fn parse(data: B, magic: &[u8; 4]) -> Result<(IndexHeader, Self)> {
+        let bytes = data.as_ref();
+        let (header, count) = parse_header(bytes, magic)?;
+
+        if header.version != FORMAT_VERSION {
+            return Err(TokenizerError::InvalidIndexFormat(format!(
+                "Version mismatch: expected {}, got {}",
+                FORMAT_VERSION, header.version
+            )));
+        }
+
+        let (payload, expected) = split_trailer(bytes)?;
+        count
+            .checked_mul(ENTRY_LEN)
+            .and_then(|len| len.checked_add(HEADER_LEN))
+            .filter(|end| *end <= payload.len())
+            .ok_or_else(|| corrupted("entry table extends past the end of the file"))?;
+
+        let table = Self {
+            end: payload.len(),
+            data,
+            count,
+            table_checksum: expected,
+        };
+        Ok((header, table))
+    }
+
+    /// Check the header and entry table against the trailer checksum
+    ///
+    /// Reads the whole entry table, so it is left to readers that go through
+    /// every entry anyway.
+    pub(crate) fn verify_entries(&self) -> Result<()> {
+        let table_end = HEADER_LEN + self.count * ENTRY_LEN;
+        if checksum(&self.data.as_ref()[..table_end]) != self.table_checksum {
+            return Err(corrupted("entry table checksum mismatch"));
+        }
+        Ok(())
+    }
+
+    /// Number of entries
+    pub(crate) fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Key of the entry at `index`
+    pub(crate) fn key(&self, index: usize) -> u64 {
+        read_u64(self.data.as_ref(), HEADER_LEN + index * ENTRY_LEN)
+    }
+
+    /// Find the entry index of a key
+    pub(crate) fn find(&self, key: u64) -> Option<usize> {
+        let (mut lo, mut hi) = (0, self.count);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            match self.key(mid).cmp(&key) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => return Some(mid),
+            }
+        }
+        None
+    }
+
+    /// Serialized bytes of the bitmap at `index`, checked against their
+    /// checksum
+    pub(crate) fn bitmap_bytes(&self, index: usize) -> Result<&[u8]> {
+        let data = self.data.as_ref();
+        let entry = HEADER_LEN + index * ENTRY_LEN;
+        let key = read_u64(data, entry);
+        let offset = read_u64(data, entry + 8) as usize;
+        let len = read_u64(data, entry + 16) as usize;
+
+        let end = offset
+            .checked_add(len)
+            .filter(|end| {
+                end.checked_add(BITMAP_CHECKSUM_LEN)
+                    .is_some_and(|e| e <= self.end)
+            })
+            .ok_or_else(|| corrupted("bitmap extends past the end of the file"))?;
+
+        let bytes = &data[offset..end];
+        if bitmap_checksum(key, bytes) != read_u64(data, end) {
+            return Err(corrupted(&format!(
+                "bitmap checksum mismatch for key {:#x}",
+                key
+            )));
+        }
+        Ok(bytes)
+    }
+
+    /// Deserialize the bitmap at `index`
+    pub(crate) fn bitmap(&self, index: usize) -> Result<RoaringBitmap> {
+        RoaringBitmap::deserialize_from(self.bitmap_bytes(index)?)
+            .map_err(|e| TokenizerError::Serialization(e.to_string()))
+    }
+
+    /// Look up and deserialize the bitmap for a key
+    ///
+    /// Fails with `TokenizerError::Corrupted` if the bitmap's checksum does
+    /// not match.
+    pub(crate) fn get(&self, key: u64) -> Result<Option<RoaringBitmap>> {
+        self.find(key).map(|index| self.bitmap(index)).transpose()
+    }
+
+    /// Iterate over all entries in key order
+    pub(crate) fn iter(&self) -> impl Iterator<Item = Result<(u64, RoaringBitmap)>> + '_ {
+        (0..self.count).map(|i| Ok((self.key(i), self.bitmap(i)?)))
+    }
+}
+
+/// Checksum stored after a bitmap: covers its key and serialized bytes
+fn bitmap_checksum(key: u64, bytes: &[u8]) -> u64 {
+    let mut checksum = Checksum::new();
+    checksum.update(&key.to_le_bytes());
+    checksum.update(bytes);
+    checksum.finish()
+}
+
+pub(crate) fn read_u64(data: &[u8], offset: usize) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::TRAILER_LEN;
+
+    const MAGIC: &[u8; 4] = b"TEST";
+
+    fn encode(entries: &[(u64, &[u32])]) -> (IndexHeader, Vec<u8>) {
+        let header = IndexHeader::new();
+        let bitmaps: Vec<(u64, RoaringBitmap)> = entries
+            .iter()
+            .map(|(key, ids)| (*key, ids.iter().copied().collect()))
+            .collect();
+        let refs = bitmaps.iter().map(|(k, b)| (*k, b)).collect();
+
+        let mut data = Vec::new();
+        write_table(&mut data, MAGIC, &header, refs).unwrap();
+        (header, data)
+    }
+
+    #[test]
+    fn test_roundtrip_sorted_lookup() {
+        let (header, data) = encode(&[(30, &[1, 2]), (10, &[0]), (20, &[5, 6, 7])]);
+        let (parsed, table) = BitmapTable::parse(&data[..], MAGIC).unwrap();
+
+        assert_eq!(parsed, header);
+        assert_eq!(table.len(), 3);
+        assert_eq!(table.key(0), 10);
+        assert_eq!(table.key(2), 30);
+        assert_eq!(table.get(20).unwrap().unwrap().len(), 3);
+        assert!(table.get(25).unwrap().is_none());
+
+        let keys: Vec<u64> = table.iter().map(|e| e.unwrap().0).collect();
+        assert_eq!(keys, vec![10, 20, 30]);
+    }
+
+    #[test]
+    fn test_empty_table() {
+        let (_, data) = encode(&[]);
+        assert_eq!(data.len(), HEADER_LEN + TRAILER_LEN);
+        let (_, table) = BitmapTable::parse(&data[..], MAGIC).unwrap();
+        assert_eq!(table.len(), 0);
+        assert!(table.get(1).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_rejects_bad_magic_and_truncation() {
+        let (_, data) = encode(&[(1, &[1]), (2, &[2])]);
+        assert!(BitmapTable::parse(&data[..], b"NOPE").is_err());
+
+        // Table cut short
+        assert!(BitmapTable::parse(&data[..HEADER_LEN + 10], MAGIC).is_err());
+
+        // Bitmap region cut short: the trailer is gone
+        assert!(matches!(
+            BitmapTable::parse(&data[..data.len() - 1], MAGIC),
+            Err(TokenizerError::Corrupted(_))
+        ));
+    }
+
+    #[test]
+    fn test_detects_bit_flips() {
+        let (_, data) = encode(&[(1, &[1]), (2, &[2, 3])]);
+
+        // A flip in the entry table fails its bitmap, and the table check
+        let mut flipped = data.clone();
+        flipped[HEADER_LEN + ENTRY_LEN] ^= 0x01;
+        let (_, table) = BitmapTable::parse(&flipped[..], MAGIC).unwrap();
+        assert!(matches!(table.bitmap(1), Err(TokenizerError::Corrupted(_))));
+        assert!(matches!(
+            table.verify_entries(),
+            Err(TokenizerError::Corrupted(_))
+        ));
+        assert!(BitmapTable::parse(&data[..], MAGIC)
+            .unwrap()
+            .1
+            .verify_entries()
+            .is_ok());
+
+        // A flip in a bitmap fails only that bitmap
+        let mut flipped = data.clone();
+        let last_bitmap = data.len() - TRAILER_LEN - BITMAP_CHECKSUM_LEN - 1;
+        flipped[last_bitmap] ^= 0x40;
+        let (_, table) = BitmapTable::parse(&flipped[..], MAGIC).unwrap();
+        assert!(matches!(table.bitmap(1), Err(TokenizerError::Corrupted(_))));
+        assert!(matches!(table.get(2), Err(TokenizerError::Corrupted(_))));
+        assert_eq!(table.get(1).unwrap().unwrap().len(), 1);
+        assert!(table.iter().any(|entry| entry.is_err()));
+    }
+
+    #[test]
+    fn test_rejects_other_version() {
+        let (_, mut data) = encode(&[(1, &[1])]);
+        data[4] = data[4].wrapping_add(1);
+        assert!(matches!(
+            BitmapTable::parse(&data[..], MAGIC),
+            Err(TokenizerError::InvalidIndexFormat(_))
+        ));
+    }
+}
@@ -1,10 +1,12 @@
+use crate::unicode::{char_count, decode_char, for_each_folded_byte, is_word_char};
 use memmap2::Mmap;
 use rustc_hash::{FxHashSet, FxHasher};
 use std::fs::File;
 use std::hash::{Hash, Hasher};
 use std::path::Path;
+use std::sync::Arc;
 
-/// Minimum token length to include
+/// Minimum token length to include, in characters
 pub const MIN_TOKEN_LENGTH: usize = 2;
 
 /// Extract tokens from a byte slice and return their hashes
@@ -25,16 +27,58 @@ pub fn hash_token(token: &[u8]) -> u64 {
     hasher.finish()
 }
 
-/// Hash a token using FxHash (case-insensitive, lowercase)
+/// Hash a token using FxHash (case-insensitive, Unicode simple case folding)
 #[inline]
 pub fn hash_token_lower(token: &[u8]) -> u64 {
     let mut hasher = FxHasher::default();
-    for &byte in token {
-        hasher.write_u8(byte.to_ascii_lowercase());
+    if token.is_ascii() {
+        for &byte in token {
+            hasher.write_u8(byte.to_ascii_lowercase());
+        }
+    } else {
+        for_each_folded_byte(token, |byte| hasher.write_u8(byte));
     }
     hasher.finish()
 }
 
+/// Find the next run of token characters at or after `position`
+///
+/// Returns the run's byte offset and bytes, and moves `position` past it.
+/// Bytes that are not valid UTF-8 end a run.
+#[inline]
+fn next_token_run<'a>(
+    content: &'a [u8],
+    position: &mut usize,
+    is_token_char: fn(char) -> bool,
+) -> Option<(usize, &'a [u8])> {
+    loop {
+        if *position >= content.len() {
+            return None;
+        }
+        let (c, len) = decode_char(&content[*position..]);
+        if c.is_some_and(is_token_char) {
+            break;
+        }
+        *position += len;
+    }
+
+    let start = *position;
+    while *position < content.len() {
+        let (c, len) = decode_char(&content[*position..]);
+        if !c.is_some_and(is_token_char) {
+            break;
+        }
+        *position += len;
+    }
+    Some((start, &content[start..*position]))
+}
+
+/// Check if a character is part of a legacy token (letters, marks and digits)
+#[inline]
+fn is_legacy_token_char(c: char) -> bool {
+    c != '_' && is_word_char(c)
+}
+
 /// Iterator that yields token hashes from content
 pub struct TokenIterator<'a> {
     content: &'a [u8],
@@ -48,34 +92,6 @@ impl<'a> TokenIterator<'a> {
             position: 0,
         }
     }
-
-    /// Skip non-alphanumeric characters
-    #[inline]
-    fn skip_delimiters(&mut self) {
-        while self.position < self.content.len()
-            && !self.content[self.position].is_ascii_alphanumeric()
-        {
-            self.position += 1;
-        }
-    }
-
-    /// Read next token bytes
-    #[inline]
-    fn read_token(&mut self) -> Option<&'a [u8]> {
-        let start = self.position;
-
-        while self.position < self.content.len()
-            && self.content[self.position].is_ascii_alphanumeric()
-        {
-            self.position += 1;
-        }
-
-        if self.position > start {
-            Some(&self.content[start..self.position])
-        } else {
-            None
-        }
-    }
 }
 
 impl<'a> Iterator for TokenIterator<'a> {
@@ -83,18 +99,10 @@ impl<'a> Iterator for TokenIterator<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
-            self.skip_delimiters();
-
-            if self.position >= self.content.len() {
-                return None;
-            }
-
-            if let Some(token) = self.read_token() {
-                if token.len() >= MIN_TOKEN_LENGTH {
-                    return Some(hash_token(token));
-                }
-            } else {
-                return None;
+            let (_, token) =
+                next_token_run(self.content, &mut self.position, is_legacy_token_char)?;
+            if char_count(token) >= MIN_TOKEN_LENGTH {
+                return Some(hash_token(token));
             }
         }
     }
@@ -104,54 +112,21 @@ impl<'a> Iterator for TokenIterator<'a> {
 // Exact Mode Tokenizer (keeps _ and - as part of tokens)
 // ============================================================================
 
-/// Check if a byte is an exact-mode delimiter
-/// Exact mode keeps: a-z, A-Z, 0-9, _, -
-/// Splits on: whitespace, brackets, quotes, operators, punctuation
+/// Check if a character is part of an exact-mode token
+/// Exact mode keeps: `XID_Continue` characters (letters, marks, digits, _) and -
+/// Splits on: whitespace, brackets, quotes, operators, punctuation, symbols
 #[inline]
-fn is_exact_delimiter(byte: u8) -> bool {
-    matches!(
-        byte,
-        b' ' | b'\t'
-            | b'\n'
-            | b'\r'
-            | b'('
-            | b')'
-            | b'['
-            | b']'
-            | b'{'
-            | b'}'
-            | b'<'
-            | b'>'
-            | b'"'
-            | b'\''
-            | b'`'
-            | b','
-            | b';'
-            | b':'
-            | b'.'
-            | b'+'
-            | b'='
-            | b'/'
-            | b'\\'
-            | b'@'
-            | b'#'
-            | b'$'
-            | b'%'
-            | b'^'
-            | b'&'
-            | b'*'
-            | b'!'
-            | b'?'
-            | b'|'
-            | b'~'
-            | 0 // null byte
-    )
+fn is_exact_token_char(c: char) -> bool {
+    c == '-' || is_word_char(c)
 }
 
-/// Check if a byte is part of an exact-mode token
-#[inline]
-fn is_exact_token_char(byte: u8) -> bool {
-    byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'-'
+/// Iterate over exact-mode token candidates with their byte offsets
+///
+/// Yields every maximal run of token characters, including runs shorter
+/// than `MIN_TOKEN_LENGTH`; callers apply their own length cut-off.
+pub(crate) fn exact_token_spans(content: &[u8]) -> impl Iterator<Item = (usize, &[u8])> + '_ {
+    let mut position = 0;
+    std::iter::from_fn(move || next_token_run(content, &mut position, is_exact_token_char))
 }
 
 /// Iterator that yields exact-mode token hashes from content
@@ -167,37 +142,6 @@ impl<'a> ExactTokenIterator<'a> {
             position: 0,
         }
     }
-
-    /// Skip delimiter characters
-    #[inline]
-    fn skip_delimiters(&mut self) {
-        while self.position < self.content.len() && is_exact_delimiter(self.content[self.position])
-        {
-            self.position += 1;
-        }
-    }
-
-    /// Read next token bytes
-    #[inline]
-    fn read_token(&mut self) -> Option<&'a [u8]> {
-        let start = self.position;
-
-        while self.position < self.content.len()
-            && is_exact_token_char(self.content[self.position])
-        {
-            self.position += 1;
-        }
-
-        if self.position > start {
-            Some(&self.content[start..self.position])
-        } else {
-            // Skip unknown characters that aren't delimiters but aren't token chars either
-            if self.position < self.content.len() {
-                self.position += 1;
-            }
-            None
-        }
-    }
 }
 
 impl<'a> Iterator for ExactTokenIterator<'a> {
@@ -205,16 +149,9 @@ impl<'a> Iterator for ExactTokenIterator<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
-            self.skip_delimiters();
-
-            if self.position >= self.content.len() {
-                return None;
-            }
-
-            if let Some(token) = self.read_token() {
-                if token.len() >= MIN_TOKEN_LENGTH {
-                    return Some(hash_token(token));
-                }
+            let (_, token) = next_token_run(self.content, &mut self.position, is_exact_token_char)?;
+            if char_count(token) >= MIN_TOKEN_LENGTH {
+                return Some(hash_token(token));
             }
         }
     }
@@ -230,6 +167,157 @@ pub fn tokenize_query_exact(query: &str) -> Vec<u64> {
     tokenize_exact(query.as_bytes()).collect()
 }
 
+// ============================================================================
+// Pluggable tokenizers
+// ============================================================================
+
+/// Token rules for the exact, case-insensitive, normalized and subword indexes
+///
+/// Index building and queries against those indexes split text through the
+/// same `Tokenizer`, so a query always looks for tokens the way they were
+/// indexed. Every index file header records the tokenizer's fingerprint,
+/// and an index is only opened with the tokenizer it was built with.
+/// Trigrams for fuzzy and regex search keep their own fixed rules.
+///
+/// ```
+/// use tokenizer::Tokenizer;
+///
+/// /// PHP variables keep their `$`
+/// struct PhpTokenizer;
+///
+/// impl Tokenizer for PhpTokenizer {
+///     fn name(&self) -> &str {
+///         "php-v1"
+///     }
+///
+///     fn token_spans<'a>(
+///         &self,
+///         content: &'a [u8],
+///     ) -> Box<dyn Iterator<Item = (usize, &'a [u8])> + 'a> {
+///         let is_token_byte = |b: u8| b == b'$' || b == b'_' || b.is_ascii_alphanumeric();
+///         let mut position = 0;
+///         Box::new(std::iter::from_fn(move || {
+///             while position < content.len() && !is_token_byte(content[position]) {
+///                 position += 1;
+///             }
+///             let start = position;
+///             while position < content.len() && is_token_byte(content[position]) {
+///                 position += 1;
+///             }
+///             (start < position).then(|| (start, &content[start..position]))
+///         }))
+///     }
+/// }
+///
+/// let tokens: Vec<&[u8]> = PhpTokenizer.tokens(b"echo $name;").collect();
+/// assert_eq!(tokens, [&b"echo"[..], &b"$name"[..]]);
+/// ```
+pub trait Tokenizer: Send + Sync {
+    /// Name of the rules; use a new name whenever the rules change
+    fn name(&self) -> &str;
+
+    /// Split content into tokens, yielding each with its byte offset
+    ///
+    /// Every token yielded is indexed, so rules such as a minimum length
+    /// belong here.
+    fn token_spans<'a>(
+        &self,
+        content: &'a [u8],
+    ) -> Box<dyn Iterator<Item = (usize, &'a [u8])> + 'a>;
+
+    /// Split content into tokens
+    fn tokens<'a>(&self, content: &'a [u8]) -> Box<dyn Iterator<Item = &'a [u8]> + 'a> {
+        Box::new(self.token_spans(content).map(|(_, token)| token))
+    }
+
+    /// Fingerprint of the name, as recorded in index headers
+    fn fingerprint(&self) -> u64 {
+        hash_token(self.name().as_bytes())
+    }
+}
+
+impl std::fmt::Debug for dyn Tokenizer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Tokenizer").field(&self.name()).finish()
+    }
+}
+
+/// Built-in exact-mode rules, the default
+///
+/// Tokens are runs of `XID_Continue` characters and `-` (`my_var`,
+/// `user-service`) of at least `MIN_TOKEN_LENGTH` characters.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExactTokenizer;
+
+impl Tokenizer for ExactTokenizer {
+    fn name(&self) -> &str {
+        "exact"
+    }
+
+    fn token_spans<'a>(
+        &self,
+        content: &'a [u8],
+    ) -> Box<dyn Iterator<Item = (usize, &'a [u8])> + 'a> {
+        Box::new(
+            exact_token_spans(content).filter(|(_, token)| char_count(token) >= MIN_TOKEN_LENGTH),
+        )
+    }
+}
+
+/// Built-in legacy rules
+///
+/// Like `ExactTokenizer` but `_` and `-` split tokens too, so `my_var` is
+/// `my` and `var`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LegacyTokenizer;
+
+impl Tokenizer for LegacyTokenizer {
+    fn name(&self) -> &str {
+        "legacy"
+    }
+
+    fn token_spans<'a>(
+        &self,
+        content: &'a [u8],
+    ) -> Box<dyn Iterator<Item = (usize, &'a [u8])> + 'a> {
+        let mut position = 0;
+        Box::new(
+            std::iter::from_fn(move || {
+                next_token_run(content, &mut position, is_legacy_token_char)
+            })
+            .filter(|(_, token)| char_count(token) >= MIN_TOKEN_LENGTH),
+        )
+    }
+}
+
+/// Names of the built-in tokenizers
+pub const BUILTIN_TOKENIZERS: [&str; 2] = ["exact", "legacy"];
+
+/// Get a built-in tokenizer by name
+pub fn builtin_tokenizer(name: &str) -> Option<Arc<dyn Tokenizer>> {
+    match name {
+        "exact" => Some(Arc::new(ExactTokenizer)),
+        "legacy" => Some(Arc::new(LegacyTokenizer)),
+        _ => None,
+    }
+}
+
+/// Find the built-in tokenizer with a fingerprint
+pub fn builtin_tokenizer_by_fingerprint(fingerprint: u64) -> Option<Arc<dyn Tokenizer>> {
+    BUILTIN_TOKENIZERS
+        .iter()
+        .filter_map(|name| builtin_tokenizer(name))
+        .find(|tokenizer| tokenizer.fingerprint() == fingerprint)
+}
+
+/// Describe the tokenizer behind a fingerprint: its name when built in
+pub fn describe_tokenizer(fingerprint: u64) -> String {
+    match builtin_tokenizer_by_fingerprint(fingerprint) {
+        Some(tokenizer) => tokenizer.name().to_string(),
+        None => format!("custom ({:016x})", fingerprint),
+    }
+}
+
 // ============================================================================
 // Case-Insensitive Exact Mode Tokenizer
 // ============================================================================
@@ -247,34 +335,6 @@ impl<'a> ExactTokenLowerIterator<'a> {
             position: 0,
         }
     }
-
-    #[inline]
-    fn skip_delimiters(&mut self) {
-        while self.position < self.content.len() && is_exact_delimiter(self.content[self.position])
-        {
-            self.position += 1;
-        }
-    }
-
-    #[inline]
-    fn read_token(&mut self) -> Option<&'a [u8]> {
-        let start = self.position;
-
-        while self.position < self.content.len()
-            && is_exact_token_char(self.content[self.position])
-        {
-            self.position += 1;
-        }
-
-        if self.position > start {
-            Some(&self.content[start..self.position])
-        } else {
-            if self.position < self.content.len() {
-                self.position += 1;
-            }
-            None
-        }
-    }
 }
 
 impl<'a> Iterator for ExactTokenLowerIterator<'a> {
@@ -282,16 +342,9 @@ impl<'a> Iterator for ExactTokenLowerIterator<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
-            self.skip_delimiters();
-
-            if self.position >= self.content.len() {
-                return None;
-            }
-
-            if let Some(token) = self.read_token() {
-                if token.len() >= MIN_TOKEN_LENGTH {
-                    return Some(hash_token_lower(token));
-                }
+            let (_, token) = next_token_run(self.content, &mut self.position, is_exact_token_char)?;
+            if char_count(token) >= MIN_TOKEN_LENGTH {
+                return Some(hash_token_lower(token));
             }
         }
     }
@@ -307,8 +360,8 @@ pub fn tokenize_query_exact_lower(query: &str) -> Vec<u64> {
     tokenize_exact_lower(query.as_bytes()).collect()
 }
 
-/// Extract unique case-insensitive exact-mode token hashes from a file
-pub fn extract_exact_tokens_lower_from_file(path: &Path) -> std::io::Result<Vec<u64>> {
+/// Extract unique exact-mode token hashes from a file
+pub fn extract_exact_tokens_from_file(path: &Path) -> std::io::Result<Vec<u64>> {
     let file = File::open(path)?;
     let metadata = file.metadata()?;
 
@@ -324,29 +377,71 @@ pub fn extract_exact_tokens_lower_from_file(path: &Path) -> std::io::Result<Vec<
         return Ok(Vec::new());
     }
 
-    let unique_tokens: FxHashSet<u64> = tokenize_exact_lower(&mmap[..]).collect();
+    let unique_tokens: FxHashSet<u64> = tokenize_exact(&mmap[..]).collect();
     Ok(unique_tokens.into_iter().collect())
 }
 
-/// Extract unique exact-mode token hashes from a file
-pub fn extract_exact_tokens_from_file(path: &Path) -> std::io::Result<Vec<u64>> {
-    let file = File::open(path)?;
-    let metadata = file.metadata()?;
+// ============================================================================
+// Normalized Exact Mode Tokenizer
+// ============================================================================
 
-    if metadata.len() == 0 {
-        return Ok(Vec::new());
-    }
+/// Check if a byte is dropped when normalizing a token
+#[inline]
+fn is_word_separator(byte: u8) -> bool {
+    byte == b'_' || byte == b'-'
+}
 
-    let mmap = unsafe { Mmap::map(&file)? };
+/// Hash a token with `_` and `-` removed and letters case-folded
+///
+/// `userId`, `user_id` and `USER-ID` all hash like `userid`.
+#[inline]
+pub fn hash_token_normalized(token: &[u8]) -> u64 {
+    let mut hasher = FxHasher::default();
+    for_each_folded_byte(token, |byte| {
+        if !is_word_separator(byte) {
+            hasher.write_u8(byte);
+        }
+    });
+    hasher.finish()
+}
 
-    // Check for binary file (null bytes in first 8KB)
-    let check_len = std::cmp::min(8192, mmap.len());
-    if mmap[..check_len].contains(&0) {
-        return Ok(Vec::new());
-    }
+/// Get the normalized form of a token, as hashed by `hash_token_normalized`
+pub fn normalize_token(token: &[u8]) -> Vec<u8> {
+    let mut normalized = Vec::with_capacity(token.len());
+    for_each_folded_byte(token, |byte| {
+        if !is_word_separator(byte) {
+            normalized.push(byte);
+        }
+    });
+    normalized
+}
 
-    let unique_tokens: FxHashSet<u64> = tokenize_exact(&mmap[..]).collect();
-    Ok(unique_tokens.into_iter().collect())
+/// Hash tokens in their normalized form
+///
+/// Tokens made only of `_` and `-` normalize to nothing and are skipped.
+pub(crate) fn hash_tokens_normalized<'a, I>(tokens: I) -> impl Iterator<Item = u64> + use<'a, I>
+where
+    I: Iterator<Item = &'a [u8]>,
+{
+    tokens
+        .filter(|token| token.iter().any(|&byte| !is_word_separator(byte)))
+        .map(hash_token_normalized)
+}
+
+/// Extract normalized exact-mode token hashes from a byte slice
+///
+/// Tokens made only of `_` and `-` normalize to nothing and are skipped.
+pub fn tokenize_exact_normalized(content: &[u8]) -> impl Iterator<Item = u64> + '_ {
+    hash_tokens_normalized(
+        exact_token_spans(content)
+            .map(|(_, token)| token)
+            .filter(|token| char_count(token) >= MIN_TOKEN_LENGTH),
+    )
+}
+
+/// Tokenize a string query in normalized exact mode
+pub fn tokenize_query_exact_normalized(query: &str) -> Vec<u64> {
+    tokenize_exact_normalized(query.as_bytes()).collect()
 }
 
 // ============================================================================
@@ -598,4 +693,109 @@ mod tests {
         assert_eq!(query1, query2);
         assert_eq!(query2, query3);
     }
+
+    #[test]
+    fn test_exact_token_spans_match_tokenizer() {
+        let content = b"fn my_func(a, b-c) { x.y::Zed; }\0\xc3\xa9 tail";
+        let spans: Vec<(usize, &[u8])> = exact_token_spans(content).collect();
+        assert_eq!(spans[0], (0, &b"fn"[..]));
+        assert_eq!(spans[1], (3, &b"my_func"[..]));
+
+        let from_spans: Vec<u64> = spans
+            .iter()
+            .filter(|(_, token)| char_count(token) >= MIN_TOKEN_LENGTH)
+            .map(|(_, token)| hash_token(token))
+            .collect();
+        let from_iter: Vec<u64> = tokenize_exact(content).collect();
+        assert_eq!(from_spans, from_iter);
+    }
+
+    #[test]
+    fn test_normalized_spellings_collapse() {
+        let expected = hash_token_normalized(b"userid");
+        for spelling in ["userId", "user_id", "USER-ID", "User_Id", "__userid"] {
+            assert_eq!(tokenize_query_exact_normalized(spelling), [expected]);
+        }
+        assert_eq!(normalize_token(b"HTTP_Server-2"), b"httpserver2");
+        assert_ne!(hash_token_normalized(b"user_name"), expected);
+    }
+
+    #[test]
+    fn test_tokenize_exact_normalized_skips_separator_runs() {
+        let tokens: Vec<u64> = tokenize_exact_normalized(b"a -- __ x_ getX").collect();
+        assert_eq!(
+            tokens,
+            [hash_token_normalized(b"x"), hash_token_normalized(b"getx")]
+        );
+    }
+
+    #[test]
+    fn test_unicode_token_boundaries() {
+        let content = "let naïve = Größe + café_ü; // 日本語 Привет —δ-λ".as_bytes();
+        let tokens: FxHashSet<u64> = tokenize_exact(content).collect();
+        for word in ["naïve", "Größe", "café_ü", "日本語", "Привет", "δ-λ"] {
+            assert!(tokens.contains(&hash_token(word.as_bytes())), "{}", word);
+        }
+        assert_eq!(tokens.len(), 7);
+
+        // Invalid UTF-8 splits tokens instead of being skipped
+        let spans: Vec<&[u8]> = exact_token_spans(b"ab\xffcd").map(|(_, t)| t).collect();
+        assert_eq!(spans, [&b"ab"[..], &b"cd"[..]]);
+        // One character is too short even when it takes several bytes
+        assert_eq!(tokenize_exact("é 中".as_bytes()).count(), 0);
+    }
+
+    #[test]
+    fn test_unicode_case_folding() {
+        assert_eq!(
+            hash_token_lower("ΣΊΣΥΦΟΣ".as_bytes()),
+            hash_token_lower("σίσυφος".as_bytes())
+        );
+        assert_eq!(
+            hash_token_lower("ПРИВЕТ".as_bytes()),
+            hash_token_lower("привет".as_bytes())
+        );
+        assert_eq!(hash_token_lower(b"Hello"), hash_token_lower(b"hello"));
+        assert_eq!(
+            tokenize_query_exact_lower("Größe"),
+            tokenize_exact_lower("GRÖSSE größe".as_bytes())
+                .skip(1)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            tokenize_query_exact_normalized("Straßen_Name"),
+            [hash_token_normalized("straßenname".as_bytes())]
+        );
+    }
+
+    #[test]
+    fn test_builtin_tokenizers() {
+        let content = b"my_var = user-service(x);";
+        let exact: Vec<&[u8]> = ExactTokenizer.tokens(content).collect();
+        assert_eq!(exact, [&b"my_var"[..], b"user-service"]);
+        let legacy: Vec<&[u8]> = LegacyTokenizer.tokens(content).collect();
+        assert_eq!(legacy, [&b"my"[..], b"var", b"user", b"service"]);
+        assert_eq!(
+            ExactTokenizer
+                .tokens(content)
+                .map(hash_token)
+                .collect::<Vec<_>>(),
+            tokenize_exact(content).collect::<Vec<_>>()
+        );
+
+        for name in BUILTIN_TOKENIZERS {
+            let tokenizer = builtin_tokenizer(name).unwrap();
+            let fingerprint = tokenizer.fingerprint();
+            assert_eq!(
+                builtin_tokenizer_by_fingerprint(fingerprint)
+                    .unwrap()
+                    .name(),
+                name
+            );
+            assert_eq!(describe_tokenizer(fingerprint), name);
+        }
+        assert_ne!(ExactTokenizer.fingerprint(), LegacyTokenizer.fingerprint());
+        assert!(builtin_tokenizer("php").is_none());
+        assert_eq!(describe_tokenizer(0xff), "custom (00000000000000ff)");
+    }
 }
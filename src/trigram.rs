@@ -2,55 +2,49 @@
 //!
 //! Trigrams are 3-character sequences used for approximate string matching.
 //! This module provides case-insensitive trigram extraction for fuzzy search.
+//! Trigrams are taken over Unicode code points after simple case folding, so
+//! `Größe` yields `grö`, `röß` and `öße`.
 
+use crate::unicode::{decode_char, fold_case, is_word_char};
 use memmap2::Mmap;
 use rustc_hash::FxHashSet;
 use std::fs::File;
 use std::path::Path;
 
-/// Minimum length for a token to generate trigrams from
+/// Minimum length for a token to generate trigrams from, in characters
 /// Tokens shorter than 3 chars don't produce any trigrams
 pub const MIN_TRIGRAM_TOKEN_LENGTH: usize = 3;
 
-/// Pack 3 bytes into a u32 for efficient storage
+/// Pack 3 characters into a u64 for efficient storage (21 bits each)
 #[inline]
-pub fn pack_trigram(a: u8, b: u8, c: u8) -> u32 {
-    ((a as u32) << 16) | ((b as u32) << 8) | (c as u32)
+pub fn pack_trigram(a: char, b: char, c: char) -> u64 {
+    ((a as u64) << 42) | ((b as u64) << 21) | (c as u64)
 }
 
-/// Unpack a u32 trigram into 3 bytes
+/// Unpack a u64 trigram into 3 characters
 #[inline]
-pub fn unpack_trigram(trigram: u32) -> (u8, u8, u8) {
+pub fn unpack_trigram(trigram: u64) -> (char, char, char) {
+    let unpack = |bits: u64| char::from_u32((bits & 0x1F_FFFF) as u32).unwrap_or('\u{FFFD}');
     (
-        ((trigram >> 16) & 0xFF) as u8,
-        ((trigram >> 8) & 0xFF) as u8,
-        (trigram & 0xFF) as u8,
+        unpack(trigram >> 42),
+        unpack(trigram >> 21),
+        unpack(trigram),
     )
 }
 
-/// Convert a byte to lowercase ASCII
+/// Check if a character is a valid token character for trigram extraction
+/// Includes: identifier characters (letters, digits, _) and -
 #[inline]
-fn to_lowercase(b: u8) -> u8 {
-    if b.is_ascii_uppercase() {
-        b + 32
-    } else {
-        b
-    }
-}
-
-/// Check if a byte is a valid token character for trigram extraction
-/// Includes: a-z, A-Z, 0-9, _, -
-#[inline]
-fn is_trigram_token_char(byte: u8) -> bool {
-    byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'-'
+pub(crate) fn is_trigram_token_char(c: char) -> bool {
+    c == '-' || is_word_char(c)
 }
 
 /// Iterator that extracts trigrams from content
 pub struct TrigramIterator<'a> {
     content: &'a [u8],
     position: usize,
-    // Buffer to hold current token bytes (lowercase)
-    token_buf: Vec<u8>,
+    // Buffer to hold current token characters (case-folded)
+    token_buf: Vec<char>,
     // Current position within token_buf for trigram extraction
     token_pos: usize,
 }
@@ -65,17 +59,25 @@ impl<'a> TrigramIterator<'a> {
         }
     }
 
+    /// Decode the token character at the current position, if any
+    #[inline]
+    fn token_char(&self) -> Option<(char, usize)> {
+        match decode_char(&self.content[self.position..]) {
+            (Some(c), len) if is_trigram_token_char(c) => Some((c, len)),
+            _ => None,
+        }
+    }
+
     /// Skip non-token characters
     #[inline]
     fn skip_delimiters(&mut self) {
-        while self.position < self.content.len()
-            && !is_trigram_token_char(self.content[self.position])
-        {
-            self.position += 1;
+        while self.position < self.content.len() && self.token_char().is_none() {
+            let (_, len) = decode_char(&self.content[self.position..]);
+            self.position += len;
         }
     }
 
-    /// Read next token into buffer, converting to lowercase.
+    /// Read next token into buffer, case-folding it.
     /// Skips tokens shorter than MIN_TRIGRAM_TOKEN_LENGTH.
     fn read_next_token(&mut self) -> bool {
         loop {
@@ -88,12 +90,12 @@ impl<'a> TrigramIterator<'a> {
             self.token_buf.clear();
             self.token_pos = 0;
 
-            while self.position < self.content.len()
-                && is_trigram_token_char(self.content[self.position])
-            {
-                self.token_buf
-                    .push(to_lowercase(self.content[self.position]));
-                self.position += 1;
+            while self.position < self.content.len() {
+                let Some((c, len)) = self.token_char() else {
+                    break;
+                };
+                self.token_buf.push(fold_case(c));
+                self.position += len;
             }
 
             // If token is long enough, return success
@@ -105,7 +107,7 @@ impl<'a> TrigramIterator<'a> {
     }
 
     /// Get next trigram from current token
-    fn next_trigram_from_token(&mut self) -> Option<u32> {
+    fn next_trigram_from_token(&mut self) -> Option<u64> {
         if self.token_pos + 3 <= self.token_buf.len() {
             let trigram = pack_trigram(
                 self.token_buf[self.token_pos],
@@ -121,7 +123,7 @@ impl<'a> TrigramIterator<'a> {
 }
 
 impl<'a> Iterator for TrigramIterator<'a> {
-    type Item = u32;
+    type Item = u64;
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
@@ -144,17 +146,17 @@ impl<'a> Iterator for TrigramIterator<'a> {
 }
 
 /// Extract trigrams from a byte slice
-pub fn extract_trigrams(content: &[u8]) -> impl Iterator<Item = u32> + '_ {
+pub fn extract_trigrams(content: &[u8]) -> impl Iterator<Item = u64> + '_ {
     TrigramIterator::new(content)
 }
 
 /// Extract trigrams from a query string (case-insensitive)
-pub fn extract_query_trigrams(query: &str) -> Vec<u32> {
+pub fn extract_query_trigrams(query: &str) -> Vec<u64> {
     extract_trigrams(query.as_bytes()).collect()
 }
 
 /// Extract unique trigrams from a file
-pub fn extract_trigrams_from_file(path: &Path) -> std::io::Result<Vec<u32>> {
+pub fn extract_trigrams_from_file(path: &Path) -> std::io::Result<Vec<u64>> {
     let file = File::open(path)?;
     let metadata = file.metadata()?;
 
@@ -170,7 +172,7 @@ pub fn extract_trigrams_from_file(path: &Path) -> std::io::Result<Vec<u32>> {
         return Ok(Vec::new());
     }
 
-    let unique_trigrams: FxHashSet<u32> = extract_trigrams(&mmap[..]).collect();
+    let unique_trigrams: FxHashSet<u64> = extract_trigrams(&mmap[..]).collect();
     Ok(unique_trigrams.into_iter().collect())
 }
 
@@ -180,11 +182,15 @@ mod tests {
 
     #[test]
     fn test_pack_unpack_trigram() {
-        let trigram = pack_trigram(b'a', b'b', b'c');
+        let trigram = pack_trigram('a', 'b', 'c');
         let (a, b, c) = unpack_trigram(trigram);
-        assert_eq!(a, b'a');
-        assert_eq!(b, b'b');
-        assert_eq!(c, b'c');
+        assert_eq!(a, 'a');
+        assert_eq!(b, 'b');
+        assert_eq!(c, 'c');
+        assert_eq!(
+            unpack_trigram(pack_trigram('中', '😀', 'é')),
+            ('中', '😀', 'é')
+        );
     }
 
     #[test]
@@ -194,9 +200,9 @@ mod tests {
         // "hello" -> "hel", "ell", "llo" = 3 trigrams
         assert_eq!(trigrams.len(), 3);
 
-        assert!(trigrams.contains(&pack_trigram(b'h', b'e', b'l')));
-        assert!(trigrams.contains(&pack_trigram(b'e', b'l', b'l')));
-        assert!(trigrams.contains(&pack_trigram(b'l', b'l', b'o')));
+        assert!(trigrams.contains(&pack_trigram('h', 'e', 'l')));
+        assert!(trigrams.contains(&pack_trigram('e', 'l', 'l')));
+        assert!(trigrams.contains(&pack_trigram('l', 'l', 'o')));
     }
 
     #[test]
@@ -231,9 +237,9 @@ mod tests {
         assert_eq!(trigrams.len(), 6);
 
         // Verify underscore is included
-        assert!(trigrams.contains(&pack_trigram(b'u', b'n', b'_')));
-        assert!(trigrams.contains(&pack_trigram(b'n', b'_', b'g')));
-        assert!(trigrams.contains(&pack_trigram(b'_', b'g', b'a')));
+        assert!(trigrams.contains(&pack_trigram('u', 'n', '_')));
+        assert!(trigrams.contains(&pack_trigram('n', '_', 'g')));
+        assert!(trigrams.contains(&pack_trigram('_', 'g', 'a')));
     }
 
     #[test]
@@ -260,10 +266,10 @@ mod tests {
         // "a" and "b" should be skipped, not terminate iteration
         assert_eq!(trigrams.len(), 4);
 
-        assert!(trigrams.contains(&pack_trigram(b'a', b'l', b'f')));
-        assert!(trigrams.contains(&pack_trigram(b'l', b'f', b'r')));
-        assert!(trigrams.contains(&pack_trigram(b'f', b'r', b'e')));
-        assert!(trigrams.contains(&pack_trigram(b'r', b'e', b'd')));
+        assert!(trigrams.contains(&pack_trigram('a', 'l', 'f')));
+        assert!(trigrams.contains(&pack_trigram('l', 'f', 'r')));
+        assert!(trigrams.contains(&pack_trigram('f', 'r', 'e')));
+        assert!(trigrams.contains(&pack_trigram('r', 'e', 'd')));
     }
 
     #[test]
@@ -285,8 +291,8 @@ mod tests {
         assert_eq!(query_trigrams.len(), 9);
 
         // Verify lowercase conversion
-        assert!(query_trigrams.contains(&pack_trigram(b'u', b's', b'e')));
-        assert!(query_trigrams.contains(&pack_trigram(b's', b'e', b'r')));
+        assert!(query_trigrams.contains(&pack_trigram('u', 's', 'e')));
+        assert!(query_trigrams.contains(&pack_trigram('s', 'e', 'r')));
     }
 
     #[test]
@@ -334,6 +340,27 @@ mod tests {
         assert!(trigrams.is_empty());
     }
 
+    #[test]
+    fn test_extract_trigrams_unicode() {
+        let trigrams: Vec<_> = extract_trigrams("Größe 日本語 ÉTÉ".as_bytes()).collect();
+        assert_eq!(
+            trigrams,
+            [
+                pack_trigram('g', 'r', 'ö'),
+                pack_trigram('r', 'ö', 'ß'),
+                pack_trigram('ö', 'ß', 'e'),
+                pack_trigram('日', '本', '語'),
+                pack_trigram('é', 't', 'é'),
+            ]
+        );
+        assert_eq!(
+            extract_query_trigrams("ΣΊΣΥΦΟΣ"),
+            extract_query_trigrams("σίσυφος")
+        );
+        // Invalid bytes end a token like any delimiter
+        assert!(extract_trigrams(b"ab\xffcd").next().is_none());
+    }
+
     #[test]
     fn test_only_delimiters() {
         let content = b"!@#$%^&*()";
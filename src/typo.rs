@@ -0,0 +1,135 @@
+//! Typo-tolerant token lookup: indexed tokens within an edit distance
+//!
+//! Walks the sorted token dictionary like a trie. The Levenshtein table of
+//! a token is built one row per token byte, so consecutive tokens reuse the
+//! rows of their shared prefix, and once every cell of a row exceeds the
+//! distance no token with that prefix can match and the whole run is
+//! skipped.
+
+use crate::dictionary::TokenDictionary;
+
+/// Find the dictionary tokens within `max_distance` edits of `word`
+///
+/// Edits are byte insertions, deletions and substitutions. Returns
+/// `(token, hash, distance)` sorted by distance, then token.
+pub(crate) fn similar_tokens<'a>(
+    dictionary: &'a TokenDictionary,
+    word: &[u8],
+    max_distance: u32,
+) -> Vec<(&'a [u8], u64, u32)> {
+    let max = max_distance as usize;
+    // rows[d][j]: distance between the first d bytes of the token and the
+    // first j bytes of the word
+    let mut rows: Vec<Vec<usize>> = vec![(0..=word.len()).collect()];
+    let mut previous: &[u8] = &[];
+    // Depth at which the last computed token's row went past `max`
+    let mut dead_depth: Option<usize> = None;
+    let mut found = Vec::new();
+
+    for (token, hash) in dictionary.iter() {
+        let shared = common_prefix_len(previous, token);
+        previous = token;
+        if dead_depth.is_some_and(|depth| shared >= depth) {
+            continue;
+        }
+        dead_depth = None;
+
+        rows.truncate(shared + 1);
+        for depth in shared..token.len() {
+            let row = next_row(&rows[depth], word, token[depth]);
+            let alive = row.iter().any(|&distance| distance <= max);
+            rows.push(row);
+            if !alive {
+                dead_depth = Some(depth + 1);
+                break;
+            }
+        }
+
+        if dead_depth.is_none() {
+            let distance = rows[token.len()][word.len()];
+            if distance <= max {
+                found.push((token, hash, distance as u32));
+            }
+        }
+    }
+
+    found.sort_by(|a, b| a.2.cmp(&b.2).then_with(|| a.0.cmp(b.0)));
+    found
+}
+
+/// Compute the Levenshtein row for one more token byte
+fn next_row(previous: &[usize], word: &[u8], byte: u8) -> Vec<usize> {
+    let mut row = Vec::with_capacity(previous.len());
+    row.push(previous[0] + 1);
+    for j in 1..previous.len() {
+        let substitution = previous[j - 1] + usize::from(word[j - 1] != byte);
+        let deletion = previous[j] + 1;
+        let insertion = row[j - 1] + 1;
+        row.push(substitution.min(deletion).min(insertion));
+    }
+    row
+}
+
+fn common_prefix_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(x, y)| x == y).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn similar(word: &str, max_distance: u32, tokens: &[&str]) -> Vec<(String, u32)> {
+        let mut dictionary = TokenDictionary::new();
+        for token in tokens {
+            dictionary.insert(token.as_bytes());
+        }
+        similar_tokens(&dictionary, word.as_bytes(), max_distance)
+            .into_iter()
+            .map(|(token, _, distance)| (String::from_utf8(token.to_vec()).unwrap(), distance))
+            .collect()
+    }
+
+    #[test]
+    fn test_similar_tokens() {
+        let tokens = [
+            "receive_buffer",
+            "receive_buffers",
+            "recv_buffer",
+            "release_buffer",
+            "send_buffer",
+        ];
+        assert_eq!(
+            similar("recieve_buffer", 2, &tokens),
+            [("receive_buffer".to_string(), 2)]
+        );
+        assert_eq!(
+            similar("recieve_buffer", 3, &tokens),
+            [
+                ("receive_buffer".to_string(), 2),
+                ("receive_buffers".to_string(), 3),
+                ("recv_buffer".to_string(), 3),
+            ]
+        );
+        assert_eq!(
+            similar("recv_bufer", 1, &tokens),
+            [("recv_buffer".to_string(), 1)]
+        );
+        assert_eq!(
+            similar("send_buffer", 0, &tokens),
+            [("send_buffer".to_string(), 0)]
+        );
+        assert!(similar("xyz", 1, &tokens).is_empty());
+    }
+
+    #[test]
+    fn test_ordering_and_pruned_runs() {
+        // Shared prefixes are pruned together; later runs are still checked
+        let tokens = ["aaaa", "aaab", "aaba", "abcd", "bbbb", "zzzz"];
+        assert_eq!(
+            similar("aaab", 1, &tokens),
+            [("aaab".to_string(), 0), ("aaaa".to_string(), 1)]
+        );
+        assert_eq!(similar("bbb", 1, &tokens), [("bbbb".to_string(), 1)]);
+        assert_eq!(similar("zz", 2, &tokens), [("zzzz".to_string(), 2)]);
+    }
+}
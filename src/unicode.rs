@@ -0,0 +1,195 @@
+//! UTF-8 decoding, token characters and case folding shared by the tokenizers
+//!
+//! Content is decoded as UTF-8. Bytes that are not part of a valid sequence
+//! are never token characters, so binary noise and other encodings split
+//! tokens instead of gluing them together. Token characters are those with
+//! the Unicode `XID_Continue` property: letters, combining marks, digits
+//! and connector punctuation such as `_`.
+//!
+//! Case-insensitive matching uses Unicode simple case folding: one character
+//! always folds to one character, so `Straße` and `STRASSE` stay distinct
+//! while `ΣΊΣΥΦΟΣ` and `σίσυφος` fold alike.
+
+/// Decode the character at the start of `bytes`
+///
+/// Returns the character, or None for an invalid or truncated sequence,
+/// together with the number of bytes consumed (at least one).
+#[inline]
+pub(crate) fn decode_char(bytes: &[u8]) -> (Option<char>, usize) {
+    let Some(&lead) = bytes.first() else {
+        return (None, 0);
+    };
+    if lead < 0x80 {
+        return (Some(lead as char), 1);
+    }
+
+    let width = match lead {
+        0xC2..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF4 => 4,
+        _ => return (None, 1),
+    };
+    match bytes
+        .get(..width)
+        .and_then(|sequence| std::str::from_utf8(sequence).ok())
+    {
+        Some(text) => (text.chars().next(), width),
+        None => (None, 1),
+    }
+}
+
+/// Iterate over `(offset, character, length)` for every character of `bytes`
+///
+/// Invalid bytes are yielded one at a time with no character.
+pub(crate) fn char_spans(bytes: &[u8]) -> impl Iterator<Item = (usize, Option<char>, usize)> + '_ {
+    let mut position = 0;
+    std::iter::from_fn(move || {
+        if position >= bytes.len() {
+            return None;
+        }
+        let (c, len) = decode_char(&bytes[position..]);
+        let span = (position, c, len);
+        position += len;
+        Some(span)
+    })
+}
+
+/// Count the characters of a token; invalid bytes count as one each
+#[inline]
+pub(crate) fn char_count(token: &[u8]) -> usize {
+    if token.is_ascii() {
+        return token.len();
+    }
+    char_spans(token).count()
+}
+
+/// Check if a character can be part of an identifier (`XID_Continue`)
+#[inline]
+pub(crate) fn is_word_char(c: char) -> bool {
+    if c.is_ascii() {
+        c.is_ascii_alphanumeric() || c == '_'
+    } else {
+        unicode_ident::is_xid_continue(c)
+    }
+}
+
+/// Fold a character with Unicode simple case folding
+pub fn fold_case(c: char) -> char {
+    if c.is_ascii() {
+        return c.to_ascii_lowercase();
+    }
+
+    // Characters whose folding differs from their lowercase mapping
+    match c {
+        '\u{00B5}' => '\u{03BC}',
+        '\u{017F}' => 's',
+        '\u{0345}' | '\u{1FBE}' => '\u{03B9}',
+        '\u{03C2}' => '\u{03C3}',
+        '\u{03D0}' => '\u{03B2}',
+        '\u{03D1}' => '\u{03B8}',
+        '\u{03D5}' => '\u{03C6}',
+        '\u{03D6}' => '\u{03C0}',
+        '\u{03F0}' => '\u{03BA}',
+        '\u{03F1}' => '\u{03C1}',
+        '\u{03F5}' => '\u{03B5}',
+        '\u{1C80}' => '\u{0432}',
+        '\u{1C81}' => '\u{0434}',
+        '\u{1C82}' => '\u{043E}',
+        '\u{1C83}' => '\u{0441}',
+        '\u{1C84}' | '\u{1C85}' => '\u{0442}',
+        '\u{1C86}' => '\u{044A}',
+        '\u{1C87}' => '\u{0463}',
+        '\u{1C88}' => '\u{A64B}',
+        '\u{1E9B}' => '\u{1E61}',
+        // Cherokee folds to its uppercase letters
+        '\u{13A0}'..='\u{13F5}' => c,
+        '\u{13F8}'..='\u{13FD}' => char::from_u32(c as u32 - 8).unwrap_or(c),
+        '\u{AB70}'..='\u{ABBF}' => char::from_u32(c as u32 - 0xAB70 + 0x13A0).unwrap_or(c),
+        _ => {
+            // Multi-character lowercase mappings (e.g. U+0130) have no
+            // simple folding
+            let mut lower = c.to_lowercase();
+            match (lower.next(), lower.next()) {
+                (Some(single), None) => single,
+                _ => c,
+            }
+        }
+    }
+}
+
+/// Case-fold a token; invalid bytes are kept as they are
+pub fn fold_token(token: &[u8]) -> Vec<u8> {
+    if token.is_ascii() {
+        return token.to_ascii_lowercase();
+    }
+
+    let mut folded = Vec::with_capacity(token.len());
+    for_each_folded_byte(token, |byte| folded.push(byte));
+    folded
+}
+
+/// Feed the bytes of `fold_token(token)` to `sink` without allocating
+#[inline]
+pub(crate) fn for_each_folded_byte(token: &[u8], mut sink: impl FnMut(u8)) {
+    let mut buf = [0u8; 4];
+    for (offset, c, len) in char_spans(token) {
+        match c {
+            Some(c) if c.is_ascii() => sink(c.to_ascii_lowercase() as u8),
+            Some(c) => fold_case(c)
+                .encode_utf8(&mut buf)
+                .bytes()
+                .for_each(&mut sink),
+            None => token[offset..offset + len]
+                .iter()
+                .copied()
+                .for_each(&mut sink),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_char() {
+        assert_eq!(decode_char(b"a"), (Some('a'), 1));
+        assert_eq!(decode_char("é!".as_bytes()), (Some('é'), 2));
+        assert_eq!(decode_char("中".as_bytes()), (Some('中'), 3));
+        assert_eq!(decode_char("😀".as_bytes()), (Some('😀'), 4));
+        // Stray continuation byte, truncated sequence, overlong encoding
+        assert_eq!(decode_char(b"\x80abc"), (None, 1));
+        assert_eq!(decode_char(b"\xe4\xb8"), (None, 1));
+        assert_eq!(decode_char(b"\xc0\xaf"), (None, 1));
+        assert_eq!(char_count(&["naïve".as_bytes(), b"\xff"].concat()), 6);
+        assert_eq!(char_count(b"ab\xff"), 3);
+    }
+
+    #[test]
+    fn test_word_chars() {
+        for c in ['a', 'Z', '7', '_', 'é', 'Ж', 'λ', '中', '\u{0301}'] {
+            assert!(is_word_char(c), "{:?}", c);
+        }
+        for c in ['-', ' ', '.', '€', '—', '😀', '\u{00A0}'] {
+            assert!(!is_word_char(c), "{:?}", c);
+        }
+    }
+
+    #[test]
+    fn test_simple_case_folding() {
+        assert_eq!(
+            fold_token("ΣΊΣΥΦΟΣ".as_bytes()),
+            fold_token("σίσυφος".as_bytes())
+        );
+        assert_eq!(fold_token("Привет".as_bytes()), "привет".as_bytes());
+        assert_eq!(fold_token("ÉCOLE".as_bytes()), "école".as_bytes());
+        assert_eq!(fold_case('\u{212A}'), 'k');
+        assert_eq!(fold_case('ſ'), 's');
+        assert_eq!(fold_case('\u{AB70}'), '\u{13A0}');
+        // Full foldings that expand are left out
+        assert_eq!(fold_case('ß'), 'ß');
+        assert_eq!(fold_case('\u{0130}'), '\u{0130}');
+        // Invalid bytes survive folding
+        assert_eq!(fold_token(b"AB\xffC"), b"ab\xffc");
+    }
+}
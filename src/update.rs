@@ -0,0 +1,470 @@
+//! Incremental index updates
+//!
+//! Compares the files currently on disk against the per-file metadata stored
+//! in `PathIndex` and re-tokenizes only files that were added or modified.
+
+use crate::error::{Result, TokenizerError};
+use crate::index::{FileMeta, IndexHeader, IndexSet, PathIndex};
+use crate::persistence::{load_all_with_tokenizer, save_all};
+use crate::scanner::{
+    apply_results, hash_file_contents, process_single_file, walk_files, FileOutputs, ScanConfig,
+};
+use rayon::prelude::*;
+use roaring::RoaringBitmap;
+use rustc_hash::FxHashMap;
+use std::path::{Path, PathBuf};
+
+/// A single file change to apply to a loaded index set
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileChange {
+    /// A new file that is not in the index yet
+    Added(PathBuf, FileMeta),
+    /// An indexed file whose contents changed
+    Modified(u32, FileMeta),
+    /// An indexed file whose mtime changed but whose contents did not
+    Touched(u32, FileMeta),
+    /// An indexed file that no longer exists (or is now filtered out)
+    Removed(u32),
+}
+
+/// Summary of an incremental update
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UpdateStats {
+    /// Files added to the index
+    pub added: usize,
+    /// Files re-tokenized because their contents changed
+    pub modified: usize,
+    /// Files removed from the index
+    pub removed: usize,
+    /// Files left untouched
+    pub unchanged: usize,
+    /// Unchanged files whose mtime changed; only their metadata is updated
+    pub touched: usize,
+}
+
+impl UpdateStats {
+    /// Check whether the index needs saving: any file was added, modified,
+    /// removed or touched
+    pub fn has_changes(&self) -> bool {
+        self.added + self.modified + self.removed + self.touched > 0
+    }
+}
+
+/// Update the split index files at `base` to reflect the current state of `root`
+///
+/// Only added or modified files are re-tokenized; deleted files are cleared
+/// from every bitmap. The index files are rewritten with a fresh header only
+/// when something changed, including the file filters recorded for
+/// `reindex`. Fails with `TokenizerMismatch` when the index
+/// was built with a different tokenizer than `config.tokenizer`.
+pub fn update_indexes(base: &Path, root: &Path, config: &ScanConfig) -> Result<UpdateStats> {
+    let mut indexes = load_all_with_tokenizer(base, config.tokenizer.clone())?;
+
+    let indexed_root = indexes.paths.root_path.clone();
+    if !same_directory(&indexed_root, root) {
+        return Err(TokenizerError::IndexMismatch(format!(
+            "Index was built from {}, not {}",
+            indexed_root.display(),
+            root.display()
+        )));
+    }
+
+    // Walk the root as it was spelled at build time so paths match the index
+    let changes = detect_changes(&indexes.paths, &indexed_root, config)?;
+    let stats = apply_changes(&mut indexes, &changes, config.hash_contents);
+
+    // Keep the recorded filters in step so `reindex` rebuilds the same files
+    let config_changed = indexes
+        .paths
+        .build
+        .as_mut()
+        .is_some_and(|build| build.record_update(config));
+
+    if stats.has_changes() || config_changed {
+        indexes.set_header(IndexHeader::with_tokenizer(indexes.exact.tokenizer()));
+        save_all(
+            &indexes.paths,
+            &indexes.exact,
+            &indexes.exact_lower,
+            &indexes.trigram,
+            base,
+        )?;
+    }
+
+    Ok(stats)
+}
+
+/// Check whether two paths name the same directory, whether relative or
+/// absolute
+pub(crate) fn same_directory(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Walk `root` and compare it against the files recorded in `paths`
+pub fn detect_changes(
+    paths: &PathIndex,
+    root: &Path,
+    config: &ScanConfig,
+) -> Result<Vec<FileChange>> {
+    let mut known: FxHashMap<PathBuf, u32> = paths.iter_files().map(|(id, p)| (p, id)).collect();
+    let mut changes = Vec::new();
+
+    for (path, meta) in walk_files(root, config)? {
+        let Some(file_id) = known.remove(&path) else {
+            changes.push(FileChange::Added(path, meta));
+            continue;
+        };
+
+        let Some(stored) = paths.file_meta(file_id) else {
+            continue;
+        };
+        if !meta.differs_from(stored) {
+            continue;
+        }
+
+        // mtime or size changed: when hashes are recorded, confirm the contents did too
+        if let (true, Some(stored_hash)) = (config.hash_contents, stored.content_hash) {
+            if let Ok(hash) = hash_file_contents(&path) {
+                if hash == stored_hash {
+                    let meta = FileMeta {
+                        content_hash: Some(hash),
+                        ..meta
+                    };
+                    changes.push(FileChange::Touched(file_id, meta));
+                    continue;
+                }
+            }
+        }
+
+        changes.push(FileChange::Modified(file_id, meta));
+    }
+
+    // Anything not seen during the walk was deleted
+    changes.extend(known.into_values().map(FileChange::Removed));
+
+    Ok(changes)
+}
+
+/// Apply file changes to an in-memory index set
+///
+/// The index headers are left untouched; callers stamp a new header before saving.
+pub fn apply_changes(
+    indexes: &mut IndexSet,
+    changes: &[FileChange],
+    hash_contents: bool,
+) -> UpdateStats {
+    apply_changes_with_ids(indexes, changes, hash_contents).0
+}
+
+/// Apply file changes like `apply_changes`, also returning the ID each
+/// added file was registered under, in the order of the `Added` changes
+pub(crate) fn apply_changes_with_ids(
+    indexes: &mut IndexSet,
+    changes: &[FileChange],
+    hash_contents: bool,
+) -> (UpdateStats, Vec<u32>) {
+    let mut stats = UpdateStats {
+        unchanged: indexes.paths.file_count(),
+        ..Default::default()
+    };
+
+    // Clear stale file IDs from every bitmap before anything is re-added
+    let stale: RoaringBitmap = changes
+        .iter()
+        .filter_map(|change| match change {
+            FileChange::Modified(id, _) | FileChange::Removed(id) => Some(*id),
+            _ => None,
+        })
+        .collect();
+    indexes.exact.remove_files(&stale);
+    indexes.exact_lower.remove_files(&stale);
+    indexes.trigram.remove_files(&stale);
+
+    // Removals first so that added files can reuse the freed IDs
+    for change in changes {
+        if let FileChange::Removed(id) = change {
+            if indexes.paths.file_meta(*id).is_some() {
+                indexes.paths.remove_file(*id);
+                stats.removed += 1;
+                stats.unchanged -= 1;
+            }
+        }
+    }
+
+    let mut work: Vec<(u32, PathBuf)> = Vec::new();
+    let mut added = Vec::new();
+    for change in changes {
+        match change {
+            FileChange::Added(path, meta) => {
+                let file_id = indexes.paths.register_file(path.clone());
+                indexes.paths.set_file_meta(file_id, *meta);
+                work.push((file_id, path.clone()));
+                added.push(file_id);
+                stats.added += 1;
+            }
+            FileChange::Modified(id, meta) => {
+                if let Some(path) = indexes.paths.get_file_path(*id) {
+                    indexes.paths.set_file_meta(*id, *meta);
+                    work.push((*id, path));
+                    stats.modified += 1;
+                    stats.unchanged -= 1;
+                }
+            }
+            FileChange::Touched(id, meta) => {
+                if indexes.paths.file_meta(*id).is_some() {
+                    indexes.paths.set_file_meta(*id, *meta);
+                    stats.touched += 1;
+                }
+            }
+            FileChange::Removed(_) => {}
+        }
+    }
+
+    // Keep the token dictionary, subwords, positions, term frequencies and
+    // normalized tokens in step when the index has them
+    let outputs = FileOutputs {
+        tokenizer: indexes.exact.tokenizer.clone(),
+        content_hash: hash_contents,
+        token_texts: indexes.exact.dictionary.is_some() || indexes.exact.subwords.is_some(),
+        token_positions: indexes.exact.positions.is_some(),
+        token_counts: indexes.exact.frequencies.is_some()
+            || indexes.exact_lower.frequencies.is_some(),
+        normalized_tokens: indexes.exact.normalized.is_some(),
+    };
+    let results: Vec<_> = work
+        .par_iter()
+        .map(|(file_id, path)| process_single_file(*file_id, path, &outputs))
+        .collect();
+    apply_results(indexes, results);
+
+    (stats, added)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::{load_all, save_all};
+    use crate::query::{query_exact, QueryOptions};
+    use crate::scanner::scan_and_build_indexes;
+    use crate::tokenizer::LegacyTokenizer;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    fn build(root: &Path, base: &Path, config: &ScanConfig) {
+        let (paths, exact, exact_lower, trigram) = scan_and_build_indexes(root, config).unwrap();
+        save_all(&paths, &exact, &exact_lower, &trigram, base).unwrap();
+    }
+
+    fn exact_count(base: &Path, query: &str) -> usize {
+        let indexes = load_all(base).unwrap();
+        let options = QueryOptions {
+            match_all: true,
+            ..Default::default()
+        };
+        query_exact(&indexes.paths, &indexes.exact, query, &options)
+            .unwrap()
+            .files
+            .len()
+    }
+
+    #[test]
+    fn test_update_added_modified_removed() {
+        let src = TempDir::new().unwrap();
+        let out = TempDir::new().unwrap();
+        let base = out.path().join("index.tkix");
+
+        std::fs::write(src.path().join("keep.txt"), "stable alpha").unwrap();
+        std::fs::write(src.path().join("change.txt"), "before alpha").unwrap();
+        std::fs::write(src.path().join("gone.txt"), "doomed alpha").unwrap();
+
+        let config = ScanConfig::default();
+        build(src.path(), &base, &config);
+        assert_eq!(exact_count(&base, "alpha"), 3);
+
+        std::fs::write(src.path().join("change.txt"), "after beta and more").unwrap();
+        std::fs::remove_file(src.path().join("gone.txt")).unwrap();
+        std::fs::write(src.path().join("new.txt"), "fresh beta").unwrap();
+
+        let stats = update_indexes(&base, src.path(), &config).unwrap();
+        assert_eq!(stats.added, 1);
+        assert_eq!(stats.modified, 1);
+        assert_eq!(stats.removed, 1);
+        assert_eq!(stats.unchanged, 1);
+
+        assert_eq!(exact_count(&base, "alpha"), 1);
+        assert_eq!(exact_count(&base, "beta"), 2);
+        assert_eq!(exact_count(&base, "before"), 0);
+        assert_eq!(exact_count(&base, "doomed"), 0);
+
+        let indexes = load_all(&base).unwrap();
+        assert_eq!(indexes.paths.file_count(), 3);
+        // The deleted file's slot is reused by the new file
+        assert_eq!(indexes.paths.slot_count(), 3);
+
+        // The dictionary follows: new tokens added, vanished tokens dropped
+        let tokens: Vec<&[u8]> = indexes.exact.iter_tokens().map(|(t, _)| t).collect();
+        assert_eq!(
+            tokens,
+            vec![
+                &b"after"[..],
+                b"alpha",
+                b"and",
+                b"beta",
+                b"fresh",
+                b"more",
+                b"stable"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_update_without_changes_keeps_files() {
+        let src = TempDir::new().unwrap();
+        let out = TempDir::new().unwrap();
+        let base = out.path().join("index.tkix");
+        std::fs::write(src.path().join("a.txt"), "hello world").unwrap();
+
+        let config = ScanConfig::default();
+        build(src.path(), &base, &config);
+        let before = load_all(&base).unwrap().paths.header.index_id;
+
+        let stats = update_indexes(&base, src.path(), &config).unwrap();
+        assert!(!stats.has_changes());
+        assert_eq!(stats.unchanged, 1);
+        assert_eq!(load_all(&base).unwrap().paths.header.index_id, before);
+
+        // The index can only be updated with the tokenizer it was built with
+        let legacy = ScanConfig {
+            tokenizer: Arc::new(LegacyTokenizer),
+            ..Default::default()
+        };
+        assert!(matches!(
+            update_indexes(&base, src.path(), &legacy),
+            Err(TokenizerError::TokenizerMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_update_records_new_filters() {
+        let src = TempDir::new().unwrap();
+        let out = TempDir::new().unwrap();
+        let base = out.path().join("index.tkix");
+        std::fs::write(src.path().join("a.rs"), "fn alpha() {}").unwrap();
+
+        let config = ScanConfig {
+            extensions: vec!["rs".to_string()],
+            ..Default::default()
+        };
+        build(src.path(), &base, &config);
+
+        // Same files, but other filters: reindex must pick up the new ones
+        let wider = ScanConfig {
+            extensions: vec!["rs".to_string(), "txt".to_string()],
+            ..Default::default()
+        };
+        let stats = update_indexes(&base, src.path(), &wider).unwrap();
+        assert!(!stats.has_changes());
+
+        let build = load_all(&base).unwrap().paths.build.unwrap();
+        assert_eq!(build.config.extensions, wider.extensions);
+        assert!(build.config.build_dictionary);
+    }
+
+    #[test]
+    fn test_touched_file_skipped_with_content_hash() {
+        let src = TempDir::new().unwrap();
+        let file = src.path().join("a.txt");
+        std::fs::write(&file, "same content").unwrap();
+
+        let config = ScanConfig {
+            hash_contents: true,
+            ..Default::default()
+        };
+        let (mut paths, ..) = scan_and_build_indexes(src.path(), &config).unwrap();
+        assert!(paths.file_meta(0).unwrap().content_hash.is_some());
+
+        // Pretend the mtime changed while the contents did not
+        let mut meta = *paths.file_meta(0).unwrap();
+        meta.modified += 1;
+        paths.set_file_meta(0, meta);
+
+        let changes = detect_changes(&paths, src.path(), &config).unwrap();
+        assert!(matches!(changes.as_slice(), [FileChange::Touched(0, _)]));
+    }
+
+    #[test]
+    fn test_touched_file_metadata_saved() {
+        let src = TempDir::new().unwrap();
+        let out = TempDir::new().unwrap();
+        let base = out.path().join("index.tkix");
+        let file = src.path().join("a.txt");
+        std::fs::write(&file, "same content").unwrap();
+
+        let config = ScanConfig {
+            hash_contents: true,
+            ..Default::default()
+        };
+        build(src.path(), &base, &config);
+
+        let modified = std::fs::metadata(&file).unwrap().modified().unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(modified + std::time::Duration::from_secs(5))
+            .unwrap();
+
+        let stats = update_indexes(&base, src.path(), &config).unwrap();
+        assert_eq!((stats.modified, stats.touched), (0, 1));
+
+        // The new mtime was saved, so the file is not hashed again
+        let stats = update_indexes(&base, src.path(), &config).unwrap();
+        assert!(!stats.has_changes());
+    }
+
+    #[test]
+    fn test_update_rejects_different_root() {
+        let src = TempDir::new().unwrap();
+        let other = TempDir::new().unwrap();
+        let out = TempDir::new().unwrap();
+        let base = out.path().join("index.tkix");
+        std::fs::write(src.path().join("a.txt"), "hello").unwrap();
+
+        let config = ScanConfig::default();
+        build(src.path(), &base, &config);
+
+        let result = update_indexes(&base, other.path(), &config);
+        assert!(matches!(result, Err(TokenizerError::IndexMismatch(_))));
+    }
+
+    #[test]
+    fn test_update_accepts_relative_root() {
+        let src = TempDir::new().unwrap();
+        let out = TempDir::new().unwrap();
+        let base = out.path().join("index.tkix");
+        std::fs::write(src.path().join("a.txt"), "hello").unwrap();
+
+        // The same directory, spelled relative to the working directory
+        let cwd = std::env::current_dir().unwrap();
+        let relative: PathBuf = cwd
+            .components()
+            .skip(1)
+            .map(|_| Path::new(".."))
+            .collect::<PathBuf>()
+            .join(src.path().strip_prefix("/").unwrap());
+
+        let config = ScanConfig::default();
+        build(src.path(), &base, &config);
+        let stats = update_indexes(&base, &relative, &config).unwrap();
+        assert!(!stats.has_changes(), "{:?}", stats);
+
+        build(&relative, &base, &config);
+        std::fs::write(src.path().join("b.txt"), "hello").unwrap();
+        let stats = update_indexes(&base, src.path(), &config).unwrap();
+        assert_eq!((stats.added, stats.unchanged), (1, 1));
+        assert_eq!(exact_count(&base, "hello"), 2);
+    }
+}
@@ -0,0 +1,294 @@
+//! Integrity check of a split index, behind `tokenizer verify`
+//!
+//! Every file of the index is read in full and checked against its checksums,
+//! its header is compared with the paths file, and every file ID found in a
+//! bitmap or companion file must be a live file of the paths index. Problems
+//! are collected per file instead of stopping at the first one.
+
+use crate::error::{Result, TokenizerError};
+use crate::index::{IndexHeader, PathIndex};
+use crate::persistence::{
+    dict_file, exact_file, exact_lower_file, exact_normalized_file, load_dictionary,
+    load_frequencies, load_paths, load_positions, load_subwords, map_file, paths_file, pos_file,
+    sub_file, tf_file, tf_lower_file, trigram_file, MAGIC_EXACT, MAGIC_TRIGRAM,
+};
+use crate::table::BitmapTable;
+use roaring::RoaringBitmap;
+use std::path::{Path, PathBuf};
+
+/// Outcome of checking one index file
+#[derive(Debug, Clone)]
+pub struct FileCheck {
+    /// Path of the file
+    pub path: PathBuf,
+    /// What is wrong with the file; empty if it is intact
+    pub problems: Vec<String>,
+}
+
+impl FileCheck {
+    fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            problems: Vec::new(),
+        }
+    }
+
+    /// Check if no problem was found
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Outcome of checking every file of an index
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    /// Checked files, the paths file first
+    pub files: Vec<FileCheck>,
+}
+
+impl VerifyReport {
+    /// Check if every file is intact
+    pub fn is_ok(&self) -> bool {
+        self.files.iter().all(FileCheck::is_ok)
+    }
+
+    /// Get the number of problems over all files
+    pub fn problem_count(&self) -> usize {
+        self.files.iter().map(|file| file.problems.len()).sum()
+    }
+}
+
+/// Check every file of the split index at `base`
+///
+/// Fails only if the index has no paths file; everything else is reported.
+pub fn verify_index(base: &Path) -> Result<VerifyReport> {
+    let paths_path = paths_file(base);
+    if !paths_path.exists() {
+        return Err(TokenizerError::IndexNotFound(base.display().to_string()));
+    }
+
+    let mut paths_check = FileCheck::new(paths_path.clone());
+    let paths = load_paths(&paths_path)
+        .map_err(|e| paths_check.problems.push(e.to_string()))
+        .ok();
+    let verifier = Verifier {
+        paths: paths.as_ref(),
+    };
+    let mut report = VerifyReport {
+        files: vec![paths_check],
+    };
+
+    for (path, magic, required) in [
+        (exact_file(base), MAGIC_EXACT, true),
+        (exact_lower_file(base), MAGIC_EXACT, true),
+        (exact_normalized_file(base), MAGIC_EXACT, false),
+        (trigram_file(base), MAGIC_TRIGRAM, true),
+    ] {
+        if path.exists() {
+            report.files.push(verifier.check_table(path, magic));
+        } else if required {
+            let mut check = FileCheck::new(path);
+            check.problems.push("file is missing".to_string());
+            report.files.push(check);
+        }
+    }
+
+    let dict_path = dict_file(base);
+    if dict_path.exists() {
+        let result = load_dictionary(&dict_path).map(|(header, _)| (header, None));
+        report.files.push(verifier.check_loaded(dict_path, result));
+    }
+    let sub_path = sub_file(base);
+    if sub_path.exists() {
+        let result = load_subwords(&sub_path).map(|(header, sub)| (header, Some(sub.file_ids())));
+        report.files.push(verifier.check_loaded(sub_path, result));
+    }
+    let pos_path = pos_file(base);
+    if pos_path.exists() {
+        let result = load_positions(&pos_path).map(|(header, pos)| (header, Some(pos.file_ids())));
+        report.files.push(verifier.check_loaded(pos_path, result));
+    }
+    for tf_path in [tf_file(base), tf_lower_file(base)] {
+        if tf_path.exists() {
+            let result =
+                load_frequencies(&tf_path).map(|(header, tf)| (header, Some(tf.file_ids())));
+            report.files.push(verifier.check_loaded(tf_path, result));
+        }
+    }
+
+    Ok(report)
+}
+
+/// Checks the other files against the paths file, when it could be read
+struct Verifier<'a> {
+    paths: Option<&'a PathIndex>,
+}
+
+impl Verifier<'_> {
+    /// Check a table file and every bitmap in it
+    fn check_table(&self, path: PathBuf, magic: &[u8; 4]) -> FileCheck {
+        let mut check = FileCheck::new(path);
+        let (header, table) = match map_file(&check.path).and_then(|m| BitmapTable::parse(m, magic))
+        {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                check.problems.push(e.to_string());
+                return check;
+            }
+        };
+        self.check_header(&header, &mut check);
+        if let Err(e) = table.verify_entries() {
+            check.problems.push(e.to_string());
+        }
+
+        let mut file_ids = RoaringBitmap::new();
+        let mut corrupted = 0;
+        let mut first_error = None;
+        for index in 0..table.len() {
+            match table.bitmap(index) {
+                Ok(bitmap) => file_ids |= bitmap,
+                Err(e) => {
+                    corrupted += 1;
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        if let Some(e) = first_error {
+            check.problems.push(format!(
+                "{} of {} bitmaps are corrupted, first: {}",
+                corrupted,
+                table.len(),
+                e
+            ));
+        }
+        self.check_file_ids(&file_ids, &mut check);
+        check
+    }
+
+    /// Check a companion file loaded in full, with the file IDs it refers to
+    fn check_loaded(
+        &self,
+        path: PathBuf,
+        loaded: Result<(IndexHeader, Option<RoaringBitmap>)>,
+    ) -> FileCheck {
+        let mut check = FileCheck::new(path);
+        match loaded {
+            Ok((header, file_ids)) => {
+                self.check_header(&header, &mut check);
+                if let Some(file_ids) = file_ids {
+                    self.check_file_ids(&file_ids, &mut check);
+                }
+            }
+            Err(e) => check.problems.push(e.to_string()),
+        }
+        check
+    }
+
+    fn check_header(&self, header: &IndexHeader, check: &mut FileCheck) {
+        let Some(paths) = self.paths else {
+            return;
+        };
+        if header.index_id != paths.header.index_id {
+            check
+                .problems
+                .push("belongs to another index run than the paths file".to_string());
+        } else if header.tokenizer != paths.header.tokenizer {
+            check
+                .problems
+                .push("names another tokenizer than the paths file".to_string());
+        }
+    }
+
+    /// Every file ID must be below the paths index's slot count and not removed
+    fn check_file_ids(&self, file_ids: &RoaringBitmap, check: &mut FileCheck) {
+        let Some(paths) = self.paths else {
+            return;
+        };
+        let stray = file_ids - paths.file_ids();
+        if let Some(first) = stray.min() {
+            check.problems.push(format!(
+                "refers to {} file IDs that are not files of the index, first: {} \
+                 (the index has {} files)",
+                stray.len(),
+                first,
+                paths.file_count()
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::{save_exact, save_sample_index};
+    use crate::scanner::{scan_and_build_indexes, ScanConfig};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_verify_intact_and_corrupted() {
+        let src = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        let base = out.path().join("index.tkix");
+        save_sample_index(src.path(), &base);
+
+        let report = verify_index(&base).unwrap();
+        assert!(report.is_ok(), "{:?}", report);
+        assert!(report.files.iter().any(|file| file.path == pos_file(&base)));
+
+        // Flip a bit near the end of the bitmaps and truncate the positions
+        let mut data = std::fs::read(trigram_file(&base)).unwrap();
+        let bitmap = data.len() - 30;
+        data[bitmap] ^= 0x04;
+        std::fs::write(trigram_file(&base), data).unwrap();
+        let data = std::fs::read(pos_file(&base)).unwrap();
+        std::fs::write(pos_file(&base), &data[..data.len() - 3]).unwrap();
+        std::fs::remove_file(exact_lower_file(&base)).unwrap();
+
+        let report = verify_index(&base).unwrap();
+        let failed: Vec<&Path> = report
+            .files
+            .iter()
+            .filter(|file| !file.is_ok())
+            .map(|file| file.path.as_path())
+            .collect();
+        assert_eq!(
+            failed,
+            [
+                exact_lower_file(&base).as_path(),
+                trigram_file(&base).as_path(),
+                pos_file(&base).as_path()
+            ]
+        );
+        assert_eq!(report.problem_count(), 3);
+        assert!(matches!(
+            load_positions(&pos_file(&base)),
+            Err(TokenizerError::Corrupted(_))
+        ));
+
+        assert!(matches!(
+            verify_index(&out.path().join("missing.tkix")),
+            Err(TokenizerError::IndexNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_verify_foreign_file_and_file_ids() {
+        let src = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        let base = out.path().join("index.tkix");
+        save_sample_index(src.path(), &base);
+
+        // An exact index from another run that names a file the paths lack
+        let (_, mut exact, _, _) =
+            scan_and_build_indexes(src.path(), &ScanConfig::default()).unwrap();
+        exact.add_token(42, 7);
+        save_exact(&exact, &exact_file(&base)).unwrap();
+
+        let report = verify_index(&base).unwrap();
+        let problems = &report.files[1].problems;
+        assert_eq!(report.files[1].path, exact_file(&base));
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems[0].contains("another index run"));
+        assert!(problems[1].contains("first: 7"));
+    }
+}
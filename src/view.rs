@@ -0,0 +1,323 @@
+//! Read-only, memory-mapped views of the token index files
+//!
+//! Opening a view only maps the file (or a bundle holding it) and validates
+//! its header; bitmaps are deserialized from the mapping on lookup. The
+//! lookup traits let query functions run against either a view or a fully
+//! loaded index.
+//!
+//! There are views of the exact and trigram tables only. The paths file is
+//! decoded in full even when it is mapped.
+
+use crate::dictionary::TokenDictionary;
+use crate::error::Result;
+use crate::index::{ExactTokenIndex, IndexHeader, TrigramIndex};
+use crate::persistence::MappedBytes;
+use crate::positions::PositionIndex;
+use crate::ranking::TermFrequencies;
+use crate::subword::SubwordIndex;
+use crate::table::BitmapTable;
+use crate::tokenizer::Tokenizer;
+use roaring::RoaringBitmap;
+use std::borrow::Cow;
+use std::sync::Arc;
+
+/// Index types that map exact token hashes to file bitmaps
+pub trait TokenLookup {
+    /// Get the bitmap of files containing a token hash
+    ///
+    /// Fails if the bitmap is stored corrupted.
+    fn token_bitmap(&self, token_hash: u64) -> Result<Option<Cow<'_, RoaringBitmap>>>;
+
+    /// Get the tokenizer the index was built with, used to split queries
+    fn tokenizer(&self) -> &dyn Tokenizer;
+
+    /// Get the token strings used to expand wildcard patterns, if loaded
+    fn vocabulary(&self) -> Option<&TokenDictionary> {
+        None
+    }
+
+    /// Get the token positions used to check phrases and `NEAR/n`, if loaded
+    fn positions(&self) -> Option<&PositionIndex> {
+        None
+    }
+
+    /// Get the token counts used to rank results by relevance, if loaded
+    fn frequencies(&self) -> Option<&TermFrequencies> {
+        None
+    }
+
+    /// Get the identifier parts used by subword queries, if loaded
+    fn subwords(&self) -> Option<&SubwordIndex> {
+        None
+    }
+}
+
+/// Index types that map trigrams to file bitmaps
+pub trait TrigramLookup {
+    /// Get the bitmap of files containing a trigram
+    ///
+    /// Fails if the bitmap is stored corrupted.
+    fn trigram_bitmap(&self, trigram: u64) -> Result<Option<Cow<'_, RoaringBitmap>>>;
+}
+
+impl TokenLookup for ExactTokenIndex {
+    fn token_bitmap(&self, token_hash: u64) -> Result<Option<Cow<'_, RoaringBitmap>>> {
+        Ok(self.get_bitmap(token_hash).map(Cow::Borrowed))
+    }
+
+    fn tokenizer(&self) -> &dyn Tokenizer {
+        ExactTokenIndex::tokenizer(self)
+    }
+
+    fn vocabulary(&self) -> Option<&TokenDictionary> {
+        self.dictionary()
+    }
+
+    fn positions(&self) -> Option<&PositionIndex> {
+        self.positions.as_ref()
+    }
+
+    fn frequencies(&self) -> Option<&TermFrequencies> {
+        self.frequencies.as_ref()
+    }
+
+    fn subwords(&self) -> Option<&SubwordIndex> {
+        self.subwords.as_ref()
+    }
+}
+
+impl TrigramLookup for TrigramIndex {
+    fn trigram_bitmap(&self, trigram: u64) -> Result<Option<Cow<'_, RoaringBitmap>>> {
+        Ok(self.get_bitmap(trigram).map(Cow::Borrowed))
+    }
+}
+
+/// Memory-mapped exact token index (`.exact` / `.exacti` files)
+#[derive(Debug)]
+pub struct ExactTokenView {
+    /// Header with version and index ID
+    pub header: IndexHeader,
+    table: BitmapTable<MappedBytes>,
+    tokenizer: Arc<dyn Tokenizer>,
+    dictionary: Option<TokenDictionary>,
+    positions: Option<PositionIndex>,
+    frequencies: Option<TermFrequencies>,
+    subwords: Option<SubwordIndex>,
+}
+
+impl ExactTokenView {
+    /// Wrap a parsed table; the caller checked `tokenizer` against the header
+    pub(crate) fn new(
+        header: IndexHeader,
+        table: BitmapTable<MappedBytes>,
+        tokenizer: Arc<dyn Tokenizer>,
+    ) -> Self {
+        Self {
+            header,
+            table,
+            tokenizer,
+            dictionary: None,
+            positions: None,
+            frequencies: None,
+            subwords: None,
+        }
+    }
+
+    /// Attach the token dictionary used to expand wildcard patterns
+    ///
+    /// For a `.exacti` view this must be the lowercased dictionary.
+    pub fn set_dictionary(&mut self, dictionary: TokenDictionary) {
+        self.dictionary = Some(dictionary);
+    }
+
+    /// Attach the positional index used to check phrases and `NEAR/n`
+    pub fn set_positions(&mut self, positions: PositionIndex) {
+        self.positions = Some(positions);
+    }
+
+    /// Attach the term frequencies used to rank results by relevance
+    ///
+    /// For a `.exacti` view these must be the lowercase frequencies (`.tfi`).
+    pub fn set_frequencies(&mut self, frequencies: TermFrequencies) {
+        self.frequencies = Some(frequencies);
+    }
+
+    /// Attach the subword index used by subword queries
+    pub fn set_subwords(&mut self, subwords: SubwordIndex) {
+        self.subwords = Some(subwords);
+    }
+
+    /// Get bitmap for a token hash, deserialized from the mapping
+    ///
+    /// Fails with `TokenizerError::Corrupted` if its checksum does not match.
+    pub fn get_bitmap(&self, token_hash: u64) -> Result<Option<RoaringBitmap>> {
+        self.table.get(token_hash)
+    }
+
+    /// Get total unique tokens
+    pub fn token_count(&self) -> usize {
+        self.table.len()
+    }
+
+    /// Iterate over all token hashes in ascending order
+    pub fn iter_hashes(&self) -> impl Iterator<Item = u64> + '_ {
+        (0..self.table.len()).map(|i| self.table.key(i))
+    }
+}
+
+impl TokenLookup for ExactTokenView {
+    fn token_bitmap(&self, token_hash: u64) -> Result<Option<Cow<'_, RoaringBitmap>>> {
+        Ok(self.get_bitmap(token_hash)?.map(Cow::Owned))
+    }
+
+    fn tokenizer(&self) -> &dyn Tokenizer {
+        self.tokenizer.as_ref()
+    }
+
+    fn vocabulary(&self) -> Option<&TokenDictionary> {
+        self.dictionary.as_ref()
+    }
+
+    fn positions(&self) -> Option<&PositionIndex> {
+        self.positions.as_ref()
+    }
+
+    fn frequencies(&self) -> Option<&TermFrequencies> {
+        self.frequencies.as_ref()
+    }
+
+    fn subwords(&self) -> Option<&SubwordIndex> {
+        self.subwords.as_ref()
+    }
+}
+
+/// Memory-mapped trigram index (`.tri` files)
+#[derive(Debug)]
+pub struct TrigramView {
+    /// Header with version and index ID
+    pub header: IndexHeader,
+    table: BitmapTable<MappedBytes>,
+}
+
+impl TrigramView {
+    pub(crate) fn new(header: IndexHeader, table: BitmapTable<MappedBytes>) -> Self {
+        Self { header, table }
+    }
+
+    /// Get bitmap for a trigram, deserialized from the mapping
+    ///
+    /// Fails with `TokenizerError::Corrupted` if its checksum does not match.
+    pub fn get_bitmap(&self, trigram: u64) -> Result<Option<RoaringBitmap>> {
+        self.table.get(trigram)
+    }
+
+    /// Get total unique trigrams
+    pub fn trigram_count(&self) -> usize {
+        self.table.len()
+    }
+}
+
+impl TrigramLookup for TrigramView {
+    fn trigram_bitmap(&self, trigram: u64) -> Result<Option<Cow<'_, RoaringBitmap>>> {
+        Ok(self.get_bitmap(trigram)?.map(Cow::Owned))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::checksum::TRAILER_LEN;
+    use crate::error::TokenizerError;
+    use crate::persistence::{
+        exact_file, load_exact_view, load_paths, load_trigram_view, paths_file, save_all,
+        trigram_file,
+    };
+    use crate::query::{query_exact, query_fuzzy, QueryOptions};
+    use crate::scanner::{scan_and_build_indexes, ScanConfig};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_views_match_loaded_indexes() {
+        let src = TempDir::new().unwrap();
+        let out = TempDir::new().unwrap();
+        let base = out.path().join("index.tkix");
+        std::fs::write(src.path().join("a.rs"), "fn parse_header() {}").unwrap();
+        std::fs::write(src.path().join("b.rs"), "fn parse_body() {}").unwrap();
+
+        let (paths, exact, exact_lower, trigram) =
+            scan_and_build_indexes(src.path(), &ScanConfig::default()).unwrap();
+        save_all(&paths, &exact, &exact_lower, &trigram, &base).unwrap();
+
+        let paths = load_paths(&paths_file(&base)).unwrap();
+        let exact_view = load_exact_view(&exact_file(&base)).unwrap();
+        let trigram_view = load_trigram_view(&trigram_file(&base)).unwrap();
+
+        assert_eq!(exact_view.header, paths.header);
+        assert_eq!(exact_view.token_count(), exact.token_count());
+        assert_eq!(trigram_view.trigram_count(), trigram.trigram_count());
+
+        let options = QueryOptions {
+            match_all: true,
+            ..Default::default()
+        };
+        for query in ["fn", "parse_header", "missing"] {
+            let owned = query_exact(&paths, &exact, query, &options).unwrap();
+            let viewed = query_exact(&paths, &exact_view, query, &options).unwrap();
+            assert_eq!(owned.files, viewed.files);
+        }
+        for query in ["parse", "head", "zzz"] {
+            let owned = query_fuzzy(&paths, &trigram, query, &options).unwrap();
+            let viewed = query_fuzzy(&paths, &trigram_view, query, &options).unwrap();
+            assert_eq!(owned.files, viewed.files);
+        }
+    }
+
+    #[test]
+    fn test_view_hashes_sorted() {
+        let src = TempDir::new().unwrap();
+        let out = TempDir::new().unwrap();
+        let base = out.path().join("index.tkix");
+        std::fs::write(src.path().join("a.txt"), "one two three four").unwrap();
+
+        let (paths, exact, exact_lower, trigram) =
+            scan_and_build_indexes(src.path(), &ScanConfig::default()).unwrap();
+        save_all(&paths, &exact, &exact_lower, &trigram, &base).unwrap();
+
+        let view = load_exact_view(&exact_file(&base)).unwrap();
+        let hashes: Vec<u64> = view.iter_hashes().collect();
+        assert_eq!(hashes.len(), 4);
+        assert!(hashes.windows(2).all(|w| w[0] < w[1]));
+        for hash in hashes {
+            assert_eq!(
+                view.get_bitmap(hash).unwrap().unwrap(),
+                *exact.get_bitmap(hash).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_corrupted_bitmap_fails_query() {
+        let src = TempDir::new().unwrap();
+        let out = TempDir::new().unwrap();
+        let base = out.path().join("index.tkix");
+        std::fs::write(src.path().join("a.rs"), "alpha").unwrap();
+        std::fs::write(src.path().join("b.rs"), "beta").unwrap();
+
+        let (paths, exact, exact_lower, trigram) =
+            scan_and_build_indexes(src.path(), &ScanConfig::default()).unwrap();
+        save_all(&paths, &exact, &exact_lower, &trigram, &base).unwrap();
+
+        // Flip a byte of the last bitmap, just before its checksum
+        let mut data = std::fs::read(exact_file(&base)).unwrap();
+        let last_bitmap = data.len() - TRAILER_LEN - 8 - 1;
+        data[last_bitmap] ^= 0x40;
+        std::fs::write(exact_file(&base), data).unwrap();
+
+        let paths = load_paths(&paths_file(&base)).unwrap();
+        let view = load_exact_view(&exact_file(&base)).unwrap();
+        let options = QueryOptions::default();
+        assert!(matches!(
+            query_exact(&paths, &view, "alpha beta", &options),
+            Err(TokenizerError::Corrupted(_))
+        ));
+    }
+}
@@ -0,0 +1,634 @@
+//! Live index updates driven by Linux inotify
+//!
+//! Keeps an `IndexSet` in memory, watches every included directory under the
+//! indexed root and re-tokenizes a file once its events have been quiet for
+//! the debounce interval. Pending changes are flushed to the split index files
+//! periodically with `save_all`.
+
+use crate::error::{Result, TokenizerError};
+use crate::gitignore::IgnoreStack;
+use crate::index::{FileMeta, IndexHeader, IndexSet};
+use crate::persistence::{load_all_with_tokenizer, save_all};
+use crate::rules::ScanRules;
+use crate::scanner::ScanConfig;
+use crate::update::{
+    apply_changes_with_ids, detect_changes, same_directory, FileChange, UpdateStats,
+};
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+use rustc_hash::FxHashMap;
+use std::ffi::OsString;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Per-directory ignore files; changing one triggers a rescan
+const IGNORE_FILE_NAMES: &[&str] = &[".gitignore", ".ignore"];
+
+/// Timing options for watch mode
+#[derive(Debug, Clone)]
+pub struct WatchOptions {
+    /// How long a file must be quiet before it is re-tokenized
+    pub debounce: Duration,
+
+    /// Minimum time between flushes of in-memory changes to disk
+    pub flush_interval: Duration,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        Self {
+            debounce: Duration::from_millis(200),
+            flush_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+/// A watched directory under the root
+#[derive(Debug)]
+struct WatchedDir {
+    wd: WatchDescriptor,
+    /// Ignore rules that apply to the directory's children
+    ignores: IgnoreStack,
+}
+
+/// Keeps an in-memory index set in sync with a directory tree
+pub struct IndexWatcher {
+    indexes: IndexSet,
+    base: PathBuf,
+    root: PathBuf,
+    config: ScanConfig,
+    options: WatchOptions,
+    rules: ScanRules,
+    inotify: Inotify,
+    /// Watched directories by full path
+    dirs: FxHashMap<PathBuf, WatchedDir>,
+    /// Full path of each watch descriptor
+    wds: FxHashMap<WatchDescriptor, PathBuf>,
+    /// Live file IDs by full path
+    known: FxHashMap<PathBuf, u32>,
+    /// Files with unprocessed events and the time of their latest event
+    pending: FxHashMap<PathBuf, Instant>,
+    /// Whether the whole tree must be compared against the index
+    rescan: bool,
+    /// Whether the in-memory indexes differ from the files on disk
+    dirty: bool,
+    last_flush: Instant,
+}
+
+impl IndexWatcher {
+    /// Load the split index files at `base` and start watching `root`
+    ///
+    /// Changes made while no watcher was running are picked up by the first
+    /// call to `poll`.
+    pub fn new(
+        base: &Path,
+        root: &Path,
+        config: ScanConfig,
+        options: WatchOptions,
+    ) -> Result<Self> {
+        let indexes = load_all_with_tokenizer(base, config.tokenizer.clone())?;
+
+        if !same_directory(&indexes.paths.root_path, root) {
+            return Err(TokenizerError::IndexMismatch(format!(
+                "Index was built from {}, not {}",
+                indexes.paths.root_path.display(),
+                root.display()
+            )));
+        }
+
+        Self::from_indexes(indexes, base, config, options)
+    }
+
+    /// Start watching the root of an already loaded index set
+    ///
+    /// `base` is where flushes write the split index files.
+    pub fn from_indexes(
+        mut indexes: IndexSet,
+        base: &Path,
+        config: ScanConfig,
+        options: WatchOptions,
+    ) -> Result<Self> {
+        let inotify = Inotify::init().map_err(|e| TokenizerError::Io(e.to_string()))?;
+        let root = indexes.paths.root_path.clone();
+        let known = indexes.paths.iter_files().map(|(id, p)| (p, id)).collect();
+
+        // The next flush records the filters the index is now kept in step with
+        let config_changed = indexes
+            .paths
+            .build
+            .as_mut()
+            .is_some_and(|build| build.record_update(&config));
+
+        let mut watcher = Self {
+            indexes,
+            base: base.to_path_buf(),
+            root,
+            rules: ScanRules::new(&config)?,
+            config,
+            options,
+            inotify,
+            dirs: FxHashMap::default(),
+            wds: FxHashMap::default(),
+            known,
+            pending: FxHashMap::default(),
+            rescan: true,
+            dirty: config_changed,
+            last_flush: Instant::now(),
+        };
+
+        watcher.watch_root()?;
+        Ok(watcher)
+    }
+
+    /// Get the live in-memory indexes
+    pub fn indexes(&self) -> &IndexSet {
+        &self.indexes
+    }
+
+    /// Get the number of files waiting for their debounce interval to pass
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Check whether there are changes that have not been flushed yet
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Drain queued inotify events, apply settled file changes and flush
+    /// once the flush interval has passed
+    ///
+    /// Never blocks; call it in a loop or use `run`.
+    pub fn poll(&mut self) -> Result<UpdateStats> {
+        self.read_events()?;
+
+        let stats = if self.rescan {
+            self.full_rescan()?
+        } else {
+            self.apply_pending(Instant::now())
+        };
+
+        if self.dirty && self.last_flush.elapsed() >= self.options.flush_interval {
+            self.flush()?;
+        }
+
+        Ok(stats)
+    }
+
+    /// Write the in-memory indexes to disk under a fresh header
+    ///
+    /// Returns false without writing when nothing changed since the last flush.
+    pub fn flush(&mut self) -> Result<bool> {
+        if !self.dirty {
+            return Ok(false);
+        }
+
+        let header = IndexHeader::with_tokenizer(self.indexes.exact.tokenizer());
+        self.indexes.set_header(header);
+        save_all(
+            &self.indexes.paths,
+            &self.indexes.exact,
+            &self.indexes.exact_lower,
+            &self.indexes.trigram,
+            &self.base,
+        )?;
+
+        self.dirty = false;
+        self.last_flush = Instant::now();
+        Ok(true)
+    }
+
+    /// Poll until `stop` is set, then flush any remaining changes
+    ///
+    /// `on_update` is called after every poll that changed the index.
+    pub fn run<F>(&mut self, stop: &AtomicBool, mut on_update: F) -> Result<()>
+    where
+        F: FnMut(&UpdateStats),
+    {
+        let tick = (self.options.debounce / 2)
+            .clamp(Duration::from_millis(10), Duration::from_millis(100));
+
+        while !stop.load(Ordering::Relaxed) {
+            let stats = self.poll()?;
+            if stats.has_changes() {
+                on_update(&stats);
+            }
+            std::thread::sleep(tick);
+        }
+
+        self.flush()?;
+        Ok(())
+    }
+
+    /// Read every queued event without blocking
+    fn read_events(&mut self) -> Result<()> {
+        let mut buffer = [0u8; 4096];
+
+        loop {
+            let events: Vec<(WatchDescriptor, EventMask, Option<OsString>)> =
+                match self.inotify.read_events(&mut buffer) {
+                    Ok(events) => events
+                        .map(|e| (e.wd, e.mask, e.name.map(|n| n.to_os_string())))
+                        .collect(),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                    Err(e) => return Err(TokenizerError::Io(e.to_string())),
+                };
+
+            if events.is_empty() {
+                return Ok(());
+            }
+
+            for (wd, mask, name) in events {
+                self.handle_event(wd, mask, name)?;
+            }
+        }
+    }
+
+    fn handle_event(
+        &mut self,
+        wd: WatchDescriptor,
+        mask: EventMask,
+        name: Option<OsString>,
+    ) -> Result<()> {
+        if mask.contains(EventMask::Q_OVERFLOW) {
+            // Events were dropped; only a full comparison can recover
+            self.rescan = true;
+            return Ok(());
+        }
+
+        if mask.contains(EventMask::IGNORED) {
+            // The kernel removed the watch (directory deleted or unmounted)
+            if let Some(dir) = self.wds.remove(&wd) {
+                if self.dirs.get(&dir).is_some_and(|d| d.wd == wd) {
+                    self.dirs.remove(&dir);
+                }
+            }
+            return Ok(());
+        }
+
+        // Events about the watched directory itself are reported by its parent
+        let (Some(dir), Some(name)) = (self.wds.get(&wd), name) else {
+            return Ok(());
+        };
+        let path = dir.join(&name);
+
+        if IGNORE_FILE_NAMES.iter().any(|n| name == *n) && self.config.respect_ignore_files {
+            self.rescan = true;
+        }
+
+        if mask.contains(EventMask::ISDIR) {
+            if mask.intersects(EventMask::CREATE | EventMask::MOVED_TO) {
+                let parent = self.dirs[dir].ignores.clone();
+                self.watch_tree(&path, &parent, true)?;
+            } else if mask.intersects(EventMask::DELETE | EventMask::MOVED_FROM) {
+                self.forget_tree(&path);
+            }
+            return Ok(());
+        }
+
+        self.pending.insert(path, Instant::now());
+        Ok(())
+    }
+
+    /// Watch the root directory and everything below it
+    fn watch_root(&mut self) -> Result<()> {
+        let root_ignores = if self.config.respect_ignore_files {
+            IgnoreStack::for_root(&self.root)
+        } else {
+            IgnoreStack::default()
+        };
+        let root = self.root.clone();
+        self.watch_tree(&root, &root_ignores, false)
+    }
+
+    /// Watch a directory and its included subdirectories
+    ///
+    /// With `queue_files`, every file found is queued for re-tokenization
+    /// (used for directories created or moved in after the watch started).
+    fn watch_tree(&mut self, dir: &Path, parent: &IgnoreStack, queue_files: bool) -> Result<()> {
+        let rel = dir.strip_prefix(&self.root).unwrap_or(Path::new(""));
+        let is_root = rel.as_os_str().is_empty();
+
+        if !is_root
+            && (self.rules.skip_dir(rel)
+                || (self.config.respect_ignore_files && parent.is_ignored(rel, true)))
+        {
+            return Ok(());
+        }
+
+        let ignores = if self.config.respect_ignore_files {
+            parent.enter_dir(rel)
+        } else {
+            parent.clone()
+        };
+
+        let wd = match self.inotify.watches().add(dir, watch_mask()) {
+            Ok(wd) => wd,
+            // The directory vanished before it could be watched
+            Err(_) if !is_root => return Ok(()),
+            Err(e) => return Err(TokenizerError::Io(e.to_string())),
+        };
+        self.wds.insert(wd.clone(), dir.to_path_buf());
+        self.dirs.insert(
+            dir.to_path_buf(),
+            WatchedDir {
+                wd,
+                ignores: ignores.clone(),
+            },
+        );
+
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return Ok(());
+        };
+        for entry in entries.flatten() {
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if file_type.is_dir() {
+                self.watch_tree(&entry.path(), &ignores, queue_files)?;
+            } else if queue_files {
+                self.pending.insert(entry.path(), Instant::now());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stop watching a directory that left the tree and queue its files for removal
+    fn forget_tree(&mut self, dir: &Path) {
+        let gone: Vec<PathBuf> = self
+            .dirs
+            .keys()
+            .filter(|p| p.starts_with(dir))
+            .cloned()
+            .collect();
+        for path in gone {
+            if let Some(watched) = self.dirs.remove(&path) {
+                self.wds.remove(&watched.wd);
+                // Fails harmlessly if the kernel already dropped the watch
+                let _ = self.inotify.watches().remove(watched.wd);
+            }
+        }
+
+        let now = Instant::now();
+        for path in self.known.keys().filter(|p| p.starts_with(dir)) {
+            self.pending.insert(path.clone(), now);
+        }
+    }
+
+    /// Re-tokenize every pending file whose debounce interval has passed
+    fn apply_pending(&mut self, now: Instant) -> UpdateStats {
+        let debounce = self.options.debounce;
+        let due: Vec<PathBuf> = self
+            .pending
+            .iter()
+            .filter(|(_, seen)| now.duration_since(**seen) >= debounce)
+            .map(|(path, _)| path.clone())
+            .collect();
+
+        if due.is_empty() {
+            return UpdateStats::default();
+        }
+
+        let mut changes = Vec::new();
+        for path in due {
+            self.pending.remove(&path);
+            if let Some(change) = self.resolve(&path) {
+                changes.push(change);
+            }
+        }
+
+        self.apply(&changes)
+    }
+
+    /// Compare the whole tree against the index (start-up and queue overflow)
+    fn full_rescan(&mut self) -> Result<UpdateStats> {
+        self.rescan = false;
+        self.pending.clear();
+
+        // Refresh watches and ignore rules; re-adding an existing watch is a no-op
+        self.watch_root()?;
+
+        let changes = detect_changes(&self.indexes.paths, &self.root, &self.config)?;
+        Ok(self.apply(&changes))
+    }
+
+    /// Apply changes to the in-memory indexes and keep the path lookup in sync
+    fn apply(&mut self, changes: &[FileChange]) -> UpdateStats {
+        if changes.is_empty() {
+            return UpdateStats::default();
+        }
+
+        // Removed IDs must be resolved to paths before the slots are reused
+        let removed: Vec<PathBuf> = changes
+            .iter()
+            .filter_map(|change| match change {
+                FileChange::Removed(id) => self.indexes.paths.get_file_path(*id),
+                _ => None,
+            })
+            .collect();
+
+        let (stats, added_ids) =
+            apply_changes_with_ids(&mut self.indexes, changes, self.config.hash_contents);
+
+        for path in removed {
+            self.known.remove(&path);
+        }
+        let added = changes.iter().filter_map(|change| match change {
+            FileChange::Added(path, _) => Some(path.clone()),
+            _ => None,
+        });
+        self.known.extend(added.zip(added_ids));
+
+        self.dirty = true;
+        stats
+    }
+
+    /// Work out what an event on a file means for the index
+    fn resolve(&self, path: &Path) -> Option<FileChange> {
+        let known = self.known.get(path).copied();
+
+        match (known, self.included_meta(path)) {
+            (None, Some(meta)) => Some(FileChange::Added(path.to_path_buf(), meta)),
+            (Some(id), Some(meta)) => {
+                let stored = self.indexes.paths.file_meta(id)?;
+                meta.differs_from(stored)
+                    .then_some(FileChange::Modified(id, meta))
+            }
+            (Some(id), None) => Some(FileChange::Removed(id)),
+            (None, None) => None,
+        }
+    }
+
+    /// Get a file's metadata if it exists and passes the scan filters
+    fn included_meta(&self, path: &Path) -> Option<FileMeta> {
+        let rel = path.strip_prefix(&self.root).ok()?;
+        let dir = self.dirs.get(path.parent()?)?;
+
+        let metadata = std::fs::symlink_metadata(path).ok()?;
+        if metadata.is_dir() || metadata.len() > self.config.max_file_size {
+            return None;
+        }
+        if self.rules.skip_file(rel) {
+            return None;
+        }
+        if self.config.respect_ignore_files && dir.ignores.is_ignored(rel, false) {
+            return None;
+        }
+
+        Some(FileMeta::from_metadata(&metadata))
+    }
+}
+
+/// Events that can change which files exist or what they contain
+fn watch_mask() -> WatchMask {
+    WatchMask::CREATE
+        | WatchMask::DELETE
+        | WatchMask::MODIFY
+        | WatchMask::CLOSE_WRITE
+        | WatchMask::MOVED_FROM
+        | WatchMask::MOVED_TO
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::load_all;
+    use crate::query::{query_exact, QueryOptions};
+    use crate::scanner::scan_and_build_indexes;
+    use tempfile::TempDir;
+
+    fn setup(src: &Path, base: &Path, config: &ScanConfig) -> IndexWatcher {
+        let (paths, exact, exact_lower, trigram) = scan_and_build_indexes(src, config).unwrap();
+        save_all(&paths, &exact, &exact_lower, &trigram, base).unwrap();
+
+        let options = WatchOptions {
+            debounce: Duration::from_millis(20),
+            flush_interval: Duration::from_secs(3600),
+        };
+        IndexWatcher::new(base, src, config.clone(), options).unwrap()
+    }
+
+    /// Poll until the watcher has applied everything it has seen
+    fn settle(watcher: &mut IndexWatcher) {
+        watcher.poll().unwrap();
+        for _ in 0..100 {
+            std::thread::sleep(Duration::from_millis(30));
+            watcher.poll().unwrap();
+            if watcher.pending_count() == 0 {
+                return;
+            }
+        }
+        panic!("watcher did not settle");
+    }
+
+    fn count(watcher: &IndexWatcher, query: &str) -> usize {
+        let indexes = watcher.indexes();
+        query_exact(
+            &indexes.paths,
+            &indexes.exact,
+            query,
+            &QueryOptions::default(),
+        )
+        .unwrap()
+        .files
+        .len()
+    }
+
+    #[test]
+    fn test_watch_create_modify_delete() {
+        let src = TempDir::new().unwrap();
+        let out = TempDir::new().unwrap();
+        let base = out.path().join("index.tkix");
+        std::fs::write(src.path().join("a.txt"), "alpha").unwrap();
+
+        let mut watcher = setup(src.path(), &base, &ScanConfig::default());
+        settle(&mut watcher);
+        assert_eq!(count(&watcher, "alpha"), 1);
+
+        std::fs::write(src.path().join("b.txt"), "alpha beta").unwrap();
+        settle(&mut watcher);
+        assert_eq!(count(&watcher, "alpha"), 2);
+
+        std::fs::write(src.path().join("a.txt"), "gamma").unwrap();
+        settle(&mut watcher);
+        assert_eq!(count(&watcher, "alpha"), 1);
+        assert_eq!(count(&watcher, "gamma"), 1);
+
+        std::fs::remove_file(src.path().join("b.txt")).unwrap();
+        settle(&mut watcher);
+        assert_eq!(count(&watcher, "alpha"), 0);
+        assert_eq!(watcher.indexes().paths.file_count(), 1);
+    }
+
+    #[test]
+    fn test_watch_new_directory_and_excludes() {
+        let src = TempDir::new().unwrap();
+        let out = TempDir::new().unwrap();
+        let base = out.path().join("index.tkix");
+
+        let mut watcher = setup(src.path(), &base, &ScanConfig::default());
+        settle(&mut watcher);
+
+        std::fs::create_dir_all(src.path().join("sub/deep")).unwrap();
+        std::fs::write(src.path().join("sub/deep/c.txt"), "nested").unwrap();
+        std::fs::create_dir_all(src.path().join("node_modules")).unwrap();
+        std::fs::write(src.path().join("node_modules/d.txt"), "nested").unwrap();
+        settle(&mut watcher);
+
+        // Files written after the watch was added arrive as events
+        std::fs::write(src.path().join("sub/deep/e.txt"), "nested").unwrap();
+        settle(&mut watcher);
+
+        assert_eq!(count(&watcher, "nested"), 2);
+    }
+
+    #[test]
+    fn test_watch_directory_moved_out() {
+        let src = TempDir::new().unwrap();
+        let out = TempDir::new().unwrap();
+        let base = out.path().join("index.tkix");
+        std::fs::create_dir_all(src.path().join("sub")).unwrap();
+        std::fs::write(src.path().join("sub/a.txt"), "moving").unwrap();
+
+        let mut watcher = setup(src.path(), &base, &ScanConfig::default());
+        settle(&mut watcher);
+        assert_eq!(count(&watcher, "moving"), 1);
+
+        std::fs::rename(src.path().join("sub"), out.path().join("elsewhere")).unwrap();
+        settle(&mut watcher);
+        assert_eq!(count(&watcher, "moving"), 0);
+        assert_eq!(watcher.indexes().paths.file_count(), 0);
+    }
+
+    #[test]
+    fn test_catch_up_and_flush() {
+        let src = TempDir::new().unwrap();
+        let out = TempDir::new().unwrap();
+        let base = out.path().join("index.tkix");
+        std::fs::write(src.path().join("a.txt"), "alpha").unwrap();
+
+        let config = ScanConfig::default();
+        let (paths, exact, exact_lower, trigram) =
+            scan_and_build_indexes(src.path(), &config).unwrap();
+        save_all(&paths, &exact, &exact_lower, &trigram, &base).unwrap();
+        let before = paths.header.index_id;
+
+        // Changed while no watcher was running
+        std::fs::write(src.path().join("b.txt"), "alpha").unwrap();
+
+        let mut watcher =
+            IndexWatcher::new(&base, src.path(), config, WatchOptions::default()).unwrap();
+        let stats = watcher.poll().unwrap();
+        assert_eq!(stats.added, 1);
+        assert!(watcher.is_dirty());
+
+        assert!(watcher.flush().unwrap());
+        assert!(!watcher.flush().unwrap());
+
+        let on_disk = load_all(&base).unwrap();
+        assert_ne!(on_disk.paths.header.index_id, before);
+        assert_eq!(on_disk.paths.file_count(), 2);
+    }
+}
@@ -0,0 +1,208 @@
+//! Wildcard and regex token patterns for exact-mode queries
+//!
+//! A query word containing `*` (any run of characters) or `?` (one
+//! character) is a wildcard pattern; a word wrapped in slashes
+//! (`/handle_\w+/`) is a regex.
+//! Both must match a whole token and are expanded against the token
+//! dictionary rather than the file contents.
+
+use crate::dictionary::TokenDictionary;
+use crate::unicode::{decode_char, fold_token};
+use regex::bytes::{Regex, RegexBuilder};
+
+/// Default cap on the number of tokens one pattern may expand to
+pub const DEFAULT_MAX_EXPANSIONS: usize = 1000;
+
+/// A parsed token pattern
+#[derive(Debug, Clone)]
+pub(crate) enum TokenPattern {
+    /// `*` and `?` wildcards over token characters
+    Wildcard(Vec<u8>),
+    /// Regex anchored to the whole token
+    Regex(Regex),
+}
+
+impl TokenPattern {
+    /// Parse a query word as a pattern
+    ///
+    /// Returns None for plain words. With `ignore_case` the pattern is
+    /// matched against a case-folded vocabulary.
+    pub(crate) fn parse(word: &str, ignore_case: bool) -> Option<Result<Self, String>> {
+        if word.len() > 2 && word.starts_with('/') && word.ends_with('/') {
+            let source = &word[1..word.len() - 1];
+            let regex = RegexBuilder::new(&format!("^(?:{})$", source))
+                .case_insensitive(ignore_case)
+                .build()
+                .map_err(|e| e.to_string());
+            return Some(regex.map(TokenPattern::Regex));
+        }
+
+        if word.contains(['*', '?']) {
+            let pattern = if ignore_case {
+                fold_token(word.as_bytes())
+            } else {
+                word.as_bytes().to_vec()
+            };
+            return Some(Ok(TokenPattern::Wildcard(pattern)));
+        }
+
+        None
+    }
+
+    /// Check whether a token matches the pattern
+    pub(crate) fn matches(&self, token: &[u8]) -> bool {
+        match self {
+            TokenPattern::Wildcard(pattern) => wildcard_match(pattern, token),
+            TokenPattern::Regex(regex) => regex.is_match(token),
+        }
+    }
+
+    /// Literal bytes every match starts with
+    fn literal_prefix(&self) -> &[u8] {
+        match self {
+            TokenPattern::Wildcard(pattern) => {
+                let end = pattern
+                    .iter()
+                    .position(|b| matches!(b, b'*' | b'?'))
+                    .unwrap_or(pattern.len());
+                &pattern[..end]
+            }
+            TokenPattern::Regex(_) => &[],
+        }
+    }
+
+    /// Find the hashes of up to `limit` matching tokens
+    ///
+    /// Returns the hashes and whether more tokens matched than the limit.
+    pub(crate) fn expand(&self, dictionary: &TokenDictionary, limit: usize) -> (Vec<u64>, bool) {
+        let mut hashes = Vec::new();
+        for (token, hash) in dictionary.iter_prefix(self.literal_prefix()) {
+            if self.matches(token) {
+                if hashes.len() == limit {
+                    return (hashes, true);
+                }
+                hashes.push(hash);
+            }
+        }
+        (hashes, false)
+    }
+}
+
+/// Match `text` against a pattern of literal bytes, `*` and `?`
+///
+/// `?` and each step of `*` consume a whole UTF-8 character.
+fn wildcard_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Position after the last `*` and the text position it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                p += 1;
+                backtrack = Some((p, t));
+            }
+            Some(b'?') => {
+                p += 1;
+                t += decode_char(&text[t..]).1;
+            }
+            Some(&c) if c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                // Let the last `*` absorb one more character
+                Some((star_p, star_t)) => {
+                    let next_t = star_t + decode_char(&text[star_t..]).1;
+                    p = star_p;
+                    t = next_t;
+                    backtrack = Some((star_p, next_t));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dictionary(tokens: &[&str]) -> TokenDictionary {
+        let mut dictionary = TokenDictionary::new();
+        for token in tokens {
+            dictionary.insert(token.as_bytes());
+        }
+        dictionary
+    }
+
+    fn expand(word: &str, tokens: &[&str]) -> Vec<String> {
+        let dictionary = dictionary(tokens);
+        let pattern = TokenPattern::parse(word, false).unwrap().unwrap();
+        let (hashes, _) = pattern.expand(&dictionary, usize::MAX);
+        hashes
+            .iter()
+            .map(|h| String::from_utf8(dictionary.get(*h).unwrap().to_vec()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match(b"handle_*", b"handle_request"));
+        assert!(wildcard_match(b"handle_*", b"handle_"));
+        assert!(!wildcard_match(b"handle_*", b"handler"));
+        assert!(wildcard_match(b"*Request", b"HttpRequest"));
+        assert!(!wildcard_match(b"*Request", b"RequestBody"));
+        assert!(wildcard_match(b"f?o", b"foo"));
+        assert!(!wildcard_match(b"f?o", b"fo"));
+        assert!(wildcard_match(b"a*b*c", b"axxbyybc"));
+        assert!(!wildcard_match(b"a*b*c", b"axxbyyb"));
+        // Wildcards step over whole characters
+        assert!(wildcard_match("caf?".as_bytes(), "café".as_bytes()));
+        assert!(!wildcard_match("caf??".as_bytes(), "café".as_bytes()));
+        assert!(wildcard_match("*?".as_bytes(), "日".as_bytes()));
+        assert!(!wildcard_match(b"*\xa5", "日".as_bytes()));
+    }
+
+    #[test]
+    fn test_parse_plain_words() {
+        assert!(TokenPattern::parse("handle", false).is_none());
+        assert!(TokenPattern::parse("/", false).is_none());
+        assert!(TokenPattern::parse("//", false).is_none());
+        assert!(TokenPattern::parse("/(/", false).unwrap().is_err());
+    }
+
+    #[test]
+    fn test_expand_patterns() {
+        let tokens = [
+            "handle_get",
+            "handle_post",
+            "handler",
+            "HttpRequest",
+            "Request",
+        ];
+        assert_eq!(expand("handle_*", &tokens), ["handle_get", "handle_post"]);
+        assert_eq!(expand("*Request", &tokens), ["HttpRequest", "Request"]);
+        assert_eq!(
+            expand("/handle[a-z_]+/", &tokens),
+            ["handle_get", "handle_post", "handler"]
+        );
+        // Regexes match whole tokens
+        assert_eq!(expand("/Req/", &tokens), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_expansion_limit() {
+        let dictionary = dictionary(&["aa1", "aa2", "aa3"]);
+        let pattern = TokenPattern::parse("aa*", false).unwrap().unwrap();
+        let (hashes, truncated) = pattern.expand(&dictionary, 2);
+        assert_eq!(hashes.len(), 2);
+        assert!(truncated);
+
+        let (hashes, truncated) = pattern.expand(&dictionary, 3);
+        assert_eq!(hashes.len(), 3);
+        assert!(!truncated);
+    }
+}